@@ -0,0 +1,562 @@
+//! HTTP/JSON API over the indexer store.
+//!
+//! Health is computed against a `Snapshot` of protocol state and oracle
+//! prices that a background task refreshes; positions, insurance funds and
+//! liquidation records come from the store. Amounts are in the program's
+//! units (PRICE_PRECISION prices and sizes, quote base units) and keys are
+//! base58.
+//!
+//! - `GET /positions?market=&owner=&max_distance_bps=&unhealthy=&limit=`:
+//!   health reports, closest to liquidation first. `max_distance_bps=500`
+//!   is "within 5% of liquidation".
+//! - `GET /positions/{address}`
+//! - `GET /markets?at_risk_bps=`: per-market aggregates.
+//! - `GET /insurance`
+//! - `GET /liquidations?owner=&since=&last_secs=&cursor=&limit=`: newest
+//!   first; pass the returned `next_cursor` for the next page.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, MutexGuard, RwLock};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use liquidation_math::{liquidation_trigger, LiquidationTrigger, BPS_DENOM};
+use liquidation_program::{LiquidationRecord, Position};
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use tracing::warn;
+
+use crate::indexer::SharedStore;
+use crate::keeper::{haircut, load_prices, position_health, unix_now, Prices};
+use crate::queue::market_tiers;
+use crate::scan::ProtocolState;
+use crate::store::{symbol, Indexed, RecordQuery, Store};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1_000;
+const DEFAULT_AT_RISK_BPS: u64 = 500;
+
+/// Protocol state and prices as of one refresh.
+pub struct Snapshot {
+    pub state: ProtocolState,
+    pub prices: Prices,
+    pub slot: u64,
+    pub taken_at: i64,
+}
+
+pub type SharedSnapshot = Arc<RwLock<Option<Arc<Snapshot>>>>;
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub store: SharedStore,
+    pub snapshot: SharedSnapshot,
+}
+
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/positions", get(positions))
+        .route("/positions/:address", get(position))
+        .route("/markets", get(markets))
+        .route("/insurance", get(insurance))
+        .route("/liquidations", get(liquidations))
+        .with_state(state)
+}
+
+/// Rebuild the snapshot from the store and RPC every `interval`, forever.
+/// A failed refresh keeps the previous snapshot.
+pub async fn refresh_snapshots(client: RpcClient, store: SharedStore, snapshot: SharedSnapshot, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        match take_snapshot(&client, &store).await {
+            Ok(fresh) => {
+                if let Ok(mut current) = snapshot.write() {
+                    *current = Some(Arc::new(fresh));
+                }
+            }
+            Err(err) => warn!("snapshot refresh failed: {err:#}"),
+        }
+    }
+}
+
+async fn take_snapshot(client: &RpcClient, store: &SharedStore) -> anyhow::Result<Snapshot> {
+    let state = ProtocolState::from_store(client, store).await?;
+    let slot = client.get_slot().await?;
+    let taken_at = unix_now();
+    let prices = load_prices(client, &state.oracles(), taken_at).await?;
+    Ok(Snapshot { state, prices, slot, taken_at })
+}
+
+pub enum ApiError {
+    BadRequest(String),
+    NotFound,
+    /// No snapshot yet.
+    Unavailable,
+    Internal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Internal(err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            Self::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            Self::NotFound => (StatusCode::NOT_FOUND, "not found".into()),
+            Self::Unavailable => (StatusCode::SERVICE_UNAVAILABLE, "no snapshot yet".into()),
+            Self::Internal(err) => {
+                warn!("request failed: {err:#}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal error".into())
+            }
+        };
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+impl ApiState {
+    fn snapshot(&self) -> Result<Arc<Snapshot>, ApiError> {
+        let snapshot = self.snapshot.read().map_err(|_| anyhow!("snapshot lock poisoned"))?;
+        snapshot.clone().ok_or(ApiError::Unavailable)
+    }
+
+    fn store(&self) -> Result<MutexGuard<'_, Store>, ApiError> {
+        Ok(self.store.lock().map_err(|_| anyhow!("store lock poisoned"))?)
+    }
+}
+
+fn parse_key(key: &str) -> Result<Pubkey, ApiError> {
+    Pubkey::from_str(key).map_err(|_| ApiError::BadRequest(format!("bad address {key}")))
+}
+
+fn limit(requested: Option<usize>) -> usize {
+    requested.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct PositionReport {
+    pub address: String,
+    pub owner: String,
+    pub market: String,
+    pub symbol: String,
+    pub is_long: bool,
+    pub size: u64,
+    pub entry_price: u64,
+    pub collateral: i64,
+    pub price: u64,
+    pub notional: i128,
+    pub pnl: i128,
+    pub margin: i128,
+    pub margin_ratio_bps: i128,
+    pub maintenance_margin_bps: u64,
+    pub healthy: bool,
+    /// Price at which it becomes liquidatable; `None` if no price does.
+    pub liquidation_price: Option<u64>,
+    /// How far the price has to move to get there, in bps of the current
+    /// price; 0 once it has.
+    pub distance_bps: Option<u64>,
+    pub slot: u64,
+}
+
+/// `None` for positions that can't be priced right now.
+fn report(snapshot: &Snapshot, key: &Pubkey, position: &Position) -> Option<PositionReport> {
+    let state = &snapshot.state;
+    let market = state.markets.get(&position.market)?;
+    let (price, health) = position_health(state, &snapshot.prices, position)?;
+
+    let trigger = haircut(state, &snapshot.prices, position)
+        .and_then(|haircut| liquidation_trigger(&position.terms(), haircut, &market_tiers(market)));
+    let liquidation_price = match trigger {
+        Some(LiquidationTrigger::At(at)) => Some(at.0),
+        Some(LiquidationTrigger::Never) => None,
+        None => Some(price),
+    };
+    let distance_bps = liquidation_price.map(|at| distance_bps(price, at, position.is_long));
+
+    Some(PositionReport {
+        address: key.to_string(),
+        owner: position.owner.to_string(),
+        market: position.market.to_string(),
+        symbol: symbol(&market.symbol),
+        is_long: position.is_long,
+        size: position.size,
+        entry_price: position.entry_price,
+        collateral: position.collateral,
+        price,
+        notional: health.notional.0,
+        pnl: health.pnl.0,
+        margin: health.margin.0,
+        margin_ratio_bps: health.margin_ratio_bps,
+        maintenance_margin_bps: health.maintenance_margin.0,
+        healthy: health.is_healthy(),
+        liquidation_price,
+        distance_bps,
+        slot: snapshot.slot,
+    })
+}
+
+/// Move from `price` to `trigger`, in bps of `price`, in the direction that
+/// hurts the position.
+fn distance_bps(price: u64, trigger: u64, is_long: bool) -> u64 {
+    let gap = if is_long { price.saturating_sub(trigger) } else { trigger.saturating_sub(price) };
+    if price == 0 {
+        return if gap == 0 { 0 } else { u64::MAX };
+    }
+    u64::try_from(gap as u128 * BPS_DENOM as u128 / price as u128).unwrap_or(u64::MAX)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PositionsQuery {
+    pub market: Option<String>,
+    pub owner: Option<String>,
+    pub max_distance_bps: Option<u64>,
+    pub unhealthy: Option<bool>,
+    pub limit: Option<usize>,
+}
+
+async fn positions(State(api): State<ApiState>, Query(query): Query<PositionsQuery>) -> ApiResult<Vec<PositionReport>> {
+    let market = query.market.as_deref().map(parse_key).transpose()?;
+    let owner = query.owner.as_deref().map(parse_key).transpose()?;
+    let snapshot = api.snapshot()?;
+
+    let mut reports: Vec<PositionReport> = snapshot
+        .state
+        .positions
+        .iter()
+        .filter(|(_, position)| market.is_none_or(|market| position.market == market))
+        .filter(|(_, position)| owner.is_none_or(|owner| position.owner == owner))
+        .filter_map(|(key, position)| report(&snapshot, key, position))
+        .filter(|report| query.unhealthy.is_none_or(|unhealthy| report.healthy != unhealthy))
+        .filter(|report| {
+            query
+                .max_distance_bps
+                .is_none_or(|max| report.distance_bps.is_some_and(|distance| distance <= max))
+        })
+        .collect();
+    reports.sort_by_key(|report| (report.distance_bps.unwrap_or(u64::MAX), report.address.clone()));
+    reports.truncate(limit(query.limit));
+    Ok(Json(reports))
+}
+
+async fn position(State(api): State<ApiState>, Path(address): Path<String>) -> ApiResult<PositionReport> {
+    let key = parse_key(&address)?;
+    let indexed = api.store()?.position(&key)?.ok_or(ApiError::NotFound)?;
+    let snapshot = api.snapshot()?;
+    let report = report(&snapshot, &key, &indexed.account).ok_or(ApiError::Unavailable)?;
+    Ok(Json(report))
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MarketReport {
+    pub address: String,
+    pub market_index: u16,
+    pub symbol: String,
+    pub oracle: String,
+    pub price: Option<u64>,
+    pub positions: usize,
+    pub long_open_interest: u64,
+    pub short_open_interest: u64,
+    /// Sum of position notionals at `price`.
+    pub notional: i128,
+    pub collateral: i128,
+    pub unhealthy: usize,
+    /// Positions within `at_risk_bps` of their liquidation price.
+    pub at_risk: usize,
+    /// Positions that couldn't be priced.
+    pub unpriced: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct MarketsQuery {
+    pub at_risk_bps: Option<u64>,
+}
+
+async fn markets(State(api): State<ApiState>, Query(query): Query<MarketsQuery>) -> ApiResult<Vec<MarketReport>> {
+    let snapshot = api.snapshot()?;
+    let at_risk_bps = query.at_risk_bps.unwrap_or(DEFAULT_AT_RISK_BPS);
+
+    let mut reports: HashMap<Pubkey, MarketReport> = snapshot
+        .state
+        .markets
+        .iter()
+        .map(|(key, market)| {
+            let report = MarketReport {
+                address: key.to_string(),
+                market_index: market.market_index,
+                symbol: symbol(&market.symbol),
+                oracle: market.oracle.to_string(),
+                price: snapshot.prices.get(&market.oracle).copied(),
+                long_open_interest: market.long_open_interest,
+                short_open_interest: market.short_open_interest,
+                ..MarketReport::default()
+            };
+            (*key, report)
+        })
+        .collect();
+
+    for (key, position) in &snapshot.state.positions {
+        let Some(aggregate) = reports.get_mut(&position.market) else {
+            continue;
+        };
+        aggregate.positions += 1;
+        aggregate.collateral += position.collateral as i128;
+        match report(&snapshot, key, position) {
+            Some(report) => {
+                aggregate.notional += report.notional;
+                aggregate.unhealthy += usize::from(!report.healthy);
+                aggregate.at_risk += usize::from(report.distance_bps.is_some_and(|distance| distance <= at_risk_bps));
+            }
+            None => aggregate.unpriced += 1,
+        }
+    }
+
+    let mut reports: Vec<MarketReport> = reports.into_values().collect();
+    reports.sort_by_key(|report| report.market_index);
+    Ok(Json(reports))
+}
+
+#[derive(Debug, Serialize)]
+pub struct InsuranceReport {
+    pub address: String,
+    pub insurance_vault: String,
+    pub balance: u64,
+    /// Quote actually in the vault, from the latest snapshot.
+    pub vault_balance: Option<u64>,
+    pub total_contributions: u64,
+    pub total_bad_debt_covered: u64,
+    pub utilization_ratio: u64,
+    pub slot: u64,
+}
+
+async fn insurance(State(api): State<ApiState>) -> ApiResult<Vec<InsuranceReport>> {
+    let funds = api.store()?.insurance_funds()?;
+    let snapshot = api.snapshot.read().map_err(|_| anyhow!("snapshot lock poisoned"))?.clone();
+
+    let reports = funds
+        .into_iter()
+        .map(|Indexed { address, slot, account }| InsuranceReport {
+            address: address.to_string(),
+            insurance_vault: account.insurance_vault.to_string(),
+            balance: account.balance,
+            vault_balance: snapshot
+                .as_ref()
+                .filter(|snapshot| snapshot.state.insurance_fund == address)
+                .map(|snapshot| snapshot.state.insurance_vault_balance),
+            total_contributions: account.total_contributions,
+            total_bad_debt_covered: account.total_bad_debt_covered,
+            utilization_ratio: account.utilization_ratio,
+            slot,
+        })
+        .collect();
+    Ok(Json(reports))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LiquidationsQuery {
+    pub owner: Option<String>,
+    /// Unix timestamp of the oldest record.
+    pub since: Option<i64>,
+    /// Only records from the last this many seconds.
+    pub last_secs: Option<i64>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecordReport {
+    pub address: String,
+    pub position_owner: String,
+    pub liquidator: String,
+    pub symbol: String,
+    pub liquidated_size: u64,
+    pub liquidation_price: u64,
+    pub margin_before: i64,
+    pub margin_after: i64,
+    pub liquidator_reward: u64,
+    pub bad_debt: u64,
+    pub timestamp: i64,
+    pub slot: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecordPage {
+    pub records: Vec<RecordReport>,
+    /// Pass as `cursor` for the next page; `None` on the last one.
+    pub next_cursor: Option<String>,
+}
+
+fn parse_cursor(cursor: &str) -> Result<(i64, Pubkey), ApiError> {
+    let bad = || ApiError::BadRequest(format!("bad cursor {cursor}"));
+    let (timestamp, address) = cursor.split_once(':').ok_or_else(bad)?;
+    Ok((timestamp.parse().map_err(|_| bad())?, Pubkey::from_str(address).map_err(|_| bad())?))
+}
+
+async fn liquidations(State(api): State<ApiState>, Query(query): Query<LiquidationsQuery>) -> ApiResult<RecordPage> {
+    let last_secs_since = query.last_secs.map(|secs| unix_now() - secs);
+    let record_query = RecordQuery {
+        owner: query.owner.as_deref().map(parse_key).transpose()?,
+        since: query.since.max(last_secs_since),
+        before: query.cursor.as_deref().map(parse_cursor).transpose()?,
+        limit: limit(query.limit),
+    };
+    let records = api.store()?.liquidation_records(&record_query)?;
+
+    let next_cursor = (records.len() == record_query.limit)
+        .then(|| records.last().map(|last| format!("{}:{}", last.account.timestamp, last.address)))
+        .flatten();
+    let records = records
+        .into_iter()
+        .map(|Indexed { address, slot, account }| {
+            let LiquidationRecord {
+                position_owner,
+                liquidator,
+                symbol: name,
+                liquidated_size,
+                liquidation_price,
+                margin_before,
+                margin_after,
+                liquidator_reward,
+                bad_debt,
+                timestamp,
+            } = account;
+            RecordReport {
+                address: address.to_string(),
+                position_owner: position_owner.to_string(),
+                liquidator: liquidator.to_string(),
+                symbol: symbol(&name),
+                liquidated_size,
+                liquidation_price,
+                margin_before,
+                margin_after,
+                liquidator_reward,
+                bad_debt,
+                timestamp,
+                slot,
+            }
+        })
+        .collect();
+    Ok(Json(RecordPage { records, next_cursor }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keeper::tests::{long, prices, price, state, MARKET};
+    use anchor_lang::AccountSerialize;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use serde_json::Value;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    fn bytes<T: AccountSerialize>(account: &T) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    fn api(positions: Vec<(Pubkey, Position)>, market_price: u64) -> ApiState {
+        let mut store = Store::open_in_memory().unwrap();
+        for (key, position) in &positions {
+            store.apply(key, 1, Some(&bytes(position))).unwrap();
+        }
+        let snapshot = Snapshot { state: state(positions), prices: prices(market_price), slot: 7, taken_at: 0 };
+        ApiState { store: Arc::new(Mutex::new(store)), snapshot: Arc::new(RwLock::new(Some(Arc::new(snapshot)))) }
+    }
+
+    async fn get(api: &ApiState, uri: &str) -> (StatusCode, Value) {
+        let response = router(api.clone()).oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn positions_near_liquidation() {
+        // the long liquidates at ~96.41; the safer one has twice the collateral
+        let (risky, safe) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let safer = Position { collateral: 120_000_000, ..long() };
+        let api = api(vec![(risky, long()), (safe, safer)], price(100));
+
+        let (status, all) = get(&api, "/positions").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(all.as_array().unwrap().len(), 2);
+        assert_eq!(all[0]["address"], risky.to_string());
+        assert_eq!(all[0]["distance_bps"], 358);
+
+        let (_, near) = get(&api, "/positions?max_distance_bps=500").await;
+        assert_eq!(near.as_array().unwrap().len(), 1);
+
+        let (status, one) = get(&api, &format!("/positions/{safe}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(one["healthy"], true);
+        assert_eq!(one["margin"], 120_000_000);
+
+        assert_eq!(get(&api, &format!("/positions/{}", Pubkey::new_unique())).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(get(&api, "/positions/nope").await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn market_aggregates() {
+        let api = api(vec![(Pubkey::new_unique(), long()), (Pubkey::new_unique(), long())], price(95));
+        let (_, markets) = get(&api, "/markets").await;
+        let market = &markets[0];
+        assert_eq!(market["address"], MARKET.to_string());
+        assert_eq!(market["positions"], 2);
+        assert_eq!(market["unhealthy"], 2);
+        assert_eq!(market["at_risk"], 2);
+        assert_eq!(market["notional"], 1_900_000_000);
+    }
+
+    #[tokio::test]
+    async fn liquidation_history_pages() {
+        let api = api(vec![], price(100));
+        for timestamp in [unix_now() - 7_200, unix_now() - 60, unix_now() - 30] {
+            let record = LiquidationRecord {
+                position_owner: Pubkey::new_unique(),
+                liquidator: Pubkey::new_unique(),
+                symbol: *b"SOL-PERP\0\0\0\0\0\0\0\0",
+                liquidated_size: 1,
+                liquidation_price: 2,
+                margin_before: 3,
+                margin_after: 4,
+                liquidator_reward: 5,
+                bad_debt: 0,
+                timestamp,
+            };
+            api.store.lock().unwrap().apply(&Pubkey::new_unique(), 1, Some(&bytes(&record))).unwrap();
+        }
+
+        let (_, hour) = get(&api, "/liquidations?last_secs=3600").await;
+        assert_eq!(hour["records"].as_array().unwrap().len(), 2);
+        assert_eq!(hour["records"][0]["symbol"], "SOL-PERP");
+
+        let (_, first) = get(&api, "/liquidations?limit=2").await;
+        let cursor = first["next_cursor"].as_str().unwrap();
+        let (_, second) = get(&api, &format!("/liquidations?limit=2&cursor={cursor}")).await;
+        assert_eq!(second["records"].as_array().unwrap().len(), 1);
+        assert_eq!(second["next_cursor"], Value::Null);
+
+        assert_eq!(get(&api, "/liquidations?cursor=x").await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn unavailable_until_the_first_snapshot() {
+        let api = ApiState { store: Arc::new(Mutex::new(Store::open_in_memory().unwrap())), snapshot: SharedSnapshot::default() };
+        assert_eq!(get(&api, "/markets").await.0, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(get(&api, "/insurance").await, (StatusCode::OK, Value::Array(vec![])));
+    }
+}
@@ -0,0 +1,561 @@
+//! Offline backtests of liquidation parameters: replay a historical price
+//! series against a snapshot of positions from the indexer's store, once per
+//! parameter set, and total up what the program would have done.
+//!
+//! Every price is treated as an accepted oracle price and every unhealthy
+//! position is liquidated at the first price that makes it so, with the
+//! program's own `plan_liquidation`. Non-quote collateral counts as zero,
+//! which only ever makes a position look worse.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use liquidation_math::{
+    cover_bad_debt, plan_liquidation_fraction, tier_maintenance_margin_bps, Bps, FullPlan, LiquidationContext, LiquidationPlan,
+    PositionTerms, Price, QuoteAmount, Tier, LIQUIDATOR_REWARD_BPS, PARTIAL_LIQUIDATION_BPS, PRICE_PRECISION,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::queue::market_tiers;
+use crate::store::{symbol, Store};
+
+/// One row of a price file: `timestamp,market,price`, the market by symbol
+/// and the price in whole quote per unit (`101.25`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PricePoint {
+    pub timestamp: i64,
+    pub market: String,
+    pub price: Price,
+}
+
+#[derive(Deserialize)]
+struct CsvRow {
+    timestamp: i64,
+    market: String,
+    price: String,
+}
+
+/// Read a price file, sorted by timestamp. `.parquet` files need the
+/// `parquet` feature; anything else is read as CSV with a header row.
+pub fn read_prices(path: &Path) -> Result<Vec<PricePoint>> {
+    let mut points = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("parquet") => read_parquet(path)?,
+        _ => read_csv(File::open(path).with_context(|| format!("opening {}", path.display()))?)?,
+    };
+    // stable, so rows with the same timestamp keep file order
+    points.sort_by_key(|point| point.timestamp);
+    Ok(points)
+}
+
+fn read_csv(reader: impl std::io::Read) -> Result<Vec<PricePoint>> {
+    let mut points = Vec::new();
+    for (line, row) in csv::Reader::from_reader(reader).deserialize::<CsvRow>().enumerate() {
+        // line 1 is the header
+        let row = row.with_context(|| format!("price file line {}", line + 2))?;
+        let price = parse_price(&row.price).ok_or_else(|| anyhow!("line {}: bad price {:?}", line + 2, row.price))?;
+        points.push(PricePoint { timestamp: row.timestamp, market: row.market, price });
+    }
+    Ok(points)
+}
+
+/// Columns as in the CSV; the price may be a string, a float or an integer
+/// number of whole units.
+#[cfg(feature = "parquet")]
+fn read_parquet(path: &Path) -> Result<Vec<PricePoint>> {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let reader = SerializedFileReader::new(file)?;
+    let mut points = Vec::new();
+    for (index, row) in reader.get_row_iter(None)?.enumerate() {
+        let (mut timestamp, mut market, mut price) = (None, None, None);
+        for (name, field) in row?.get_column_iter() {
+            match (name.as_str(), field) {
+                ("timestamp", Field::Long(ts)) => timestamp = Some(*ts),
+                ("timestamp", Field::Int(ts)) => timestamp = Some(i64::from(*ts)),
+                ("timestamp", Field::TimestampMillis(ms)) => timestamp = Some(ms / 1_000),
+                ("timestamp", Field::TimestampMicros(us)) => timestamp = Some(us / 1_000_000),
+                ("market", Field::Str(name)) => market = Some(name.clone()),
+                ("price", Field::Str(text)) => price = parse_price(text),
+                ("price", Field::Double(value)) => price = float_price(*value),
+                ("price", Field::Float(value)) => price = float_price(f64::from(*value)),
+                ("price", Field::Long(whole)) => {
+                    price = u64::try_from(*whole).ok().and_then(|w| w.checked_mul(PRICE_PRECISION)).filter(|&p| p > 0).map(Price)
+                }
+                _ => {}
+            }
+        }
+        match (timestamp, market, price) {
+            (Some(timestamp), Some(market), Some(price)) => points.push(PricePoint { timestamp, market, price }),
+            _ => bail!("{} row {index}: needs timestamp, market and a positive price", path.display()),
+        }
+    }
+    Ok(points)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn read_parquet(path: &Path) -> Result<Vec<PricePoint>> {
+    bail!("{}: reading Parquet needs the `parquet` feature", path.display())
+}
+
+#[cfg(feature = "parquet")]
+fn float_price(value: f64) -> Option<Price> {
+    let scaled = (value * PRICE_PRECISION as f64).round();
+    (scaled >= 1.0 && scaled < u64::MAX as f64).then_some(Price(scaled as u64))
+}
+
+/// A decimal price as PRICE_PRECISION fixed point. Digits past the sixth
+/// decimal are truncated; zero is not a price.
+pub fn parse_price(text: &str) -> Option<Price> {
+    let (whole, fraction) = text.trim().split_once('.').unwrap_or((text.trim(), ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(whole) || !digits(fraction) {
+        return None;
+    }
+
+    let whole: u64 = if whole.is_empty() { 0 } else { whole.parse().ok()? };
+    let fraction = &fraction[..fraction.len().min(6)];
+    let fraction: u64 = format!("{fraction:0<6}").parse().ok()?;
+    let price = whole.checked_mul(PRICE_PRECISION)?.checked_add(fraction)?;
+    (price > 0).then_some(Price(price))
+}
+
+/// Parameters to backtest. Anything left out keeps the program's value.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct ParamSet {
+    pub name: String,
+    #[serde(default = "default_reward_bps")]
+    pub reward_bps: u64,
+    /// Share of the size `liquidate_partial` closes.
+    #[serde(default = "default_partial_bps")]
+    pub partial_bps: u64,
+    /// Maintenance margin tiers for every market; each market's own tiers
+    /// if not set.
+    #[serde(default)]
+    pub tiers: Option<Vec<TierParams>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct TierParams {
+    pub max_notional: u64,
+    pub maintenance_margin_bps: u64,
+}
+
+fn default_reward_bps() -> u64 {
+    LIQUIDATOR_REWARD_BPS
+}
+
+fn default_partial_bps() -> u64 {
+    PARTIAL_LIQUIDATION_BPS
+}
+
+impl ParamSet {
+    /// What the program runs today.
+    pub fn current() -> Self {
+        Self {
+            name: "current".into(),
+            reward_bps: LIQUIDATOR_REWARD_BPS,
+            partial_bps: PARTIAL_LIQUIDATION_BPS,
+            tiers: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ParamFile {
+    set: Vec<ParamSet>,
+}
+
+/// Parameter sets from a TOML file of `[[set]]` tables.
+pub fn read_params(path: &Path) -> Result<Vec<ParamSet>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let file: ParamFile = toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+    for set in &file.set {
+        if set.partial_bps == 0 || set.partial_bps > 10_000 {
+            bail!("{}: partial_bps must be in 1..=10000", set.name);
+        }
+    }
+    Ok(file.set)
+}
+
+#[derive(Clone, Debug)]
+struct BookMarket {
+    symbol: String,
+    tiers: Vec<Tier>,
+    /// Last accepted oracle price.
+    price: Price,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BookPosition {
+    market: usize,
+    terms: PositionTerms,
+}
+
+/// Markets, open positions and the insurance balance to start from.
+#[derive(Clone, Debug, Default)]
+pub struct Book {
+    markets: Vec<BookMarket>,
+    positions: Vec<BookPosition>,
+    pub insurance: u64,
+}
+
+impl Book {
+    /// Snapshot of the store. The insurance balance is the fund's booked
+    /// balance unless given.
+    pub fn from_store(store: &Store, insurance: Option<u64>) -> Result<Self> {
+        let markets = store.markets()?;
+        let index: HashMap<_, _> = markets.iter().enumerate().map(|(i, m)| (m.address, i)).collect();
+
+        let mut book = Book::default();
+        for market in &markets {
+            book.add_market(&symbol(&market.account.symbol), Price(market.account.last_accepted_price), market_tiers(&market.account));
+        }
+        for position in store.open_positions()? {
+            if let Some(&market) = index.get(&position.account.market) {
+                book.positions.push(BookPosition { market, terms: position.account.terms() });
+            }
+        }
+        book.insurance = match insurance {
+            Some(balance) => balance,
+            None => store.insurance_funds()?.first().map_or(0, |fund| fund.account.balance),
+        };
+        Ok(book)
+    }
+
+    pub fn add_market(&mut self, symbol: &str, price: Price, tiers: Vec<Tier>) {
+        self.markets.push(BookMarket { symbol: symbol.into(), tiers, price });
+    }
+
+    /// A position on a market added earlier.
+    pub fn add_position(&mut self, symbol: &str, terms: PositionTerms) -> Result<()> {
+        let market = self.markets.iter().position(|m| m.symbol == symbol).ok_or_else(|| anyhow!("no market {symbol}"))?;
+        self.positions.push(BookPosition { market, terms });
+        Ok(())
+    }
+
+    pub fn positions(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Symbol and last accepted price of each market, in market order.
+    pub fn markets(&self) -> impl Iterator<Item = (&str, Price)> {
+        self.markets.iter().map(|m| (m.symbol.as_str(), m.price))
+    }
+}
+
+/// What the liquidation rules did to one position at one price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Partial { liquidator_reward: u64 },
+    Full { liquidator_reward: u64 },
+    /// A full liquidation `bad_debt` short. How much of it, and of the
+    /// reward, gets paid depends on the insurance fund; see
+    /// `cover_bad_debt`.
+    BadDebt { bad_debt: u64, max_reward: u64 },
+    /// The math overflowed; the position is dropped.
+    Skipped,
+}
+
+/// A book's open positions, run through the liquidation rules one price at
+/// a time.
+#[derive(Clone, Debug)]
+pub struct Replay {
+    markets: HashMap<String, usize>,
+    tiers: Vec<Vec<Tier>>,
+    /// Open positions by market.
+    positions: Vec<Vec<PositionTerms>>,
+    reward: Bps,
+    fraction: Bps,
+}
+
+impl Replay {
+    pub fn new(book: &Book, params: &ParamSet) -> Self {
+        let mut positions = vec![Vec::new(); book.markets.len()];
+        for position in &book.positions {
+            positions[position.market].push(position.terms);
+        }
+        let tiers = book
+            .markets
+            .iter()
+            .map(|market| match &params.tiers {
+                Some(set) => set
+                    .iter()
+                    .map(|t| Tier { max_notional: t.max_notional, maintenance_margin_bps: t.maintenance_margin_bps })
+                    .collect(),
+                None => market.tiers.clone(),
+            })
+            .collect();
+
+        Self {
+            markets: book.markets.iter().enumerate().map(|(i, m)| (m.symbol.clone(), i)).collect(),
+            tiers,
+            positions,
+            reward: Bps(params.reward_bps),
+            fraction: Bps(params.partial_bps),
+        }
+    }
+
+    pub fn market(&self, symbol: &str) -> Option<usize> {
+        self.markets.get(symbol).copied()
+    }
+
+    /// Liquidate whatever `price` makes unhealthy on `market` (an index in
+    /// book order), reporting each liquidation to `outcome`.
+    pub fn step(&mut self, market: usize, price: Price, mut outcome: impl FnMut(Outcome)) {
+        let tiers = &self.tiers[market];
+        // an unlimited fund, so a bad debt plan carries the whole deficit and
+        // the full reward; the caller's fund decides what is actually paid
+        let context = LiquidationContext {
+            haircut: QuoteAmount::ZERO,
+            has_deposits: false,
+            insurance_balance: u64::MAX,
+            reward_bps: self.reward,
+        };
+
+        self.positions[market].retain_mut(|terms| {
+            let plan = plan_liquidation_fraction(terms, price, &context, self.fraction, |notional| {
+                tier_maintenance_margin_bps(tiers, notional)
+            });
+            match plan {
+                None => {
+                    outcome(Outcome::Skipped);
+                    false
+                }
+                Some((_, LiquidationPlan::Healthy)) => true,
+                Some((_, LiquidationPlan::Partial(fill))) => {
+                    outcome(Outcome::Partial { liquidator_reward: fill.liquidator_reward });
+                    terms.size = fill.remaining_size;
+                    terms.entry_price = fill.entry_price;
+                    terms.collateral = QuoteAmount::from_i64(fill.new_collateral);
+                    true
+                }
+                Some((_, LiquidationPlan::Full(full))) => {
+                    outcome(match full {
+                        // no deposits here, so never deferred
+                        FullPlan::Deferred { .. } => Outcome::Full { liquidator_reward: 0 },
+                        FullPlan::Leftover { liquidator_reward, .. } => Outcome::Full { liquidator_reward },
+                        FullPlan::BadDebt { bad_debt, liquidator_reward, .. } => {
+                            Outcome::BadDebt { bad_debt, max_reward: liquidator_reward }
+                        }
+                    });
+                    false
+                }
+            }
+        });
+    }
+}
+
+/// What one parameter set did over the whole series. Amounts in quote
+/// base units.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub name: String,
+    pub partial_liquidations: u64,
+    pub full_liquidations: u64,
+    /// Full liquidations that ended in bad debt.
+    pub bad_debt_liquidations: u64,
+    pub bad_debt: u64,
+    pub insurance_covered: u64,
+    /// Bad debt the insurance fund could not cover.
+    pub uncovered_bad_debt: u64,
+    pub insurance_start: u64,
+    pub insurance_end: u64,
+    /// insurance_end below insurance_start, in bps of the start.
+    pub insurance_drawdown_bps: u64,
+    pub liquidator_revenue: u64,
+    /// Positions the math could not evaluate (overflow).
+    pub skipped: u64,
+}
+
+/// Replay `prices` against `book` with `params`. Rows for markets the book
+/// doesn't have are ignored.
+pub fn run(book: &Book, prices: &[PricePoint], params: &ParamSet) -> Report {
+    let mut report = Report {
+        name: params.name.clone(),
+        insurance_start: book.insurance,
+        ..Report::default()
+    };
+    let mut insurance = book.insurance;
+    let mut replay = Replay::new(book, params);
+
+    for point in prices {
+        let Some(market) = replay.market(&point.market) else { continue };
+        replay.step(market, point.price, |outcome| match outcome {
+            Outcome::Partial { liquidator_reward } => {
+                report.partial_liquidations += 1;
+                report.liquidator_revenue += liquidator_reward;
+            }
+            Outcome::Full { liquidator_reward } => {
+                report.full_liquidations += 1;
+                report.liquidator_revenue += liquidator_reward;
+            }
+            Outcome::BadDebt { bad_debt, max_reward } => {
+                let (covered, reward) = cover_bad_debt(bad_debt, max_reward, insurance);
+                insurance -= covered + reward;
+                report.full_liquidations += 1;
+                report.bad_debt_liquidations += 1;
+                report.bad_debt += bad_debt;
+                report.insurance_covered += covered;
+                report.uncovered_bad_debt += bad_debt - covered;
+                report.liquidator_revenue += reward;
+            }
+            Outcome::Skipped => report.skipped += 1,
+        });
+    }
+
+    report.insurance_end = insurance;
+    if book.insurance > 0 {
+        let drawn = (book.insurance - insurance) as u128 * 10_000 / book.insurance as u128;
+        report.insurance_drawdown_bps = drawn as u64;
+    }
+    report
+}
+
+/// Reports as an aligned table, one row per parameter set.
+pub fn table(reports: &[Report]) -> String {
+    let header = [
+        "set", "partial", "full", "bad debt #", "bad debt", "covered", "uncovered", "insurance end", "drawdown bps",
+        "liq. revenue", "skipped",
+    ];
+    let rows = reports
+        .iter()
+        .map(|r| {
+            vec![
+                r.name.clone(),
+                r.partial_liquidations.to_string(),
+                r.full_liquidations.to_string(),
+                r.bad_debt_liquidations.to_string(),
+                r.bad_debt.to_string(),
+                r.insurance_covered.to_string(),
+                r.uncovered_bad_debt.to_string(),
+                r.insurance_end.to_string(),
+                r.insurance_drawdown_bps.to_string(),
+                r.liquidator_revenue.to_string(),
+                r.skipped.to_string(),
+            ]
+        })
+        .collect();
+    render_table(&header, rows)
+}
+
+/// Right-aligned columns under `header`.
+pub(crate) fn render_table(header: &[&str], rows: Vec<Vec<String>>) -> String {
+    let widths: Vec<usize> = (0..header.len())
+        .map(|col| rows.iter().map(|row| row[col].len()).chain([header[col].len()]).max().unwrap_or(0))
+        .collect();
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<String> = cells.iter().zip(&widths).map(|(cell, width)| format!("{cell:>width$}")).collect();
+        cells.join("  ")
+    };
+
+    let mut out = line(header.to_vec());
+    for row in &rows {
+        out.push('\n');
+        out.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use liquidation_math::Quantity;
+
+    const USD: u64 = PRICE_PRECISION;
+
+    /// 10 long at 100 on 60 of collateral: maintenance at about 96.41.
+    fn book(insurance: u64) -> Book {
+        let mut book = Book { insurance, ..Book::default() };
+        book.add_market("SOL-PERP", Price(100 * USD), Vec::new());
+        book.add_position(
+            "SOL-PERP",
+            PositionTerms {
+                entry_price: Price(100 * USD),
+                size: Quantity(10 * USD),
+                collateral: QuoteAmount::from_u64(60 * USD),
+                is_long: true,
+            },
+        )
+        .unwrap();
+        book
+    }
+
+    fn series(prices: &[&str]) -> Vec<PricePoint> {
+        let mut csv = String::from("timestamp,market,price\n");
+        for (ts, price) in prices.iter().enumerate() {
+            csv.push_str(&format!("{ts},SOL-PERP,{price}\n"));
+        }
+        csv.push_str("99,ETH-PERP,1\n");
+        read_csv(csv.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parses_decimal_prices() {
+        assert_eq!(parse_price("101.25"), Some(Price(101_250_000)));
+        assert_eq!(parse_price("7"), Some(Price(7 * USD)));
+        assert_eq!(parse_price(".5"), Some(Price(500_000)));
+        assert_eq!(parse_price("0.12345678"), Some(Price(123_456)));
+        assert_eq!(parse_price("0"), None);
+        assert_eq!(parse_price("-1"), None);
+        assert_eq!(parse_price("1e3"), None);
+    }
+
+    #[test]
+    fn partial_restores_health() {
+        // at 96 the margin ratio is 208 bps; closing 5 pays 2.5% of the 10
+        // of margin it carried and leaves 19.75 behind the rest, which
+        // stays above maintenance at 95
+        let report = run(&book(1_000 * USD), &series(&["100", "96", "95"]), &ParamSet::current());
+        assert_eq!(report.partial_liquidations, 1);
+        assert_eq!(report.full_liquidations, 0);
+        assert_eq!(report.liquidator_revenue, 250_000);
+        assert_eq!(report.insurance_end, report.insurance_start);
+    }
+
+    #[test]
+    fn gaps_leave_bad_debt() {
+        // straight to 40: half can't save it, the full close is 540 short
+        let prices = series(&["100", "40"]);
+        let report = run(&book(1_000 * USD), &prices, &ParamSet::current());
+        assert_eq!(report.full_liquidations, 1);
+        assert_eq!(report.bad_debt, 540 * USD);
+        assert_eq!(report.insurance_covered, 540 * USD);
+        assert_eq!(report.liquidator_revenue, 13_500_000);
+        assert_eq!(report.insurance_end, 446_500_000);
+        assert_eq!(report.insurance_drawdown_bps, 5_535);
+
+        // a fund too small to cover it, and nothing left for the reward
+        let report = run(&book(500 * USD), &prices, &ParamSet::current());
+        assert_eq!(report.uncovered_bad_debt, 40 * USD);
+        assert_eq!(report.liquidator_revenue, 0);
+        assert_eq!(report.insurance_end, 0);
+    }
+
+    #[test]
+    fn parameters_change_the_outcome() {
+        let prices = series(&["100", "40"]);
+        let strict = ParamSet {
+            name: "strict".into(),
+            tiers: Some(vec![TierParams { max_notional: u64::MAX, maintenance_margin_bps: 700 }]),
+            ..ParamSet::current()
+        };
+        // 7% maintenance liquidates half at 100 already, leaving 59.25
+        // behind the rest, so the drop costs 240.75 instead of 540
+        let report = run(&book(1_000 * USD), &prices, &strict);
+        assert_eq!(report.partial_liquidations, 1);
+        assert_eq!(report.full_liquidations, 1);
+        assert_eq!(report.bad_debt, 240_750_000);
+        assert_eq!(report.liquidator_revenue, 750_000 + 6_018_750);
+
+        let text = table(&[run(&book(1_000 * USD), &prices, &ParamSet::current()), report]);
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.lines().nth(2).unwrap().trim_start().starts_with("strict"));
+    }
+}
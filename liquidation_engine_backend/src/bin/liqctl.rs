@@ -0,0 +1,492 @@
+//! Admin and operator CLI for the liquidation program.
+
+use std::path::PathBuf;
+
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use liquidation_client::instructions::{self, MarketConfig, NewPosition};
+use liquidation_client::pda;
+use liquidation_program::constants::*;
+use liquidation_program::risk_tiers::RiskTier;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+
+use liquidation_engine_backend::backtest::parse_price;
+use liquidation_engine_backend::config::CliConfig;
+use liquidation_engine_backend::inspect;
+use liquidation_engine_backend::keeper::{self, Liquidation};
+use liquidation_engine_backend::scan::{decode, ProtocolState};
+use liquidation_engine_backend::store::IndexedKind;
+
+#[derive(Parser)]
+#[command(about = "Admin and operator commands for the liquidation program")]
+struct Cli {
+    /// Config file [default: ~/.config/liqctl/config.toml if present]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// RPC URL or cluster name, overriding the config.
+    #[arg(long, short = 'u', global = true)]
+    url: Option<String>,
+
+    /// Signing keypair, overriding the config.
+    #[arg(long, short = 'k', global = true)]
+    keypair: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the program's singleton accounts and markets.
+    #[command(subcommand)]
+    Init(Init),
+    /// Change a market's guard settings, limits and risk tiers.
+    #[command(subcommand)]
+    Market(MarketCommand),
+    /// Add collateral assets and change their weights.
+    #[command(subcommand)]
+    Collateral(CollateralCommand),
+    /// Open and close positions.
+    #[command(subcommand)]
+    Position(PositionCommand),
+    /// Print a program account.
+    #[command(subcommand)]
+    Inspect(Inspect),
+    /// Liquidate one position now.
+    Liquidate(LiquidateArgs),
+    /// Print a program-derived address and its bump.
+    #[command(subcommand)]
+    Pda(Pda),
+}
+
+#[derive(Subcommand)]
+enum Init {
+    /// The insurance fund and its vault.
+    InsuranceFund {
+        /// Quote mint.
+        #[arg(long)]
+        mint: Pubkey,
+        /// Fund authority [default: the keypair]
+        #[arg(long)]
+        authority: Option<Pubkey>,
+        /// Keypair for the new fund account [default: a fresh one]
+        #[arg(long)]
+        fund_keypair: Option<PathBuf>,
+    },
+    /// The quote vault backing every position.
+    ProtocolVault {
+        #[arg(long)]
+        mint: Pubkey,
+    },
+    /// A market at `--index`.
+    Market(InitMarketArgs),
+    /// The collateral registry.
+    CollateralRegistry,
+}
+
+#[derive(Args)]
+struct InitMarketArgs {
+    #[arg(long)]
+    index: u16,
+    /// Up to 16 bytes, e.g. SOL-PERP.
+    #[arg(long, value_parser = parse_symbol)]
+    symbol: [u8; 16],
+    #[arg(long)]
+    oracle: Pubkey,
+    #[arg(long, default_value_t = 500)]
+    max_price_deviation_bps: u64,
+    #[arg(long, default_value_t = 10)]
+    deviation_persist_slots: u64,
+    /// Per side, in quote base units; 0 for no limit.
+    #[arg(long, default_value_t = 0)]
+    max_open_interest: u64,
+    /// In quote base units; 0 for no limit.
+    #[arg(long, default_value_t = 0)]
+    max_position_notional: u64,
+}
+
+#[derive(Subcommand)]
+enum MarketCommand {
+    UpdateConfig {
+        market: Pubkey,
+        #[arg(long)]
+        max_price_deviation_bps: u64,
+        #[arg(long)]
+        deviation_persist_slots: u64,
+    },
+    SetLimits {
+        market: Pubkey,
+        #[arg(long)]
+        max_open_interest: u64,
+        #[arg(long)]
+        max_position_notional: u64,
+    },
+    SetRiskTiers {
+        market: Pubkey,
+        /// `MAX_NOTIONAL:MAINTENANCE_MARGIN_BPS`, smallest first; may be
+        /// repeated.
+        #[arg(long = "tier", value_parser = parse_tier)]
+        tiers: Vec<RiskTier>,
+    },
+    /// Feed the market's oracle price through its deviation guard.
+    RefreshPrice { market: Pubkey },
+}
+
+#[derive(Subcommand)]
+enum CollateralCommand {
+    AddAsset {
+        #[arg(long)]
+        mint: Pubkey,
+        #[arg(long)]
+        oracle: Pubkey,
+        #[arg(long)]
+        weight_bps: u64,
+    },
+    SetWeight {
+        asset_index: u8,
+        #[arg(long)]
+        weight_bps: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum PositionCommand {
+    /// Open a position owned by the keypair.
+    Create {
+        #[arg(long)]
+        market: Pubkey,
+        #[arg(long, value_enum)]
+        side: Side,
+        /// Base size, e.g. 1.5.
+        #[arg(long, value_parser = parse_fixed)]
+        size: u64,
+        /// Entry price, e.g. 101.25.
+        #[arg(long, value_parser = parse_fixed)]
+        entry_price: u64,
+        /// Quote base units.
+        #[arg(long)]
+        collateral: i64,
+        #[arg(long, default_value_t = 1)]
+        leverage: u16,
+    },
+    Close { position: Pubkey },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Side {
+    Long,
+    Short,
+}
+
+#[derive(Subcommand)]
+enum Inspect {
+    Position(InspectArgs),
+    Fund(InspectArgs),
+    Record(InspectArgs),
+    Market(InspectArgs),
+    /// Every liquidation record of a position.
+    Records(InspectArgs),
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    address: Pubkey,
+    /// Print JSON instead of aligned text.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct LiquidateArgs {
+    position: Pubkey,
+    /// Instruction to send; `auto` sends what the keeper would and refuses
+    /// healthy positions.
+    #[arg(long, value_enum, default_value_t = Kind::Auto)]
+    kind: Kind,
+    /// Quote token account that receives the reward [default: the keypair's ATA]
+    #[arg(long)]
+    liquidator_token_account: Option<Pubkey>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Kind {
+    Auto,
+    Partial,
+    Full,
+}
+
+#[derive(Subcommand)]
+enum Pda {
+    /// VAULT_SEED
+    ProtocolVault,
+    /// VAULT_AUTH_SEED, VAULT_SEED
+    VaultAuthority,
+    /// INSURANCE_SEED
+    InsuranceVault,
+    /// INSURANCE_AUTH_SEED, INSURANCE_SEED
+    InsuranceAuthority,
+    /// COLLATERAL_REGISTRY_SEED
+    CollateralRegistry,
+    /// COLLATERAL_VAULT_SEED, mint
+    CollateralVault { mint: Pubkey },
+    /// MARKET_SEED, index
+    Market { index: u16 },
+    /// LIQ_RECORD_SEED, position, count
+    LiquidationRecord { position: Pubkey, count: u32 },
+    /// AUCTION_SEED, position, asset index
+    Auction { position: Pubkey, asset_index: u8 },
+}
+
+struct Ctl {
+    client: RpcClient,
+    config: CliConfig,
+    keypair: Option<PathBuf>,
+}
+
+impl Ctl {
+    fn signer(&self) -> Result<Keypair> {
+        let path = self.config.keypair_path(self.keypair.as_deref());
+        read_keypair_file(&path).map_err(|err| anyhow!("reading {}: {err}", path.display()))
+    }
+
+    async fn account_data(&self, address: &Pubkey) -> Result<Vec<u8>> {
+        let account = self.client.get_account(address).await.with_context(|| format!("getAccountInfo {address}"))?;
+        Ok(account.data)
+    }
+
+    /// Sign with the keypair and `extra`, send and wait for confirmation.
+    async fn send(&self, payer: &Keypair, instruction: Instruction, extra: &[&Keypair]) -> Result<()> {
+        let mut signers = vec![payer];
+        signers.extend_from_slice(extra);
+        let blockhash = self.client.get_latest_blockhash().await.context("getLatestBlockhash")?;
+        let transaction = Transaction::new_signed_with_payer(&[instruction], Some(&payer.pubkey()), &signers, blockhash);
+        let signature = self.client.send_and_confirm_transaction(&transaction).await?;
+        println!("{signature}");
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let config = CliConfig::load(cli.config.as_deref())?;
+    let client = RpcClient::new_with_commitment(config.rpc_url(cli.url.as_deref()), config.commitment()?);
+    let ctl = Ctl { client, config, keypair: cli.keypair };
+
+    match cli.command {
+        Command::Init(init) => run_init(&ctl, init).await,
+        Command::Market(command) => run_market(&ctl, command).await,
+        Command::Collateral(command) => run_collateral(&ctl, command).await,
+        Command::Position(command) => run_position(&ctl, command).await,
+        Command::Inspect(inspect) => run_inspect(&ctl, inspect).await,
+        Command::Liquidate(args) => run_liquidate(&ctl, args).await,
+        Command::Pda(which) => {
+            let (address, bump) = derive(&which);
+            println!("{address} {bump}");
+            Ok(())
+        }
+    }
+}
+
+async fn run_init(ctl: &Ctl, init: Init) -> Result<()> {
+    let payer = ctl.signer()?;
+    match init {
+        Init::InsuranceFund { mint, authority, fund_keypair } => {
+            let fund = match fund_keypair {
+                Some(path) => read_keypair_file(&path).map_err(|err| anyhow!("reading {}: {err}", path.display()))?,
+                None => Keypair::new(),
+            };
+            let token_program = token_program(ctl, &mint).await?;
+            let authority = authority.unwrap_or(payer.pubkey());
+            let ix = instructions::initialize_insurance_fund(payer.pubkey(), fund.pubkey(), mint, token_program, authority);
+            ctl.send(&payer, ix, &[&fund]).await?;
+            println!("insurance fund {}", fund.pubkey());
+            Ok(())
+        }
+        Init::ProtocolVault { mint } => {
+            let token_program = token_program(ctl, &mint).await?;
+            ctl.send(&payer, instructions::initialize_protocol_vault(payer.pubkey(), mint, token_program), &[]).await
+        }
+        Init::Market(args) => {
+            let config = MarketConfig {
+                max_price_deviation_bps: args.max_price_deviation_bps,
+                deviation_persist_slots: args.deviation_persist_slots,
+                max_open_interest: args.max_open_interest,
+                max_position_notional: args.max_position_notional,
+            };
+            let ix = instructions::initialize_market(payer.pubkey(), args.oracle, args.index, args.symbol, config);
+            ctl.send(&payer, ix, &[]).await?;
+            println!("market {}", pda::market(args.index));
+            Ok(())
+        }
+        Init::CollateralRegistry => ctl.send(&payer, instructions::initialize_collateral_registry(payer.pubkey()), &[]).await,
+    }
+}
+
+async fn run_market(ctl: &Ctl, command: MarketCommand) -> Result<()> {
+    let payer = ctl.signer()?;
+    let authority = payer.pubkey();
+    let ix = match command {
+        MarketCommand::UpdateConfig { market, max_price_deviation_bps, deviation_persist_slots } => {
+            instructions::update_market_config(authority, market, max_price_deviation_bps, deviation_persist_slots)
+        }
+        MarketCommand::SetLimits { market, max_open_interest, max_position_notional } => {
+            instructions::set_market_limits(authority, market, max_open_interest, max_position_notional)
+        }
+        MarketCommand::SetRiskTiers { market, tiers } => instructions::set_risk_tiers(authority, market, tiers),
+        MarketCommand::RefreshPrice { market } => {
+            let decoded: liquidation_program::Market = decode(&ctl.account_data(&market).await?)?;
+            instructions::refresh_market_price(market, decoded.oracle)
+        }
+    };
+    ctl.send(&payer, ix, &[]).await
+}
+
+async fn run_collateral(ctl: &Ctl, command: CollateralCommand) -> Result<()> {
+    let payer = ctl.signer()?;
+    let ix = match command {
+        CollateralCommand::AddAsset { mint, oracle, weight_bps } => {
+            let token_program = token_program(ctl, &mint).await?;
+            instructions::add_collateral_asset(payer.pubkey(), mint, oracle, token_program, weight_bps)
+        }
+        CollateralCommand::SetWeight { asset_index, weight_bps } => {
+            instructions::set_collateral_weight(payer.pubkey(), asset_index, weight_bps)
+        }
+    };
+    ctl.send(&payer, ix, &[]).await
+}
+
+async fn run_position(ctl: &Ctl, command: PositionCommand) -> Result<()> {
+    let owner = ctl.signer()?;
+    match command {
+        PositionCommand::Create { market, side, size, entry_price, collateral, leverage } => {
+            let position = Keypair::new();
+            let new = NewPosition { entry_price, size, collateral, is_long: matches!(side, Side::Long), leverage };
+            let ix = instructions::create_position(owner.pubkey(), position.pubkey(), market, new);
+            ctl.send(&owner, ix, &[&position]).await?;
+            println!("position {}", position.pubkey());
+            Ok(())
+        }
+        PositionCommand::Close { position } => {
+            let decoded: liquidation_program::Position = decode(&ctl.account_data(&position).await?)?;
+            if decoded.owner != owner.pubkey() {
+                bail!("position {position} is owned by {}", decoded.owner);
+            }
+            let market: liquidation_program::Market = decode(&ctl.account_data(&decoded.market).await?)?;
+            let registry: liquidation_program::CollateralRegistry =
+                decode(&ctl.account_data(&pda::collateral_registry()).await?)?;
+            let ix = instructions::close_position(position, &decoded, &market, &registry);
+            ctl.send(&owner, ix, &[]).await
+        }
+    }
+}
+
+async fn run_inspect(ctl: &Ctl, inspect: Inspect) -> Result<()> {
+    let (args, expected) = match &inspect {
+        Inspect::Position(args) | Inspect::Records(args) => (args, IndexedKind::Position),
+        Inspect::Fund(args) => (args, IndexedKind::InsuranceFund),
+        Inspect::Record(args) => (args, IndexedKind::LiquidationRecord),
+        Inspect::Market(args) => (args, IndexedKind::Market),
+    };
+    let data = ctl.account_data(&args.address).await?;
+    let mut values = vec![inspect::describe(&args.address, &data, Some(expected))?];
+
+    if let Inspect::Records(_) = inspect {
+        let position: liquidation_program::Position = decode(&data)?;
+        let addresses: Vec<Pubkey> =
+            (0..position.liquidation_count).map(|count| pda::liquidation_record(&args.address, count)).collect();
+        values.clear();
+        for chunk in addresses.chunks(100) {
+            let accounts = ctl.client.get_multiple_accounts(chunk).await.context("getMultipleAccounts")?;
+            for (address, account) in chunk.iter().zip(accounts) {
+                // skip records that have since been closed
+                if let Some(account) = account {
+                    values.push(inspect::describe(address, &account.data, Some(IndexedKind::LiquidationRecord))?);
+                }
+            }
+        }
+    }
+
+    if args.json {
+        let json = if let [value] = values.as_slice() { value.clone() } else { values.into() };
+        println!("{}", serde_json::to_string_pretty(&json)?);
+    } else {
+        let blocks: Vec<String> = values.iter().map(inspect::pretty).collect();
+        println!("{}", blocks.join("\n\n"));
+    }
+    Ok(())
+}
+
+async fn run_liquidate(ctl: &Ctl, args: LiquidateArgs) -> Result<()> {
+    let liquidator = ctl.signer()?;
+    let state = ProtocolState::load(&ctl.client).await?;
+    let (key, position) = state
+        .positions
+        .iter()
+        .find(|(key, _)| *key == args.position)
+        .ok_or_else(|| anyhow!("no open position {}", args.position))?;
+
+    let kind = match args.kind {
+        Kind::Partial => Liquidation::Partial,
+        Kind::Full => Liquidation::Full,
+        Kind::Auto => {
+            let prices = keeper::load_prices(&ctl.client, &state.oracles(), keeper::unix_now()).await?;
+            let slot = ctl.client.get_slot().await.context("getSlot")?;
+            let candidate = keeper::evaluate(&state, &prices, slot, *key, position)
+                .ok_or_else(|| anyhow!("{key} is healthy at current prices"))?;
+            eprintln!("{key}: {:?}", candidate.plan);
+            candidate.kind()
+        }
+    };
+
+    let token_account = args.liquidator_token_account.unwrap_or_else(|| {
+        get_associated_token_address_with_program_id(&liquidator.pubkey(), &state.quote_mint, &state.token_program)
+    });
+    let accounts = keeper::liquidation_accounts(&state, *key, position, liquidator.pubkey(), token_account)
+        .ok_or_else(|| anyhow!("market {} is not loaded", position.market))?;
+    ctl.send(&liquidator, accounts.instruction(kind), &[]).await
+}
+
+/// The token program that owns `mint`, classic or Token-2022.
+async fn token_program(ctl: &Ctl, mint: &Pubkey) -> Result<Pubkey> {
+    let account = ctl.client.get_account(mint).await.with_context(|| format!("getAccountInfo {mint}"))?;
+    Ok(account.owner)
+}
+
+fn derive(which: &Pda) -> (Pubkey, u8) {
+    match which {
+        Pda::ProtocolVault => pda::find(&[VAULT_SEED]),
+        Pda::VaultAuthority => pda::find(&[VAULT_AUTH_SEED, VAULT_SEED]),
+        Pda::InsuranceVault => pda::find(&[INSURANCE_SEED]),
+        Pda::InsuranceAuthority => pda::find(&[INSURANCE_AUTH_SEED, INSURANCE_SEED]),
+        Pda::CollateralRegistry => pda::find(&[COLLATERAL_REGISTRY_SEED]),
+        Pda::CollateralVault { mint } => pda::find(&[COLLATERAL_VAULT_SEED, mint.as_ref()]),
+        Pda::Market { index } => pda::find(&[MARKET_SEED, &index.to_le_bytes()]),
+        Pda::LiquidationRecord { position, count } => pda::find(&[LIQ_RECORD_SEED, position.as_ref(), &count.to_le_bytes()]),
+        Pda::Auction { position, asset_index } => pda::find(&[AUCTION_SEED, position.as_ref(), &[*asset_index]]),
+    }
+}
+
+fn parse_symbol(text: &str) -> Result<[u8; 16]> {
+    if text.is_empty() || text.len() > 16 {
+        bail!("symbol must be 1 to 16 bytes");
+    }
+    let mut symbol = [0; 16];
+    symbol[..text.len()].copy_from_slice(text.as_bytes());
+    Ok(symbol)
+}
+
+fn parse_fixed(text: &str) -> Result<u64> {
+    parse_price(text).map(|price| price.0).ok_or_else(|| anyhow!("expected a decimal with at most 6 places"))
+}
+
+fn parse_tier(text: &str) -> Result<RiskTier> {
+    let (max_notional, bps) = text.split_once(':').ok_or_else(|| anyhow!("expected MAX_NOTIONAL:MARGIN_BPS"))?;
+    Ok(RiskTier { max_notional: max_notional.parse()?, maintenance_margin_bps: bps.parse()? })
+}
@@ -0,0 +1,104 @@
+//! `liqctl` settings: cluster, keypair and commitment, from a TOML file
+//! (`~/.config/liqctl/config.toml` unless given) and overridden by flags.
+//!
+//! ```toml
+//! url = "devnet"                      # or a full RPC URL
+//! keypair = "~/.config/solana/id.json"
+//! commitment = "confirmed"
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use solana_sdk::commitment_config::CommitmentConfig;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CliConfig {
+    /// RPC URL or a cluster name: localnet, devnet, testnet, mainnet-beta.
+    pub url: Option<String>,
+    pub keypair: Option<String>,
+    pub commitment: Option<String>,
+}
+
+impl CliConfig {
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("HOME").map(|home| Path::new(&home).join(".config/liqctl/config.toml"))
+    }
+
+    /// Read `path`, or the default path if it exists; defaults otherwise.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path().filter(|path| path.exists()) {
+                Some(path) => path,
+                None => return Ok(Self::default()),
+            },
+        };
+        let text = std::fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// `flag` if given, then the file, then a local validator.
+    pub fn rpc_url(&self, flag: Option<&str>) -> String {
+        cluster_url(flag.or(self.url.as_deref()).unwrap_or("localnet"))
+    }
+
+    /// `flag` if given, then the file, then the Solana CLI's default keypair.
+    pub fn keypair_path(&self, flag: Option<&Path>) -> PathBuf {
+        match (flag, &self.keypair) {
+            (Some(path), _) => path.to_path_buf(),
+            (None, Some(path)) => expand_home(path),
+            (None, None) => expand_home("~/.config/solana/id.json"),
+        }
+    }
+
+    /// Confirmed unless set.
+    pub fn commitment(&self) -> Result<CommitmentConfig> {
+        match &self.commitment {
+            Some(level) => CommitmentConfig::from_str(level).map_err(|_| anyhow!("unknown commitment {level:?}")),
+            None => Ok(CommitmentConfig::confirmed()),
+        }
+    }
+}
+
+/// RPC URL for a cluster name; anything else is taken as a URL already.
+pub fn cluster_url(url_or_moniker: &str) -> String {
+    match url_or_moniker {
+        "localnet" | "l" => "http://127.0.0.1:8899",
+        "devnet" | "d" => "https://api.devnet.solana.com",
+        "testnet" | "t" => "https://api.testnet.solana.com",
+        "mainnet-beta" | "m" => "https://api.mainnet-beta.solana.com",
+        url => url,
+    }
+    .to_string()
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_override_the_file() {
+        let config: CliConfig = toml::from_str("url = \"devnet\"\nkeypair = \"/keys/admin.json\"").unwrap();
+        assert_eq!(config.rpc_url(None), "https://api.devnet.solana.com");
+        assert_eq!(config.rpc_url(Some("http://rpc.example:8899")), "http://rpc.example:8899");
+        assert_eq!(config.keypair_path(None), PathBuf::from("/keys/admin.json"));
+        assert_eq!(config.keypair_path(Some(Path::new("other.json"))), PathBuf::from("other.json"));
+        assert_eq!(config.commitment().unwrap(), CommitmentConfig::confirmed());
+
+        assert_eq!(CliConfig::default().rpc_url(None), "http://127.0.0.1:8899");
+        assert!(toml::from_str::<CliConfig>("rpc = \"devnet\"").is_err());
+        let finalized = CliConfig { commitment: Some("finalized".into()), ..CliConfig::default() };
+        assert_eq!(finalized.commitment().unwrap(), CommitmentConfig::finalized());
+    }
+}
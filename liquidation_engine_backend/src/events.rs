@@ -0,0 +1,401 @@
+//! Decoding the program's `emit!` events from transaction logs.
+//!
+//! Anchor logs each event as `Program data: <base64>`, the base64 of the
+//! event's 8-byte discriminator followed by its Borsh encoding. Logs come
+//! either live from a `logsSubscribe` on the program, or replayed from a
+//! file of `LoggedTransaction`s, one JSON object per line, which the live
+//! source can write as it goes. Decoded events are handed to every sink in
+//! an `EventPipeline`.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::StreamExt;
+use liquidation_program::state::{LiquidationEvent, ProtocolInsolvencyEvent, RewardSource};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter};
+use solana_sdk::commitment_config::CommitmentConfig;
+use tracing::{debug, warn};
+
+const PROGRAM_DATA: &str = "Program data: ";
+
+/// Delay before resubscribing after the log subscription fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// An event the backend consumes.
+pub enum Event {
+    Liquidation(LiquidationEvent),
+    ProtocolInsolvency(ProtocolInsolvencyEvent),
+}
+
+impl Event {
+    /// Decode one `Program data:` payload. `None` for other events and for
+    /// data that isn't an event at all.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (discriminator, mut body) = (data.get(..8)?, data.get(8..)?);
+        if discriminator == LiquidationEvent::discriminator() {
+            LiquidationEvent::deserialize(&mut body).ok().map(Self::Liquidation)
+        } else if discriminator == ProtocolInsolvencyEvent::discriminator() {
+            ProtocolInsolvencyEvent::deserialize(&mut body).ok().map(Self::ProtocolInsolvency)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Liquidation(_) => "LiquidationEvent",
+            Self::ProtocolInsolvency(_) => "ProtocolInsolvencyEvent",
+        }
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        match self {
+            Self::Liquidation(event) => event.timestamp,
+            Self::ProtocolInsolvency(event) => event.timestamp,
+        }
+    }
+
+    /// The event's fields as JSON, with keys in base58.
+    pub fn fields(&self) -> Value {
+        match self {
+            Self::Liquidation(e) => json!({
+                "position_owner": e.position_owner.to_string(),
+                "liquidator": e.liquidator.to_string(),
+                "symbol_id": e.symbol_id,
+                "liquidated_size": e.liquidated_size,
+                "liquidation_price": e.liquidation_price,
+                "margin_before": e.margin_before,
+                "margin_after": e.margin_after,
+                "liquidator_reward": e.liquidator_reward,
+                "trader_payout": e.trader_payout,
+                "insurance_covered": e.insurance_covered,
+                "bad_debt": e.bad_debt,
+                "timestamp": e.timestamp,
+                "reward_source": match e.reward_source {
+                    RewardSource::ProtocolVault => "protocol_vault",
+                    RewardSource::InsuranceVault => "insurance_vault",
+                },
+            }),
+            Self::ProtocolInsolvency(e) => json!({
+                "amount": e.amount,
+                "timestamp": e.timestamp,
+            }),
+        }
+    }
+}
+
+/// An event with the transaction it came from.
+pub struct DecodedEvent {
+    pub signature: String,
+    pub slot: u64,
+    /// Position among the transaction's decoded events.
+    pub index: u32,
+    pub event: Event,
+}
+
+impl DecodedEvent {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "signature": self.signature,
+            "slot": self.slot,
+            "index": self.index,
+            "event": self.event.name(),
+            "data": self.event.fields(),
+        })
+    }
+}
+
+/// A successful transaction's logs, as stored for replay.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoggedTransaction {
+    pub signature: String,
+    pub slot: u64,
+    pub logs: Vec<String>,
+}
+
+impl LoggedTransaction {
+    /// Events the program itself emitted. `Program data:` lines are
+    /// attributed to whichever program is executing, so data logged by a
+    /// program the liquidation program calls, or one calling it, is skipped.
+    pub fn events(&self) -> Vec<DecodedEvent> {
+        let program = liquidation_program::ID.to_string();
+        let mut stack: Vec<&str> = Vec::new();
+        let mut events = Vec::new();
+
+        for line in &self.logs {
+            if let Some(data) = line.strip_prefix(PROGRAM_DATA) {
+                if stack.last() != Some(&program.as_str()) {
+                    continue;
+                }
+                match STANDARD.decode(data).ok().and_then(|bytes| Event::decode(&bytes)) {
+                    Some(event) => events.push(DecodedEvent {
+                        signature: self.signature.clone(),
+                        slot: self.slot,
+                        index: events.len() as u32,
+                        event,
+                    }),
+                    None => debug!(signature = %self.signature, "skipping program data that isn't a consumed event"),
+                }
+            } else if let Some(rest) = line.strip_prefix("Program ") {
+                let mut words = rest.split_whitespace();
+                match (words.next(), words.next()) {
+                    (Some(id), Some("invoke")) => stack.push(id),
+                    (Some(id), Some("success" | "failed:")) if stack.last() == Some(&id) => {
+                        stack.pop();
+                    }
+                    _ => {}
+                }
+            }
+        }
+        events
+    }
+}
+
+/// Where decoded events go.
+#[async_trait]
+pub trait EventSink: Send {
+    fn name(&self) -> &str;
+
+    async fn handle(&mut self, event: &DecodedEvent) -> Result<()>;
+}
+
+/// Fans events out to every sink. A sink that fails is logged and the
+/// others still get the event.
+#[derive(Default)]
+pub struct EventPipeline {
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl EventPipeline {
+    pub fn with(mut self, sink: impl EventSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Decode `transaction`'s events and dispatch them. Returns how many
+    /// there were.
+    pub async fn process(&mut self, transaction: &LoggedTransaction) -> usize {
+        let events = transaction.events();
+        for event in &events {
+            for sink in &mut self.sinks {
+                if let Err(err) = sink.handle(event).await {
+                    warn!(sink = sink.name(), signature = %event.signature, "sink failed: {err:#}");
+                }
+            }
+        }
+        events.len()
+    }
+}
+
+/// Dispatch every transaction in a file written by `follow_logs`.
+pub async fn replay(path: &Path, pipeline: &mut EventPipeline) -> Result<usize> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let mut count = 0;
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let transaction: LoggedTransaction =
+            serde_json::from_str(&line).with_context(|| format!("{}:{}", path.display(), number + 1))?;
+        count += pipeline.process(&transaction).await;
+    }
+    Ok(count)
+}
+
+/// Dispatch events from the program's logs as transactions confirm, until
+/// interrupted. With `record`, every successful transaction's logs are also
+/// appended there for `replay`.
+pub async fn follow_logs(ws_url: &str, pipeline: &mut EventPipeline, record: Option<&Path>) -> Result<()> {
+    let mut record = match record {
+        Some(path) => {
+            let file = File::options().create(true).append(true).open(path);
+            Some(BufWriter::new(file.with_context(|| format!("opening {}", path.display()))?))
+        }
+        None => None,
+    };
+
+    loop {
+        tokio::select! {
+            result = subscribe(ws_url, pipeline, record.as_mut()) => {
+                warn!("log subscription ended: {:#}", result.err().unwrap_or(anyhow!("stream closed")));
+            }
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn subscribe(ws_url: &str, pipeline: &mut EventPipeline, mut record: Option<&mut BufWriter<File>>) -> Result<()> {
+    let pubsub = PubsubClient::new(ws_url).await.context("connecting websocket")?;
+    let filter = RpcTransactionLogsFilter::Mentions(vec![liquidation_program::ID.to_string()]);
+    let config = RpcTransactionLogsConfig { commitment: Some(CommitmentConfig::confirmed()) };
+    let (mut logs, _unsubscribe) = pubsub.logs_subscribe(filter, config).await.context("logsSubscribe")?;
+
+    while let Some(response) = logs.next().await {
+        // a failed transaction's events never happened
+        if response.value.err.is_some() {
+            continue;
+        }
+        let transaction = LoggedTransaction {
+            signature: response.value.signature,
+            slot: response.context.slot,
+            logs: response.value.logs,
+        };
+        if let Some(writer) = record.as_deref_mut() {
+            serde_json::to_writer(&mut *writer, &transaction)?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+        }
+        pipeline.process(&transaction).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use anchor_lang::Event as _;
+    use solana_sdk::pubkey::Pubkey;
+    use std::sync::{Arc, Mutex};
+
+    pub(crate) fn liquidation(timestamp: i64) -> LiquidationEvent {
+        LiquidationEvent {
+            position_owner: Pubkey::new_from_array([7; 32]),
+            liquidator: Pubkey::new_from_array([9; 32]),
+            symbol_id: 3,
+            liquidated_size: 5_000_000,
+            liquidation_price: 95_000_000,
+            margin_before: 10_000_000,
+            margin_after: 20_000_000,
+            liquidator_reward: 11_875_000,
+            trader_payout: 0,
+            insurance_covered: 0,
+            bad_debt: 0,
+            timestamp,
+            reward_source: RewardSource::ProtocolVault,
+        }
+    }
+
+    pub(crate) fn data_log(event: &impl anchor_lang::Event) -> String {
+        format!("{PROGRAM_DATA}{}", STANDARD.encode(event.data()))
+    }
+
+    pub(crate) fn transaction(logs: Vec<String>) -> LoggedTransaction {
+        LoggedTransaction { signature: "sig".into(), slot: 42, logs }
+    }
+
+    fn invoke(program: &str, depth: u8) -> String {
+        format!("Program {program} invoke [{depth}]")
+    }
+
+    #[test]
+    fn decodes_events_the_program_emitted() {
+        let program = liquidation_program::ID.to_string();
+        let other = Pubkey::new_unique().to_string();
+        let insolvency = ProtocolInsolvencyEvent { amount: 5, timestamp: 1 };
+
+        let tx = transaction(vec![
+            invoke(&program, 1),
+            "Program log: Instruction: LiquidatePartial".into(),
+            invoke(&other, 2),
+            data_log(&liquidation(0)),
+            format!("Program {other} success"),
+            data_log(&liquidation(100)),
+            data_log(&insolvency),
+            format!("{PROGRAM_DATA}not base64!"),
+            format!("Program {program} consumed 1000 of 200000 compute units"),
+            format!("Program {program} success"),
+            data_log(&liquidation(200)),
+        ]);
+
+        let events = tx.events();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0].event, Event::Liquidation(e) if e.timestamp == 100));
+        assert!(matches!(&events[1].event, Event::ProtocolInsolvency(e) if e.amount == 5));
+        assert_eq!((events[1].index, events[1].slot), (1, 42));
+
+        let json = events[0].to_json();
+        assert_eq!(json["event"], "LiquidationEvent");
+        assert_eq!(json["data"]["position_owner"], Pubkey::new_from_array([7; 32]).to_string());
+        assert_eq!(json["data"]["liquidator_reward"], 11_875_000);
+        assert_eq!(json["data"]["reward_source"], "protocol_vault");
+    }
+
+    #[test]
+    fn ignores_other_events_and_garbage() {
+        assert!(Event::decode(&[0; 4]).is_none());
+        let seized = liquidation_program::state::CollateralSeizedEvent {
+            position_owner: Pubkey::default(),
+            liquidator: Pubkey::default(),
+            mint: Pubkey::default(),
+            seized_amount: 1,
+            repaid_amount: 1,
+            collateral_price: 1,
+            timestamp: 0,
+        };
+        assert!(Event::decode(&seized.data()).is_none());
+        // right discriminator, truncated body
+        assert!(Event::decode(&liquidation(0).data()[..20]).is_none());
+    }
+
+    /// Collects the timestamps of what it is handed.
+    struct Collect(Arc<Mutex<Vec<i64>>>);
+
+    #[async_trait]
+    impl EventSink for Collect {
+        fn name(&self) -> &str {
+            "collect"
+        }
+
+        async fn handle(&mut self, event: &DecodedEvent) -> Result<()> {
+            self.0.lock().unwrap().push(event.event.timestamp());
+            Ok(())
+        }
+    }
+
+    struct Fail;
+
+    #[async_trait]
+    impl EventSink for Fail {
+        fn name(&self) -> &str {
+            "fail"
+        }
+
+        async fn handle(&mut self, _: &DecodedEvent) -> Result<()> {
+            Err(anyhow!("down"))
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_a_recorded_file_into_every_sink() {
+        let program = liquidation_program::ID.to_string();
+        let path = std::env::temp_dir().join(format!("replay-{}.jsonl", Pubkey::new_unique()));
+        let mut file = File::create(&path).unwrap();
+        for timestamp in [1, 2] {
+            let tx = transaction(vec![invoke(&program, 1), data_log(&liquidation(timestamp)), format!("Program {program} success")]);
+            writeln!(file, "{}\n", serde_json::to_string(&tx).unwrap()).unwrap();
+        }
+        drop(file);
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut pipeline = EventPipeline::default().with(Fail).with(Collect(seen.clone()));
+        assert_eq!(replay(&path, &mut pipeline).await.unwrap(), 2);
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+//! Keeps the SQLite store in step with the chain.
+//!
+//! The indexer subscribes to every program account change and to new roots,
+//! then bootstraps from `getProgramAccounts`. Updates arrive at confirmed
+//! commitment and are stored with their slot. When a slot is rooted, events
+//! and versions written since the previous root at slots the finalized chain
+//! skipped are dropped, and the accounts written since then are re-read at
+//! finalized commitment and their history up to that point replaced.
+//! Together that undoes anything written on a fork that was abandoned. If
+//! the websocket drops, it resubscribes and bootstraps again.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::StreamExt;
+use liquidation_program::{InsuranceFund, LiquidationRecord, Market, Position};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_response::{Response, RpcKeyedAccount};
+use solana_sdk::account::Account;
+use solana_sdk::clock::Slot;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use tracing::{debug, info, warn};
+
+use crate::scan::raw_program_accounts;
+use crate::store::Store;
+
+pub type SharedStore = Arc<Mutex<Store>>;
+
+/// Delay before reconnecting after the subscriptions fail.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+pub struct Indexer {
+    /// RPC client at confirmed commitment.
+    pub client: RpcClient,
+    pub ws_url: String,
+    pub store: SharedStore,
+}
+
+impl Indexer {
+    /// Follow the chain until interrupted, reconnecting on errors.
+    pub async fn run(&self) -> Result<()> {
+        loop {
+            tokio::select! {
+                result = self.follow() => {
+                    warn!("indexer subscription ended: {:#}", result.err().unwrap_or(anyhow!("stream closed")));
+                }
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    /// Index every program account as of now. Returns the slot the snapshot
+    /// is stored at, which is no later than the data it holds.
+    pub async fn bootstrap(&self) -> Result<Slot> {
+        let slot = self.client.get_slot().await.context("getSlot")?;
+
+        let mut accounts = Vec::new();
+        accounts.extend(raw_program_accounts::<Position>(&self.client).await?);
+        accounts.extend(raw_program_accounts::<Market>(&self.client).await?);
+        accounts.extend(raw_program_accounts::<InsuranceFund>(&self.client).await?);
+        accounts.extend(raw_program_accounts::<LiquidationRecord>(&self.client).await?);
+
+        let accounts: Vec<(Pubkey, Vec<u8>)> = accounts.into_iter().map(|(key, account)| (key, account.data)).collect();
+        self.store()?.replace_all(&accounts, slot)?;
+        info!(slot, accounts = accounts.len(), "indexer bootstrapped");
+        Ok(slot)
+    }
+
+    /// Subscribe, bootstrap, then apply updates until a stream fails.
+    async fn follow(&self) -> Result<()> {
+        let pubsub = PubsubClient::new(&self.ws_url).await.context("connecting websocket")?;
+        let config = RpcProgramAccountsConfig {
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(CommitmentConfig::confirmed()),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        let (mut accounts, _unsubscribe_accounts) = pubsub
+            .program_subscribe(&liquidation_program::ID, Some(config))
+            .await
+            .context("programSubscribe")?;
+        let (mut roots, _unsubscribe_roots) = pubsub.root_subscribe().await.context("rootSubscribe")?;
+
+        // subscribed first, so nothing between the snapshot and the first
+        // notification is missed
+        self.bootstrap().await?;
+
+        loop {
+            tokio::select! {
+                update = accounts.next() => match update {
+                    Some(update) => self.on_account(update)?,
+                    None => bail!("account subscription closed"),
+                },
+                root = roots.next() => match root {
+                    Some(root) => self.on_root(root).await?,
+                    None => bail!("root subscription closed"),
+                },
+            }
+        }
+    }
+
+    fn on_account(&self, update: Response<RpcKeyedAccount>) -> Result<()> {
+        let slot = update.context.slot;
+        let address: Pubkey = update.value.pubkey.parse().context("notification pubkey")?;
+        let account: Account =
+            update.value.account.decode().ok_or(anyhow!("undecodable notification for {address}"))?;
+
+        debug!(%address, slot, "account update");
+        self.store()?.apply(&address, slot, live_data(&account))
+    }
+
+    async fn on_root(&self, root: Slot) -> Result<()> {
+        let previous = self.store()?.root()?.unwrap_or(0);
+        if root <= previous {
+            return Ok(());
+        }
+
+        let changed = self.store()?.updated_between(previous, root)?;
+
+        // events carry no finalized copy to re-read, so drop those from
+        // slots that didn't make it into the rooted chain, and any account
+        // versions with them
+        let written = self.store()?.written_between(previous, root)?;
+        if let (Some(&first), Some(&last)) = (written.first(), written.last()) {
+            let rooted = self
+                .client
+                .get_blocks_with_commitment(first, Some(last), CommitmentConfig::finalized())
+                .await
+                .context("getBlocks")?;
+            let dropped = self.store()?.drop_abandoned(previous, root, &rooted)?;
+            if dropped > 0 {
+                info!(dropped, root, "dropped events from an abandoned fork");
+            }
+        }
+
+        for keys in changed.chunks(100) {
+            let finalized = self
+                .client
+                .get_multiple_accounts_with_commitment(keys, CommitmentConfig::finalized())
+                .await
+                .context("getMultipleAccounts")?;
+            let slot = finalized.context.slot;
+
+            let mut store = self.store()?;
+            for (key, account) in keys.iter().zip(&finalized.value) {
+                if store.reconcile(key, slot, account.as_ref().and_then(live_data))? {
+                    info!(%key, slot, "dropped writes from an abandoned fork");
+                }
+            }
+        }
+
+        self.store()?.set_root(root)
+    }
+
+    fn store(&self) -> Result<MutexGuard<'_, Store>> {
+        self.store.lock().map_err(|_| anyhow!("store lock poisoned"))
+    }
+}
+
+/// An account's data, or `None` once it has been closed (reassigned away
+/// from the program and drained).
+fn live_data(account: &Account) -> Option<&[u8]> {
+    (account.owner == liquidation_program::ID && account.lamports > 0).then_some(&account.data[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::{data_log, liquidation, transaction};
+    use crate::events::LoggedTransaction;
+    use crate::store::tests::{bytes, position};
+    use serde_json::json;
+    use solana_account_decoder::UiAccount;
+    use solana_client::rpc_request::RpcRequest;
+    use std::collections::HashMap;
+
+    // `data` as the finalized chain holds it, owned by the program
+    fn finalized(address: &Pubkey, data: Vec<u8>) -> UiAccount {
+        let account = Account { lamports: 1, data, owner: liquidation_program::ID, ..Account::default() };
+        UiAccount::encode(address, &account, UiAccountEncoding::Base64, None, None)
+    }
+
+    fn collateral(store: &SharedStore, address: &Pubkey) -> i64 {
+        store.lock().unwrap().position(address).unwrap().expect("indexed position").account.collateral
+    }
+
+    #[tokio::test]
+    async fn root_drops_what_an_abandoned_fork_wrote() {
+        let store: SharedStore = Arc::new(Mutex::new(Store::open_in_memory().unwrap()));
+        let address = Pubkey::new_unique();
+        let program = liquidation_program::ID.to_string();
+        let liquidated_at = |slot, timestamp| LoggedTransaction {
+            slot,
+            ..transaction(vec![
+                format!("Program {program} invoke [1]"),
+                data_log(&liquidation(timestamp)),
+                format!("Program {program} success"),
+            ])
+        };
+
+        {
+            let mut store = store.lock().unwrap();
+            store.apply(&address, 10, Some(&bytes(&position(1, 100)))).unwrap();
+            store.set_root(10).unwrap();
+
+            // slot 11 is on a fork that liquidates the position; slot 12,
+            // on the chain that gets rooted, only tops it up
+            store.apply(&address, 11, Some(&bytes(&position(0, 0)))).unwrap();
+            for event in liquidated_at(11, 111).events() {
+                store.insert_event(&event).unwrap();
+            }
+            store.apply(&address, 12, Some(&bytes(&position(1, 120)))).unwrap();
+            store.apply(&address, 13, Some(&bytes(&position(1, 130)))).unwrap();
+        }
+        assert_eq!(collateral(&store, &address), 130);
+        assert_eq!(store.lock().unwrap().events(None, 10).unwrap().len(), 1);
+
+        let mocks = HashMap::from([
+            (RpcRequest::GetBlocks, json!([12, 13])),
+            (
+                RpcRequest::GetMultipleAccounts,
+                json!({
+                    "context": { "slot": 13 },
+                    "value": [finalized(&address, bytes(&position(1, 130)))],
+                }),
+            ),
+        ]);
+        let indexer = Indexer {
+            client: RpcClient::new_mock_with_mocks("succeeds".into(), mocks),
+            ws_url: String::new(),
+            store: store.clone(),
+        };
+        indexer.on_root(13).await.unwrap();
+
+        let store = store.lock().unwrap();
+        assert!(store.events(None, 10).unwrap().is_empty());
+        assert_eq!(store.written_between(10, 13).unwrap(), vec![13]);
+        assert_eq!(store.position(&address).unwrap().unwrap().account.collateral, 130);
+        assert_eq!(store.root().unwrap(), Some(13));
+    }
+}
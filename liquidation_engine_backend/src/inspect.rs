@@ -0,0 +1,178 @@
+//! Program accounts as JSON, and as aligned `key  value` text for people.
+
+use anyhow::{anyhow, bail, Result};
+use liquidation_math::PRICE_PRECISION;
+use liquidation_program::{InsuranceFund, LiquidationRecord, Market, Position};
+use serde_json::{json, Map, Value};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::scan::decode;
+use crate::store::{symbol, IndexedKind};
+
+/// Fields in PRICE_PRECISION, shown with their decimal value as well.
+const PRICE_FIELDS: [&str; 5] = ["entry_price", "liquidation_price", "last_accepted_price", "pending_price", "size"];
+
+pub fn position(address: &Pubkey, p: &Position) -> Value {
+    json!({
+        "type": "Position",
+        "address": address.to_string(),
+        "owner": p.owner.to_string(),
+        "market": p.market.to_string(),
+        "side": if p.is_long { "long" } else { "short" },
+        "size": p.size,
+        "entry_price": p.entry_price,
+        "collateral": p.collateral,
+        "leverage": p.leverage,
+        "deposits": p.deposits,
+        "open_auctions": p.open_auctions,
+        "liquidation_count": p.liquidation_count,
+        "last_update_ts": p.last_update_ts,
+    })
+}
+
+pub fn market(address: &Pubkey, m: &Market) -> Value {
+    let tiers: Vec<Value> = m.risk_tiers[..m.num_risk_tiers as usize]
+        .iter()
+        .map(|tier| json!({ "max_notional": tier.max_notional, "maintenance_margin_bps": tier.maintenance_margin_bps }))
+        .collect();
+    json!({
+        "type": "Market",
+        "address": address.to_string(),
+        "market_index": m.market_index,
+        "symbol": symbol(&m.symbol),
+        "authority": m.authority.to_string(),
+        "oracle": m.oracle.to_string(),
+        "max_price_deviation_bps": m.max_price_deviation_bps,
+        "deviation_persist_slots": m.deviation_persist_slots,
+        "last_accepted_price": m.last_accepted_price,
+        "last_accepted_slot": m.last_accepted_slot,
+        "pending_price": m.pending_price,
+        "pending_since_slot": m.pending_since_slot,
+        "long_open_interest": m.long_open_interest,
+        "short_open_interest": m.short_open_interest,
+        "max_open_interest": m.max_open_interest,
+        "max_position_notional": m.max_position_notional,
+        "risk_tiers": tiers,
+    })
+}
+
+pub fn insurance_fund(address: &Pubkey, f: &InsuranceFund) -> Value {
+    json!({
+        "type": "InsuranceFund",
+        "address": address.to_string(),
+        "authority": f.authority.to_string(),
+        "insurance_vault": f.insurance_vault.to_string(),
+        "balance": f.balance,
+        "total_contributions": f.total_contributions,
+        "total_bad_debt_covered": f.total_bad_debt_covered,
+        "utilization_ratio": f.utilization_ratio,
+    })
+}
+
+pub fn liquidation_record(address: &Pubkey, r: &LiquidationRecord) -> Value {
+    json!({
+        "type": "LiquidationRecord",
+        "address": address.to_string(),
+        "position_owner": r.position_owner.to_string(),
+        "liquidator": r.liquidator.to_string(),
+        "symbol": symbol(&r.symbol),
+        "liquidated_size": r.liquidated_size,
+        "liquidation_price": r.liquidation_price,
+        "margin_before": r.margin_before,
+        "margin_after": r.margin_after,
+        "liquidator_reward": r.liquidator_reward,
+        "bad_debt": r.bad_debt,
+        "timestamp": r.timestamp,
+    })
+}
+
+/// Decode any of the accounts above, checking it is a `expected` if given.
+pub fn describe(address: &Pubkey, data: &[u8], expected: Option<IndexedKind>) -> Result<Value> {
+    let kind = IndexedKind::of(data).ok_or_else(|| anyhow!("{address} is not a position, market, insurance fund or record"))?;
+    if let Some(expected) = expected.filter(|&expected| expected != kind) {
+        bail!("{address} is a {kind:?}, not a {expected:?}");
+    }
+    Ok(match kind {
+        IndexedKind::Position => position(address, &decode(data)?),
+        IndexedKind::Market => market(address, &decode(data)?),
+        IndexedKind::InsuranceFund => insurance_fund(address, &decode(data)?),
+        IndexedKind::LiquidationRecord => liquidation_record(address, &decode(data)?),
+    })
+}
+
+/// One `key  value` line per field of an object; nested values stay JSON.
+pub fn pretty(value: &Value) -> String {
+    let Some(fields) = value.as_object() else { return value.to_string() };
+    let width = fields.keys().map(String::len).max().unwrap_or(0);
+    fields.iter().map(|(key, value)| format!("{key:<width$}  {}", field(key, value))).collect::<Vec<_>>().join("\n")
+}
+
+fn field(key: &str, value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Number(number) if PRICE_FIELDS.contains(&key) => match number.as_u64() {
+            Some(fixed) => format!("{fixed} ({})", decimal(fixed)),
+            None => number.to_string(),
+        },
+        Value::Array(items) if items.iter().all(Value::is_object) && !items.is_empty() => {
+            items.iter().map(|item| format!("\n  {}", compact(item))).collect()
+        }
+        other => other.to_string(),
+    }
+}
+
+fn compact(object: &Value) -> String {
+    let fields: &Map<String, Value> = match object.as_object() {
+        Some(fields) => fields,
+        None => return object.to_string(),
+    };
+    fields.iter().map(|(key, value)| format!("{key}={value}")).collect::<Vec<_>>().join(" ")
+}
+
+/// PRICE_PRECISION fixed point as a decimal, trailing zeros dropped.
+fn decimal(fixed: u64) -> String {
+    let (whole, fraction) = (fixed / PRICE_PRECISION, fixed % PRICE_PRECISION);
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{fraction:06}");
+    format!("{whole}.{}", fraction.trim_end_matches('0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::AccountSerialize;
+
+    #[test]
+    fn describes_by_discriminator() {
+        let fund = InsuranceFund {
+            authority: Pubkey::new_unique(),
+            insurance_vault: Pubkey::new_unique(),
+            balance: 42,
+            total_contributions: 0,
+            total_bad_debt_covered: 0,
+            utilization_ratio: 0,
+        };
+        let mut data = Vec::new();
+        fund.try_serialize(&mut data).unwrap();
+        let address = Pubkey::new_unique();
+
+        let value = describe(&address, &data, None).unwrap();
+        assert_eq!(value["type"], "InsuranceFund");
+        assert_eq!(value["balance"], 42);
+        assert!(describe(&address, &data, Some(IndexedKind::Position)).is_err());
+        assert!(describe(&address, &[0; 8], None).is_err());
+
+        let text = pretty(&value);
+        assert!(text.lines().any(|line| line.starts_with("balance ") && line.ends_with(" 42")));
+    }
+
+    #[test]
+    fn prices_show_their_decimal_value() {
+        assert_eq!(field("entry_price", &json!(101_250_000u64)), "101250000 (101.25)");
+        assert_eq!(field("entry_price", &json!(7_000_000u64)), "7000000 (7)");
+        assert_eq!(field("collateral", &json!(-5)), "-5");
+        assert_eq!(decimal(1), "0.000001");
+    }
+}
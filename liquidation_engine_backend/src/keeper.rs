@@ -0,0 +1,644 @@
+//! The keeper loop: follow the positions (through the indexer's changes, or
+//! by scanning them all), price the ones whose liquidation trigger was
+//! crossed the way the program will, and send `liquidate_partial` or
+//! `liquidate_full` for the unhealthy ones. In a dry run it simulates them
+//! instead; see `shadow`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anyhow::{anyhow, Context, Result};
+use liquidation_math::{evaluate_position, plan_liquidation, Bps, HealthReport, LiquidationContext, LiquidationPlan, Price, QuoteAmount};
+use liquidation_program::collateral::{has_deposits, haircut_collateral_value};
+use liquidation_program::constants::{LIQUIDATOR_REWARD_BPS, MAX_COLLATERAL_ASSETS};
+use liquidation_program::price_guard::{apply_price_guard, PriceCheck};
+use liquidation_program::risk_tiers::maintenance_margin_bps;
+use liquidation_program::{LiquidationRecord, Position};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::Transaction;
+use tracing::{debug, info, warn};
+
+pub use liquidation_client::instructions::{Liquidation, LiquidationAccounts};
+
+use crate::indexer::SharedStore;
+use crate::metrics::Metrics;
+use crate::oracle::read_oracle;
+use crate::queue::LiquidationQueue;
+use crate::scan::ProtocolState;
+use crate::shadow::{Predicted, Prediction, Shadow};
+
+/// Most accounts `getMultipleAccounts` returns per call.
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// An unhealthy position and what the program will do to it.
+#[derive(Clone, Copy, Debug)]
+pub struct Candidate {
+    pub position: Pubkey,
+    pub price: u64,
+    pub health: HealthReport,
+    pub plan: LiquidationPlan,
+}
+
+impl Candidate {
+    pub fn kind(&self) -> Liquidation {
+        match self.plan {
+            LiquidationPlan::Full(_) => Liquidation::Full,
+            LiquidationPlan::Healthy | LiquidationPlan::Partial(_) => Liquidation::Partial,
+        }
+    }
+}
+
+/// Validated oracle prices by oracle account, PRICE_PRECISION.
+pub type Prices = HashMap<Pubkey, u64>;
+
+/// Oracle reads for one pass.
+#[derive(Debug, Default)]
+pub struct Oracles {
+    /// Prices the program would accept.
+    pub prices: Prices,
+    /// Publish time of every oracle that decoded, accepted or not.
+    pub publish_times: HashMap<Pubkey, i64>,
+}
+
+/// Read every oracle in `keys`. Oracles the program would refuse (stale,
+/// not trading, too uncertain) get no price, and the positions that depend
+/// on them are left out.
+pub async fn load_oracles(client: &RpcClient, keys: &[Pubkey], now: i64) -> Result<Oracles> {
+    let mut oracles = Oracles::default();
+    for chunk in keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let accounts = client.get_multiple_accounts(chunk).await.context("getMultipleAccounts")?;
+        for (key, account) in chunk.iter().zip(accounts) {
+            let Some(account) = account else {
+                warn!(%key, "oracle account not found");
+                continue;
+            };
+            let read = match read_oracle(key, &account, now) {
+                Ok(read) => read,
+                Err(err) => {
+                    warn!("{err:#}");
+                    continue;
+                }
+            };
+            oracles.publish_times.insert(*key, read.publish_time);
+            match read.price {
+                Ok(price) => {
+                    oracles.prices.insert(*key, price);
+                }
+                Err(err) => warn!("{err:#}"),
+            }
+        }
+    }
+    Ok(oracles)
+}
+
+/// Accepted prices of every oracle in `keys`; see `load_oracles`.
+pub async fn load_prices(client: &RpcClient, keys: &[Pubkey], now: i64) -> Result<Prices> {
+    Ok(load_oracles(client, keys, now).await?.prices)
+}
+
+/// The liquidation the program would carry out on `position` at `slot`, or
+/// `None` if it is healthy or can't be judged (no price, price still pending
+/// in the deviation guard).
+pub fn evaluate(state: &ProtocolState, prices: &Prices, slot: u64, key: Pubkey, position: &Position) -> Option<Candidate> {
+    if position.size == 0 {
+        return None;
+    }
+    let market = state.markets.get(&position.market)?;
+    let price = *prices.get(&market.oracle)?;
+
+    // the instruction reverts while a jump is pending
+    let mut guarded = market.clone();
+    if apply_price_guard(&mut guarded, price, slot) != PriceCheck::Accepted {
+        debug!(%key, price, "price pending in the deviation guard");
+        return None;
+    }
+
+    let context = LiquidationContext {
+        haircut: haircut(state, prices, position)?,
+        has_deposits: has_deposits(position),
+        insurance_balance: state.insurance_vault_balance,
+        reward_bps: Bps(LIQUIDATOR_REWARD_BPS),
+    };
+    let (health, plan) = plan_liquidation(&position.terms(), Price(price), &context, |notional| {
+        maintenance_margin_bps(market, notional)
+    })?;
+
+    match plan {
+        LiquidationPlan::Healthy => None,
+        _ => Some(Candidate { position: key, price, health, plan }),
+    }
+}
+
+/// Haircut value of `position`'s non-quote collateral, or `None` if one of
+/// its assets has no price.
+pub fn haircut(state: &ProtocolState, prices: &Prices, position: &Position) -> Option<QuoteAmount> {
+    let registry = &state.collateral_registry;
+    let mut asset_prices = [0u64; MAX_COLLATERAL_ASSETS];
+    for (i, asset) in registry.assets[..registry.num_assets as usize].iter().enumerate() {
+        if position.deposits[i] > 0 {
+            asset_prices[i] = *prices.get(&asset.oracle)?;
+        }
+    }
+    haircut_collateral_value(registry, position, &asset_prices).ok().map(QuoteAmount)
+}
+
+/// Health of `position` at its market's oracle price, as the program would
+/// judge it, with that price. `None` without a price for the market or for
+/// one of its collateral assets.
+pub fn position_health(state: &ProtocolState, prices: &Prices, position: &Position) -> Option<(u64, HealthReport)> {
+    let market = state.markets.get(&position.market)?;
+    let price = *prices.get(&market.oracle)?;
+    let health = evaluate_position(&position.terms(), Price(price), haircut(state, prices, position)?, |notional| {
+        maintenance_margin_bps(market, notional)
+    })?;
+    Some((price, health))
+}
+
+/// Every position the program would liquidate right now, worst margin first.
+pub fn find_candidates(state: &ProtocolState, prices: &Prices, slot: u64) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = state
+        .positions
+        .iter()
+        .filter_map(|(key, position)| evaluate(state, prices, slot, *key, position))
+        .collect();
+    candidates.sort_by_key(|candidate| candidate.health.margin_ratio_bps);
+    candidates
+}
+
+/// Accounts for liquidating `key` with `liquidator` as signer, or `None`
+/// if its market is not in `state`.
+pub fn liquidation_accounts(
+    state: &ProtocolState,
+    key: Pubkey,
+    position: &Position,
+    liquidator: Pubkey,
+    liquidator_token_account: Pubkey,
+) -> Option<LiquidationAccounts> {
+    let market = state.markets.get(&position.market)?;
+    Some(LiquidationAccounts::new(
+        &state.protocol(),
+        &state.collateral_registry,
+        key,
+        position,
+        market,
+        liquidator,
+        liquidator_token_account,
+    ))
+}
+
+/// Positions the indexer saw change, with their latest state or `None` if
+/// they were closed. Other accounts that changed come with `None` too.
+type ChangedPositions = Vec<(Pubkey, Option<Position>)>;
+
+/// What one pass did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PassSummary {
+    pub scanned: usize,
+    /// Positions whose liquidation trigger the prices have crossed, the
+    /// only ones evaluated.
+    pub crossed: usize,
+    pub unhealthy: usize,
+    pub liquidated: usize,
+    /// Liquidations simulated instead of sent, in a dry run.
+    pub simulated: usize,
+    pub failed: usize,
+}
+
+pub struct Keeper {
+    pub client: RpcClient,
+    pub payer: Keypair,
+    /// Receives liquidator rewards; the payer's quote ATA if not set.
+    pub liquidator_token_account: Option<Pubkey>,
+    /// Indexer store to read program accounts from; each pass scans with
+    /// `getProgramAccounts` if not set.
+    pub store: Option<SharedStore>,
+    /// Positions by liquidation trigger, kept across passes so a pass only
+    /// evaluates the ones the current prices have crossed.
+    pub queue: Mutex<LiquidationQueue>,
+    pub metrics: Arc<Metrics>,
+    /// When each currently unhealthy position was first found unhealthy,
+    /// for the liquidation latency histogram.
+    pub first_seen: Mutex<HashMap<Pubkey, Instant>>,
+    /// Set for a dry run: liquidations are simulated, never sent, and the
+    /// predictions checked against what other keepers land.
+    pub shadow: Option<Mutex<Shadow>>,
+}
+
+impl Keeper {
+    /// Scan, evaluate and liquidate once.
+    pub async fn run_once(&self) -> Result<PassSummary> {
+        let result = self.pass().await;
+        match &result {
+            Ok(summary) => self.metrics.observe_pass(summary),
+            Err(_) => self.metrics.pass_failures.inc(),
+        }
+        result
+    }
+
+    async fn pass(&self) -> Result<PassSummary> {
+        let (mut state, changed) = match &self.store {
+            Some(store) => self.load_from_store(store).await?,
+            None => (ProtocolState::load(&self.client).await?, None),
+        };
+        let slot = self.client.get_slot().await.context("getSlot")?;
+        let now = unix_now();
+        let oracles = load_oracles(&self.client, &state.oracles(), now).await?;
+        self.metrics.observe_state(&state, &oracles, now);
+        if let Some(store) = &self.store {
+            let store = store.lock().map_err(|_| anyhow!("store lock poisoned"))?;
+            self.metrics.bad_debt.set(store.total_bad_debt()?.try_into().unwrap_or(i64::MAX));
+        }
+        let prices = oracles.prices;
+
+        // only the crossed positions go on to be evaluated
+        let (scanned, crossed) = {
+            let mut queue = self.queue.lock().map_err(|_| anyhow!("queue lock poisoned"))?;
+            match changed {
+                Some(changed) => {
+                    queue.set_markets(&state.markets);
+                    queue.reprice(&state, &prices);
+                    for (key, position) in &changed {
+                        queue.update(&state, &prices, *key, position.as_ref());
+                    }
+                }
+                None => queue.sync(&state, &prices),
+            }
+            let crossed = queue.crossed_at(&state, &prices);
+            state.positions =
+                crossed.iter().filter_map(|key| Some((*key, queue.position(key)?.clone()))).collect();
+            (queue.len(), crossed)
+        };
+
+        let candidates = find_candidates(&state, &prices, slot);
+        let mut summary = PassSummary {
+            scanned,
+            crossed: crossed.len(),
+            unhealthy: candidates.len(),
+            ..PassSummary::default()
+        };
+
+        let seen = Instant::now();
+        {
+            let mut first_seen = self.first_seen.lock().map_err(|_| anyhow!("first-seen lock poisoned"))?;
+            first_seen.retain(|key, _| candidates.iter().any(|c| c.position == *key));
+            for candidate in &candidates {
+                first_seen.entry(candidate.position).or_insert(seen);
+            }
+        }
+
+        if let Some(shadow) = &self.shadow {
+            self.check_predictions(shadow).await?;
+            for candidate in &candidates {
+                match self.simulate(&state, candidate).await {
+                    Ok(prediction) => {
+                        summary.simulated += 1;
+                        self.metrics.predicted(&prediction);
+                        log_prediction(&prediction);
+                        let mut shadow = shadow.lock().map_err(|_| anyhow!("shadow lock poisoned"))?;
+                        shadow.predict(prediction, Instant::now());
+                    }
+                    Err(err) => {
+                        summary.failed += 1;
+                        warn!(position = %candidate.position, kind = ?candidate.kind(), "simulation failed: {err:#}");
+                    }
+                }
+            }
+            return Ok(summary);
+        }
+
+        for candidate in &candidates {
+            let kind = candidate.kind();
+            self.metrics.attempted(kind);
+            match self.liquidate(&state, candidate).await {
+                Ok(signature) => {
+                    summary.liquidated += 1;
+                    let first_seen = self.first_seen.lock().ok().and_then(|mut seen| seen.remove(&candidate.position));
+                    let latency = first_seen.map_or(0.0, |at| at.elapsed().as_secs_f64());
+                    self.metrics.succeeded(kind, latency);
+                    info!(position = %candidate.position, ?kind, %signature, "liquidated");
+                }
+                Err(err) => {
+                    summary.failed += 1;
+                    self.metrics.failed(kind, &err);
+                    warn!(position = %candidate.position, ?kind, "liquidation failed: {err:#}");
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// The protocol's accounts from the indexer store, and the positions that
+    /// changed there since the last pass, `None` once closed. The first pass
+    /// gets no changes and every open position in the state instead; later
+    /// ones get a state without positions.
+    async fn load_from_store(&self, store: &SharedStore) -> Result<(ProtocolState, Option<ChangedPositions>)> {
+        let changed = {
+            let mut store = store.lock().map_err(|_| anyhow!("store lock poisoned"))?;
+            match store.take_changed() {
+                Some(keys) => Some(
+                    keys.into_iter()
+                        .map(|key| Ok((key, store.position(&key)?.map(|indexed| indexed.account))))
+                        .collect::<Result<ChangedPositions>>()?,
+                ),
+                None => None,
+            }
+        };
+        let state = match changed {
+            Some(_) => ProtocolState::from_store_without_positions(&self.client, store).await?,
+            None => ProtocolState::from_store(&self.client, store).await?,
+        };
+        Ok((state, changed))
+    }
+
+    /// Run passes every `interval` until interrupted. A failed pass is
+    /// logged and retried on the next tick.
+    pub async fn run(&self, interval: Duration) -> Result<()> {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => match self.run_once().await {
+                    Ok(summary) => info!(?summary, "pass complete"),
+                    Err(err) => warn!("pass failed: {err:#}"),
+                },
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+        }
+    }
+
+    async fn liquidate(&self, state: &ProtocolState, candidate: &Candidate) -> Result<Signature> {
+        let instruction = self.instruction_for(state, candidate).context("position left the snapshot")?;
+        let blockhash = self.client.get_latest_blockhash().await.context("getLatestBlockhash")?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            blockhash,
+        );
+        Ok(self.client.send_and_confirm_transaction(&transaction).await?)
+    }
+
+    /// Simulate the liquidation `liquidate` would send. Signatures aren't
+    /// checked, but the payer must exist to pay the fee.
+    async fn simulate(&self, state: &ProtocolState, candidate: &Candidate) -> Result<Prediction> {
+        let accounts = self.accounts_for(state, candidate).context("position left the snapshot")?;
+        let kind = candidate.kind();
+        let transaction = Transaction::new_with_payer(&[accounts.instruction(kind)], Some(&self.payer.pubkey()));
+        let config = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            replace_recent_blockhash: true,
+            commitment: Some(self.client.commitment()),
+            ..RpcSimulateTransactionConfig::default()
+        };
+        let response =
+            self.client.simulate_transaction_with_config(&transaction, config).await.context("simulateTransaction")?;
+        Ok(Prediction::from_simulation(
+            candidate.position,
+            accounts.liquidation_record,
+            kind,
+            response.context.slot,
+            &response.value,
+        ))
+    }
+
+    /// Look for the records of pending predictions, compare the ones
+    /// another keeper has landed and drop the ones that expired.
+    async fn check_predictions(&self, shadow: &Mutex<Shadow>) -> Result<()> {
+        let lock = || shadow.lock().map_err(|_| anyhow!("shadow lock poisoned"));
+        let records = lock()?.pending();
+        for chunk in records.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let accounts = self.client.get_multiple_accounts(chunk).await.context("getMultipleAccounts")?;
+            for (key, account) in chunk.iter().zip(accounts) {
+                let Some(account) = account else { continue };
+                let landed: LiquidationRecord = match liquidation_client::accounts::decode(&account.data) {
+                    Ok(record) => record,
+                    Err(err) => {
+                        warn!(record = %key, "undecodable liquidation record: {err}");
+                        continue;
+                    }
+                };
+                let Some(comparison) = lock()?.resolve(key, &landed) else { continue };
+                self.metrics.compared(&comparison);
+                let prediction = &comparison.prediction;
+                if comparison.matches() {
+                    info!(position = %prediction.position, liquidator = %comparison.liquidator, "prediction matched the landed liquidation");
+                } else {
+                    warn!(
+                        position = %prediction.position,
+                        liquidator = %comparison.liquidator,
+                        predicted = ?prediction.predicted,
+                        landed = ?comparison.landed,
+                        reward_error = comparison.reward_error(),
+                        bad_debt_error = comparison.bad_debt_error(),
+                        "prediction differs from the landed liquidation"
+                    );
+                }
+            }
+        }
+        for prediction in lock()?.expire(Instant::now()) {
+            self.metrics.expired();
+            debug!(position = %prediction.position, predicted = ?prediction.predicted, "no liquidation landed for prediction");
+        }
+        Ok(())
+    }
+
+    pub fn instruction_for(&self, state: &ProtocolState, candidate: &Candidate) -> Option<Instruction> {
+        Some(self.accounts_for(state, candidate)?.instruction(candidate.kind()))
+    }
+
+    fn accounts_for(&self, state: &ProtocolState, candidate: &Candidate) -> Option<LiquidationAccounts> {
+        let (_, position) = state.positions.iter().find(|(key, _)| *key == candidate.position)?;
+        let liquidator = self.payer.pubkey();
+        let token_account = self.liquidator_token_account.unwrap_or_else(|| {
+            get_associated_token_address_with_program_id(&liquidator, &state.quote_mint, &state.token_program)
+        });
+        liquidation_accounts(state, candidate.position, position, liquidator, token_account)
+    }
+}
+
+fn log_prediction(prediction: &Prediction) {
+    let (position, kind, units) = (prediction.position, prediction.kind, prediction.units_consumed);
+    match &prediction.predicted {
+        Predicted::Liquidated { outcome, insurance_covered } => info!(
+            %position,
+            ?kind,
+            slot = prediction.slot,
+            reward = outcome.liquidator_reward,
+            bad_debt = outcome.bad_debt,
+            size = outcome.liquidated_size,
+            insurance_covered,
+            ?units,
+            "simulated liquidation"
+        ),
+        Predicted::Healthy => info!(%position, ?kind, ?units, "simulated liquidation found the position healthy"),
+        Predicted::Failed(error) => warn!(%position, ?kind, ?units, error, "simulated liquidation failed"),
+    }
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use anchor_lang::InstructionData;
+    use liquidation_client::pda;
+    use liquidation_math::{FullPlan, PRICE_PRECISION};
+    use liquidation_program::collateral::CollateralAsset;
+    use liquidation_program::{instruction, CollateralRegistry, InsuranceFund, Market};
+    use solana_sdk::instruction::AccountMeta;
+
+    pub(crate) const ORACLE: Pubkey = Pubkey::new_from_array([1; 32]);
+    pub(crate) const MARKET: Pubkey = Pubkey::new_from_array([2; 32]);
+    pub(crate) const ASSET_ORACLE: Pubkey = Pubkey::new_from_array([3; 32]);
+
+    pub(crate) fn price(whole: u64) -> u64 {
+        whole * PRICE_PRECISION
+    }
+
+    /// One market with a single default tier and a collateral asset at 100%.
+    pub(crate) fn state(positions: Vec<(Pubkey, Position)>) -> ProtocolState {
+        let market = Market { oracle: ORACLE, ..Market::default() };
+        let mut registry = CollateralRegistry {
+            authority: Pubkey::default(),
+            num_assets: 1,
+            assets: [CollateralAsset::default(); MAX_COLLATERAL_ASSETS],
+        };
+        registry.assets[0] = CollateralAsset {
+            oracle: ASSET_ORACLE,
+            decimals: 6,
+            weight_bps: 10_000,
+            ..CollateralAsset::default()
+        };
+
+        ProtocolState {
+            markets: HashMap::from([(MARKET, market)]),
+            positions,
+            collateral_registry: registry,
+            insurance_fund: Pubkey::new_unique(),
+            insurance: InsuranceFund {
+                authority: Pubkey::default(),
+                insurance_vault: pda::insurance_vault(),
+                balance: 0,
+                total_contributions: 0,
+                total_bad_debt_covered: 0,
+                utilization_ratio: 0,
+            },
+            insurance_vault_balance: 0,
+            quote_mint: Pubkey::new_unique(),
+            token_program: anchor_spl::token::ID,
+        }
+    }
+
+    /// 10 long at 100 on 60 of collateral.
+    pub(crate) fn long() -> Position {
+        Position {
+            owner: Pubkey::new_unique(),
+            market: MARKET,
+            size: 10 * PRICE_PRECISION,
+            entry_price: price(100),
+            collateral: 60_000_000,
+            is_long: true,
+            last_update_ts: 0,
+            leverage: 20,
+            deposits: [0; MAX_COLLATERAL_ASSETS],
+            open_auctions: 0,
+            liquidation_count: 0,
+        }
+    }
+
+    pub(crate) fn prices(market: u64) -> Prices {
+        HashMap::from([(ORACLE, market), (ASSET_ORACLE, price(1))])
+    }
+
+    #[test]
+    fn picks_the_instruction_the_program_will_run() {
+        let key = Pubkey::new_unique();
+        let state = state(vec![(key, long())]);
+
+        assert!(evaluate(&state, &prices(price(100)), 0, key, &long()).is_none());
+
+        let partial = evaluate(&state, &prices(price(96)), 0, key, &long()).unwrap();
+        assert!(matches!(partial.plan, LiquidationPlan::Partial(_)));
+        assert_eq!(partial.kind(), Liquidation::Partial);
+
+        let full = evaluate(&state, &prices(price(40)), 0, key, &long()).unwrap();
+        assert!(matches!(full.plan, LiquidationPlan::Full(FullPlan::BadDebt { .. })));
+        assert_eq!(full.kind(), Liquidation::Full);
+    }
+
+    #[test]
+    fn skips_positions_it_cannot_judge() {
+        let key = Pubkey::new_unique();
+        let state = state(vec![(key, long())]);
+
+        // no market price
+        assert!(evaluate(&state, &HashMap::new(), 0, key, &long()).is_none());
+
+        // collateral deposit without a price for it
+        let mut with_deposit = long();
+        with_deposit.deposits[0] = 1;
+        let only_market = HashMap::from([(ORACLE, price(95))]);
+        assert!(evaluate(&state, &only_market, 0, key, &with_deposit).is_none());
+
+        // price jumped out of the deviation band and hasn't persisted yet
+        let mut guarded = state;
+        let market = guarded.markets.get_mut(&MARKET).unwrap();
+        market.last_accepted_price = price(100);
+        market.max_price_deviation_bps = 100;
+        market.deviation_persist_slots = 10;
+        assert!(evaluate(&guarded, &prices(price(40)), 0, key, &long()).is_none());
+    }
+
+    #[test]
+    fn haircut_collateral_keeps_a_position_healthy() {
+        let key = Pubkey::new_unique();
+        let state = state(vec![(key, long())]);
+
+        // 100 of collateral tokens at 1 covers the loss at 95
+        let mut with_deposit = long();
+        with_deposit.deposits[0] = 100_000_000;
+        assert!(evaluate(&state, &prices(price(95)), 0, key, &with_deposit).is_none());
+    }
+
+    #[test]
+    fn worst_positions_go_first() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let worse = Position { collateral: 20_000_000, ..long() };
+        let state = state(vec![(a, long()), (b, worse)]);
+
+        let order: Vec<Pubkey> = find_candidates(&state, &prices(price(95)), 0)
+            .iter()
+            .map(|candidate| candidate.position)
+            .collect();
+        assert_eq!(order, vec![b, a]);
+    }
+
+    #[test]
+    fn instruction_carries_collateral_oracles() {
+        let key = Pubkey::new_unique();
+        let mut position = long();
+        position.deposits[0] = 5;
+        position.liquidation_count = 3;
+        let state = state(vec![(key, position.clone())]);
+        let liquidator = Pubkey::new_unique();
+
+        let accounts = liquidation_accounts(&state, key, &position, liquidator, Pubkey::new_unique()).unwrap();
+        assert_eq!(accounts.liquidation_record, pda::liquidation_record(&key, 3));
+
+        let ix = accounts.instruction(Liquidation::Full);
+        assert_eq!(ix.data, instruction::LiquidateFull {}.data());
+        // 16 named accounts, then the collateral oracle
+        assert_eq!(ix.accounts.len(), 17);
+        assert_eq!(ix.accounts[16], AccountMeta::new_readonly(ASSET_ORACLE, false));
+        let signers: Vec<Pubkey> = ix.accounts.iter().filter(|meta| meta.is_signer).map(|meta| meta.pubkey).collect();
+        assert_eq!(signers, vec![liquidator]);
+    }
+}
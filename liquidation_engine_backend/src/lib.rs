@@ -0,0 +1,58 @@
+//! Off-chain side of the liquidation program: a keeper that finds unhealthy
+//! positions and liquidates them.
+//!
+//! Health is judged with the program's own code (`liquidation_math` and the
+//! program crate's oracle, price guard, tier and collateral functions), so
+//! what the keeper sends is what the program will do. Instructions, PDAs
+//! and account decoding come from `liquidation_client`.
+//!
+//! Locally, run a `solana-test-validator` with the program from a
+//! `mock-oracle` build; `MockOracle` accounts then serve as price feeds and
+//! `keeper --keypair <file>` talks to it on the default RPC URL.
+//!
+//! `index` keeps a SQLite copy of the program's positions, markets,
+//! insurance funds and liquidation records up to date from account
+//! subscriptions; `keeper --db <file>` reads positions from it instead of
+//! scanning every pass.
+//!
+//! `events` decodes the program's `LiquidationEvent`s and
+//! `ProtocolInsolvencyEvent`s from its logs, live or from a recorded file,
+//! and sends them to stdout, the SQLite store and/or webhooks.
+//!
+//! `serve` runs the indexer and an HTTP/JSON API over its store: position
+//! health, market aggregates, the insurance fund and liquidation history.
+//!
+//! `keeper --metrics-bind <addr>` exports Prometheus metrics on `/metrics`:
+//! liquidation outcomes and latency, insurance fund balances, bad debt and
+//! oracle staleness.
+//!
+//! `keeper --dry-run` runs it in shadow mode: every liquidation it would
+//! send is simulated instead, with the predicted reward, bad debt and
+//! compute units logged, and later compared with the record another keeper
+//! lands for the same position.
+//!
+//! `backtest` replays a historical price series against the indexed
+//! positions with different reward, partial fraction and tier parameters;
+//! `simulate` samples price paths over them instead, to size the insurance
+//! fund.
+//!
+//! The `liqctl` binary covers the rest of the program's instructions for
+//! admins and operators: initialising accounts, market and collateral
+//! settings, positions, manual liquidations, inspecting accounts and
+//! deriving PDAs, with its cluster and keypair read from a config file.
+
+pub mod api;
+pub mod backtest;
+pub mod config;
+pub mod events;
+pub mod indexer;
+pub mod inspect;
+pub mod keeper;
+pub mod metrics;
+pub mod oracle;
+pub mod queue;
+pub mod scan;
+pub mod shadow;
+pub mod simulate;
+pub mod sinks;
+pub mod store;
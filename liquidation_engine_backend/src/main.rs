@@ -1,3 +1,362 @@
-fn main() {
-    println!("Hello, world!");
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::read_keypair_file;
+use tracing_subscriber::EnvFilter;
+
+use liquidation_engine_backend::api::{self, ApiState, SharedSnapshot};
+use liquidation_engine_backend::backtest::{self, Book, ParamSet};
+use liquidation_engine_backend::events::{self, EventPipeline};
+use liquidation_engine_backend::indexer::Indexer;
+use liquidation_engine_backend::keeper::Keeper;
+use liquidation_engine_backend::metrics::{self, Metrics};
+use liquidation_engine_backend::simulate::{self, SimConfig};
+use liquidation_engine_backend::sinks::{SqliteSink, StdoutSink, WebhookSink};
+use liquidation_engine_backend::store::Store;
+
+#[derive(Parser)]
+#[command(about = "Liquidation engine for the liquidation program")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Scan positions and liquidate unhealthy ones.
+    Keeper(KeeperArgs),
+    /// Index program accounts into a SQLite database.
+    Index(IndexArgs),
+    /// Decode program events from logs and send them to sinks.
+    Events(EventsArgs),
+    /// Index program accounts and serve the HTTP/JSON API over them.
+    Serve(ServeArgs),
+    /// Replay a price series against indexed positions per parameter set.
+    Backtest(BacktestArgs),
+    /// Monte Carlo the indexed positions to size the insurance fund.
+    Simulate(SimulateArgs),
+}
+
+#[derive(Args)]
+struct SimulateArgs {
+    /// Indexer database to take markets and open positions from.
+    #[arg(long, env = "INDEX_DB", default_value = "liquidations.db")]
+    db: PathBuf,
+
+    /// TOML file of price models, regimes and fund sizes.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Number of paths, overriding the config.
+    #[arg(long)]
+    paths: Option<usize>,
+
+    /// Random seed, overriding the config.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Insurance balance to evaluate in quote base units, overriding the
+    /// config; may be repeated.
+    #[arg(long)]
+    fund: Vec<u64>,
+
+    /// Print the report as JSON instead of a table.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct BacktestArgs {
+    /// Price series: `timestamp,market,price` CSV, or Parquet with the same
+    /// columns.
+    prices: PathBuf,
+
+    /// Indexer database to take markets and open positions from.
+    #[arg(long, env = "INDEX_DB", default_value = "liquidations.db")]
+    db: PathBuf,
+
+    /// TOML file of `[[set]]` parameter sets [default: the program's].
+    #[arg(long)]
+    params: Option<PathBuf>,
+
+    /// Starting insurance balance in quote base units [default: the
+    /// indexed fund's balance].
+    #[arg(long)]
+    insurance: Option<u64>,
+
+    /// Print reports as JSON instead of a table.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    index: IndexArgs,
+
+    /// Address to listen on.
+    #[arg(long, env = "BIND", default_value = "127.0.0.1:8080")]
+    bind: SocketAddr,
+
+    /// Milliseconds between refreshes of prices and protocol state.
+    #[arg(long, default_value_t = 2_000)]
+    refresh_ms: u64,
+}
+
+#[derive(Args)]
+struct EventsArgs {
+    /// Websocket endpoint to subscribe to program logs on.
+    #[arg(long, env = "WS_URL", default_value = "ws://127.0.0.1:8900")]
+    ws_url: String,
+
+    /// Read transactions from a file written by --record instead of
+    /// subscribing.
+    #[arg(long, conflicts_with = "record")]
+    replay: Option<PathBuf>,
+
+    /// Append every transaction's logs to this file for later replay.
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Print events as JSON lines; the default when no other sink is given.
+    #[arg(long)]
+    stdout: bool,
+
+    /// Store events in this SQLite database.
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    /// POST each event as JSON to this URL; may be repeated.
+    #[arg(long)]
+    webhook: Vec<String>,
+}
+
+#[derive(Args)]
+struct IndexArgs {
+    /// JSON RPC endpoint; defaults to a local solana-test-validator.
+    #[arg(long, env = "RPC_URL", default_value = "http://127.0.0.1:8899")]
+    rpc_url: String,
+
+    /// Websocket endpoint for account subscriptions.
+    #[arg(long, env = "WS_URL", default_value = "ws://127.0.0.1:8900")]
+    ws_url: String,
+
+    /// SQLite database file, created if missing.
+    #[arg(long, env = "INDEX_DB", default_value = "liquidations.db")]
+    db: PathBuf,
+}
+
+#[derive(Args)]
+struct KeeperArgs {
+    /// JSON RPC endpoint; defaults to a local solana-test-validator.
+    #[arg(long, env = "RPC_URL", default_value = "http://127.0.0.1:8899")]
+    rpc_url: String,
+
+    /// Keypair that signs and pays for liquidations.
+    #[arg(long, env = "KEEPER_KEYPAIR")]
+    keypair: PathBuf,
+
+    /// Quote token account that receives rewards [default: the keypair's ATA]
+    #[arg(long)]
+    liquidator_token_account: Option<Pubkey>,
+
+    /// Milliseconds between passes.
+    #[arg(long, default_value_t = 2_000)]
+    interval_ms: u64,
+
+    /// Run a single pass and exit.
+    #[arg(long)]
+    once: bool,
+
+    /// Run the indexer alongside and read positions from this SQLite
+    /// database instead of scanning every pass.
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    /// Websocket endpoint for the indexer.
+    #[arg(long, env = "WS_URL", default_value = "ws://127.0.0.1:8900")]
+    ws_url: String,
+
+    /// Serve Prometheus metrics on `/metrics` at this address.
+    #[arg(long)]
+    metrics_bind: Option<SocketAddr>,
+
+    /// Simulate liquidations instead of sending them, log the predicted
+    /// reward, bad debt and compute units, and compare them with what other
+    /// keepers land.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+
+    match Cli::parse().command {
+        Command::Keeper(args) => run_keeper(args).await,
+        Command::Index(args) => run_indexer(args).await,
+        Command::Events(args) => run_events(args).await,
+        Command::Serve(args) => run_server(args).await,
+        Command::Backtest(args) => run_backtest(args),
+        Command::Simulate(args) => run_simulation(args),
+    }
+}
+
+async fn run_server(args: ServeArgs) -> Result<()> {
+    let indexer = Indexer {
+        client: rpc_client(args.index.rpc_url.clone()),
+        ws_url: args.index.ws_url,
+        store: Arc::new(Mutex::new(Store::open(&args.index.db)?)),
+    };
+    indexer.bootstrap().await?;
+    let store = indexer.store.clone();
+    tokio::spawn(async move { indexer.run().await });
+
+    let snapshot = SharedSnapshot::default();
+    tokio::spawn(api::refresh_snapshots(
+        rpc_client(args.index.rpc_url),
+        store.clone(),
+        snapshot.clone(),
+        Duration::from_millis(args.refresh_ms),
+    ));
+
+    let listener = tokio::net::TcpListener::bind(args.bind).await?;
+    tracing::info!(bind = %args.bind, "serving");
+    axum::serve(listener, api::router(ApiState { store, snapshot }))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
+}
+
+async fn run_events(args: EventsArgs) -> Result<()> {
+    let mut pipeline = EventPipeline::default();
+    if let Some(path) = &args.db {
+        pipeline = pipeline.with(SqliteSink { store: Arc::new(Mutex::new(Store::open(path)?)) });
+    }
+    for url in &args.webhook {
+        pipeline = pipeline.with(WebhookSink::new(url)?);
+    }
+    if args.stdout || pipeline.is_empty() {
+        pipeline = pipeline.with(StdoutSink);
+    }
+
+    match &args.replay {
+        Some(path) => {
+            let count = events::replay(path, &mut pipeline).await?;
+            tracing::info!(count, "replay complete");
+            Ok(())
+        }
+        None => events::follow_logs(&args.ws_url, &mut pipeline, args.record.as_deref()).await,
+    }
+}
+
+fn rpc_client(url: String) -> RpcClient {
+    RpcClient::new_with_commitment(url, CommitmentConfig::confirmed())
+}
+
+async fn run_indexer(args: IndexArgs) -> Result<()> {
+    let indexer = Indexer {
+        client: rpc_client(args.rpc_url),
+        ws_url: args.ws_url,
+        store: Arc::new(Mutex::new(Store::open(&args.db)?)),
+    };
+    indexer.run().await
+}
+
+async fn run_keeper(args: KeeperArgs) -> Result<()> {
+    let payer = read_keypair_file(&args.keypair)
+        .map_err(|err| anyhow!("reading {}: {err}", args.keypair.display()))?;
+
+    let store = match &args.db {
+        Some(path) => {
+            let indexer = Indexer {
+                client: rpc_client(args.rpc_url.clone()),
+                ws_url: args.ws_url.clone(),
+                store: Arc::new(Mutex::new(Store::open(path)?)),
+            };
+            // a full snapshot before the first pass, then follow in the background
+            indexer.bootstrap().await?;
+            let store = indexer.store.clone();
+            tokio::spawn(async move { indexer.run().await });
+            Some(store)
+        }
+        None => None,
+    };
+
+    let metrics = Arc::new(Metrics::new()?);
+    if let Some(bind) = args.metrics_bind {
+        let listener = tokio::net::TcpListener::bind(bind).await?;
+        tracing::info!(%bind, "serving metrics");
+        let router = metrics::router(metrics.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+    }
+
+    let keeper = Keeper {
+        client: rpc_client(args.rpc_url),
+        payer,
+        liquidator_token_account: args.liquidator_token_account,
+        store,
+        queue: Mutex::default(),
+        metrics: metrics.clone(),
+        first_seen: Mutex::default(),
+        shadow: args.dry_run.then(Mutex::default),
+    };
+
+    if args.once {
+        let summary = keeper.run_once().await?;
+        tracing::info!(?summary, "pass complete");
+        return Ok(());
+    }
+    keeper.run(Duration::from_millis(args.interval_ms)).await
+}
+
+fn run_backtest(args: BacktestArgs) -> Result<()> {
+    let book = Book::from_store(&Store::open(&args.db)?, args.insurance)?;
+    let prices = backtest::read_prices(&args.prices)?;
+    let sets = match &args.params {
+        Some(path) => backtest::read_params(path)?,
+        None => vec![ParamSet::current()],
+    };
+    tracing::info!(positions = book.positions(), prices = prices.len(), sets = sets.len(), "backtesting");
+
+    let reports: Vec<_> = sets.iter().map(|set| backtest::run(&book, &prices, set)).collect();
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        println!("{}", backtest::table(&reports));
+    }
+    Ok(())
+}
+
+fn run_simulation(args: SimulateArgs) -> Result<()> {
+    let book = Book::from_store(&Store::open(&args.db)?, None)?;
+    let mut config = match &args.config {
+        Some(path) => SimConfig::read(path)?,
+        None => SimConfig::default(),
+    };
+    config.paths = args.paths.unwrap_or(config.paths);
+    config.seed = args.seed.unwrap_or(config.seed);
+    if !args.fund.is_empty() {
+        config.fund_sizes = args.fund;
+    }
+    tracing::info!(positions = book.positions(), paths = config.paths, steps = config.steps, "simulating");
+
+    let report = simulate::simulate(&book, &config)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{}", simulate::table(&report));
+    }
+    Ok(())
 }
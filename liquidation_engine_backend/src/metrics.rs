@@ -0,0 +1,323 @@
+//! Prometheus metrics for the keeper and the protocol state it reads,
+//! served as text on `/metrics`.
+
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{
+    histogram_opts, opts, Encoder, Histogram, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Registry, TextEncoder,
+};
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::rpc_request::{RpcError, RpcResponseErrorData};
+use solana_client::rpc_response::RpcSimulateTransactionResult;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::transaction::TransactionError;
+
+use crate::keeper::{Liquidation, Oracles, PassSummary};
+use crate::scan::ProtocolState;
+use crate::shadow::{Comparison, Predicted, Prediction};
+use crate::store::symbol;
+
+pub struct Metrics {
+    registry: Registry,
+    pub passes: IntCounter,
+    pub pass_failures: IntCounter,
+    pub positions_scanned: IntGauge,
+    pub positions_crossed: IntGauge,
+    pub positions_unhealthy: IntGauge,
+    pub liquidations_attempted: IntCounterVec,
+    pub liquidations_succeeded: IntCounterVec,
+    pub liquidations_failed: IntCounterVec,
+    pub liquidation_latency: Histogram,
+    pub insurance_vault_balance: IntGauge,
+    pub insurance_fund_balance: IntGauge,
+    pub insurance_bad_debt_covered: IntGauge,
+    pub bad_debt: IntGauge,
+    pub oracle_staleness: IntGaugeVec,
+    pub shadow_predictions: IntCounterVec,
+    pub shadow_compute_units: Histogram,
+    pub shadow_comparisons: IntCounterVec,
+    pub shadow_reward_error: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("liquidation".into()), None)?;
+
+        let passes = IntCounter::new("keeper_passes_total", "Keeper passes run")?;
+        let pass_failures = IntCounter::new("keeper_pass_failures_total", "Keeper passes that failed before liquidating")?;
+        let positions_scanned = IntGauge::new("keeper_positions_scanned", "Open positions in the last pass")?;
+        let positions_crossed =
+            IntGauge::new("keeper_positions_crossed", "Positions whose liquidation trigger the last pass's prices crossed")?;
+        let positions_unhealthy = IntGauge::new("keeper_positions_unhealthy", "Positions found liquidatable in the last pass")?;
+        let liquidations_attempted = IntCounterVec::new(
+            opts!("keeper_liquidations_attempted_total", "Liquidation transactions sent"),
+            &["kind"],
+        )?;
+        let liquidations_succeeded = IntCounterVec::new(
+            opts!("keeper_liquidations_succeeded_total", "Liquidation transactions confirmed"),
+            &["kind"],
+        )?;
+        let liquidations_failed = IntCounterVec::new(
+            opts!("keeper_liquidations_failed_total", "Liquidation transactions that failed, by program error"),
+            &["kind", "error"],
+        )?;
+        let liquidation_latency = Histogram::with_opts(histogram_opts!(
+            "keeper_liquidation_latency_seconds",
+            "Time from a position first found unhealthy to its liquidation confirming",
+            vec![0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 300.0]
+        ))?;
+        let insurance_vault_balance =
+            IntGauge::new("insurance_vault_balance", "Quote held by the insurance vault token account")?;
+        let insurance_fund_balance = IntGauge::new("insurance_fund_balance", "InsuranceFund.balance as booked by the program")?;
+        let insurance_bad_debt_covered =
+            IntGauge::new("insurance_bad_debt_covered", "InsuranceFund.total_bad_debt_covered")?;
+        let bad_debt = IntGauge::new("bad_debt_total", "Sum of bad_debt over indexed LiquidationRecords")?;
+        let oracle_staleness = IntGaugeVec::new(
+            opts!("oracle_staleness_seconds", "Seconds since each market's oracle last published"),
+            &["market"],
+        )?;
+        let shadow_predictions = IntCounterVec::new(
+            opts!("keeper_shadow_predictions_total", "Liquidations simulated in dry-run mode, by predicted outcome"),
+            &["kind", "outcome"],
+        )?;
+        let shadow_compute_units = Histogram::with_opts(histogram_opts!(
+            "keeper_shadow_compute_units",
+            "Compute units consumed by simulated liquidations",
+            vec![25_000.0, 50_000.0, 100_000.0, 150_000.0, 200_000.0, 400_000.0, 1_400_000.0]
+        ))?;
+        let shadow_comparisons = IntCounterVec::new(
+            opts!(
+                "keeper_shadow_comparisons_total",
+                "Dry-run predictions checked against what other keepers landed, by result"
+            ),
+            &["result"],
+        )?;
+        let shadow_reward_error = IntGauge::new(
+            "keeper_shadow_reward_error",
+            "Landed minus predicted liquidator reward of the last compared liquidation",
+        )?;
+
+        registry.register(Box::new(passes.clone()))?;
+        registry.register(Box::new(pass_failures.clone()))?;
+        registry.register(Box::new(positions_scanned.clone()))?;
+        registry.register(Box::new(positions_crossed.clone()))?;
+        registry.register(Box::new(positions_unhealthy.clone()))?;
+        registry.register(Box::new(liquidations_attempted.clone()))?;
+        registry.register(Box::new(liquidations_succeeded.clone()))?;
+        registry.register(Box::new(liquidations_failed.clone()))?;
+        registry.register(Box::new(liquidation_latency.clone()))?;
+        registry.register(Box::new(insurance_vault_balance.clone()))?;
+        registry.register(Box::new(insurance_fund_balance.clone()))?;
+        registry.register(Box::new(insurance_bad_debt_covered.clone()))?;
+        registry.register(Box::new(bad_debt.clone()))?;
+        registry.register(Box::new(oracle_staleness.clone()))?;
+        registry.register(Box::new(shadow_predictions.clone()))?;
+        registry.register(Box::new(shadow_compute_units.clone()))?;
+        registry.register(Box::new(shadow_comparisons.clone()))?;
+        registry.register(Box::new(shadow_reward_error.clone()))?;
+
+        Ok(Self {
+            registry,
+            passes,
+            pass_failures,
+            positions_scanned,
+            positions_crossed,
+            positions_unhealthy,
+            liquidations_attempted,
+            liquidations_succeeded,
+            liquidations_failed,
+            liquidation_latency,
+            insurance_vault_balance,
+            insurance_fund_balance,
+            insurance_bad_debt_covered,
+            bad_debt,
+            oracle_staleness,
+            shadow_predictions,
+            shadow_compute_units,
+            shadow_comparisons,
+            shadow_reward_error,
+        })
+    }
+
+    /// Protocol-side gauges from a pass's snapshot.
+    pub fn observe_state(&self, state: &ProtocolState, oracles: &Oracles, now: i64) {
+        self.insurance_vault_balance.set(gauge(state.insurance_vault_balance));
+        self.insurance_fund_balance.set(gauge(state.insurance.balance));
+        self.insurance_bad_debt_covered.set(gauge(state.insurance.total_bad_debt_covered));
+
+        self.oracle_staleness.reset();
+        for market in state.markets.values() {
+            if let Some(published) = oracles.publish_times.get(&market.oracle) {
+                self.oracle_staleness.with_label_values(&[&symbol(&market.symbol)]).set(now - published);
+            }
+        }
+    }
+
+    pub fn observe_pass(&self, summary: &PassSummary) {
+        self.passes.inc();
+        self.positions_scanned.set(summary.scanned as i64);
+        self.positions_crossed.set(summary.crossed as i64);
+        self.positions_unhealthy.set(summary.unhealthy as i64);
+    }
+
+    pub fn attempted(&self, kind: Liquidation) {
+        self.liquidations_attempted.with_label_values(&[kind_label(kind)]).inc();
+    }
+
+    pub fn succeeded(&self, kind: Liquidation, latency_secs: f64) {
+        self.liquidations_succeeded.with_label_values(&[kind_label(kind)]).inc();
+        self.liquidation_latency.observe(latency_secs);
+    }
+
+    pub fn failed(&self, kind: Liquidation, err: &anyhow::Error) {
+        let error = err.downcast_ref::<ClientError>().map_or_else(|| "keeper".into(), error_label);
+        self.liquidations_failed.with_label_values(&[kind_label(kind), &error]).inc();
+    }
+
+    pub fn predicted(&self, prediction: &Prediction) {
+        let outcome = match &prediction.predicted {
+            Predicted::Liquidated { .. } => "liquidated",
+            Predicted::Healthy => "healthy",
+            Predicted::Failed(error) => error,
+        };
+        self.shadow_predictions.with_label_values(&[kind_label(prediction.kind), outcome]).inc();
+        if let Some(units) = prediction.units_consumed {
+            self.shadow_compute_units.observe(units as f64);
+        }
+    }
+
+    pub fn compared(&self, comparison: &Comparison) {
+        let result = if comparison.matches() { "match" } else { "mismatch" };
+        self.shadow_comparisons.with_label_values(&[result]).inc();
+        self.shadow_reward_error.set(comparison.reward_error().clamp(i64::MIN.into(), i64::MAX.into()) as i64);
+    }
+
+    /// A dry-run prediction nothing landed for.
+    pub fn expired(&self) {
+        self.shadow_comparisons.with_label_values(&["expired"]).inc();
+    }
+
+    /// Everything in the text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // writing to a Vec can't fail, and every metric is well-formed
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+fn gauge(amount: u64) -> i64 {
+    i64::try_from(amount).unwrap_or(i64::MAX)
+}
+
+fn kind_label(kind: Liquidation) -> &'static str {
+    match kind {
+        Liquidation::Partial => "partial",
+        Liquidation::Full => "full",
+    }
+}
+
+/// The program's `ErrorCode` name for a failed liquidation, from the
+/// `Error Code: <name>.` line Anchor logs, or the transaction error when
+/// there is none.
+pub fn error_label(err: &ClientError) -> String {
+    if let ClientErrorKind::RpcError(RpcError::RpcResponseError {
+        data: RpcResponseErrorData::SendTransactionPreflightFailure(simulation),
+        ..
+    }) = err.kind()
+    {
+        if let Some(code) = logged_error_code(simulation) {
+            return code.to_string();
+        }
+    }
+
+    match err.get_transaction_error() {
+        Some(error) => transaction_error_label(&error),
+        None => "rpc".into(),
+    }
+}
+
+/// `error_label` for a `simulateTransaction` result; `None` if it succeeded.
+pub fn simulation_error_label(simulation: &RpcSimulateTransactionResult) -> Option<String> {
+    let error = simulation.err.as_ref()?;
+    Some(logged_error_code(simulation).map_or_else(|| transaction_error_label(error), String::from))
+}
+
+fn logged_error_code(simulation: &RpcSimulateTransactionResult) -> Option<&str> {
+    simulation.logs.iter().flatten().find_map(|line| {
+        let (_, rest) = line.split_once("Error Code: ")?;
+        rest.split('.').next()
+    })
+}
+
+fn transaction_error_label(error: &TransactionError) -> String {
+    match error {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => format!("Custom({code})"),
+        TransactionError::InstructionError(_, error) => variant(&format!("{error:?}")),
+        error => variant(&format!("{error:?}")),
+    }
+}
+
+/// `Name` of a `Name(..)` or `Name { .. }` debug string.
+fn variant(debug: &str) -> String {
+    debug.split(['(', ' ', '{']).next().unwrap_or(debug).to_string()
+}
+
+pub fn router(metrics: Arc<Metrics>) -> Router {
+    Router::new().route("/metrics", get(serve_metrics)).with_state(metrics)
+}
+
+async fn serve_metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], metrics.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preflight_failure(logs: Vec<&str>) -> ClientError {
+        ClientError::from(RpcError::RpcResponseError {
+            code: -32002,
+            message: "Transaction simulation failed".into(),
+            data: RpcResponseErrorData::SendTransactionPreflightFailure(RpcSimulateTransactionResult {
+                err: Some(TransactionError::InstructionError(0, InstructionError::Custom(6005))),
+                logs: Some(logs.into_iter().map(String::from).collect()),
+                accounts: None,
+                units_consumed: None,
+                return_data: None,
+            }),
+        })
+    }
+
+    #[test]
+    fn failures_are_labelled_by_error_code() {
+        let err = preflight_failure(vec![
+            "Program log: AnchorError occurred. Error Code: PositionHealthy. Error Number: 6005. Error Message: Position is healthy.",
+        ]);
+        assert_eq!(error_label(&err), "PositionHealthy");
+
+        assert_eq!(error_label(&preflight_failure(vec![])), "Custom(6005)");
+        let blockhash = ClientError::from(TransactionError::BlockhashNotFound);
+        assert_eq!(error_label(&blockhash), "BlockhashNotFound");
+    }
+
+    #[test]
+    fn renders_counters_by_label() {
+        let metrics = Metrics::new().unwrap();
+        metrics.attempted(Liquidation::Partial);
+        metrics.succeeded(Liquidation::Partial, 1.5);
+        metrics.failed(Liquidation::Full, &anyhow::anyhow!("position left the snapshot"));
+        metrics.observe_pass(&PassSummary { scanned: 10, crossed: 3, unhealthy: 2, liquidated: 1, simulated: 0, failed: 1 });
+
+        let text = metrics.render();
+        assert!(text.contains("liquidation_keeper_liquidations_attempted_total{kind=\"partial\"} 1"));
+        assert!(text.contains("liquidation_keeper_liquidations_failed_total{error=\"keeper\",kind=\"full\"} 1"));
+        assert!(text.contains("liquidation_keeper_positions_scanned 10"));
+        assert!(text.contains("liquidation_keeper_liquidation_latency_seconds_count 1"));
+    }
+}
@@ -0,0 +1,91 @@
+//! Oracle reads with the program's own checks, so the keeper only acts on
+//! prices a liquidation would accept.
+
+use anchor_lang::solana_program::account_info::IntoAccountInfo;
+use anyhow::{anyhow, Result};
+use liquidation_program::oracle::{validate_price, MockPriceSource, PriceSource, PythPriceSource};
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+
+/// An oracle account as read, before and after the program's checks.
+pub struct OracleRead {
+    /// Unix time the price was published, whether or not it is accepted.
+    pub publish_time: i64,
+    /// What `validate_price` makes of it.
+    pub price: Result<u64>,
+}
+
+/// Read `account` as `get_oracle_price` would at unix time `now`. Accounts
+/// owned by the program are read as `MockOracle`s, as in a `mock-oracle`
+/// build on a local validator. Fails only if it isn't an oracle at all.
+pub fn read_oracle(key: &Pubkey, account: &Account, now: i64) -> Result<OracleRead> {
+    let mut account = account.clone();
+    let is_mock = account.owner == liquidation_program::ID;
+    let info = (key, &mut account).into_account_info();
+
+    let read = if is_mock {
+        MockPriceSource::load(&info).map(|source| checked(&source, now))
+    } else {
+        PythPriceSource::load(&info).map(|source| checked(&source, now))
+    };
+    let (publish_time, price) = read.map_err(|err| anyhow!("oracle {key}: {err}"))?;
+    Ok(OracleRead { publish_time, price: price.map_err(|err| anyhow!("oracle {key}: {err}")) })
+}
+
+fn checked(source: &impl PriceSource, now: i64) -> (i64, anchor_lang::Result<u64>) {
+    (source.publish_time(), validate_price(source, now))
+}
+
+/// Price in PRICE_PRECISION that `get_oracle_price` would return for
+/// `account` at unix time `now`.
+pub fn read_price(key: &Pubkey, account: &Account, now: i64) -> Result<u64> {
+    read_oracle(key, account, now)?.price
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::AccountSerialize;
+    use liquidation_program::oracle::OracleStatus;
+    use liquidation_program::MockOracle;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn mock(price: i64, publish_time: i64) -> Account {
+        let oracle = MockOracle {
+            authority: Pubkey::new_unique(),
+            price,
+            conf: 0,
+            expo: -8,
+            publish_time,
+            status: OracleStatus::Trading,
+        };
+        let mut data = Vec::new();
+        oracle.try_serialize(&mut data).unwrap();
+        Account { lamports: 1, data, owner: liquidation_program::ID, executable: false, rent_epoch: 0 }
+    }
+
+    #[test]
+    fn mock_oracle_is_scaled_like_on_chain() {
+        let key = Pubkey::new_unique();
+        assert_eq!(read_price(&key, &mock(9_512_345_678, NOW), NOW).unwrap(), 95_123_457);
+    }
+
+    #[test]
+    fn stale_and_foreign_accounts_are_refused() {
+        let key = Pubkey::new_unique();
+        assert!(read_price(&key, &mock(9_500_000_000, NOW - 60), NOW).is_err());
+
+        // same bytes under another owner are read as Pyth, which they aren't
+        let foreign = Account { owner: Pubkey::new_unique(), ..mock(9_500_000_000, NOW) };
+        assert!(read_price(&key, &foreign, NOW).is_err());
+    }
+
+    #[test]
+    fn stale_prices_still_report_their_age() {
+        let key = Pubkey::new_unique();
+        let read = read_oracle(&key, &mock(9_500_000_000, NOW - 60), NOW).unwrap();
+        assert_eq!(read.publish_time, NOW - 60);
+        assert!(read.price.is_err());
+    }
+}
@@ -0,0 +1,360 @@
+//! Positions indexed by liquidation trigger, per market.
+//!
+//! Each position's trigger (`liquidation_math::liquidation_trigger`) is
+//! computed once from its terms, haircut and its market's tiers, and only
+//! recomputed when one of those changes: the keeper feeds in the positions
+//! the indexer saw change, and positions with non-quote collateral are
+//! re-keyed only when the price of an asset they hold moves. On a price the
+//! keeper then only evaluates the longs whose trigger is at or above it and
+//! the shorts whose trigger is at or below it; everything else is healthy by
+//! construction.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use liquidation_math::{liquidation_trigger, LiquidationTrigger, Price, QuoteAmount, Tier};
+use liquidation_program::constants::MAX_COLLATERAL_ASSETS;
+use liquidation_program::{Market, Position};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::keeper::{haircut, Prices};
+use crate::scan::ProtocolState;
+
+/// A position and what its trigger was computed from.
+#[derive(Clone)]
+struct Entry {
+    /// Latest copy, to evaluate and liquidate the position from.
+    position: Position,
+    /// `None` while a collateral price is missing; such positions are always
+    /// evaluated.
+    haircut: Option<QuoteAmount>,
+    trigger: LiquidationTrigger,
+}
+
+/// What a collateral asset's haircut depends on: oracle, decimals, weight,
+/// and the price it was last valued at.
+type AssetPricing = (Pubkey, u8, u64, Option<u64>);
+
+#[derive(Debug, Default)]
+struct MarketQueue {
+    tiers: Vec<Tier>,
+    /// Liquidatable at or below their trigger.
+    longs: BTreeSet<(Price, Pubkey)>,
+    /// Liquidatable at or above their trigger.
+    shorts: BTreeSet<(Price, Pubkey)>,
+}
+
+impl MarketQueue {
+    fn side(&mut self, is_long: bool) -> &mut BTreeSet<(Price, Pubkey)> {
+        if is_long {
+            &mut self.longs
+        } else {
+            &mut self.shorts
+        }
+    }
+
+    fn crossed(&self, price: Price) -> impl Iterator<Item = Pubkey> + '_ {
+        let longs = self.longs.range((price, Pubkey::default())..);
+        let shorts = self.shorts.range(..=(price, Pubkey::new_from_array([u8::MAX; 32])));
+        longs.chain(shorts).map(|(_, key)| *key)
+    }
+}
+
+#[derive(Default)]
+pub struct LiquidationQueue {
+    markets: HashMap<Pubkey, MarketQueue>,
+    entries: HashMap<Pubkey, Entry>,
+    /// Positions with a deposit of each collateral asset, by registry index.
+    holders: [HashSet<Pubkey>; MAX_COLLATERAL_ASSETS],
+    /// Each registry slot as the haircuts in the queue were computed.
+    assets: [Option<AssetPricing>; MAX_COLLATERAL_ASSETS],
+}
+
+impl LiquidationQueue {
+    /// Open positions in the queue, including those that can never be
+    /// liquidated.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn trigger(&self, position: &Pubkey) -> Option<LiquidationTrigger> {
+        self.entries.get(position).map(|entry| entry.trigger)
+    }
+
+    /// The latest copy of `position` the queue was given.
+    pub fn position(&self, position: &Pubkey) -> Option<&Position> {
+        self.entries.get(position).map(|entry| &entry.position)
+    }
+
+    /// Bring the queue in line with `state`, which holds every position:
+    /// markets and prices as in `set_markets` and `reprice`, positions as in
+    /// `update`, and positions no longer in `state` are dropped.
+    pub fn sync(&mut self, state: &ProtocolState, prices: &Prices) {
+        self.set_markets(&state.markets);
+        self.reprice(state, prices);
+
+        let live: HashSet<Pubkey> = state.positions.iter().map(|(key, _)| *key).collect();
+        let gone: Vec<Pubkey> = self.entries.keys().filter(|key| !live.contains(key)).copied().collect();
+        for key in gone {
+            self.remove(&key);
+        }
+
+        for (key, position) in &state.positions {
+            self.update(state, prices, *key, Some(position));
+        }
+    }
+
+    /// Follow `markets`: the positions of a market that is new or whose tiers
+    /// changed are re-keyed. Positions of a market missing from `markets`
+    /// are kept but never come out of `crossed`.
+    pub fn set_markets(&mut self, markets: &HashMap<Pubkey, Market>) {
+        let mut changed: HashSet<Pubkey> = self.markets.keys().filter(|key| !markets.contains_key(key)).copied().collect();
+        self.markets.retain(|key, _| markets.contains_key(key));
+
+        for (key, market) in markets {
+            let tiers = market_tiers(market);
+            if self.markets.get(key).is_none_or(|queue| queue.tiers != tiers) {
+                self.markets.insert(*key, MarketQueue { tiers, ..MarketQueue::default() });
+                changed.insert(*key);
+            }
+        }
+        if changed.is_empty() {
+            return;
+        }
+
+        for (key, entry) in &mut self.entries {
+            if changed.contains(&entry.position.market) {
+                entry.trigger = place(&mut self.markets, *key, &entry.position, entry.haircut);
+            }
+        }
+    }
+
+    /// Re-key the positions holding a collateral asset whose price or
+    /// registry entry changed since the last call. Everything else keeps the
+    /// haircut it has.
+    pub fn reprice(&mut self, state: &ProtocolState, prices: &Prices) {
+        let registry = &state.collateral_registry;
+        let assets = &registry.assets[..registry.num_assets as usize];
+
+        let mut moved = HashSet::new();
+        for (i, pricing) in self.assets.iter_mut().enumerate() {
+            let now = assets
+                .get(i)
+                .map(|asset| (asset.oracle, asset.decimals, asset.weight_bps, prices.get(&asset.oracle).copied()));
+            if *pricing != now {
+                *pricing = now;
+                moved.extend(self.holders[i].iter().copied());
+            }
+        }
+
+        for key in moved {
+            let Some(entry) = self.entries.get(&key) else { continue };
+            let haircut = haircut(state, prices, &entry.position);
+            if haircut != entry.haircut {
+                let position = entry.position.clone();
+                self.remove(&key);
+                self.insert(key, position, haircut);
+            }
+        }
+    }
+
+    /// Follow a change to position `key`: `None` once it is closed. Its
+    /// haircut and trigger are only recomputed if its market, terms or
+    /// deposits changed; otherwise only the copy is replaced.
+    pub fn update(&mut self, state: &ProtocolState, prices: &Prices, key: Pubkey, position: Option<&Position>) {
+        let Some(position) = position.filter(|position| position.size > 0) else {
+            self.remove(&key);
+            return;
+        };
+        if let Some(entry) = self.entries.get_mut(&key) {
+            let before = &entry.position;
+            if before.market == position.market && before.terms() == position.terms() && before.deposits == position.deposits {
+                entry.position = position.clone();
+                return;
+            }
+        }
+        self.remove(&key);
+        self.insert(key, position.clone(), haircut(state, prices, position));
+    }
+
+    fn insert(&mut self, key: Pubkey, position: Position, haircut: Option<QuoteAmount>) {
+        for (i, holders) in self.holders.iter_mut().enumerate() {
+            if position.deposits[i] > 0 {
+                holders.insert(key);
+            }
+        }
+        let trigger = place(&mut self.markets, key, &position, haircut);
+        self.entries.insert(key, Entry { position, haircut, trigger });
+    }
+
+    pub fn remove(&mut self, key: &Pubkey) {
+        let Some(entry) = self.entries.remove(key) else {
+            return;
+        };
+        for holders in &mut self.holders {
+            holders.remove(key);
+        }
+        if let (LiquidationTrigger::At(price), Some(queue)) = (entry.trigger, self.markets.get_mut(&entry.position.market)) {
+            queue.side(entry.position.is_long).remove(&(price, *key));
+        }
+    }
+
+    /// Positions in `market` that may be unhealthy at `price`.
+    pub fn crossed(&self, market: &Pubkey, price: Price) -> Vec<Pubkey> {
+        self.markets.get(market).map_or_else(Vec::new, |queue| queue.crossed(price).collect())
+    }
+
+    /// Positions that may be unhealthy at `prices`, across every market with
+    /// a price.
+    pub fn crossed_at(&self, state: &ProtocolState, prices: &Prices) -> HashSet<Pubkey> {
+        state
+            .markets
+            .iter()
+            .filter_map(|(key, market)| Some((key, Price(*prices.get(&market.oracle)?))))
+            .flat_map(|(key, price)| self.crossed(key, price))
+            .collect()
+    }
+}
+
+/// Compute `position`'s trigger and file it under its market, if the queue
+/// has that market; without it the position is never crossed. Whatever the
+/// position was filed under before must already be removed.
+fn place(
+    markets: &mut HashMap<Pubkey, MarketQueue>,
+    key: Pubkey,
+    position: &Position,
+    haircut: Option<QuoteAmount>,
+) -> LiquidationTrigger {
+    let Some(queue) = markets.get_mut(&position.market) else {
+        return LiquidationTrigger::Never;
+    };
+    let terms = position.terms();
+    let trigger = haircut
+        .and_then(|haircut| liquidation_trigger(&terms, haircut, &queue.tiers))
+        .unwrap_or(LiquidationTrigger::always(terms.is_long));
+    if let LiquidationTrigger::At(price) = trigger {
+        queue.side(terms.is_long).insert((price, key));
+    }
+    trigger
+}
+
+/// `market`'s maintenance tiers in `liquidation_math`'s form.
+pub fn market_tiers(market: &Market) -> Vec<Tier> {
+    market.risk_tiers[..market.num_risk_tiers as usize]
+        .iter()
+        .map(|tier| Tier { max_notional: tier.max_notional, maintenance_margin_bps: tier.maintenance_margin_bps })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keeper::{evaluate, tests::*};
+    use liquidation_math::PRICE_PRECISION;
+    use liquidation_program::risk_tiers::RiskTier;
+
+    fn short() -> Position {
+        Position { is_long: false, ..long() }
+    }
+
+    #[test]
+    fn only_crossed_positions_come_out() {
+        let (l, s) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let state = state(vec![(l, long()), (s, short())]);
+        let mut queue = LiquidationQueue::default();
+        queue.sync(&state, &prices(price(100)));
+        assert_eq!(queue.len(), 2);
+
+        let at = |p: u64| queue.crossed_at(&state, &prices(p));
+        assert!(at(price(100)).is_empty());
+        assert_eq!(at(price(95)), HashSet::from([l]));
+        assert_eq!(at(price(105)), HashSet::from([s]));
+
+        // whatever the queue leaves out really is healthy
+        for p in (180..=220).map(|half| half * PRICE_PRECISION / 2) {
+            for (key, position) in &state.positions {
+                if evaluate(&state, &prices(p), 0, *key, position).is_some() {
+                    assert!(at(p).contains(key), "{key} unhealthy at {p} but not crossed");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rekeys_on_changes_and_drops_closed_positions() {
+        let key = Pubkey::new_unique();
+        let mut queue = LiquidationQueue::default();
+        queue.sync(&state(vec![(key, long())]), &prices(price(100)));
+        let before = queue.trigger(&key).unwrap();
+
+        let topped_up = Position { collateral: 200_000_000, ..long() };
+        queue.sync(&state(vec![(key, topped_up.clone())]), &prices(price(100)));
+        assert!(queue.trigger(&key).unwrap() < before);
+
+        // a stricter tier re-keys the whole market
+        let mut stricter = state(vec![(key, topped_up)]);
+        let market = stricter.markets.values_mut().next().unwrap();
+        market.risk_tiers[0] = RiskTier { max_notional: u64::MAX, maintenance_margin_bps: 5_000 };
+        market.num_risk_tiers = 1;
+        let lenient = queue.trigger(&key).unwrap();
+        queue.sync(&stricter, &prices(price(100)));
+        assert!(queue.trigger(&key).unwrap() > lenient);
+
+        queue.sync(&state(vec![]), &prices(price(100)));
+        assert!(queue.is_empty());
+        assert!(queue.crossed_at(&stricter, &prices(0)).is_empty());
+    }
+
+    #[test]
+    fn follows_changes_without_recomputing_the_rest() {
+        let (key, backed) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut with_deposit = long();
+        with_deposit.deposits[0] = 20_000_000;
+        let state = state(vec![(key, long()), (backed, with_deposit.clone())]);
+        let mut queue = LiquidationQueue::default();
+        queue.sync(&state, &prices(price(100)));
+        let (plain, held) = (queue.trigger(&key).unwrap(), queue.trigger(&backed).unwrap());
+
+        // a change that leaves the terms alone only replaces the copy, even
+        // with prices that would give a different haircut
+        let liquidated_once = Position { liquidation_count: 1, ..with_deposit.clone() };
+        queue.update(&state, &HashMap::new(), backed, Some(&liquidated_once));
+        assert_eq!(queue.trigger(&backed), Some(held));
+        assert_eq!(queue.position(&backed).unwrap().liquidation_count, 1);
+
+        // the market price moving re-keys nothing; the asset price moving
+        // re-keys only the position holding it
+        queue.reprice(&state, &prices(price(90)));
+        assert_eq!(queue.trigger(&backed), Some(held));
+        let mut cheaper = prices(price(100));
+        cheaper.insert(ASSET_ORACLE, price(1) / 2);
+        queue.reprice(&state, &cheaper);
+        assert!(queue.trigger(&backed).unwrap() > held);
+        assert_eq!(queue.trigger(&key), Some(plain));
+
+        // a closed position drops out
+        queue.update(&state, &cheaper, key, None);
+        assert_eq!(queue.trigger(&key), None);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn missing_collateral_price_means_always_evaluate() {
+        let key = Pubkey::new_unique();
+        let mut position = long();
+        position.deposits[0] = 1_000_000;
+        let state = state(vec![(key, position)]);
+
+        let mut queue = LiquidationQueue::default();
+        let mut no_asset = prices(price(100));
+        no_asset.retain(|oracle, _| *oracle == ORACLE);
+        queue.sync(&state, &no_asset);
+        assert_eq!(queue.trigger(&key), Some(LiquidationTrigger::always(true)));
+
+        queue.sync(&state, &prices(price(100)));
+        assert!(queue.trigger(&key) < Some(LiquidationTrigger::always(true)));
+    }
+}
@@ -0,0 +1,195 @@
+//! Loading the program's accounts over RPC.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anchor_lang::{AccountDeserialize, Discriminator};
+use anchor_spl::token_interface::TokenAccount;
+use anyhow::{anyhow, Context, Result};
+use liquidation_client::instructions::Protocol;
+use liquidation_client::{accounts, pda};
+use liquidation_program::{CollateralRegistry, InsuranceFund, Market, Position};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+use tracing::warn;
+
+use crate::store::{Indexed, Store};
+
+/// Matches accounts whose first 8 bytes are `T`'s Anchor discriminator.
+pub fn discriminator_filter<T: Discriminator>() -> RpcFilterType {
+    RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, &T::discriminator()))
+}
+
+/// Decode an Anchor account, checking its discriminator.
+pub fn decode<T: AccountDeserialize>(data: &[u8]) -> Result<T> {
+    accounts::decode(data).map_err(|err| anyhow!("{err}"))
+}
+
+/// `getProgramAccounts` config for every account of type `T`, base64 at the
+/// client's commitment.
+pub fn program_accounts_config<T: Discriminator>(client: &RpcClient) -> RpcProgramAccountsConfig {
+    RpcProgramAccountsConfig {
+        filters: Some(vec![discriminator_filter::<T>()]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(client.commitment()),
+            ..RpcAccountInfoConfig::default()
+        },
+        with_context: None,
+    }
+}
+
+/// Every program account of type `T`, undecoded.
+pub async fn raw_program_accounts<T: Discriminator>(client: &RpcClient) -> Result<Vec<(Pubkey, Account)>> {
+    client
+        .get_program_accounts_with_config(&liquidation_program::ID, program_accounts_config::<T>(client))
+        .await
+        .context("getProgramAccounts")
+}
+
+/// Every program account of type `T`. Accounts that carry the discriminator
+/// but don't decode (an old layout, say) are skipped with a warning.
+pub async fn program_accounts<T>(client: &RpcClient) -> Result<Vec<(Pubkey, T)>>
+where
+    T: AccountDeserialize + Discriminator,
+{
+    let accounts = raw_program_accounts::<T>(client).await?;
+
+    Ok(accounts
+        .into_iter()
+        .filter_map(|(key, account)| match decode::<T>(&account.data) {
+            Ok(decoded) => Some((key, decoded)),
+            Err(err) => {
+                warn!(%key, "skipping undecodable account: {err}");
+                None
+            }
+        })
+        .collect())
+}
+
+/// Everything a liquidation pass reads, fetched in one go.
+pub struct ProtocolState {
+    pub markets: HashMap<Pubkey, Market>,
+    pub positions: Vec<(Pubkey, Position)>,
+    pub collateral_registry: CollateralRegistry,
+    pub insurance_fund: Pubkey,
+    pub insurance: InsuranceFund,
+    /// Quote actually held by the insurance vault, which is what bad debt is
+    /// covered from.
+    pub insurance_vault_balance: u64,
+    pub quote_mint: Pubkey,
+    /// Owner of the quote mint: SPL Token or Token-2022.
+    pub token_program: Pubkey,
+}
+
+impl ProtocolState {
+    pub async fn load(client: &RpcClient) -> Result<Self> {
+        let markets = program_accounts::<Market>(client).await?.into_iter().collect();
+        let positions = program_accounts::<Position>(client).await?;
+        let funds = program_accounts::<InsuranceFund>(client).await?;
+        Self::with_accounts(client, markets, positions, funds).await
+    }
+
+    /// Same as `load`, with markets, positions and insurance funds read from
+    /// the indexer's store instead of scanned.
+    pub async fn from_store(client: &RpcClient, store: &Mutex<Store>) -> Result<Self> {
+        let positions = {
+            let store = store.lock().map_err(|_| anyhow!("store lock poisoned"))?;
+            store.open_positions()?
+        };
+        let mut state = Self::from_store_without_positions(client, store).await?;
+        state.positions = positions.into_iter().map(|indexed| (indexed.address, indexed.account)).collect();
+        Ok(state)
+    }
+
+    /// `from_store` with `positions` left empty, for a caller that follows
+    /// them through `Store::take_changed`.
+    pub async fn from_store_without_positions(client: &RpcClient, store: &Mutex<Store>) -> Result<Self> {
+        let (markets, funds) = {
+            let store = store.lock().map_err(|_| anyhow!("store lock poisoned"))?;
+            (store.markets()?, store.insurance_funds()?)
+        };
+        fn pair<T>(indexed: Indexed<T>) -> (Pubkey, T) {
+            (indexed.address, indexed.account)
+        }
+        Self::with_accounts(
+            client,
+            markets.into_iter().map(pair).collect(),
+            Vec::new(),
+            funds.into_iter().map(pair).collect(),
+        )
+        .await
+    }
+
+    async fn with_accounts(
+        client: &RpcClient,
+        markets: HashMap<Pubkey, Market>,
+        positions: Vec<(Pubkey, Position)>,
+        funds: Vec<(Pubkey, InsuranceFund)>,
+    ) -> Result<Self> {
+        let insurance_vault = pda::insurance_vault();
+        let (insurance_fund, insurance) = funds
+            .into_iter()
+            .find(|(_, fund)| fund.insurance_vault == insurance_vault)
+            .ok_or(anyhow!("no InsuranceFund for vault {insurance_vault}"))?;
+
+        let keys = [pda::collateral_registry(), insurance_vault, pda::protocol_vault()];
+        let [registry, insurance_vault, protocol_vault] = fetch_all(client, &keys).await?;
+        let insurance_vault = decode_token_account(&insurance_vault)?;
+        let protocol_vault = decode_token_account(&protocol_vault)?;
+
+        let quote_mint = protocol_vault.mint;
+        let [mint] = fetch_all(client, &[quote_mint]).await?;
+
+        Ok(Self {
+            markets,
+            positions,
+            collateral_registry: decode(&registry.data).context("collateral registry")?,
+            insurance_fund,
+            insurance,
+            insurance_vault_balance: insurance_vault.amount,
+            quote_mint,
+            token_program: mint.owner,
+        })
+    }
+
+    /// Oracles the pass needs prices for: every market's and every
+    /// collateral asset's.
+    pub fn oracles(&self) -> Vec<Pubkey> {
+        let registry = &self.collateral_registry;
+        let mut oracles: Vec<Pubkey> = self
+            .markets
+            .values()
+            .map(|market| market.oracle)
+            .chain(registry.assets[..registry.num_assets as usize].iter().map(|asset| asset.oracle))
+            .collect();
+        oracles.sort();
+        oracles.dedup();
+        oracles
+    }
+
+    /// The accounts every liquidation shares.
+    pub fn protocol(&self) -> Protocol {
+        Protocol { insurance_fund: self.insurance_fund, quote_mint: self.quote_mint, token_program: self.token_program }
+    }
+}
+
+/// Fetch accounts that must all exist.
+pub async fn fetch_all<const N: usize>(client: &RpcClient, keys: &[Pubkey; N]) -> Result<[Account; N]> {
+    let accounts = client.get_multiple_accounts(keys).await.context("getMultipleAccounts")?;
+    let accounts: Vec<Account> = accounts
+        .into_iter()
+        .zip(keys)
+        .map(|(account, key)| account.ok_or(anyhow!("account {key} not found")))
+        .collect::<Result<_>>()?;
+
+    accounts.try_into().map_err(|_| anyhow!("RPC returned the wrong number of accounts"))
+}
+
+fn decode_token_account(account: &Account) -> Result<TokenAccount> {
+    TokenAccount::try_deserialize(&mut &account.data[..]).map_err(|err| anyhow!("token account: {err}"))
+}
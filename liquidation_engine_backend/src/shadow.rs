@@ -0,0 +1,295 @@
+//! Shadow mode: the keeper scans and decides as usual, but simulates each
+//! liquidation instead of sending it, then checks its predictions against
+//! the liquidations other keepers land.
+//!
+//! A liquidation creates its `LiquidationRecord` at
+//! `[LIQ_RECORD_SEED, position, liquidation_count]`, so predictions are
+//! kept by that address: an account appearing there is the record of the
+//! very liquidation that was predicted.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use liquidation_program::state::LiquidationEvent;
+use liquidation_program::LiquidationRecord;
+use solana_client::rpc_response::RpcSimulateTransactionResult;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::events::{Event, LoggedTransaction};
+use crate::keeper::Liquidation;
+use crate::metrics::simulation_error_label;
+
+/// How long a prediction waits for another keeper to land it.
+pub const PREDICTION_TTL: Duration = Duration::from_secs(600);
+
+/// What a liquidation did, in the fields its event and its record share.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Outcome {
+    pub liquidated_size: u64,
+    pub liquidation_price: u64,
+    pub liquidator_reward: u64,
+    pub bad_debt: u64,
+}
+
+impl From<&LiquidationEvent> for Outcome {
+    fn from(event: &LiquidationEvent) -> Self {
+        Self {
+            liquidated_size: event.liquidated_size,
+            liquidation_price: event.liquidation_price,
+            liquidator_reward: event.liquidator_reward,
+            bad_debt: event.bad_debt,
+        }
+    }
+}
+
+impl From<&LiquidationRecord> for Outcome {
+    fn from(record: &LiquidationRecord) -> Self {
+        Self {
+            liquidated_size: record.liquidated_size,
+            liquidation_price: record.liquidation_price,
+            liquidator_reward: record.liquidator_reward,
+            bad_debt: record.bad_debt,
+        }
+    }
+}
+
+/// What the simulation says the liquidation would do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Predicted {
+    Liquidated { outcome: Outcome, insurance_covered: u64 },
+    /// Succeeded without liquidating: the program found the position
+    /// healthy at its own price.
+    Healthy,
+    /// Failed, with the program's error code.
+    Failed(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Prediction {
+    pub position: Pubkey,
+    /// The `LiquidationRecord` the liquidation would create.
+    pub record: Pubkey,
+    pub kind: Liquidation,
+    /// Slot the simulation ran at.
+    pub slot: u64,
+    pub units_consumed: Option<u64>,
+    pub predicted: Predicted,
+}
+
+impl Prediction {
+    pub fn from_simulation(
+        position: Pubkey,
+        record: Pubkey,
+        kind: Liquidation,
+        slot: u64,
+        simulation: &RpcSimulateTransactionResult,
+    ) -> Self {
+        let predicted = match simulation_error_label(simulation) {
+            Some(error) => Predicted::Failed(error),
+            None => {
+                let logged = LoggedTransaction {
+                    signature: String::new(),
+                    slot,
+                    logs: simulation.logs.clone().unwrap_or_default(),
+                };
+                logged
+                    .events()
+                    .into_iter()
+                    .find_map(|decoded| match decoded.event {
+                        Event::Liquidation(event) => Some(Predicted::Liquidated {
+                            outcome: Outcome::from(&event),
+                            insurance_covered: event.insurance_covered,
+                        }),
+                        Event::ProtocolInsolvency(_) => None,
+                    })
+                    .unwrap_or(Predicted::Healthy)
+            }
+        };
+        Self { position, record, kind, slot, units_consumed: simulation.units_consumed, predicted }
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        match self.predicted {
+            Predicted::Liquidated { outcome, .. } => Some(outcome),
+            Predicted::Healthy | Predicted::Failed(_) => None,
+        }
+    }
+}
+
+/// A prediction next to the liquidation another keeper landed for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Comparison {
+    pub prediction: Prediction,
+    pub landed: Outcome,
+    pub liquidator: Pubkey,
+}
+
+impl Comparison {
+    pub fn new(prediction: Prediction, landed: &LiquidationRecord) -> Self {
+        Self { prediction, landed: Outcome::from(landed), liquidator: landed.liquidator }
+    }
+
+    /// Whether the simulation predicted exactly what landed. Prices and
+    /// the insurance balance move between the two, so a mismatch is not
+    /// necessarily a bug; the differences say how far off it was.
+    pub fn matches(&self) -> bool {
+        self.prediction.outcome() == Some(self.landed)
+    }
+
+    /// Landed minus predicted reward, or the whole landed reward if no
+    /// liquidation was predicted.
+    pub fn reward_error(&self) -> i128 {
+        let predicted = self.prediction.outcome().unwrap_or_default();
+        i128::from(self.landed.liquidator_reward) - i128::from(predicted.liquidator_reward)
+    }
+
+    /// Landed minus predicted bad debt, as `reward_error`.
+    pub fn bad_debt_error(&self) -> i128 {
+        let predicted = self.prediction.outcome().unwrap_or_default();
+        i128::from(self.landed.bad_debt) - i128::from(predicted.bad_debt)
+    }
+}
+
+/// Predictions waiting for a liquidation to land, by record address.
+#[derive(Default)]
+pub struct Shadow {
+    pending: HashMap<Pubkey, (Prediction, Instant)>,
+}
+
+impl Shadow {
+    /// Keep `prediction`, replacing an earlier one for the same liquidation;
+    /// the latest is the closest to what will land.
+    pub fn predict(&mut self, prediction: Prediction, now: Instant) {
+        self.pending.insert(prediction.record, (prediction, now));
+    }
+
+    /// Record addresses to look for.
+    pub fn pending(&self) -> Vec<Pubkey> {
+        self.pending.keys().copied().collect()
+    }
+
+    /// Settle the prediction for `record` against what landed there.
+    pub fn resolve(&mut self, record: &Pubkey, landed: &LiquidationRecord) -> Option<Comparison> {
+        let (prediction, _) = self.pending.remove(record)?;
+        Some(Comparison::new(prediction, landed))
+    }
+
+    /// Drop and return predictions nothing landed for within `PREDICTION_TTL`.
+    pub fn expire(&mut self, now: Instant) -> Vec<Prediction> {
+        let expired: Vec<Pubkey> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, at))| now.saturating_duration_since(*at) >= PREDICTION_TTL)
+            .map(|(record, _)| *record)
+            .collect();
+        expired.iter().filter_map(|record| self.pending.remove(record)).map(|(prediction, _)| prediction).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::{data_log, liquidation};
+    use solana_sdk::instruction::InstructionError;
+    use solana_sdk::transaction::TransactionError;
+
+    fn simulation(err: Option<TransactionError>, logs: Vec<String>) -> RpcSimulateTransactionResult {
+        RpcSimulateTransactionResult {
+            err,
+            logs: Some(logs),
+            accounts: None,
+            units_consumed: Some(48_000),
+            return_data: None,
+        }
+    }
+
+    fn logs(body: Vec<String>) -> Vec<String> {
+        let program = liquidation_program::ID;
+        let mut logs = vec![format!("Program {program} invoke [1]")];
+        logs.extend(body);
+        logs.push(format!("Program {program} success"));
+        logs
+    }
+
+    fn prediction(simulation: &RpcSimulateTransactionResult) -> Prediction {
+        Prediction::from_simulation(Pubkey::new_unique(), Pubkey::new_unique(), Liquidation::Partial, 7, simulation)
+    }
+
+    fn record(event: &LiquidationEvent) -> LiquidationRecord {
+        LiquidationRecord {
+            position_owner: event.position_owner,
+            liquidator: event.liquidator,
+            symbol: [0; 16],
+            liquidated_size: event.liquidated_size,
+            liquidation_price: event.liquidation_price,
+            margin_before: event.margin_before,
+            margin_after: event.margin_after,
+            liquidator_reward: event.liquidator_reward,
+            bad_debt: event.bad_debt,
+            timestamp: event.timestamp,
+        }
+    }
+
+    #[test]
+    fn predicts_from_the_simulated_event() {
+        let event = liquidation(0);
+        let liquidated = prediction(&simulation(None, logs(vec![data_log(&event)])));
+        assert_eq!(liquidated.units_consumed, Some(48_000));
+        assert_eq!(
+            liquidated.predicted,
+            Predicted::Liquidated { outcome: Outcome::from(&event), insurance_covered: 0 }
+        );
+
+        // the program closes the record and returns when it finds the position healthy
+        let healthy = prediction(&simulation(None, logs(vec![])));
+        assert_eq!(healthy.predicted, Predicted::Healthy);
+
+        let failed = prediction(&simulation(
+            Some(TransactionError::InstructionError(0, InstructionError::Custom(6005))),
+            logs(vec!["Program log: AnchorError occurred. Error Code: PositionHealthy. Error Number: 6005.".into()]),
+        ));
+        assert_eq!(failed.predicted, Predicted::Failed("PositionHealthy".into()));
+    }
+
+    #[test]
+    fn compares_predictions_with_landed_records() {
+        let event = liquidation(0);
+        let mut shadow = Shadow::default();
+        let predicted = prediction(&simulation(None, logs(vec![data_log(&event)])));
+        let key = predicted.record;
+        shadow.predict(predicted, Instant::now());
+        assert_eq!(shadow.pending(), vec![key]);
+
+        let exact = shadow.resolve(&key, &record(&event)).unwrap();
+        assert!(exact.matches());
+        assert_eq!(exact.liquidator, event.liquidator);
+        assert!(shadow.pending().is_empty());
+        assert!(shadow.resolve(&key, &record(&event)).is_none());
+
+        // the price moved before another keeper landed it
+        let moved = LiquidationEvent { liquidator_reward: event.liquidator_reward + 500, bad_debt: 20, ..liquidation(0) };
+        let off = Comparison::new(exact.prediction.clone(), &record(&moved));
+        assert!(!off.matches());
+        assert_eq!((off.reward_error(), off.bad_debt_error()), (500, 20));
+
+        // predicted no liquidation, but one landed
+        let healthy = prediction(&simulation(None, logs(vec![])));
+        let missed = Comparison::new(healthy, &record(&event));
+        assert!(!missed.matches());
+        assert_eq!(missed.reward_error(), i128::from(event.liquidator_reward));
+    }
+
+    #[test]
+    fn unlanded_predictions_expire() {
+        let mut shadow = Shadow::default();
+        let start = Instant::now();
+        let old = prediction(&simulation(None, logs(vec![])));
+        let fresh = prediction(&simulation(None, logs(vec![])));
+        shadow.predict(old.clone(), start);
+        shadow.predict(fresh.clone(), start + PREDICTION_TTL / 2);
+
+        assert!(shadow.expire(start + PREDICTION_TTL / 2).is_empty());
+        assert_eq!(shadow.expire(start + PREDICTION_TTL), vec![old]);
+        assert_eq!(shadow.pending(), vec![fresh.record]);
+    }
+}
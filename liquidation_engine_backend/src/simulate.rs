@@ -0,0 +1,377 @@
+//! Monte Carlo sizing of the insurance fund: sample price paths over the
+//! indexed position book, run the liquidation rules step by step, and see
+//! how often each fund size leaves bad debt uncovered (what the program
+//! reports as a `ProtocolInsolvencyEvent`).
+//!
+//! Each market follows a geometric Brownian motion with lognormal jumps.
+//! All markets share a volatility regime, which switches as a Markov chain,
+//! and a common factor set by `correlation`. Positions never change except
+//! by liquidation; the fund never refills.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use liquidation_math::{cover_bad_debt, Price, PRICE_PRECISION};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal, Poisson, StandardNormal};
+use serde::{Deserialize, Serialize};
+
+use crate::backtest::{render_table, Book, Outcome, ParamSet, Replay};
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 3600.0;
+
+/// Price model of one market. Rates are annualised.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MarketModel {
+    /// Starting price in whole quote per unit; the market's last accepted
+    /// price if not set.
+    pub price: Option<f64>,
+    pub drift: f64,
+    pub volatility: f64,
+    /// Expected jumps per year.
+    pub jump_intensity: f64,
+    /// Mean and standard deviation of a jump's log return.
+    pub jump_mean: f64,
+    pub jump_std: f64,
+}
+
+impl Default for MarketModel {
+    fn default() -> Self {
+        Self {
+            price: None,
+            drift: 0.0,
+            volatility: 0.8,
+            jump_intensity: 4.0,
+            jump_mean: -0.05,
+            jump_std: 0.1,
+        }
+    }
+}
+
+/// A volatility regime, scaling every market's volatility and jump
+/// intensity while it lasts.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Regime {
+    pub name: String,
+    pub volatility_scale: f64,
+    pub jump_scale: f64,
+    /// Chance of staying in this regime each step; on leaving, the next
+    /// regime is picked uniformly from the others.
+    pub persistence: f64,
+}
+
+impl Default for Regime {
+    fn default() -> Self {
+        Self { name: "base".into(), volatility_scale: 1.0, jump_scale: 1.0, persistence: 1.0 }
+    }
+}
+
+/// Everything `simulate` reads besides the book.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SimConfig {
+    pub paths: usize,
+    pub steps: usize,
+    pub step_secs: f64,
+    pub seed: u64,
+    /// Insurance balances to evaluate, quote base units; the book's
+    /// balance if empty.
+    pub fund_sizes: Vec<u64>,
+    /// Correlation of every pair of markets' diffusion, 0..=1.
+    pub correlation: f64,
+    /// Model for markets not listed in `markets`.
+    pub default: MarketModel,
+    /// Models by market symbol.
+    pub markets: HashMap<String, MarketModel>,
+    /// Regimes; paths start in the first. One calm regime if empty.
+    pub regimes: Vec<Regime>,
+    /// Liquidation parameters; the program's if not set.
+    pub params: Option<ParamSet>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            paths: 1_000,
+            steps: 1_440,
+            step_secs: 60.0,
+            seed: 0,
+            fund_sizes: Vec::new(),
+            correlation: 0.0,
+            default: MarketModel::default(),
+            markets: HashMap::new(),
+            regimes: Vec::new(),
+            params: None,
+        }
+    }
+}
+
+impl SimConfig {
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let config: Self = toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        if !(0.0..=1.0).contains(&config.correlation) {
+            bail!("correlation must be in 0..=1");
+        }
+        if config.regimes.iter().any(|r| !(0.0..=1.0).contains(&r.persistence)) {
+            bail!("regime persistence must be in 0..=1");
+        }
+        Ok(config)
+    }
+}
+
+/// Uncovered bad debt at one fund size across all paths.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct FundReport {
+    pub fund: u64,
+    /// Share of paths with any uncovered bad debt.
+    pub insolvency_probability: f64,
+    pub mean_uncovered: f64,
+    pub p50_uncovered: u64,
+    pub p95_uncovered: u64,
+    pub p99_uncovered: u64,
+    pub max_uncovered: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SimReport {
+    pub paths: usize,
+    /// Share of paths with any bad debt at all, whatever the fund.
+    pub bad_debt_probability: f64,
+    pub mean_bad_debt: f64,
+    pub funds: Vec<FundReport>,
+}
+
+/// Starting price and model per market, in book order.
+fn market_models(book: &Book, config: &SimConfig) -> Result<Vec<(f64, MarketModel)>> {
+    book.markets()
+        .map(|(symbol, last)| {
+            let model = config.markets.get(symbol).copied().unwrap_or(config.default);
+            let price = match model.price {
+                Some(price) => price,
+                None if last.0 > 0 => last.0 as f64 / PRICE_PRECISION as f64,
+                None => return Err(anyhow!("{symbol} has no accepted price yet; set markets.{symbol}.price")),
+            };
+            Ok((price, model))
+        })
+        .collect()
+}
+
+/// Bad debts, and the reward owed on each, of one sampled path in order.
+fn sample_path(book: &Book, params: &ParamSet, config: &SimConfig, models: &[(f64, MarketModel)], regimes: &[Regime], rng: &mut StdRng) -> Vec<(u64, u64)> {
+    let mut replay = Replay::new(book, params);
+    let mut prices: Vec<f64> = models.iter().map(|(price, _)| *price).collect();
+    let mut regime = 0;
+    let mut debts = Vec::new();
+
+    let dt = config.step_secs / SECONDS_PER_YEAR;
+    let (common, own) = (config.correlation.sqrt(), (1.0 - config.correlation).sqrt());
+
+    for _ in 0..config.steps {
+        if regimes.len() > 1 && rng.gen::<f64>() >= regimes[regime].persistence {
+            let next = rng.gen_range(0..regimes.len() - 1);
+            regime = if next >= regime { next + 1 } else { next };
+        }
+        let Regime { volatility_scale, jump_scale, .. } = regimes[regime];
+        let shared: f64 = StandardNormal.sample(rng);
+
+        for (market, (price, (_, model))) in prices.iter_mut().zip(models).enumerate() {
+            let sigma = model.volatility * volatility_scale;
+            let own_shock: f64 = StandardNormal.sample(rng);
+            let shock = common * shared + own * own_shock;
+            let mut log_return = (model.drift - sigma * sigma / 2.0) * dt + sigma * dt.sqrt() * shock;
+
+            let intensity = model.jump_intensity * jump_scale * dt;
+            if intensity > 0.0 {
+                let jumps = Poisson::new(intensity).map_or(0.0, |poisson| poisson.sample(rng));
+                if jumps > 0.0 {
+                    // the sum of n normal jumps is normal
+                    let jump = Normal::new(model.jump_mean * jumps, model.jump_std * jumps.sqrt()).map_or(0.0, |n| n.sample(rng));
+                    log_return += jump;
+                }
+            }
+            *price *= log_return.exp();
+
+            let fixed = (*price * PRICE_PRECISION as f64).round().clamp(1.0, u64::MAX as f64) as u64;
+            replay.step(market, Price(fixed), |outcome| {
+                if let Outcome::BadDebt { bad_debt, max_reward } = outcome {
+                    debts.push((bad_debt, max_reward));
+                }
+            });
+        }
+    }
+    debts
+}
+
+/// Run every path and report the uncovered bad debt at each fund size.
+pub fn simulate(book: &Book, config: &SimConfig) -> Result<SimReport> {
+    let models = market_models(book, config)?;
+    let regimes = if config.regimes.is_empty() { vec![Regime::default()] } else { config.regimes.clone() };
+    let params = config.params.clone().unwrap_or_else(ParamSet::current);
+    let funds = if config.fund_sizes.is_empty() { vec![book.insurance] } else { config.fund_sizes.clone() };
+
+    let mut bad_debt = Vec::with_capacity(config.paths);
+    let mut uncovered: Vec<Vec<u64>> = vec![Vec::with_capacity(config.paths); funds.len()];
+    for path in 0..config.paths {
+        // one generator per path, so a path doesn't depend on the ones before
+        let mut rng = StdRng::seed_from_u64(config.seed.wrapping_add(path as u64));
+        let debts = sample_path(book, &params, config, &models, &regimes, &mut rng);
+
+        bad_debt.push(debts.iter().map(|(debt, _)| debt).sum::<u64>());
+        for (fund, uncovered) in funds.iter().zip(&mut uncovered) {
+            let mut balance = *fund;
+            let mut short = 0;
+            for &(debt, max_reward) in &debts {
+                let (covered, reward) = cover_bad_debt(debt, max_reward, balance);
+                balance -= covered + reward;
+                short += debt - covered;
+            }
+            uncovered.push(short);
+        }
+    }
+
+    let paths = config.paths.max(1) as f64;
+    Ok(SimReport {
+        paths: config.paths,
+        bad_debt_probability: bad_debt.iter().filter(|&&debt| debt > 0).count() as f64 / paths,
+        mean_bad_debt: bad_debt.iter().map(|&debt| debt as f64).sum::<f64>() / paths,
+        funds: funds
+            .iter()
+            .zip(uncovered)
+            .map(|(&fund, mut uncovered)| {
+                uncovered.sort_unstable();
+                FundReport {
+                    fund,
+                    insolvency_probability: uncovered.iter().filter(|&&short| short > 0).count() as f64 / paths,
+                    mean_uncovered: uncovered.iter().map(|&short| short as f64).sum::<f64>() / paths,
+                    p50_uncovered: quantile(&uncovered, 0.50),
+                    p95_uncovered: quantile(&uncovered, 0.95),
+                    p99_uncovered: quantile(&uncovered, 0.99),
+                    max_uncovered: uncovered.last().copied().unwrap_or(0),
+                }
+            })
+            .collect(),
+    })
+}
+
+/// Nearest-rank quantile of sorted values.
+fn quantile(sorted: &[u64], q: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// One row per fund size.
+pub fn table(report: &SimReport) -> String {
+    let header = ["fund", "P(insolvent)", "mean uncovered", "p50", "p95", "p99", "max"];
+    let rows = report
+        .funds
+        .iter()
+        .map(|f| {
+            vec![
+                f.fund.to_string(),
+                format!("{:.4}", f.insolvency_probability),
+                format!("{:.0}", f.mean_uncovered),
+                f.p50_uncovered.to_string(),
+                f.p95_uncovered.to_string(),
+                f.p99_uncovered.to_string(),
+                f.max_uncovered.to_string(),
+            ]
+        })
+        .collect();
+    format!(
+        "{} paths, P(bad debt) {:.4}, mean bad debt {:.0}\n{}",
+        report.paths,
+        report.bad_debt_probability,
+        report.mean_bad_debt,
+        render_table(&header, rows)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use liquidation_math::{PositionTerms, Quantity, QuoteAmount};
+
+    const USD: u64 = PRICE_PRECISION;
+
+    /// 10 long at 100 on 60 of collateral.
+    fn book() -> Book {
+        let mut book = Book::default();
+        book.add_market("SOL-PERP", Price(100 * USD), Vec::new());
+        book.add_position(
+            "SOL-PERP",
+            PositionTerms {
+                entry_price: Price(100 * USD),
+                size: Quantity(10 * USD),
+                collateral: QuoteAmount::from_u64(60 * USD),
+                is_long: true,
+            },
+        )
+        .unwrap();
+        book
+    }
+
+    fn config(default: MarketModel) -> SimConfig {
+        SimConfig { paths: 20, steps: 10, fund_sizes: vec![100 * USD, 2_000 * USD], default, ..SimConfig::default() }
+    }
+
+    #[test]
+    fn flat_prices_never_liquidate() {
+        let flat = MarketModel { volatility: 0.0, jump_intensity: 0.0, ..MarketModel::default() };
+        let report = simulate(&book(), &config(flat)).unwrap();
+        assert_eq!(report.bad_debt_probability, 0.0);
+        assert!(report.funds.iter().all(|f| f.insolvency_probability == 0.0 && f.max_uncovered == 0));
+    }
+
+    #[test]
+    fn a_crash_is_only_covered_by_a_big_enough_fund() {
+        // dozens of e^-1 jumps in the first step take the price to nothing:
+        // the long is closed about 940 short
+        let crash = MarketModel {
+            volatility: 0.0,
+            jump_intensity: 50.0 * SECONDS_PER_YEAR / 60.0,
+            jump_mean: -1.0,
+            jump_std: 0.0,
+            ..MarketModel::default()
+        };
+        let report = simulate(&book(), &config(crash)).unwrap();
+        assert_eq!(report.bad_debt_probability, 1.0);
+
+        let (small, big) = (&report.funds[0], &report.funds[1]);
+        assert_eq!(small.insolvency_probability, 1.0);
+        assert!(small.p50_uncovered > 800 * USD);
+        assert_eq!(big.insolvency_probability, 0.0);
+        assert_eq!(big.max_uncovered, 0);
+    }
+
+    #[test]
+    fn paths_are_reproducible() {
+        let mut config = config(MarketModel { volatility: 3.0, ..MarketModel::default() });
+        config.regimes = vec![
+            Regime::default(),
+            Regime { name: "stress".into(), volatility_scale: 5.0, jump_scale: 20.0, persistence: 0.9 },
+        ];
+        config.regimes[0].persistence = 0.5;
+        assert_eq!(simulate(&book(), &config).unwrap(), simulate(&book(), &config).unwrap());
+
+        config.markets.insert("SOL-PERP".into(), MarketModel { price: Some(1e-9), ..MarketModel::default() });
+        assert!(simulate(&book(), &config).is_ok());
+    }
+
+    #[test]
+    fn nearest_rank_quantiles() {
+        let values: Vec<u64> = (1..=100).collect();
+        assert_eq!(quantile(&values, 0.5), 50);
+        assert_eq!(quantile(&values, 0.99), 99);
+        assert_eq!(quantile(&values, 1.0), 100);
+        assert_eq!(quantile(&[7], 0.0), 7);
+        assert_eq!(quantile(&[], 0.5), 0);
+    }
+}
@@ -0,0 +1,160 @@
+//! `EventSink`s: stdout as JSON lines, the SQLite store, and webhooks.
+
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+
+use crate::events::{DecodedEvent, EventSink};
+use crate::indexer::SharedStore;
+
+/// Prints each event as one line of JSON.
+pub struct StdoutSink;
+
+#[async_trait]
+impl EventSink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    async fn handle(&mut self, event: &DecodedEvent) -> Result<()> {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", event.to_json())?;
+        Ok(())
+    }
+}
+
+/// Writes each event to the store's `events` table.
+pub struct SqliteSink {
+    pub store: SharedStore,
+}
+
+#[async_trait]
+impl EventSink for SqliteSink {
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+
+    async fn handle(&mut self, event: &DecodedEvent) -> Result<()> {
+        let store = self.store.lock().map_err(|_| anyhow!("store lock poisoned"))?;
+        store.insert_event(event)
+    }
+}
+
+/// POSTs each event's JSON to a URL; anything but a 2xx is an error.
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookSink {
+    pub fn new(url: impl Into<String>) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?;
+        Ok(Self { client, url: url.into() })
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    async fn handle(&mut self, event: &DecodedEvent) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(&event.to_json())
+            .send()
+            .await
+            .with_context(|| format!("POST {}", self.url))?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::{data_log, liquidation, transaction};
+    use crate::store::Store;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    fn event() -> DecodedEvent {
+        let program = liquidation_program::ID.to_string();
+        let tx = transaction(vec![
+            format!("Program {program} invoke [1]"),
+            data_log(&liquidation(100)),
+            format!("Program {program} success"),
+        ]);
+        tx.events().pop().expect("decoded event")
+    }
+
+    type Received = Arc<Mutex<Vec<Value>>>;
+
+    async fn accept(State(received): State<Received>, Json(body): Json<Value>) -> StatusCode {
+        received.lock().unwrap().push(body);
+        StatusCode::NO_CONTENT
+    }
+
+    async fn reject() -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    /// A local webhook receiver: `/events` records what it's sent, `/down` fails.
+    async fn receiver() -> (String, Received) {
+        let received = Received::default();
+        let router = Router::new()
+            .route("/events", post(accept))
+            .route("/down", post(reject))
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn webhook_posts_the_event_json() {
+        let (url, received) = receiver().await;
+        let event = event();
+
+        let mut sink = WebhookSink::new(format!("{url}/events")).unwrap();
+        sink.handle(&event).await.unwrap();
+
+        assert_eq!(*received.lock().unwrap(), vec![event.to_json()]);
+    }
+
+    #[tokio::test]
+    async fn webhook_error_status_is_an_error() {
+        let (url, received) = receiver().await;
+
+        let mut sink = WebhookSink::new(format!("{url}/down")).unwrap();
+        let err = sink.handle(&event()).await.unwrap_err();
+        assert!(err.to_string().contains("503"), "{err:#}");
+
+        // and so is a 404 from a path the receiver has no route for
+        let mut sink = WebhookSink::new(format!("{url}/missing")).unwrap();
+        assert!(sink.handle(&event()).await.is_err());
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn sqlite_sink_round_trips_the_event() {
+        let store: SharedStore = Arc::new(Mutex::new(Store::open_in_memory().unwrap()));
+        let event = event();
+
+        let mut sink = SqliteSink { store: store.clone() };
+        sink.handle(&event).await.unwrap();
+        // the same event again, e.g. from a replayed log, is stored once
+        sink.handle(&event).await.unwrap();
+
+        let stored = store.lock().unwrap().events(None, 10).unwrap();
+        assert_eq!(stored, vec![event.to_json()]);
+    }
+}
@@ -0,0 +1,781 @@
+//! SQLite store for the indexer.
+//!
+//! Every account update is kept as a version keyed by (address, slot) in
+//! `account_versions`; the typed tables (`positions`, `markets`,
+//! `insurance_funds`, `liquidation_records`) always hold the decoded latest
+//! version of each account, and `events` the program events decoded from
+//! transaction logs. Dropping the slots of an abandoned fork or replacing an
+//! account's history with finalized data only touches versions and events,
+//! then rebuilds the typed rows from what is left. Versions below the root are
+//! pruned down to the newest one.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use anchor_lang::AccountDeserialize;
+use anyhow::{anyhow, Context, Result};
+use liquidation_client::accounts::AccountKind;
+use liquidation_program::{InsuranceFund, LiquidationRecord, Market, Position};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::events::DecodedEvent;
+use crate::scan::decode;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS account_versions (
+    address TEXT NOT NULL,
+    slot INTEGER NOT NULL,
+    data BLOB,                          -- NULL: closed at this slot
+    PRIMARY KEY (address, slot)
+);
+CREATE INDEX IF NOT EXISTS account_versions_slot ON account_versions (slot);
+
+CREATE TABLE IF NOT EXISTS positions (
+    address TEXT PRIMARY KEY,
+    slot INTEGER NOT NULL,
+    owner TEXT NOT NULL,
+    market TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    entry_price INTEGER NOT NULL,
+    collateral INTEGER NOT NULL,
+    is_long INTEGER NOT NULL,
+    liquidation_count INTEGER NOT NULL,
+    data BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS positions_market ON positions (market);
+CREATE INDEX IF NOT EXISTS positions_owner ON positions (owner);
+
+CREATE TABLE IF NOT EXISTS markets (
+    address TEXT PRIMARY KEY,
+    slot INTEGER NOT NULL,
+    market_index INTEGER NOT NULL,
+    symbol TEXT NOT NULL,
+    oracle TEXT NOT NULL,
+    data BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS insurance_funds (
+    address TEXT PRIMARY KEY,
+    slot INTEGER NOT NULL,
+    insurance_vault TEXT NOT NULL,
+    balance INTEGER NOT NULL,
+    total_bad_debt_covered INTEGER NOT NULL,
+    data BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS liquidation_records (
+    address TEXT PRIMARY KEY,
+    slot INTEGER NOT NULL,
+    position_owner TEXT NOT NULL,
+    liquidator TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    liquidated_size INTEGER NOT NULL,
+    liquidation_price INTEGER NOT NULL,
+    margin_before INTEGER NOT NULL,
+    margin_after INTEGER NOT NULL,
+    liquidator_reward INTEGER NOT NULL,
+    bad_debt INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    data BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS liquidation_records_timestamp ON liquidation_records (timestamp);
+CREATE INDEX IF NOT EXISTS liquidation_records_owner ON liquidation_records (position_owner);
+
+CREATE TABLE IF NOT EXISTS events (
+    signature TEXT NOT NULL,
+    event_index INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    data TEXT NOT NULL,                 -- the event's fields as JSON
+    PRIMARY KEY (signature, event_index)
+);
+CREATE INDEX IF NOT EXISTS events_timestamp ON events (timestamp);
+
+CREATE TABLE IF NOT EXISTS indexer_state (
+    key TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
+";
+
+const TYPED_TABLES: [&str; 4] = ["positions", "markets", "insurance_funds", "liquidation_records"];
+
+/// An account as of the slot it was last written.
+#[derive(Clone, Debug)]
+pub struct Indexed<T> {
+    pub address: Pubkey,
+    pub slot: u64,
+    pub account: T,
+}
+
+/// Account types the indexer keeps, by discriminator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexedKind {
+    Position,
+    Market,
+    InsuranceFund,
+    LiquidationRecord,
+}
+
+impl IndexedKind {
+    pub fn of(data: &[u8]) -> Option<Self> {
+        match AccountKind::of(data)? {
+            AccountKind::Position => Some(Self::Position),
+            AccountKind::Market => Some(Self::Market),
+            AccountKind::InsuranceFund => Some(Self::InsuranceFund),
+            AccountKind::LiquidationRecord => Some(Self::LiquidationRecord),
+            AccountKind::CollateralRegistry | AccountKind::CollateralAuction | AccountKind::MockOracle => None,
+        }
+    }
+}
+
+/// Filter and page for `Store::liquidation_records`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecordQuery {
+    pub owner: Option<Pubkey>,
+    /// Unix timestamp of the oldest record to include.
+    pub since: Option<i64>,
+    /// (timestamp, address) of the last record of the previous page; only
+    /// older records are returned.
+    pub before: Option<(i64, Pubkey)>,
+    pub limit: usize,
+}
+
+pub struct Store {
+    conn: Connection,
+    /// Addresses whose indexed account changed since the last
+    /// `take_changed`; `None` until it is first called.
+    changed: Option<HashSet<Pubkey>>,
+}
+
+impl Store {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let conn = Connection::open(path).with_context(|| format!("opening {}", path.display()))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA).context("creating schema")?;
+        Ok(Self { conn, changed: None })
+    }
+
+    /// Record `address` as of `slot`: its data, or `None` if it was closed.
+    /// Data of a type the indexer doesn't keep is ignored. A version older
+    /// than the one already stored is kept but doesn't change the typed row.
+    pub fn apply(&mut self, address: &Pubkey, slot: u64, data: Option<&[u8]>) -> Result<()> {
+        if data.is_some_and(|data| IndexedKind::of(data).is_none()) {
+            return Ok(());
+        }
+
+        let tx = self.conn.transaction()?;
+        write_version(&tx, address, slot, data)?;
+        tx.commit()?;
+        self.changed([*address]);
+        Ok(())
+    }
+
+    /// Replace everything indexed with a full snapshot taken at `slot`, in
+    /// one transaction. Accounts indexed before but missing from the
+    /// snapshot were closed.
+    pub fn replace_all(&mut self, accounts: &[(Pubkey, Vec<u8>)], slot: u64) -> Result<()> {
+        let known = self.indexed_addresses()?;
+        let tx = self.conn.transaction()?;
+        for (address, data) in accounts {
+            if IndexedKind::of(data).is_some() {
+                write_version(&tx, address, slot, Some(data))?;
+            }
+        }
+        let snapshot: HashSet<&Pubkey> = accounts.iter().map(|(key, _)| key).collect();
+        let closed: Vec<Pubkey> = known.into_iter().filter(|address| !snapshot.contains(address)).collect();
+        for address in &closed {
+            write_version(&tx, address, slot, None)?;
+        }
+        tx.commit()?;
+        self.changed(accounts.iter().map(|(address, _)| *address).chain(closed));
+        Ok(())
+    }
+
+    /// Slots in (`after`, `up_to`] that a version or an event was written
+    /// at, ascending.
+    pub fn written_between(&self, after: u64, up_to: u64) -> Result<Vec<u64>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT slot FROM account_versions WHERE slot > ?1 AND slot <= ?2
+             UNION SELECT slot FROM events WHERE slot > ?1 AND slot <= ?2
+             ORDER BY slot",
+        )?;
+        let rows = stmt.query_map(params![after, up_to], |row| row.get(0))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Drop every version and event written in (`after`, `up_to`] at a slot
+    /// missing from `rooted`, the slots the finalized chain holds there: the
+    /// fork they were on was abandoned. Returns how many events went.
+    pub fn drop_abandoned(&mut self, after: u64, up_to: u64, rooted: &[u64]) -> Result<usize> {
+        let rooted: HashSet<u64> = rooted.iter().copied().collect();
+        let abandoned: Vec<u64> =
+            self.written_between(after, up_to)?.into_iter().filter(|slot| !rooted.contains(slot)).collect();
+
+        let tx = self.conn.transaction()?;
+        let mut events = 0;
+        let mut addresses = HashSet::new();
+        for slot in abandoned {
+            events += tx.execute("DELETE FROM events WHERE slot = ?1", [slot])?;
+            addresses.extend(select_addresses(&tx, "SELECT address FROM account_versions WHERE slot = ?1", slot)?);
+            tx.execute("DELETE FROM account_versions WHERE slot = ?1", [slot])?;
+        }
+        for address in &addresses {
+            refresh(&tx, address)?;
+        }
+        tx.commit()?;
+        self.changed(addresses);
+        Ok(events)
+    }
+
+    /// Replace `address`'s history up to `slot` with finalized `data`.
+    /// Returns true if that changed the indexed account, i.e. something
+    /// written earlier didn't make it into the finalized chain.
+    pub fn reconcile(&mut self, address: &Pubkey, slot: u64, data: Option<&[u8]>) -> Result<bool> {
+        let data = data.filter(|data| IndexedKind::of(data).is_some());
+
+        let tx = self.conn.transaction()?;
+        let before = latest_version(&tx, address)?;
+        tx.execute(
+            "DELETE FROM account_versions WHERE address = ?1 AND slot <= ?2",
+            params![address.to_string(), slot],
+        )?;
+        tx.execute(
+            "INSERT INTO account_versions (address, slot, data) VALUES (?1, ?2, ?3)",
+            params![address.to_string(), slot, data],
+        )?;
+        refresh(&tx, address)?;
+        let after = latest_version(&tx, address)?;
+        tx.commit()?;
+
+        let changed = before.and_then(|(_, data)| data) != after.and_then(|(_, data)| data);
+        if changed {
+            self.changed([*address]);
+        }
+        Ok(changed)
+    }
+
+    /// Addresses whose indexed account changed since the last call, or
+    /// `None` on the first: changes are only tracked from then on, so the
+    /// caller starts from a full read.
+    pub fn take_changed(&mut self) -> Option<HashSet<Pubkey>> {
+        self.changed.replace(HashSet::new())
+    }
+
+    /// Addresses with a version in (`after`, `up_to`].
+    pub fn updated_between(&self, after: u64, up_to: u64) -> Result<Vec<Pubkey>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT address FROM account_versions WHERE slot > ?1 AND slot <= ?2")?;
+        let rows = stmt.query_map(params![after, up_to], |row| row.get::<_, String>(0))?;
+        rows.map(|address| parse_pubkey(&address?)).collect()
+    }
+
+    /// Record a new root and prune history below it: each account keeps its
+    /// newest version at or below the root, and closed accounts are
+    /// forgotten once their closing is rooted.
+    pub fn set_root(&mut self, root: u64) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM account_versions AS v WHERE v.slot < ?1 AND EXISTS (
+                SELECT 1 FROM account_versions AS w
+                WHERE w.address = v.address AND w.slot > v.slot AND w.slot <= ?1
+            )",
+            [root],
+        )?;
+        tx.execute(
+            "DELETE FROM account_versions AS v WHERE v.data IS NULL AND v.slot <= ?1 AND NOT EXISTS (
+                SELECT 1 FROM account_versions AS w WHERE w.address = v.address AND w.slot > v.slot
+            )",
+            [root],
+        )?;
+        tx.execute("INSERT OR REPLACE INTO indexer_state (key, value) VALUES ('root', ?1)", [root])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn root(&self) -> Result<Option<u64>> {
+        Ok(self
+            .conn
+            .query_row("SELECT value FROM indexer_state WHERE key = 'root'", [], |row| row.get(0))
+            .optional()?)
+    }
+
+    /// Highest slot of any stored version.
+    pub fn latest_slot(&self) -> Result<Option<u64>> {
+        Ok(self.conn.query_row("SELECT MAX(slot) FROM account_versions", [], |row| row.get(0))?)
+    }
+
+    /// Sum of `bad_debt` over every indexed liquidation record.
+    pub fn total_bad_debt(&self) -> Result<u64> {
+        let total: i64 = self.conn.query_row("SELECT COALESCE(SUM(bad_debt), 0) FROM liquidation_records", [], |row| row.get(0))?;
+        Ok(total as u64)
+    }
+
+    pub fn position(&self, address: &Pubkey) -> Result<Option<Indexed<Position>>> {
+        self.query("SELECT address, slot, data FROM positions WHERE address = ?1", [address.to_string()])
+            .map(|mut rows| rows.pop())
+    }
+
+    /// Positions with a non-zero size.
+    pub fn open_positions(&self) -> Result<Vec<Indexed<Position>>> {
+        self.query("SELECT address, slot, data FROM positions WHERE size > 0 ORDER BY address", [])
+    }
+
+    pub fn positions_by_market(&self, market: &Pubkey) -> Result<Vec<Indexed<Position>>> {
+        self.query(
+            "SELECT address, slot, data FROM positions WHERE market = ?1 ORDER BY address",
+            [market.to_string()],
+        )
+    }
+
+    pub fn positions_by_owner(&self, owner: &Pubkey) -> Result<Vec<Indexed<Position>>> {
+        self.query(
+            "SELECT address, slot, data FROM positions WHERE owner = ?1 ORDER BY address",
+            [owner.to_string()],
+        )
+    }
+
+    pub fn markets(&self) -> Result<Vec<Indexed<Market>>> {
+        self.query("SELECT address, slot, data FROM markets ORDER BY market_index", [])
+    }
+
+    pub fn insurance_funds(&self) -> Result<Vec<Indexed<InsuranceFund>>> {
+        self.query("SELECT address, slot, data FROM insurance_funds ORDER BY address", [])
+    }
+
+    /// Liquidation records matching `query`, newest first.
+    pub fn liquidation_records(&self, query: &RecordQuery) -> Result<Vec<Indexed<LiquidationRecord>>> {
+        let (before_ts, before_address) = query.before.map_or((None, None), |(ts, address)| (Some(ts), Some(address.to_string())));
+        self.query(
+            "SELECT address, slot, data FROM liquidation_records
+             WHERE (?1 IS NULL OR position_owner = ?1) AND (?2 IS NULL OR timestamp >= ?2)
+               AND (?3 IS NULL OR timestamp < ?3 OR (timestamp = ?3 AND address < ?4))
+             ORDER BY timestamp DESC, address DESC LIMIT ?5",
+            params![
+                query.owner.as_ref().map(Pubkey::to_string),
+                query.since,
+                before_ts,
+                before_address,
+                query.limit as i64
+            ],
+        )
+    }
+
+    /// Store a decoded event. Storing the same one again is a no-op, so a
+    /// log file can be replayed over a live database.
+    pub fn insert_event(&self, event: &DecodedEvent) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO events (signature, event_index, slot, name, timestamp, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                event.signature,
+                event.index,
+                event.slot,
+                event.event.name(),
+                event.event.timestamp(),
+                event.event.fields().to_string()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stored events, newest first, in `DecodedEvent::to_json` form;
+    /// optionally only those named `name`.
+    pub fn events(&self, name: Option<&str>, limit: usize) -> Result<Vec<Value>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT signature, event_index, slot, name, data FROM events
+             WHERE ?1 IS NULL OR name = ?1
+             ORDER BY timestamp DESC, slot DESC, signature, event_index LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![name, limit as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, u64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+
+        rows.map(|row| {
+            let (signature, index, slot, name, data) = row?;
+            let data: Value = serde_json::from_str(&data).context("stored event")?;
+            Ok(json!({ "signature": signature, "slot": slot, "index": index, "event": name, "data": data }))
+        })
+        .collect()
+    }
+
+    fn query<T: AccountDeserialize>(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<Indexed<T>>> {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let rows = stmt.query_map(params, |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?, row.get::<_, Vec<u8>>(2)?))
+        })?;
+
+        rows.map(|row| {
+            let (address, slot, data) = row?;
+            Ok(Indexed { address: parse_pubkey(&address)?, slot, account: decode(&data)? })
+        })
+        .collect()
+    }
+
+    fn changed(&mut self, addresses: impl IntoIterator<Item = Pubkey>) {
+        if let Some(changed) = &mut self.changed {
+            changed.extend(addresses);
+        }
+    }
+
+    fn indexed_addresses(&self) -> Result<Vec<Pubkey>> {
+        let mut addresses = Vec::new();
+        for table in TYPED_TABLES {
+            let mut stmt = self.conn.prepare(&format!("SELECT address FROM {table}"))?;
+            for address in stmt.query_map([], |row| row.get::<_, String>(0))? {
+                addresses.push(parse_pubkey(&address?)?);
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+fn parse_pubkey(address: &str) -> Result<Pubkey> {
+    Pubkey::from_str(address).map_err(|err| anyhow!("bad address {address} in store: {err}"))
+}
+
+fn select_addresses(conn: &Connection, sql: &str, slot: u64) -> Result<Vec<Pubkey>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([slot], |row| row.get::<_, String>(0))?;
+    rows.map(|address| parse_pubkey(&address?)).collect()
+}
+
+fn latest_version(conn: &Connection, address: &Pubkey) -> Result<Option<(u64, Option<Vec<u8>>)>> {
+    Ok(conn
+        .query_row(
+            "SELECT slot, data FROM account_versions WHERE address = ?1 ORDER BY slot DESC LIMIT 1",
+            [address.to_string()],
+            |row: &Row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?)
+}
+
+/// Rebuild `address`'s typed row from its latest version.
+/// Store `address`'s version at `slot` and bring its typed row up to date.
+fn write_version(conn: &Connection, address: &Pubkey, slot: u64, data: Option<&[u8]>) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO account_versions (address, slot, data) VALUES (?1, ?2, ?3)",
+        params![address.to_string(), slot, data],
+    )?;
+    refresh(conn, address)
+}
+
+fn refresh(conn: &Connection, address: &Pubkey) -> Result<()> {
+    let key = address.to_string();
+    for table in TYPED_TABLES {
+        conn.execute(&format!("DELETE FROM {table} WHERE address = ?1"), [&key])?;
+    }
+
+    let Some((slot, Some(data))) = latest_version(conn, address)? else {
+        return Ok(());
+    };
+
+    match IndexedKind::of(&data) {
+        Some(IndexedKind::Position) => {
+            let p: Position = decode(&data)?;
+            conn.execute(
+                "INSERT INTO positions
+                 (address, slot, owner, market, size, entry_price, collateral, is_long, liquidation_count, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    key,
+                    slot,
+                    p.owner.to_string(),
+                    p.market.to_string(),
+                    p.size,
+                    p.entry_price,
+                    p.collateral,
+                    p.is_long,
+                    p.liquidation_count,
+                    data
+                ],
+            )?;
+        }
+        Some(IndexedKind::Market) => {
+            let m: Market = decode(&data)?;
+            conn.execute(
+                "INSERT INTO markets (address, slot, market_index, symbol, oracle, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![key, slot, m.market_index, symbol(&m.symbol), m.oracle.to_string(), data],
+            )?;
+        }
+        Some(IndexedKind::InsuranceFund) => {
+            let f: InsuranceFund = decode(&data)?;
+            conn.execute(
+                "INSERT INTO insurance_funds (address, slot, insurance_vault, balance, total_bad_debt_covered, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![key, slot, f.insurance_vault.to_string(), f.balance, f.total_bad_debt_covered, data],
+            )?;
+        }
+        Some(IndexedKind::LiquidationRecord) => {
+            let r: LiquidationRecord = decode(&data)?;
+            conn.execute(
+                "INSERT INTO liquidation_records
+                 (address, slot, position_owner, liquidator, symbol, liquidated_size, liquidation_price,
+                  margin_before, margin_after, liquidator_reward, bad_debt, timestamp, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                params![
+                    key,
+                    slot,
+                    r.position_owner.to_string(),
+                    r.liquidator.to_string(),
+                    symbol(&r.symbol),
+                    r.liquidated_size,
+                    r.liquidation_price,
+                    r.margin_before,
+                    r.margin_after,
+                    r.liquidator_reward,
+                    r.bad_debt,
+                    r.timestamp,
+                    data
+                ],
+            )?;
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// A zero-padded symbol as text.
+pub fn symbol(bytes: &[u8; 16]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use anchor_lang::AccountSerialize;
+    use liquidation_program::constants::MAX_COLLATERAL_ASSETS;
+
+    pub(crate) fn position(size: u64, collateral: i64) -> Position {
+        Position {
+            owner: Pubkey::new_from_array([7; 32]),
+            market: Pubkey::new_from_array([8; 32]),
+            size,
+            entry_price: 100_000_000,
+            collateral,
+            is_long: true,
+            last_update_ts: 0,
+            leverage: 10,
+            deposits: [0; MAX_COLLATERAL_ASSETS],
+            open_auctions: 0,
+            liquidation_count: 0,
+        }
+    }
+
+    fn record(timestamp: i64) -> LiquidationRecord {
+        LiquidationRecord {
+            position_owner: Pubkey::new_from_array([7; 32]),
+            liquidator: Pubkey::new_unique(),
+            symbol: *b"SOL-PERP\0\0\0\0\0\0\0\0",
+            liquidated_size: 1,
+            liquidation_price: 2,
+            margin_before: -3,
+            margin_after: 4,
+            liquidator_reward: 5,
+            bad_debt: 6,
+            timestamp,
+        }
+    }
+
+    pub(crate) fn bytes<T: AccountSerialize>(account: &T) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    fn collateral(store: &Store, address: &Pubkey) -> Option<i64> {
+        store.position(address).unwrap().map(|p| p.account.collateral)
+    }
+
+    #[test]
+    fn latest_version_wins_whatever_the_arrival_order() {
+        let mut store = Store::open_in_memory().unwrap();
+        let key = Pubkey::new_unique();
+
+        store.apply(&key, 10, Some(&bytes(&position(1, 100)))).unwrap();
+        store.apply(&key, 12, Some(&bytes(&position(1, 120)))).unwrap();
+        store.apply(&key, 11, Some(&bytes(&position(1, 110)))).unwrap();
+
+        let indexed = store.position(&key).unwrap().unwrap();
+        assert_eq!((indexed.slot, indexed.account.collateral), (12, 120));
+        assert_eq!(store.latest_slot().unwrap(), Some(12));
+
+        store.apply(&key, 13, None).unwrap();
+        assert!(store.position(&key).unwrap().is_none());
+    }
+
+    #[test]
+    fn abandoned_slots_are_dropped() {
+        let mut store = Store::open_in_memory().unwrap();
+        let (kept, forked) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        store.apply(&kept, 10, Some(&bytes(&position(1, 100)))).unwrap();
+        store.apply(&kept, 15, Some(&bytes(&position(1, 150)))).unwrap();
+        store.apply(&forked, 16, Some(&bytes(&position(1, 160)))).unwrap();
+        store.apply(&kept, 17, Some(&bytes(&position(1, 170)))).unwrap();
+        assert_eq!(store.written_between(10, 17).unwrap(), vec![15, 16, 17]);
+
+        // 15 and 16 were on a fork; 17 is rooted
+        assert_eq!(store.drop_abandoned(12, 20, &[13, 17]).unwrap(), 0);
+        assert_eq!(collateral(&store, &kept), Some(170));
+        assert_eq!(collateral(&store, &forked), None);
+        assert_eq!(store.written_between(10, 17).unwrap(), vec![17]);
+
+        // slots outside the range are left alone
+        store.apply(&kept, 21, Some(&bytes(&position(1, 210)))).unwrap();
+        store.drop_abandoned(17, 20, &[]).unwrap();
+        assert_eq!(collateral(&store, &kept), Some(210));
+    }
+
+    #[test]
+    fn reconcile_reports_writes_the_finalized_chain_dropped() {
+        let mut store = Store::open_in_memory().unwrap();
+        let key = Pubkey::new_unique();
+        store.apply(&key, 10, Some(&bytes(&position(1, 100)))).unwrap();
+        store.apply(&key, 11, Some(&bytes(&position(1, 110)))).unwrap();
+        store.apply(&key, 14, Some(&bytes(&position(1, 140)))).unwrap();
+
+        // the finalized chain agrees at slot 11: nothing changes
+        assert!(!store.reconcile(&key, 11, Some(&bytes(&position(1, 110)))).unwrap());
+        assert_eq!(collateral(&store, &key), Some(140));
+
+        // at 14 it has a different value: the fork that wrote 140 is gone
+        assert!(store.reconcile(&key, 14, Some(&bytes(&position(1, 130)))).unwrap());
+        assert_eq!(collateral(&store, &key), Some(130));
+        assert_eq!(store.updated_between(11, 14).unwrap(), vec![key]);
+    }
+
+    #[test]
+    fn root_prunes_history_but_keeps_the_rooted_version() {
+        let mut store = Store::open_in_memory().unwrap();
+        let (key, closed) = (Pubkey::new_unique(), Pubkey::new_unique());
+        store.apply(&key, 10, Some(&bytes(&position(1, 100)))).unwrap();
+        store.apply(&key, 20, Some(&bytes(&position(1, 200)))).unwrap();
+        store.apply(&key, 30, Some(&bytes(&position(1, 300)))).unwrap();
+        store.apply(&closed, 10, Some(&bytes(&position(1, 1)))).unwrap();
+        store.apply(&closed, 20, None).unwrap();
+
+        store.set_root(25).unwrap();
+        assert_eq!(store.root().unwrap(), Some(25));
+
+        // abandoning what came after the root falls back to the root's version
+        store.drop_abandoned(25, 30, &[]).unwrap();
+        assert_eq!(collateral(&store, &key), Some(200));
+        assert!(store.updated_between(0, 19).unwrap().is_empty());
+    }
+
+    #[test]
+    fn snapshot_closes_accounts_it_no_longer_contains() {
+        let mut store = Store::open_in_memory().unwrap();
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        store.replace_all(&[(a, bytes(&position(1, 1))), (b, bytes(&position(1, 2)))], 10).unwrap();
+        store.replace_all(&[(a, bytes(&position(0, 1)))], 20).unwrap();
+
+        assert!(store.position(&b).unwrap().is_none());
+        assert_eq!(store.positions_by_owner(&position(0, 0).owner).unwrap().len(), 1);
+        // a's size went to zero
+        assert!(store.open_positions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn changes_are_tracked_from_the_first_take() {
+        let mut store = Store::open_in_memory().unwrap();
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        store.apply(&a, 10, Some(&bytes(&position(1, 100)))).unwrap();
+        assert_eq!(store.take_changed(), None);
+
+        store.apply(&b, 11, Some(&bytes(&position(1, 110)))).unwrap();
+        assert_eq!(store.take_changed(), Some(HashSet::from([b])));
+        assert_eq!(store.take_changed(), Some(HashSet::new()));
+
+        // rewritten by the finalized chain, or dropped with an abandoned fork
+        assert!(store.reconcile(&a, 12, Some(&bytes(&position(1, 120)))).unwrap());
+        store.drop_abandoned(10, 11, &[10]).unwrap();
+        assert_eq!(store.take_changed(), Some(HashSet::from([a, b])));
+
+        // a snapshot touches everything in it and everything it closes
+        store.replace_all(&[(b, bytes(&position(1, 1)))], 20).unwrap();
+        assert_eq!(store.take_changed(), Some(HashSet::from([a, b])));
+    }
+
+    #[test]
+    fn ignores_accounts_it_does_not_index() {
+        let mut store = Store::open_in_memory().unwrap();
+        store.apply(&Pubkey::new_unique(), 1, Some(&[0u8; 64])).unwrap();
+        assert_eq!(store.latest_slot().unwrap(), None);
+    }
+
+    #[test]
+    fn events_are_stored_once_and_dropped_with_their_fork() {
+        use crate::events::tests::{data_log, liquidation, transaction};
+
+        let mut store = Store::open_in_memory().unwrap();
+        let program = liquidation_program::ID.to_string();
+        let tx = transaction(vec![
+            format!("Program {program} invoke [1]"),
+            data_log(&liquidation(100)),
+            format!("Program {program} success"),
+        ]);
+        for event in tx.events().iter().chain(&tx.events()) {
+            store.insert_event(event).unwrap();
+        }
+
+        let events = store.events(Some("LiquidationEvent"), 10).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0], tx.events()[0].to_json());
+        assert!(store.events(Some("ProtocolInsolvencyEvent"), 10).unwrap().is_empty());
+
+        assert_eq!(store.drop_abandoned(41, 42, &[]).unwrap(), 1);
+        assert!(store.events(None, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn records_newest_first() {
+        let mut store = Store::open_in_memory().unwrap();
+        for ts in [100, 300, 200] {
+            store.apply(&Pubkey::new_unique(), 1, Some(&bytes(&record(ts)))).unwrap();
+        }
+        let owner = record(0).position_owner;
+
+        let times = |query: RecordQuery| -> Vec<i64> {
+            let records = store.liquidation_records(&RecordQuery { limit: 10, ..query }).unwrap();
+            records.iter().map(|r| r.account.timestamp).collect()
+        };
+        assert_eq!(times(RecordQuery::default()), vec![300, 200, 100]);
+        assert_eq!(times(RecordQuery { owner: Some(owner), since: Some(200), ..RecordQuery::default() }), vec![300, 200]);
+        assert!(times(RecordQuery { owner: Some(Pubkey::new_unique()), ..RecordQuery::default() }).is_empty());
+
+        // page through one at a time
+        let mut before = None;
+        let mut pages = Vec::new();
+        loop {
+            let page = store.liquidation_records(&RecordQuery { before, limit: 1, ..RecordQuery::default() }).unwrap();
+            let Some(last) = page.last() else { break };
+            before = Some((last.account.timestamp, last.address));
+            pages.push(last.account.timestamp);
+        }
+        assert_eq!(pages, vec![300, 200, 100]);
+        assert_eq!(store.total_bad_debt().unwrap(), 18);
+    }
+}
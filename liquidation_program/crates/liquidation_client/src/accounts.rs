@@ -0,0 +1,116 @@
+//! Decoding the program's accounts by their Anchor discriminator.
+
+use anchor_lang::{AccountDeserialize, Discriminator};
+use liquidation_program::{
+    CollateralAuction, CollateralRegistry, InsuranceFund, LiquidationRecord, Market, MockOracle, Position,
+};
+
+/// Decode an account of type `T`, checking its discriminator.
+pub fn decode<T: AccountDeserialize>(data: &[u8]) -> anchor_lang::Result<T> {
+    T::try_deserialize(&mut &data[..])
+}
+
+/// The program's account types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccountKind {
+    Position,
+    Market,
+    InsuranceFund,
+    LiquidationRecord,
+    CollateralRegistry,
+    CollateralAuction,
+    MockOracle,
+}
+
+impl AccountKind {
+    pub const ALL: [Self; 7] = [
+        Self::Position,
+        Self::Market,
+        Self::InsuranceFund,
+        Self::LiquidationRecord,
+        Self::CollateralRegistry,
+        Self::CollateralAuction,
+        Self::MockOracle,
+    ];
+
+    pub fn discriminator(self) -> [u8; 8] {
+        match self {
+            Self::Position => Position::discriminator(),
+            Self::Market => Market::discriminator(),
+            Self::InsuranceFund => InsuranceFund::discriminator(),
+            Self::LiquidationRecord => LiquidationRecord::discriminator(),
+            Self::CollateralRegistry => CollateralRegistry::discriminator(),
+            Self::CollateralAuction => CollateralAuction::discriminator(),
+            Self::MockOracle => MockOracle::discriminator(),
+        }
+    }
+
+    /// The type `data` says it holds, if it is one of the program's.
+    pub fn of(data: &[u8]) -> Option<Self> {
+        let discriminator = data.get(..8)?;
+        Self::ALL.into_iter().find(|kind| kind.discriminator() == discriminator)
+    }
+}
+
+/// Any of the program's accounts, decoded.
+#[derive(Clone)]
+pub enum ProgramAccount {
+    Position(Position),
+    Market(Market),
+    InsuranceFund(InsuranceFund),
+    LiquidationRecord(LiquidationRecord),
+    CollateralRegistry(Box<CollateralRegistry>),
+    CollateralAuction(CollateralAuction),
+    MockOracle(MockOracle),
+}
+
+impl ProgramAccount {
+    pub fn decode(data: &[u8]) -> anchor_lang::Result<Self> {
+        let kind = AccountKind::of(data).ok_or(anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch)?;
+        Ok(match kind {
+            AccountKind::Position => Self::Position(decode(data)?),
+            AccountKind::Market => Self::Market(decode(data)?),
+            AccountKind::InsuranceFund => Self::InsuranceFund(decode(data)?),
+            AccountKind::LiquidationRecord => Self::LiquidationRecord(decode(data)?),
+            AccountKind::CollateralRegistry => Self::CollateralRegistry(Box::new(decode(data)?)),
+            AccountKind::CollateralAuction => Self::CollateralAuction(decode(data)?),
+            AccountKind::MockOracle => Self::MockOracle(decode(data)?),
+        })
+    }
+
+    pub fn kind(&self) -> AccountKind {
+        match self {
+            Self::Position(_) => AccountKind::Position,
+            Self::Market(_) => AccountKind::Market,
+            Self::InsuranceFund(_) => AccountKind::InsuranceFund,
+            Self::LiquidationRecord(_) => AccountKind::LiquidationRecord,
+            Self::CollateralRegistry(_) => AccountKind::CollateralRegistry,
+            Self::CollateralAuction(_) => AccountKind::CollateralAuction,
+            Self::MockOracle(_) => AccountKind::MockOracle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::prelude::Pubkey;
+    use anchor_lang::AccountSerialize;
+
+    #[test]
+    fn decodes_by_discriminator() {
+        let auction = CollateralAuction { position: Pubkey::new_unique(), asset_index: 1, ..CollateralAuction::default() };
+        let mut data = Vec::new();
+        auction.try_serialize(&mut data).unwrap();
+
+        assert_eq!(AccountKind::of(&data), Some(AccountKind::CollateralAuction));
+        let ProgramAccount::CollateralAuction(decoded) = ProgramAccount::decode(&data).unwrap() else {
+            panic!("decoded as another type");
+        };
+        assert_eq!(decoded.position, auction.position);
+
+        assert!(decode::<Position>(&data).is_err());
+        assert!(ProgramAccount::decode(&[0; 8]).is_err());
+        assert_eq!(AccountKind::of(&data[..7]), None);
+    }
+}
@@ -0,0 +1,554 @@
+//! A builder for every program instruction, with each derivable account
+//! filled in. Accounts the program creates from a fresh keypair (the
+//! insurance fund, positions, mock oracles) must also sign.
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::{system_program, sysvar};
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use liquidation_program::risk_tiers::RiskTier;
+use liquidation_program::{accounts, instruction, CollateralAuction, CollateralRegistry, Market, Position};
+
+use crate::pda;
+
+fn build(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: liquidation_program::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+/// The insurance fund and the quote mint with its token program, shared by
+/// every instruction that moves quote.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Protocol {
+    pub insurance_fund: Pubkey,
+    pub quote_mint: Pubkey,
+    pub token_program: Pubkey,
+}
+
+/// Oracles of the collateral `position` holds, in registry order: the
+/// remaining accounts of every instruction that values it.
+pub fn collateral_oracles(registry: &CollateralRegistry, position: &Position) -> Vec<Pubkey> {
+    registry.assets[..registry.num_assets as usize]
+        .iter()
+        .zip(position.deposits)
+        .filter(|(_, deposit)| *deposit > 0)
+        .map(|(asset, _)| asset.oracle)
+        .collect()
+}
+
+fn with_oracles(mut instruction: Instruction, oracles: &[Pubkey]) -> Instruction {
+    instruction.accounts.extend(oracles.iter().map(|oracle| AccountMeta::new_readonly(*oracle, false)));
+    instruction
+}
+
+pub fn initialize_insurance_fund(
+    payer: Pubkey,
+    insurance_fund: Pubkey,
+    mint: Pubkey,
+    token_program: Pubkey,
+    authority: Pubkey,
+) -> Instruction {
+    build(
+        accounts::InitializeInsuranceFund {
+            insurance_fund,
+            insurance_vault: pda::insurance_vault(),
+            insurance_authority: pda::insurance_authority(),
+            payer,
+            mint,
+            system_program: system_program::ID,
+            token_program,
+            rent: sysvar::rent::ID,
+        },
+        instruction::InitializeInsuranceFund { authority },
+    )
+}
+
+pub fn initialize_protocol_vault(payer: Pubkey, mint: Pubkey, token_program: Pubkey) -> Instruction {
+    build(
+        accounts::InitializeProtocolVault {
+            protocol_vault: pda::protocol_vault(),
+            vault_authority: pda::vault_authority(),
+            insurance_vault: pda::insurance_vault(),
+            payer,
+            mint,
+            system_program: system_program::ID,
+            token_program,
+            rent: sysvar::rent::ID,
+        },
+        instruction::InitializeProtocolVault {},
+    )
+}
+
+/// Market limits and deviation guard settings for `initialize_market`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MarketConfig {
+    pub max_price_deviation_bps: u64,
+    pub deviation_persist_slots: u64,
+    pub max_open_interest: u64,
+    pub max_position_notional: u64,
+}
+
+pub fn initialize_market(authority: Pubkey, oracle: Pubkey, market_index: u16, symbol: [u8; 16], config: MarketConfig) -> Instruction {
+    build(
+        accounts::InitializeMarket {
+            market: pda::market(market_index),
+            oracle,
+            authority,
+            system_program: system_program::ID,
+        },
+        instruction::InitializeMarket {
+            market_index,
+            symbol,
+            max_price_deviation_bps: config.max_price_deviation_bps,
+            deviation_persist_slots: config.deviation_persist_slots,
+            max_open_interest: config.max_open_interest,
+            max_position_notional: config.max_position_notional,
+        },
+    )
+}
+
+pub fn update_market_config(
+    authority: Pubkey,
+    market: Pubkey,
+    max_price_deviation_bps: u64,
+    deviation_persist_slots: u64,
+) -> Instruction {
+    build(
+        accounts::UpdateMarketConfig { market, authority },
+        instruction::UpdateMarketConfig { max_price_deviation_bps, deviation_persist_slots },
+    )
+}
+
+pub fn set_market_limits(authority: Pubkey, market: Pubkey, max_open_interest: u64, max_position_notional: u64) -> Instruction {
+    build(
+        accounts::UpdateMarketConfig { market, authority },
+        instruction::SetMarketLimits { max_open_interest, max_position_notional },
+    )
+}
+
+pub fn set_risk_tiers(authority: Pubkey, market: Pubkey, tiers: Vec<RiskTier>) -> Instruction {
+    build(accounts::UpdateMarketConfig { market, authority }, instruction::SetRiskTiers { tiers })
+}
+
+/// Permissionless.
+pub fn refresh_market_price(market: Pubkey, oracle: Pubkey) -> Instruction {
+    build(accounts::RefreshMarketPrice { market, oracle }, instruction::RefreshMarketPrice {})
+}
+
+pub fn initialize_collateral_registry(authority: Pubkey) -> Instruction {
+    build(
+        accounts::InitializeCollateralRegistry {
+            collateral_registry: pda::collateral_registry(),
+            authority,
+            system_program: system_program::ID,
+        },
+        instruction::InitializeCollateralRegistry {},
+    )
+}
+
+pub fn add_collateral_asset(authority: Pubkey, mint: Pubkey, oracle: Pubkey, token_program: Pubkey, weight_bps: u64) -> Instruction {
+    build(
+        accounts::AddCollateralAsset {
+            collateral_registry: pda::collateral_registry(),
+            mint,
+            oracle,
+            collateral_vault: pda::collateral_vault(&mint),
+            vault_authority: pda::vault_authority(),
+            authority,
+            system_program: system_program::ID,
+            token_program,
+            rent: sysvar::rent::ID,
+        },
+        instruction::AddCollateralAsset { weight_bps },
+    )
+}
+
+pub fn set_collateral_weight(authority: Pubkey, asset_index: u8, weight_bps: u64) -> Instruction {
+    build(
+        accounts::UpdateCollateralRegistry { collateral_registry: pda::collateral_registry(), authority },
+        instruction::SetCollateralWeight { asset_index, weight_bps },
+    )
+}
+
+/// A position owner's token account for one collateral asset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollateralTransfer {
+    pub owner: Pubkey,
+    pub position: Pubkey,
+    pub asset_index: u8,
+    pub mint: Pubkey,
+    pub owner_token_account: Pubkey,
+    pub token_program: Pubkey,
+}
+
+pub fn deposit_collateral(transfer: CollateralTransfer, amount: u64) -> Instruction {
+    build(
+        accounts::DepositCollateral {
+            position: transfer.position,
+            collateral_registry: pda::collateral_registry(),
+            collateral_vault: pda::collateral_vault(&transfer.mint),
+            collateral_mint: transfer.mint,
+            owner_token_account: transfer.owner_token_account,
+            owner: transfer.owner,
+            token_program: transfer.token_program,
+        },
+        instruction::DepositCollateral { asset_index: transfer.asset_index, amount },
+    )
+}
+
+/// Only from a closed position.
+pub fn withdraw_collateral(transfer: CollateralTransfer, amount: u64) -> Instruction {
+    build(
+        accounts::WithdrawCollateral {
+            position: transfer.position,
+            collateral_registry: pda::collateral_registry(),
+            collateral_vault: pda::collateral_vault(&transfer.mint),
+            vault_authority: pda::vault_authority(),
+            collateral_mint: transfer.mint,
+            owner_token_account: transfer.owner_token_account,
+            owner: transfer.owner,
+            token_program: transfer.token_program,
+        },
+        instruction::WithdrawCollateral { asset_index: transfer.asset_index, amount },
+    )
+}
+
+/// What `create_position` opens, in the program's units.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NewPosition {
+    pub entry_price: u64,
+    pub size: u64,
+    pub collateral: i64,
+    pub is_long: bool,
+    pub leverage: u16,
+}
+
+pub fn create_position(owner: Pubkey, position: Pubkey, market: Pubkey, new: NewPosition) -> Instruction {
+    build(
+        accounts::CreatePosition { position, market, owner, system_program: system_program::ID },
+        instruction::CreatePosition {
+            entry_price: new.entry_price,
+            size: new.size,
+            collateral: new.collateral,
+            is_long: new.is_long,
+            leverage: new.leverage,
+        },
+    )
+}
+
+/// Closes `key` for its owner, who must sign. Refused below maintenance.
+pub fn close_position(key: Pubkey, position: &Position, market: &Market, registry: &CollateralRegistry) -> Instruction {
+    let instruction = build(
+        accounts::ClosePosition {
+            position: key,
+            market: position.market,
+            oracle: market.oracle,
+            collateral_registry: pda::collateral_registry(),
+            owner: position.owner,
+        },
+        instruction::ClosePosition {},
+    );
+    with_oracles(instruction, &collateral_oracles(registry, position))
+}
+
+/// Which liquidation instruction to send.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Liquidation {
+    Partial,
+    Full,
+}
+
+/// Accounts shared by `liquidate_partial`, `liquidate_full` and
+/// `liquidate_collateral`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LiquidationAccounts {
+    pub position: Pubkey,
+    pub market: Pubkey,
+    pub insurance_fund: Pubkey,
+    pub quote_mint: Pubkey,
+    pub liquidator_token_account: Pubkey,
+    pub trader_token_account: Pubkey,
+    pub liquidation_record: Pubkey,
+    pub liquidator: Pubkey,
+    pub oracle: Pubkey,
+    pub token_program: Pubkey,
+    /// Oracles of the position's non-quote collateral, passed as remaining
+    /// accounts.
+    pub collateral_oracles: Vec<Pubkey>,
+}
+
+impl LiquidationAccounts {
+    /// Accounts for liquidating `key`, on `market`, with `liquidator` as
+    /// signer. The trader is paid to their associated token account for the
+    /// quote mint.
+    pub fn new(
+        protocol: &Protocol,
+        registry: &CollateralRegistry,
+        key: Pubkey,
+        position: &Position,
+        market: &Market,
+        liquidator: Pubkey,
+        liquidator_token_account: Pubkey,
+    ) -> Self {
+        Self {
+            position: key,
+            market: position.market,
+            insurance_fund: protocol.insurance_fund,
+            quote_mint: protocol.quote_mint,
+            liquidator_token_account,
+            trader_token_account: get_associated_token_address_with_program_id(
+                &position.owner,
+                &protocol.quote_mint,
+                &protocol.token_program,
+            ),
+            liquidation_record: pda::liquidation_record(&key, position.liquidation_count),
+            liquidator,
+            oracle: market.oracle,
+            token_program: protocol.token_program,
+            collateral_oracles: collateral_oracles(registry, position),
+        }
+    }
+
+    pub fn instruction(&self, kind: Liquidation) -> Instruction {
+        let instruction = match kind {
+            Liquidation::Partial => build(
+                accounts::LiquidatePartial {
+                    position: self.position,
+                    market: self.market,
+                    collateral_registry: pda::collateral_registry(),
+                    insurance_fund: self.insurance_fund,
+                    insurance_authority: pda::insurance_authority(),
+                    insurance_vault: pda::insurance_vault(),
+                    vault_authority: pda::vault_authority(),
+                    protocol_vault: pda::protocol_vault(),
+                    quote_mint: self.quote_mint,
+                    liquidator_token_account: self.liquidator_token_account,
+                    trader_token_account: self.trader_token_account,
+                    liquidation_record: self.liquidation_record,
+                    liquidator: self.liquidator,
+                    oracle: self.oracle,
+                    token_program: self.token_program,
+                    system_program: system_program::ID,
+                },
+                instruction::LiquidatePartial {},
+            ),
+            Liquidation::Full => build(
+                accounts::LiquidateFull {
+                    position: self.position,
+                    market: self.market,
+                    collateral_registry: pda::collateral_registry(),
+                    insurance_fund: self.insurance_fund,
+                    insurance_authority: pda::insurance_authority(),
+                    insurance_vault: pda::insurance_vault(),
+                    vault_authority: pda::vault_authority(),
+                    protocol_vault: pda::protocol_vault(),
+                    quote_mint: self.quote_mint,
+                    liquidator_token_account: self.liquidator_token_account,
+                    trader_token_account: self.trader_token_account,
+                    liquidation_record: self.liquidation_record,
+                    liquidator: self.liquidator,
+                    oracle: self.oracle,
+                    token_program: self.token_program,
+                    system_program: system_program::ID,
+                },
+                instruction::LiquidateFull {},
+            ),
+        };
+        with_oracles(instruction, &self.collateral_oracles)
+    }
+
+    /// Repay `repay_amount` quote from the liquidator's token account for
+    /// asset `asset_index` of mint `collateral_mint`, paid out to
+    /// `liquidator_collateral_account`.
+    pub fn collateral_instruction(
+        &self,
+        asset_index: u8,
+        collateral_mint: Pubkey,
+        liquidator_collateral_account: Pubkey,
+        repay_amount: u64,
+    ) -> Instruction {
+        let instruction = build(
+            accounts::LiquidateCollateral {
+                position: self.position,
+                market: self.market,
+                oracle: self.oracle,
+                collateral_registry: pda::collateral_registry(),
+                collateral_vault: pda::collateral_vault(&collateral_mint),
+                protocol_vault: pda::protocol_vault(),
+                vault_authority: pda::vault_authority(),
+                quote_mint: self.quote_mint,
+                collateral_mint,
+                liquidator_quote_account: self.liquidator_token_account,
+                liquidator_collateral_account,
+                liquidator: self.liquidator,
+                token_program: self.token_program,
+            },
+            instruction::LiquidateCollateral { asset_index, repay_amount },
+        );
+        with_oracles(instruction, &self.collateral_oracles)
+    }
+}
+
+/// Permissionless; `keeper` pays the auction's rent and gets it back on
+/// settle.
+pub fn start_collateral_auction(keeper: Pubkey, position: Pubkey, asset_index: u8, collateral_oracle: Pubkey) -> Instruction {
+    build(
+        accounts::StartCollateralAuction {
+            position,
+            collateral_registry: pda::collateral_registry(),
+            collateral_oracle,
+            auction: pda::auction(&position, asset_index),
+            keeper,
+            system_program: system_program::ID,
+        },
+        instruction::StartCollateralAuction { asset_index },
+    )
+}
+
+/// A bidder and the token accounts it pays quote from and receives
+/// collateral into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bidder {
+    pub authority: Pubkey,
+    pub quote_account: Pubkey,
+    pub collateral_account: Pubkey,
+}
+
+pub fn bid_collateral_auction(
+    protocol: &Protocol,
+    auction: &CollateralAuction,
+    bidder: Bidder,
+    max_amount: u64,
+    max_price: u64,
+) -> Instruction {
+    build(
+        accounts::BidCollateralAuction {
+            auction: pda::auction(&auction.position, auction.asset_index),
+            position: auction.position,
+            collateral_registry: pda::collateral_registry(),
+            collateral_vault: pda::collateral_vault(&auction.mint),
+            protocol_vault: pda::protocol_vault(),
+            vault_authority: pda::vault_authority(),
+            quote_mint: protocol.quote_mint,
+            collateral_mint: auction.mint,
+            bidder_quote_account: bidder.quote_account,
+            bidder_collateral_account: bidder.collateral_account,
+            bidder: bidder.authority,
+            token_program: protocol.token_program,
+        },
+        instruction::BidCollateralAuction { max_amount, max_price },
+    )
+}
+
+/// Permissionless; the rent goes back to whoever started the auction.
+pub fn settle_collateral_auction(protocol: &Protocol, auction: &CollateralAuction) -> Instruction {
+    build(
+        accounts::SettleCollateralAuction {
+            auction: pda::auction(&auction.position, auction.asset_index),
+            position: auction.position,
+            payer: auction.payer,
+            insurance_fund: protocol.insurance_fund,
+            insurance_vault: pda::insurance_vault(),
+            insurance_authority: pda::insurance_authority(),
+            protocol_vault: pda::protocol_vault(),
+            quote_mint: protocol.quote_mint,
+            token_program: protocol.token_program,
+        },
+        instruction::SettleCollateralAuction {},
+    )
+}
+
+/// Builders for the mock oracle's instructions, which only `mock-oracle`
+/// builds of the program have.
+#[cfg(feature = "mock-oracle")]
+pub mod mock_oracle {
+    use anchor_lang::prelude::Pubkey;
+    use anchor_lang::solana_program::instruction::Instruction;
+    use anchor_lang::solana_program::system_program;
+    use liquidation_program::mock_oracle::{accounts, instruction};
+    use liquidation_program::oracle::OracleStatus;
+
+    use super::build;
+
+    /// A mock oracle's reading; `publish_time` of `None` is the current clock.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct MockPrice {
+        pub price: i64,
+        pub conf: u64,
+        pub expo: i32,
+        pub publish_time: Option<i64>,
+        pub status: OracleStatus,
+    }
+
+    pub fn initialize_mock_oracle(authority: Pubkey, mock_oracle: Pubkey, price: i64, conf: u64, expo: i32) -> Instruction {
+        build(
+            accounts::InitializeMockOracle { mock_oracle, authority, system_program: system_program::ID },
+            instruction::InitializeMockOracle { price, conf, expo },
+        )
+    }
+
+    pub fn set_mock_oracle(authority: Pubkey, mock_oracle: Pubkey, reading: MockPrice) -> Instruction {
+        build(
+            accounts::SetMockOracle { mock_oracle, authority },
+            instruction::SetMockOracle {
+                price: reading.price,
+                conf: reading.conf,
+                expo: reading.expo,
+                publish_time: reading.publish_time,
+                status: reading.status,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::Discriminator;
+
+    #[test]
+    fn market_instructions_derive_the_market() {
+        let authority = Pubkey::new_unique();
+        let ix = initialize_market(authority, Pubkey::new_unique(), 3, *b"SOL-PERP\0\0\0\0\0\0\0\0", MarketConfig::default());
+        assert_eq!(ix.program_id, liquidation_program::ID);
+        assert_eq!(ix.accounts[0].pubkey, pda::market(3));
+        assert!(ix.accounts[0].is_writable);
+        assert!(ix.accounts[2].is_signer);
+        assert_eq!(&ix.data[..8], &instruction::InitializeMarket::DISCRIMINATOR);
+        // index, then the symbol
+        assert_eq!(&ix.data[8..10], &3u16.to_le_bytes());
+        assert_eq!(&ix.data[10..18], b"SOL-PERP");
+    }
+
+    #[test]
+    fn new_accounts_sign() {
+        let (owner, position, market) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let ix = create_position(owner, position, market, NewPosition { size: 1, ..NewPosition::default() });
+        let signers: Vec<Pubkey> = ix.accounts.iter().filter(|meta| meta.is_signer).map(|meta| meta.pubkey).collect();
+        assert_eq!(signers, vec![position, owner]);
+    }
+
+    #[test]
+    fn auctions_are_found_from_their_account() {
+        let auction = CollateralAuction {
+            position: Pubkey::new_unique(),
+            payer: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            asset_index: 2,
+            ..CollateralAuction::default()
+        };
+        let protocol = Protocol {
+            insurance_fund: Pubkey::new_unique(),
+            quote_mint: Pubkey::new_unique(),
+            token_program: anchor_spl::token::ID,
+        };
+        let ix = settle_collateral_auction(&protocol, &auction);
+        assert_eq!(ix.accounts[0].pubkey, pda::auction(&auction.position, 2));
+        assert_eq!(ix.accounts[2].pubkey, auction.payer);
+        assert!(ix.accounts[2].is_writable);
+    }
+}
@@ -0,0 +1,14 @@
+//! Client side of the liquidation program, for the keeper, `liqctl` and
+//! third-party liquidators: a builder for every instruction, the PDAs its
+//! account constraints check, and decoders for its accounts.
+//!
+//! Everything is built from the program crate's own `accounts` and
+//! `instruction` types, so account order and argument layout follow the
+//! program; `tests/idl.rs` checks the builders against the IDL parsed from
+//! the program source.
+
+pub mod accounts;
+pub mod instructions;
+pub mod pda;
+
+pub use liquidation_program::ID;
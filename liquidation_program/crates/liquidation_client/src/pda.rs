@@ -0,0 +1,54 @@
+//! Program-derived addresses, with the seeds the program's account
+//! constraints check.
+
+use anchor_lang::prelude::Pubkey;
+use liquidation_program::constants::*;
+
+/// Address and bump of the program's PDA for `seeds`.
+pub fn find(seeds: &[&[u8]]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(seeds, &liquidation_program::ID)
+}
+
+fn pda(seeds: &[&[u8]]) -> Pubkey {
+    find(seeds).0
+}
+
+/// Quote vault backing every position.
+pub fn protocol_vault() -> Pubkey {
+    pda(&[VAULT_SEED])
+}
+
+/// Signs transfers out of the protocol vault and the collateral vaults.
+pub fn vault_authority() -> Pubkey {
+    pda(&[VAULT_AUTH_SEED, VAULT_SEED])
+}
+
+pub fn insurance_vault() -> Pubkey {
+    pda(&[INSURANCE_SEED])
+}
+
+pub fn insurance_authority() -> Pubkey {
+    pda(&[INSURANCE_AUTH_SEED, INSURANCE_SEED])
+}
+
+pub fn collateral_registry() -> Pubkey {
+    pda(&[COLLATERAL_REGISTRY_SEED])
+}
+
+/// Holds every deposit of `mint`.
+pub fn collateral_vault(mint: &Pubkey) -> Pubkey {
+    pda(&[COLLATERAL_VAULT_SEED, mint.as_ref()])
+}
+
+pub fn auction(position: &Pubkey, asset_index: u8) -> Pubkey {
+    pda(&[AUCTION_SEED, position.as_ref(), &[asset_index]])
+}
+
+pub fn market(index: u16) -> Pubkey {
+    pda(&[MARKET_SEED, &index.to_le_bytes()])
+}
+
+/// Record written by a position's `count`th liquidation.
+pub fn liquidation_record(position: &Pubkey, count: u32) -> Pubkey {
+    pda(&[LIQ_RECORD_SEED, position.as_ref(), &count.to_le_bytes()])
+}
@@ -0,0 +1,260 @@
+//! Checks the builders against the IDL parsed from the program source:
+//! one builder per instruction, its discriminator, every account's signer
+//! and writable flags in order, and the address of every account the
+//! program derives from `seeds = [...]` or pins to `address = pda::...`.
+
+use std::collections::HashMap;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::AccountDeserialize;
+use anchor_syn::codegen::program::common::sighash;
+use anchor_syn::idl::parse::file::parse;
+use anchor_syn::idl::types::{Idl, IdlAccountItem};
+use anchor_syn::parser::accounts;
+use anchor_syn::parser::context::CrateContext;
+use anchor_syn::AccountField;
+use liquidation_client::accounts::AccountKind;
+use liquidation_client::instructions::*;
+use liquidation_program::constants::*;
+use liquidation_program::pda;
+use liquidation_program::risk_tiers::RiskTier;
+use liquidation_program::{CollateralAuction, CollateralRegistry, Market, Position};
+use quote::ToTokens;
+
+const PROGRAM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../programs/liquidation_program/src/lib.rs");
+
+// the values the sample instructions are built with, for evaluating seeds
+const MARKET_INDEX: u16 = 7;
+const ASSET_INDEX: u8 = 2;
+const LIQUIDATION_COUNT: u32 = 3;
+
+fn idl() -> Idl {
+    parse(PROGRAM, "0.1.0".into(), false, true, false).expect("parsing the program")
+}
+
+/// How the program pins an account's address.
+enum Derivation {
+    Seeds(Vec<String>),
+    Pda(Pubkey),
+}
+
+/// The derivation of every PDA account, by accounts struct and field.
+fn derivations() -> HashMap<(String, String), Derivation> {
+    let context = CrateContext::parse(PROGRAM).expect("parsing the program");
+    let mut derivations = HashMap::new();
+    for item in context.structs() {
+        let derives_accounts = item.attrs.iter().any(|attr| attr.to_token_stream().to_string().contains("Accounts"));
+        if !derives_accounts {
+            continue;
+        }
+        let parsed = accounts::parse(item).expect("parsing an accounts struct");
+        for field in parsed.fields {
+            let AccountField::Field(field) = field else { continue };
+            let key = (parsed.ident.to_string(), field.ident.to_string());
+            if let Some(group) = field.constraints.seeds {
+                let exprs = group.seeds.iter().map(|seed| seed.to_token_stream().to_string()).collect();
+                derivations.insert(key, Derivation::Seeds(exprs));
+            } else if let Some(address) = field.constraints.address {
+                if let Some(address) = program_pda(&address.address.to_token_stream().to_string()) {
+                    derivations.insert(key, Derivation::Pda(address));
+                }
+            }
+        }
+    }
+    derivations
+}
+
+/// The address an `address = pda::...` expression evaluates to; `None` for
+/// addresses read from other accounts.
+fn program_pda(expr: &str) -> Option<Pubkey> {
+    let call = expr.strip_prefix("pda :: ")?;
+    let address = match call {
+        "vault_authority () . 0" => pda::vault_authority().0,
+        "insurance_authority () . 0" => pda::insurance_authority().0,
+        "protocol_vault ()" => pda::protocol_vault(),
+        "insurance_vault ()" => pda::insurance_vault(),
+        "collateral_registry ()" => pda::collateral_registry(),
+        other => panic!("no value for `pda::{other}`; add it to the test"),
+    };
+    Some(address)
+}
+
+/// Accounts struct of each instruction handler, from its `Context<...>`.
+fn contexts() -> HashMap<String, String> {
+    let source = std::fs::read_to_string(PROGRAM).unwrap();
+    let mut contexts = HashMap::new();
+    for handler in source.split("pub fn ").skip(1) {
+        let name = handler.split('(').next().unwrap().trim();
+        let Some(context) = handler.split_once("Context<").map(|(_, rest)| rest.split('>').next().unwrap()) else { continue };
+        contexts.entry(name.to_string()).or_insert_with(|| context.to_string());
+    }
+    contexts
+}
+
+fn snake(camel: &str) -> String {
+    camel.chars().fold(String::new(), |mut snake, c| {
+        if c.is_ascii_uppercase() {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+        snake
+    })
+}
+
+/// A zeroed account, to set only the fields a builder reads.
+fn zeroed<T: AccountDeserialize>(len: usize) -> T {
+    T::try_deserialize_unchecked(&mut &vec![0; len][..]).unwrap()
+}
+
+fn key(n: u8) -> Pubkey {
+    Pubkey::new_from_array([n; 32])
+}
+
+/// One instruction from every builder.
+fn samples() -> Vec<(&'static str, Instruction)> {
+    let (authority, mint, oracle, market, position) = (key(1), key(2), key(3), key(4), key(5));
+    let protocol = Protocol { insurance_fund: key(6), quote_mint: key(7), token_program: anchor_spl::token::ID };
+
+    let mut open: Position = zeroed(Position::LEN);
+    open.owner = authority;
+    open.market = market;
+    open.liquidation_count = LIQUIDATION_COUNT;
+    open.deposits[0] = 1;
+    let mut on_market: Market = zeroed(Market::LEN);
+    on_market.oracle = oracle;
+    let mut registry: CollateralRegistry = zeroed(CollateralRegistry::LEN);
+    registry.num_assets = 1;
+    registry.assets[0].oracle = key(8);
+    let liquidation = LiquidationAccounts::new(&protocol, &registry, position, &open, &on_market, key(9), key(10));
+
+    let transfer = CollateralTransfer {
+        owner: authority,
+        position,
+        asset_index: ASSET_INDEX,
+        mint,
+        owner_token_account: key(11),
+        token_program: protocol.token_program,
+    };
+    let auction = CollateralAuction { position, payer: key(12), mint, asset_index: ASSET_INDEX, ..CollateralAuction::default() };
+    let bidder = Bidder { authority: key(13), quote_account: key(14), collateral_account: key(15) };
+    let tiers = vec![RiskTier { max_notional: 1, maintenance_margin_bps: 250 }];
+
+    vec![
+        ("initialize_insurance_fund", initialize_insurance_fund(authority, protocol.insurance_fund, mint, protocol.token_program, authority)),
+        ("initialize_protocol_vault", initialize_protocol_vault(authority, mint, protocol.token_program)),
+        ("create_position", create_position(authority, position, market, NewPosition::default())),
+        ("close_position", close_position(position, &open, &on_market, &registry)),
+        ("initialize_market", initialize_market(authority, oracle, MARKET_INDEX, [0; 16], MarketConfig::default())),
+        ("update_market_config", update_market_config(authority, market, 1, 1)),
+        ("set_market_limits", set_market_limits(authority, market, 1, 1)),
+        ("set_risk_tiers", set_risk_tiers(authority, market, tiers)),
+        ("refresh_market_price", refresh_market_price(market, oracle)),
+        ("initialize_collateral_registry", initialize_collateral_registry(authority)),
+        ("add_collateral_asset", add_collateral_asset(authority, mint, oracle, protocol.token_program, 8_000)),
+        ("set_collateral_weight", set_collateral_weight(authority, ASSET_INDEX, 8_000)),
+        ("deposit_collateral", deposit_collateral(transfer, 1)),
+        ("withdraw_collateral", withdraw_collateral(transfer, 1)),
+        ("liquidate_collateral", liquidation.collateral_instruction(ASSET_INDEX, mint, key(16), 1)),
+        ("start_collateral_auction", start_collateral_auction(authority, position, ASSET_INDEX, oracle)),
+        ("bid_collateral_auction", bid_collateral_auction(&protocol, &auction, bidder, 1, 1)),
+        ("settle_collateral_auction", settle_collateral_auction(&protocol, &auction)),
+        ("liquidate_partial", liquidation.instruction(Liquidation::Partial)),
+        ("liquidate_full", liquidation.instruction(Liquidation::Full)),
+    ]
+}
+
+/// The bytes of one seed expression, with accounts resolved from the
+/// instruction being checked.
+fn seed(expr: &str, accounts: &HashMap<String, Pubkey>) -> Vec<u8> {
+    let constants: [(&str, &[u8]); 9] = [
+        ("VAULT_SEED", VAULT_SEED),
+        ("VAULT_AUTH_SEED", VAULT_AUTH_SEED),
+        ("INSURANCE_SEED", INSURANCE_SEED),
+        ("INSURANCE_AUTH_SEED", INSURANCE_AUTH_SEED),
+        ("LIQ_RECORD_SEED", LIQ_RECORD_SEED),
+        ("MARKET_SEED", MARKET_SEED),
+        ("COLLATERAL_REGISTRY_SEED", COLLATERAL_REGISTRY_SEED),
+        ("COLLATERAL_VAULT_SEED", COLLATERAL_VAULT_SEED),
+        ("AUCTION_SEED", AUCTION_SEED),
+    ];
+    if let Some((_, bytes)) = constants.iter().find(|(name, _)| *name == expr) {
+        return bytes.to_vec();
+    }
+    if let Some(account) = expr.strip_suffix(". key () . as_ref ()") {
+        return accounts[account.trim()].to_bytes().to_vec();
+    }
+    match expr {
+        "& market_index . to_le_bytes ()" => MARKET_INDEX.to_le_bytes().to_vec(),
+        "& [asset_index]" => vec![ASSET_INDEX],
+        "& position . liquidation_count . to_le_bytes ()" => LIQUIDATION_COUNT.to_le_bytes().to_vec(),
+        other => panic!("no value for seed `{other}`; add it to the test"),
+    }
+}
+
+#[test]
+fn every_instruction_has_a_builder() {
+    let idl = idl();
+    let mut expected: Vec<String> = idl.instructions.iter().map(|ix| snake(&ix.name)).collect();
+    let mut built: Vec<String> = samples().into_iter().map(|(name, _)| name.to_string()).collect();
+    expected.sort();
+    built.sort();
+    assert_eq!(built, expected);
+}
+
+#[test]
+fn builders_match_the_idl() {
+    let idl = idl();
+    let (derivations, contexts) = (derivations(), contexts());
+    let samples: HashMap<_, _> = samples().into_iter().collect();
+
+    for ix in &idl.instructions {
+        let name = snake(&ix.name);
+        let built = &samples[name.as_str()];
+        assert_eq!(built.program_id, liquidation_program::ID);
+        assert_eq!(built.data[..8], sighash("global", &name), "{name}: discriminator");
+
+        let idl_accounts: Vec<_> = ix
+            .accounts
+            .iter()
+            .map(|item| match item {
+                IdlAccountItem::IdlAccount(account) => account,
+                IdlAccountItem::IdlAccounts(_) => panic!("{name}: nested accounts"),
+            })
+            .collect();
+        assert!(built.accounts.len() >= idl_accounts.len(), "{name}: missing accounts");
+        let mut by_name = HashMap::new();
+        for (account, meta) in idl_accounts.iter().zip(&built.accounts) {
+            assert_eq!(meta.is_signer, account.is_signer, "{name}.{}: signer", account.name);
+            assert_eq!(meta.is_writable, account.is_mut, "{name}.{}: writable", account.name);
+            by_name.insert(snake(&account.name), meta.pubkey);
+        }
+        // anything past the IDL's accounts is a read-only oracle
+        for meta in &built.accounts[idl_accounts.len()..] {
+            assert!(!meta.is_signer && !meta.is_writable, "{name}: remaining account {}", meta.pubkey);
+        }
+
+        let context = &contexts[&name];
+        for (field, address) in &by_name {
+            match derivations.get(&(context.clone(), field.clone())) {
+                Some(Derivation::Seeds(exprs)) => {
+                    let bytes: Vec<Vec<u8>> = exprs.iter().map(|expr| seed(expr, &by_name)).collect();
+                    let slices: Vec<&[u8]> = bytes.iter().map(Vec::as_slice).collect();
+                    let (derived, _) = Pubkey::find_program_address(&slices, &liquidation_program::ID);
+                    assert_eq!(*address, derived, "{name}.{field}: seeds {exprs:?}");
+                }
+                Some(Derivation::Pda(derived)) => assert_eq!(address, derived, "{name}.{field}: address"),
+                None => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn every_account_type_decodes() {
+    let mut expected: Vec<String> = idl().accounts.iter().map(|account| account.name.clone()).collect();
+    let mut decoded: Vec<String> = AccountKind::ALL.iter().map(|kind| format!("{kind:?}")).collect();
+    expected.sort();
+    decoded.sort();
+    assert_eq!(decoded, expected);
+}
@@ -0,0 +1,39 @@
+//! Margin and liquidation math shared by the on-chain program and the
+//! liquidation engine backend.
+//!
+//! Prices and sizes are PRICE_PRECISION fixed point, quote amounts are in
+//! quote base units, each behind its own type in `units`. Everything is
+//! computed in i128, rounds in whichever direction favours the protocol, and
+//! returns `None` on overflow or on a result that does not fit its target
+//! type, never a truncated value. The program maps `None` to
+//! `ArithmeticOverflow`; a keeper running the same functions on the same
+//! inputs gets the same numbers the program will.
+
+#![no_std]
+
+pub mod margin;
+pub mod plan;
+pub mod tiers;
+pub mod trigger;
+pub mod units;
+
+pub use margin::*;
+pub use plan::*;
+pub use tiers::*;
+pub use trigger::*;
+pub use units::*;
+
+pub const PRICE_PRECISION: u64 = 1_000_000; // 1e6
+pub const BPS_DENOM: u64 = 10_000;
+pub const LIQUIDATOR_REWARD_BPS: u64 = 250; // 2.5%
+pub const DEFAULT_MAINTENANCE_MARGIN_BPS: u64 = 250; // 2.5%, single tier for new markets
+pub const PARTIAL_LIQUIDATION_BPS: u64 = 5_000; // 50%, the share liquidate_partial closes
+
+/// The parts of a position the margin math reads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PositionTerms {
+    pub entry_price: Price,
+    pub size: Quantity,
+    pub collateral: QuoteAmount,
+    pub is_long: bool,
+}
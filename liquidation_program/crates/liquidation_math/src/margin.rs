@@ -0,0 +1,150 @@
+use crate::units::{Bps, Price, QuoteAmount, Round};
+use crate::PositionTerms;
+
+/// Margin state of a position at one price. Every rounding goes against the
+/// position: PnL and the margin ratio round down, notional rounds up (which
+/// can also only move it into a stricter tier).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HealthReport {
+    pub notional: QuoteAmount,
+    pub pnl: QuoteAmount,
+    /// Collateral + PnL + haircut value of non-quote deposits
+    pub margin: QuoteAmount,
+    /// margin / notional * 10000; unbounded for a position without notional
+    pub margin_ratio_bps: i128,
+    pub maintenance_margin: Bps,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.margin > QuoteAmount::ZERO && self.margin_ratio_bps >= self.maintenance_margin.0 as i128
+    }
+}
+
+/// Health of `terms` at `price`, with `haircut` the haircut value of any
+/// non-quote collateral. `maintenance_bps` maps a notional to its tier's
+/// maintenance margin.
+pub fn evaluate_position(
+    terms: &PositionTerms,
+    price: Price,
+    haircut: QuoteAmount,
+    maintenance_bps: impl Fn(u64) -> u64,
+) -> Option<HealthReport> {
+    let notional = price.notional(terms.size, Round::Up)?;
+    let pnl = price.pnl(terms.entry_price, terms.size, terms.is_long, Round::Down)?;
+    let margin = terms.collateral.checked_add(pnl)?.checked_add(haircut)?;
+
+    Some(HealthReport {
+        notional,
+        pnl,
+        margin,
+        margin_ratio_bps: margin.ratio_bps(notional, Round::Down)?,
+        maintenance_margin: Bps(maintenance_bps(notional.to_u64()?)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Quantity;
+    use crate::{BPS_DENOM, PRICE_PRECISION};
+    use num_bigint::BigInt;
+    use num_integer::Integer;
+    use proptest::prelude::*;
+
+    fn long(entry: u64, size: u64, collateral: i64) -> PositionTerms {
+        PositionTerms {
+            entry_price: Price(entry),
+            size: Quantity(size),
+            collateral: QuoteAmount::from_i64(collateral),
+            is_long: true,
+        }
+    }
+
+    #[test]
+    fn health_example() {
+        // 10 long at 100 on 60 collateral, now 95: -50 PnL, 10 margin on 950
+        let health = evaluate_position(&long(100_000_000, 10_000_000, 60_000_000), Price(95_000_000), QuoteAmount::ZERO, |_| 250).unwrap();
+        assert_eq!(
+            health,
+            HealthReport {
+                notional: QuoteAmount(950_000_000),
+                pnl: QuoteAmount(-50_000_000),
+                margin: QuoteAmount(10_000_000),
+                margin_ratio_bps: 105,
+                maintenance_margin: Bps(250),
+            }
+        );
+        assert!(!health.is_healthy());
+    }
+
+    #[test]
+    fn rounding_goes_against_the_position() {
+        // 0.000001 units bought at 1, now 0.95: the 0.00000095 notional
+        // rounds up to 1, so does the 0.00000005 loss
+        let health = evaluate_position(&long(1_000_000, 1, 0), Price(950_000), QuoteAmount::ZERO, |_| 0).unwrap();
+        assert_eq!(health.notional, QuoteAmount(1));
+        assert_eq!(health.pnl, QuoteAmount(-1));
+        assert_eq!(health.margin_ratio_bps, -(BPS_DENOM as i128));
+
+        // no size, no notional: only the sign of the margin matters
+        let flat = evaluate_position(&long(100_000_000, 0, 5), Price(1), QuoteAmount::ZERO, |_| 250).unwrap();
+        assert_eq!(flat.margin_ratio_bps, i128::MAX);
+        assert!(flat.is_healthy());
+    }
+
+    #[test]
+    fn overflow_is_reported_not_truncated() {
+        let huge = long(0, u64::MAX, 0);
+        assert_eq!(evaluate_position(&huge, Price(u64::MAX), QuoteAmount::ZERO, |_| 0), None);
+        let terms = long(1, 1, i64::MAX);
+        assert_eq!(evaluate_position(&terms, Price(1), QuoteAmount(i128::MAX), |_| 0), None);
+    }
+
+    proptest! {
+        #[test]
+        fn pnl_matches_bigint_reference(
+            entry in any::<u64>(),
+            price in any::<u64>(),
+            size in any::<u64>(),
+            is_long in any::<bool>(),
+        ) {
+            let diff = if is_long {
+                BigInt::from(price) - BigInt::from(entry)
+            } else {
+                BigInt::from(entry) - BigInt::from(price)
+            };
+            let product = diff * BigInt::from(size);
+            let expected = product.div_floor(&BigInt::from(PRICE_PRECISION));
+
+            match Price(price).pnl(Price(entry), Quantity(size), is_long, Round::Down) {
+                Some(pnl) => prop_assert_eq!(BigInt::from(pnl.0), expected),
+                // only refused when the intermediate product can't be held
+                None => prop_assert!(i128::try_from(product).is_err()),
+            }
+        }
+
+        #[test]
+        fn health_matches_bigint_reference(
+            price in any::<u64>(),
+            size in any::<u64>(),
+            collateral in any::<i64>(),
+        ) {
+            let terms = PositionTerms { entry_price: Price(price), ..long(price, size, collateral) };
+            let notional = (BigInt::from(price) * BigInt::from(size)).div_ceil(&BigInt::from(PRICE_PRECISION));
+
+            match evaluate_position(&terms, Price(price), QuoteAmount::ZERO, |_| 0) {
+                Some(health) => {
+                    prop_assert_eq!(BigInt::from(health.notional.0), notional.clone());
+                    prop_assert_eq!(health.margin, QuoteAmount::from_i64(collateral));
+                    if health.notional > QuoteAmount::ZERO {
+                        let ratio = (BigInt::from(collateral) * BigInt::from(BPS_DENOM)).div_floor(&notional);
+                        prop_assert_eq!(BigInt::from(health.margin_ratio_bps), ratio);
+                    }
+                }
+                // only refused when the notional can't be looked up in a tier
+                None => prop_assert!(u64::try_from(notional).is_err()),
+            }
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use crate::margin::*;
+use crate::units::{div_round, Bps, Price, Quantity, QuoteAmount, Round};
+use crate::{PositionTerms, PARTIAL_LIQUIDATION_BPS};
+
+/// Result of closing half of a position. Token amounts come out as the
+/// integer types the program transfers and stores, already range checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartialFill {
+    pub closed_size: Quantity,
+    pub remaining_size: Quantity,
+    /// The remaining size is marked to the liquidation price; the whole
+    /// position's PnL so far is already in `new_collateral`.
+    pub entry_price: Price,
+    pub liquidator_reward: u64,
+    pub new_collateral: i64,
+}
+
+/// What `liquidate_partial` does to a position at a price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartialPlan {
+    /// Above maintenance margin, nothing to do.
+    Healthy,
+    /// Half of the size rounds to nothing.
+    TooSmall,
+    /// Closing half still leaves the rest below maintenance margin.
+    Insufficient,
+    Execute(PartialFill),
+}
+
+/// Plan a 50% liquidation: close half at `price`, book the PnL into the
+/// collateral and pay the liquidator `reward_bps` of the margin the closed
+/// half carried, as `plan_full` does for all of it. The trader is paid
+/// nothing; the rest of the margin stays behind the remaining half. Only
+/// executes if that half ends up healthy. The reward rounds down.
+pub fn plan_partial(
+    terms: &PositionTerms,
+    price: Price,
+    haircut: QuoteAmount,
+    reward_bps: Bps,
+    maintenance_bps: impl Fn(u64) -> u64,
+) -> Option<(HealthReport, PartialPlan)> {
+    plan_partial_fraction(terms, price, haircut, reward_bps, Bps(PARTIAL_LIQUIDATION_BPS), maintenance_bps)
+}
+
+/// `plan_partial` closing `fraction` of the size (rounded down) instead of
+/// half, for trying out other fractions off-chain.
+pub fn plan_partial_fraction(
+    terms: &PositionTerms,
+    price: Price,
+    haircut: QuoteAmount,
+    reward_bps: Bps,
+    fraction: Bps,
+    maintenance_bps: impl Fn(u64) -> u64,
+) -> Option<(HealthReport, PartialPlan)> {
+    let health = evaluate_position(terms, price, haircut, &maintenance_bps)?;
+    if health.is_healthy() {
+        return Some((health, PartialPlan::Healthy));
+    }
+
+    let closed_size = terms.size.portion(fraction)?;
+    if closed_size.is_zero() {
+        return Some((health, PartialPlan::TooSmall));
+    }
+    let remaining_size = terms.size.checked_sub(closed_size)?;
+
+    // no margin left means nothing for the remaining half to stand on
+    if health.margin.is_negative() || health.margin.is_zero() {
+        return Some((health, PartialPlan::Insufficient));
+    }
+    let closed_margin = div_round(health.margin.0.checked_mul(closed_size.0 as i128)?, terms.size.0 as i128, Round::Down)?;
+    let reward = QuoteAmount(closed_margin).apply_bps(reward_bps, Round::Down)?;
+
+    // non-quote deposits stay where they are, so take their value back out
+    let new_collateral = health.margin.checked_sub(haircut)?.checked_sub(reward)?;
+    let remaining = PositionTerms {
+        entry_price: price,
+        size: remaining_size,
+        collateral: new_collateral,
+        is_long: terms.is_long,
+    };
+    let after = evaluate_position(&remaining, price, haircut, &maintenance_bps)?;
+    if !after.is_healthy() {
+        return Some((health, PartialPlan::Insufficient));
+    }
+
+    Some((
+        health,
+        PartialPlan::Execute(PartialFill {
+            closed_size,
+            remaining_size,
+            entry_price: price,
+            liquidator_reward: reward.to_u64()?,
+            new_collateral: new_collateral.to_i64()?,
+        }),
+    ))
+}
+
+/// What `liquidate_full` does to a position at a price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FullPlan {
+    /// Deficit, but non-quote deposits are left for collateral auctions to
+    /// work off; the deficit stays on the position.
+    Deferred { margin: i64 },
+    /// Margin left over: the liquidator takes `reward_bps` of it, the trader
+    /// the rest.
+    Leftover {
+        margin: i64,
+        liquidator_reward: u64,
+        trader_payout: u64,
+    },
+    /// Deficit covered by the insurance vault as far as it goes. The
+    /// liquidator's reward comes out of whatever the fund has left.
+    BadDebt {
+        margin: i64,
+        bad_debt: u64,
+        insurance_covered: u64,
+        liquidator_reward: u64,
+    },
+}
+
+/// Plan closing the whole position at `price`. Only quote collateral counts
+/// towards the margin here. The PnL and the reward round down.
+pub fn plan_full(
+    terms: &PositionTerms,
+    price: Price,
+    has_deposits: bool,
+    insurance_balance: u64,
+    reward_bps: Bps,
+) -> Option<FullPlan> {
+    let pnl = price.pnl(terms.entry_price, terms.size, terms.is_long, Round::Down)?;
+    let margin = terms.collateral.checked_add(pnl)?;
+    let margin_i64 = margin.to_i64()?;
+
+    if margin.is_negative() && has_deposits {
+        return Some(FullPlan::Deferred { margin: margin_i64 });
+    }
+
+    if !margin.is_negative() {
+        let reward = margin.apply_bps(reward_bps, Round::Down)?;
+        return Some(FullPlan::Leftover {
+            margin: margin_i64,
+            liquidator_reward: reward.to_u64()?,
+            trader_payout: margin.checked_sub(reward)?.to_u64()?,
+        });
+    }
+
+    let deficit = margin.checked_neg()?;
+    let bad_debt = deficit.to_u64()?;
+    let max_reward = deficit.apply_bps(reward_bps, Round::Down)?.to_u64()?;
+    let (insurance_covered, liquidator_reward) = cover_bad_debt(bad_debt, max_reward, insurance_balance);
+
+    Some(FullPlan::BadDebt {
+        margin: margin_i64,
+        bad_debt,
+        insurance_covered,
+        liquidator_reward,
+    })
+}
+
+/// What an insurance vault holding `insurance_balance` pays towards a full
+/// liquidation's deficit: as much of `bad_debt` as it can, then as much of
+/// the liquidator's `max_reward` as it has left.
+pub fn cover_bad_debt(bad_debt: u64, max_reward: u64, insurance_balance: u64) -> (u64, u64) {
+    let covered = bad_debt.min(insurance_balance);
+    (covered, max_reward.min(insurance_balance - covered))
+}
+
+/// What a keeper should send for a position at a price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiquidationPlan {
+    /// Above maintenance margin; both instructions are no-ops.
+    Healthy,
+    /// `liquidate_partial` closes half and leaves the rest healthy.
+    Partial(PartialFill),
+    /// Half is not enough (or too small to split): `liquidate_full`.
+    Full(FullPlan),
+}
+
+/// Everything the liquidation instructions read besides the position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LiquidationContext {
+    /// Haircut value of the position's non-quote collateral.
+    pub haircut: QuoteAmount,
+    /// The position still holds non-quote collateral.
+    pub has_deposits: bool,
+    /// Quote in the insurance vault.
+    pub insurance_balance: u64,
+    pub reward_bps: Bps,
+}
+
+/// The liquidation the program will carry out for `terms` at `price`:
+/// partial if that restores health, full otherwise.
+pub fn plan_liquidation(
+    terms: &PositionTerms,
+    price: Price,
+    context: &LiquidationContext,
+    maintenance_bps: impl Fn(u64) -> u64,
+) -> Option<(HealthReport, LiquidationPlan)> {
+    plan_liquidation_fraction(terms, price, context, Bps(PARTIAL_LIQUIDATION_BPS), maintenance_bps)
+}
+
+/// `plan_liquidation` with the partial step closing `fraction` of the size;
+/// see `plan_partial_fraction`.
+pub fn plan_liquidation_fraction(
+    terms: &PositionTerms,
+    price: Price,
+    context: &LiquidationContext,
+    fraction: Bps,
+    maintenance_bps: impl Fn(u64) -> u64,
+) -> Option<(HealthReport, LiquidationPlan)> {
+    let (health, partial) =
+        plan_partial_fraction(terms, price, context.haircut, context.reward_bps, fraction, maintenance_bps)?;
+
+    let plan = match partial {
+        PartialPlan::Healthy => LiquidationPlan::Healthy,
+        PartialPlan::Execute(fill) => LiquidationPlan::Partial(fill),
+        PartialPlan::TooSmall | PartialPlan::Insufficient => LiquidationPlan::Full(plan_full(
+            terms,
+            price,
+            context.has_deposits,
+            context.insurance_balance,
+            context.reward_bps,
+        )?),
+    };
+
+    Some((health, plan))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BPS_DENOM, DEFAULT_MAINTENANCE_MARGIN_BPS, LIQUIDATOR_REWARD_BPS, PRICE_PRECISION};
+    use proptest::prelude::*;
+
+    const MMR_BPS: u64 = DEFAULT_MAINTENANCE_MARGIN_BPS;
+    const REWARD: Bps = Bps(LIQUIDATOR_REWARD_BPS);
+
+    fn flat_tier(_notional: u64) -> u64 {
+        MMR_BPS
+    }
+
+    /// Position opened at `entry` with `leverage` times its collateral.
+    fn terms(entry: u64, size: u64, leverage: u64, is_long: bool) -> Option<PositionTerms> {
+        let notional = Price(entry).notional(Quantity(size), Round::Down)?;
+        Some(PositionTerms {
+            entry_price: Price(entry),
+            size: Quantity(size),
+            collateral: QuoteAmount(notional.0 / leverage as i128),
+            is_long,
+        })
+    }
+
+    /// 10 long at 100 on 60 of collateral.
+    fn example() -> PositionTerms {
+        PositionTerms {
+            entry_price: Price(100_000_000),
+            size: Quantity(10_000_000),
+            collateral: QuoteAmount(60_000_000),
+            is_long: true,
+        }
+    }
+
+    // 0.000001 to 1_000_000 per unit and up to a million units, so every
+    // notional and margin fits the i64 fields it ends up in
+    fn price() -> impl Strategy<Value = u64> {
+        1..=1_000_000 * PRICE_PRECISION
+    }
+
+    fn size() -> impl Strategy<Value = u64> {
+        1..=1_000_000 * PRICE_PRECISION
+    }
+
+    // how bad an outcome is for the position; grows as the price moves against it
+    fn severity(plan: &PartialPlan) -> u8 {
+        match plan {
+            PartialPlan::Healthy => 0,
+            PartialPlan::Execute(_) => 1,
+            PartialPlan::Insufficient | PartialPlan::TooSmall => 2,
+        }
+    }
+
+    fn full_margin(plan: &FullPlan) -> i64 {
+        match *plan {
+            FullPlan::Deferred { margin }
+            | FullPlan::Leftover { margin, .. }
+            | FullPlan::BadDebt { margin, .. } => margin,
+        }
+    }
+
+    // prices ordered from worst to best for the side
+    fn worse_then_better(a: u64, b: u64, is_long: bool) -> (u64, u64) {
+        if is_long == (a <= b) {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    #[test]
+    fn partial_example() {
+        // margin 60 - 40 = 20 on 960 notional; the closed half carried 10
+        // of it, 2.5% of that goes to the liquidator and 19.75 stays behind
+        // the other half, against 12 required on 480
+        let t = example();
+        let (health, plan) = plan_partial(&t, Price(96_000_000), QuoteAmount::ZERO, REWARD, flat_tier).unwrap();
+        assert_eq!(health.margin, QuoteAmount(20_000_000));
+        assert_eq!(
+            plan,
+            PartialPlan::Execute(PartialFill {
+                closed_size: Quantity(5_000_000),
+                remaining_size: Quantity(5_000_000),
+                entry_price: Price(96_000_000),
+                liquidator_reward: 250_000,
+                new_collateral: 19_750_000,
+            })
+        );
+
+        let (_, plan) = plan_partial(&t, Price(100_000_000), QuoteAmount::ZERO, REWARD, flat_tier).unwrap();
+        assert_eq!(plan, PartialPlan::Healthy);
+        // 9.875 left of 10 against 11.875 required on 475
+        let (_, plan) = plan_partial(&t, Price(95_000_000), QuoteAmount::ZERO, REWARD, flat_tier).unwrap();
+        assert_eq!(plan, PartialPlan::Insufficient);
+        let (_, plan) = plan_partial(&t, Price(40_000_000), QuoteAmount::ZERO, REWARD, flat_tier).unwrap();
+        assert_eq!(plan, PartialPlan::Insufficient);
+    }
+
+    #[test]
+    fn full_examples() {
+        let t = example();
+        assert_eq!(
+            plan_full(&t, Price(95_000_000), false, 0, REWARD),
+            Some(FullPlan::Leftover { margin: 10_000_000, liquidator_reward: 250_000, trader_payout: 9_750_000 })
+        );
+        assert_eq!(
+            plan_full(&t, Price(90_000_000), true, 0, REWARD),
+            Some(FullPlan::Deferred { margin: -40_000_000 })
+        );
+        assert_eq!(
+            plan_full(&t, Price(90_000_000), false, 100_000_000, REWARD),
+            Some(FullPlan::BadDebt {
+                margin: -40_000_000,
+                bad_debt: 40_000_000,
+                insurance_covered: 40_000_000,
+                liquidator_reward: 1_000_000,
+            })
+        );
+        assert_eq!(
+            plan_full(&t, Price(90_000_000), false, 20_000_000, REWARD),
+            Some(FullPlan::BadDebt {
+                margin: -40_000_000,
+                bad_debt: 40_000_000,
+                insurance_covered: 20_000_000,
+                liquidator_reward: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn rounding_favours_the_protocol() {
+        // 0.000003 units bought at 1, now 0.5: the 0.0000015 loss books as
+        // 2 base units, the 0.000001 half sells for 0.0000005, i.e. nothing
+        let t = PositionTerms {
+            entry_price: Price(1_000_000),
+            size: Quantity(3),
+            collateral: QuoteAmount(1),
+            is_long: true,
+        };
+        let (health, plan) = plan_partial(&t, Price(500_000), QuoteAmount::ZERO, REWARD, flat_tier).unwrap();
+        assert_eq!(health.pnl, QuoteAmount(-2));
+        assert_eq!(plan, PartialPlan::Insufficient);
+
+        // 399 of margin pays a 9.975 reward as 9
+        let t = PositionTerms { collateral: QuoteAmount(399), ..example() };
+        assert_eq!(
+            plan_full(&t, Price(100_000_000), false, 0, REWARD),
+            Some(FullPlan::Leftover { margin: 399, liquidator_reward: 9, trader_payout: 390 })
+        );
+    }
+
+    #[test]
+    fn liquidation_plan_prefers_partial() {
+        let t = example();
+        let context = LiquidationContext { reward_bps: REWARD, ..LiquidationContext::default() };
+
+        let plan = |price| plan_liquidation(&t, Price(price), &context, flat_tier).unwrap().1;
+        assert_eq!(plan(100_000_000), LiquidationPlan::Healthy);
+        assert!(matches!(plan(96_000_000), LiquidationPlan::Partial(_)));
+        assert!(matches!(plan(95_000_000), LiquidationPlan::Full(FullPlan::Leftover { .. })));
+        assert_eq!(
+            plan(40_000_000),
+            LiquidationPlan::Full(FullPlan::BadDebt {
+                margin: -540_000_000,
+                bad_debt: 540_000_000,
+                insurance_covered: 0,
+                liquidator_reward: 0,
+            })
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics(
+            entry in any::<u64>(),
+            price in any::<u64>(),
+            size in any::<u64>(),
+            collateral in any::<i64>(),
+            haircut in 0..=u64::MAX as i128,
+            is_long in any::<bool>(),
+            deposits in any::<bool>(),
+            insurance in any::<u64>(),
+            reward_bps in 0..=BPS_DENOM,
+            mmr_bps in 0..=BPS_DENOM,
+        ) {
+            let t = PositionTerms {
+                entry_price: Price(entry),
+                size: Quantity(size),
+                collateral: QuoteAmount::from_i64(collateral),
+                is_long,
+            };
+            let (price, haircut, reward_bps) = (Price(price), QuoteAmount(haircut), Bps(reward_bps));
+            let _ = plan_partial(&t, price, haircut, reward_bps, |_| mmr_bps);
+            let _ = plan_full(&t, price, deposits, insurance, reward_bps);
+            let context = LiquidationContext { haircut, has_deposits: deposits, insurance_balance: insurance, reward_bps };
+            let _ = plan_liquidation(&t, price, &context, |_| mmr_bps);
+        }
+
+        #[test]
+        fn partial_fill_adds_up(
+            entry in price(),
+            price in price(),
+            size in size(),
+            leverage in 1u64..=100,
+            is_long in any::<bool>(),
+        ) {
+            let t = terms(entry, size, leverage, is_long).unwrap();
+            let price = Price(price);
+            let (health, plan) = plan_partial(&t, price, QuoteAmount::ZERO, REWARD, flat_tier).unwrap();
+            prop_assert_eq!(health.is_healthy(), plan == PartialPlan::Healthy);
+
+            if let PartialPlan::Execute(fill) = plan {
+                prop_assert_eq!(fill.closed_size.0 + fill.remaining_size.0, size);
+                prop_assert!(!fill.closed_size.is_zero() && fill.closed_size <= fill.remaining_size);
+
+                // the remaining half is back above maintenance margin
+                let rest = PositionTerms {
+                    entry_price: fill.entry_price,
+                    size: fill.remaining_size,
+                    collateral: QuoteAmount::from_i64(fill.new_collateral),
+                    is_long,
+                };
+                let after = evaluate_position(&rest, price, QuoteAmount::ZERO, flat_tier).unwrap();
+                prop_assert!(after.is_healthy());
+
+                // the margin is split between the liquidator and what stays
+                // on the position, so the trader is left with no more value
+                // than the position had
+                let reward = QuoteAmount::from_u64(fill.liquidator_reward);
+                prop_assert_eq!(after.margin.checked_add(reward).unwrap(), health.margin);
+                prop_assert!(after.margin <= health.margin);
+            }
+        }
+
+        #[test]
+        fn full_plan_adds_up(
+            entry in price(),
+            price in price(),
+            size in size(),
+            leverage in 1u64..=100,
+            is_long in any::<bool>(),
+            insurance in any::<u64>(),
+        ) {
+            let t = terms(entry, size, leverage, is_long).unwrap();
+            let price = Price(price);
+            let pnl = price.pnl(t.entry_price, t.size, is_long, Round::Down).unwrap();
+            let expected_margin = t.collateral.checked_add(pnl).unwrap();
+
+            match plan_full(&t, price, false, insurance, REWARD).unwrap() {
+                FullPlan::Leftover { margin, liquidator_reward, trader_payout } => {
+                    prop_assert_eq!(QuoteAmount::from_i64(margin), expected_margin);
+                    prop_assert_eq!(liquidator_reward + trader_payout, margin as u64);
+                }
+                FullPlan::BadDebt { margin, bad_debt, insurance_covered, liquidator_reward } => {
+                    prop_assert_eq!(QuoteAmount::from_i64(margin), expected_margin);
+                    prop_assert_eq!(bad_debt, margin.unsigned_abs());
+                    prop_assert!(insurance_covered <= bad_debt);
+                    prop_assert!(insurance_covered + liquidator_reward <= insurance);
+                    // the fund pays the whole deficit before it pays any reward
+                    prop_assert!(liquidator_reward == 0 || insurance_covered == bad_debt);
+                }
+                FullPlan::Deferred { .. } => prop_assert!(false, "no deposits to defer to"),
+            }
+        }
+
+        #[test]
+        fn partial_outcome_is_monotonic_in_price(
+            entry in price(),
+            a in price(),
+            b in price(),
+            size in 2..=1_000_000 * PRICE_PRECISION,
+            leverage in 1u64..=100,
+            is_long in any::<bool>(),
+        ) {
+            let t = terms(entry, size, leverage, is_long).unwrap();
+            let (worse, better) = worse_then_better(a, b, is_long);
+
+            let (_, at_worse) = plan_partial(&t, Price(worse), QuoteAmount::ZERO, REWARD, flat_tier).unwrap();
+            let (_, at_better) = plan_partial(&t, Price(better), QuoteAmount::ZERO, REWARD, flat_tier).unwrap();
+            prop_assert!(
+                severity(&at_worse) >= severity(&at_better),
+                "{:?} at {} but {:?} at {}", at_worse, worse, at_better, better
+            );
+        }
+
+        #[test]
+        fn full_outcome_is_monotonic_in_price(
+            entry in price(),
+            a in price(),
+            b in price(),
+            size in size(),
+            leverage in 1u64..=100,
+            is_long in any::<bool>(),
+            insurance in any::<u64>(),
+        ) {
+            let t = terms(entry, size, leverage, is_long).unwrap();
+            let (worse, better) = worse_then_better(a, b, is_long);
+
+            let at_worse = plan_full(&t, Price(worse), false, insurance, REWARD).unwrap();
+            let at_better = plan_full(&t, Price(better), false, insurance, REWARD).unwrap();
+            prop_assert!(full_margin(&at_worse) <= full_margin(&at_better));
+
+            let payout = |plan: &FullPlan| match *plan {
+                FullPlan::Leftover { trader_payout, .. } => trader_payout,
+                _ => 0,
+            };
+            let bad_debt = |plan: &FullPlan| match *plan {
+                FullPlan::BadDebt { bad_debt, .. } => bad_debt,
+                _ => 0,
+            };
+            prop_assert!(payout(&at_worse) <= payout(&at_better));
+            prop_assert!(bad_debt(&at_worse) >= bad_debt(&at_better));
+        }
+    }
+}
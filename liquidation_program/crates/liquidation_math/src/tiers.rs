@@ -0,0 +1,39 @@
+use crate::DEFAULT_MAINTENANCE_MARGIN_BPS;
+
+/// One maintenance margin tier. A position uses the first tier whose
+/// `max_notional` covers its current notional.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tier {
+    pub max_notional: u64,             // quote, PRICE_PRECISION scaled
+    pub maintenance_margin_bps: u64,
+}
+
+/// Maintenance margin for a position of `notional` (size * price, computed
+/// at liquidation time). Notionals above the last tier use the last tier;
+/// no tiers at all means the default.
+pub fn tier_maintenance_margin_bps(tiers: &[Tier], notional: u64) -> u64 {
+    tiers
+        .iter()
+        .find(|tier| notional <= tier.max_notional)
+        .or(tiers.last())
+        .map(|tier| tier.maintenance_margin_bps)
+        .unwrap_or(DEFAULT_MAINTENANCE_MARGIN_BPS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_first_covering_tier() {
+        let tiers = [
+            Tier { max_notional: 100, maintenance_margin_bps: 50 },
+            Tier { max_notional: 1_000, maintenance_margin_bps: 100 },
+        ];
+        assert_eq!(tier_maintenance_margin_bps(&tiers, 0), 50);
+        assert_eq!(tier_maintenance_margin_bps(&tiers, 100), 50);
+        assert_eq!(tier_maintenance_margin_bps(&tiers, 101), 100);
+        assert_eq!(tier_maintenance_margin_bps(&tiers, u64::MAX), 100);
+        assert_eq!(tier_maintenance_margin_bps(&[], 1), DEFAULT_MAINTENANCE_MARGIN_BPS);
+    }
+}
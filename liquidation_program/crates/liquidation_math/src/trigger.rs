@@ -0,0 +1,222 @@
+use crate::margin::evaluate_position;
+use crate::tiers::Tier;
+use crate::units::{Price, QuoteAmount};
+use crate::{PositionTerms, BPS_DENOM, DEFAULT_MAINTENANCE_MARGIN_BPS, PRICE_PRECISION};
+
+/// Where a position's liquidation starts, in its market's price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LiquidationTrigger {
+    /// A long can only be liquidated at or below this price, a short at or
+    /// above it.
+    At(Price),
+    /// Healthy at every price.
+    Never,
+}
+
+impl LiquidationTrigger {
+    /// A trigger every price crosses.
+    pub fn always(is_long: bool) -> Self {
+        Self::At(Price(if is_long { u64::MAX } else { 0 }))
+    }
+
+    /// Whether `price` is on the liquidatable side of the trigger.
+    pub fn is_crossed(self, price: Price, is_long: bool) -> bool {
+        match self {
+            Self::At(trigger) if is_long => price <= trigger,
+            Self::At(trigger) => price >= trigger,
+            Self::Never => false,
+        }
+    }
+}
+
+/// Bound on the prices at which `evaluate_position` can find `terms`
+/// unhealthy, given `haircut` and the market's `tiers`. Any price on the
+/// healthy side of the trigger is healthy, so a keeper only has to evaluate
+/// positions whose trigger the price has crossed.
+///
+/// Within one tier the margin ratio moves monotonically with price, so each
+/// tier gives one boundary; the trigger is the outermost of those over the
+/// prices at which that tier applies. Rounding is allowed for by solving
+/// with one quote unit less margin and one more notional than exact, so the
+/// trigger may sit a hair inside the exact liquidation price but never past
+/// it. `None` on overflow; treat that as `LiquidationTrigger::always`.
+pub fn liquidation_trigger(terms: &PositionTerms, haircut: QuoteAmount, tiers: &[Tier]) -> Option<LiquidationTrigger> {
+    let equity = terms.collateral.checked_add(haircut)?;
+    let size = terms.size.0 as i128;
+    if size == 0 {
+        // no notional: healthy exactly when the margin is positive
+        let healthy = evaluate_position(terms, Price(0), haircut, |_| 0)?.is_healthy();
+        return Some(if healthy { LiquidationTrigger::Never } else { LiquidationTrigger::always(terms.is_long) });
+    }
+
+    let default = [Tier { max_notional: u64::MAX, maintenance_margin_bps: DEFAULT_MAINTENANCE_MARGIN_BPS }];
+    let tiers = if tiers.is_empty() { &default[..] } else { tiers };
+
+    let precision = PRICE_PRECISION as i128;
+    let bps = BPS_DENOM as i128;
+    let max_price = u64::MAX as i128;
+    // size * entry - PRICE_PRECISION * (equity - 1): the slack-adjusted
+    // break-even notional, PRICE_PRECISION scaled
+    let entry_notional = size.checked_mul(terms.entry_price.0 as i128)?;
+    let scaled_equity = equity.0.checked_sub(1)?.checked_mul(precision)?;
+
+    let mut trigger: Option<i128> = None;
+    let mut below: Option<u64> = None;
+    for (i, tier) in tiers.iter().enumerate() {
+        // prices at which the rounded-up notional falls in this tier
+        let first = match below {
+            None => 0,
+            Some(lo) => lo as i128 * precision / size + 1,
+        };
+        let last = if i + 1 == tiers.len() { max_price } else { (tier.max_notional as i128 * precision / size).min(max_price) };
+        below = Some(tier.max_notional);
+        if first > last {
+            continue;
+        }
+
+        let m = tier.maintenance_margin_bps as i128;
+        if terms.is_long {
+            // healthy where size * p * (bps - m) > precision * m + bps * (size * entry - scaled_equity)
+            let rhs = bps.checked_mul(entry_notional.checked_sub(scaled_equity)?)?.checked_add(precision * m)?;
+            let last_unhealthy = if m >= bps { last } else { rhs.div_euclid(size.checked_mul(bps - m)?).min(last) };
+            if last_unhealthy >= first {
+                trigger = Some(trigger.map_or(last_unhealthy, |t| t.max(last_unhealthy)));
+            }
+        } else {
+            // healthy where size * p * (bps + m) < bps * (scaled_equity + size * entry) - precision * m
+            let rhs = bps.checked_mul(scaled_equity.checked_add(entry_notional)?)?.checked_sub(precision * m)?;
+            let denominator = size.checked_mul(bps + m)?;
+            let first_unhealthy = if rhs <= 0 { 0 } else { (rhs + denominator - 1) / denominator }.max(first);
+            if first_unhealthy <= last {
+                trigger = Some(trigger.map_or(first_unhealthy, |t| t.min(first_unhealthy)));
+            }
+        }
+    }
+
+    Some(match trigger {
+        Some(price) => LiquidationTrigger::At(Price(u64::try_from(price).ok()?)),
+        None => LiquidationTrigger::Never,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tiers::tier_maintenance_margin_bps;
+    use std::vec::Vec;
+    use crate::units::Quantity;
+    use proptest::prelude::*;
+
+    fn terms(entry: u64, size: u64, collateral: i64, is_long: bool) -> PositionTerms {
+        PositionTerms {
+            entry_price: Price(entry),
+            size: Quantity(size),
+            collateral: QuoteAmount::from_i64(collateral),
+            is_long,
+        }
+    }
+
+    fn healthy(terms: &PositionTerms, price: u64, tiers: &[Tier]) -> bool {
+        evaluate_position(terms, Price(price), QuoteAmount::ZERO, |n| tier_maintenance_margin_bps(tiers, n))
+            .unwrap()
+            .is_healthy()
+    }
+
+    #[test]
+    fn trigger_sits_at_the_liquidation_price() {
+        // 10 long at 100 on 60 collateral, 2.5%: 60 + 10 (p - 100) = 0.25 p
+        // at p = 96.410256...
+        let long = terms(100_000_000, 10_000_000, 60_000_000, true);
+        let LiquidationTrigger::At(Price(at)) = liquidation_trigger(&long, QuoteAmount::ZERO, &[]).unwrap() else {
+            panic!("long has a trigger");
+        };
+        assert!((96_410_256..96_410_300).contains(&at), "{at}");
+        assert!(healthy(&long, at + 1, &[]));
+        assert!(!healthy(&long, at - 100, &[]));
+
+        // the mirror short: 60 + 10 (100 - p) = 0.25 p at p = 103.414634...
+        let short = terms(100_000_000, 10_000_000, 60_000_000, false);
+        let LiquidationTrigger::At(Price(at)) = liquidation_trigger(&short, QuoteAmount::ZERO, &[]).unwrap() else {
+            panic!("short has a trigger");
+        };
+        assert!((103_414_600..=103_414_635).contains(&at), "{at}");
+        assert!(healthy(&short, at - 1, &[]));
+        assert!(!healthy(&short, at + 100, &[]));
+    }
+
+    #[test]
+    fn tiers_and_edge_cases() {
+        // a long backed by more than its notional is never liquidated
+        let unlevered = terms(100_000_000, 10_000_000, 2_000_000_000, true);
+        assert_eq!(liquidation_trigger(&unlevered, QuoteAmount::ZERO, &[]), Some(LiquidationTrigger::Never));
+
+        // a short always has a price that wipes it out
+        let short = terms(100_000_000, 10_000_000, 2_000_000_000, false);
+        assert!(matches!(liquidation_trigger(&short, QuoteAmount::ZERO, &[]), Some(LiquidationTrigger::At(_))));
+
+        // haircut collateral counts as margin
+        let long = terms(100_000_000, 10_000_000, 60_000_000, true);
+        let with_haircut = liquidation_trigger(&long, QuoteAmount(100_000_000), &[]).unwrap();
+        assert!(with_haircut < liquidation_trigger(&long, QuoteAmount::ZERO, &[]).unwrap());
+
+        // a 50% tier above 900 notional applies from 90 up and keeps the
+        // position liquidatable until 60 + 10 (p - 100) = 5 p at p = 188
+        let tiers = [
+            Tier { max_notional: 900_000_000, maintenance_margin_bps: 250 },
+            Tier { max_notional: u64::MAX, maintenance_margin_bps: 5_000 },
+        ];
+        let LiquidationTrigger::At(Price(at)) = liquidation_trigger(&long, QuoteAmount::ZERO, &tiers).unwrap() else {
+            panic!("long has a trigger");
+        };
+        assert!((187_999_900..=188_000_000).contains(&at), "{at}");
+        assert!(healthy(&long, at + 1, &tiers));
+        assert!(!healthy(&long, at - 100, &tiers));
+
+        // no size: depends on the margin only
+        let flat = terms(100_000_000, 0, 1, true);
+        assert_eq!(liquidation_trigger(&flat, QuoteAmount::ZERO, &[]), Some(LiquidationTrigger::Never));
+        let broke = terms(100_000_000, 0, -1, false);
+        assert_eq!(liquidation_trigger(&broke, QuoteAmount::ZERO, &[]), Some(LiquidationTrigger::always(false)));
+    }
+
+    fn price() -> impl Strategy<Value = u64> {
+        1..=1_000_000 * PRICE_PRECISION
+    }
+
+    fn tiers() -> impl Strategy<Value = Vec<Tier>> {
+        prop::collection::vec((1..=1_000_000_000 * PRICE_PRECISION, 1..=BPS_DENOM), 0..4).prop_map(|mut raw| {
+            raw.sort();
+            raw.dedup_by_key(|(max_notional, _)| *max_notional);
+            let mut bps = 0;
+            raw.into_iter()
+                .map(|(max_notional, margin)| {
+                    bps = margin.max(bps);
+                    Tier { max_notional, maintenance_margin_bps: bps }
+                })
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn healthy_on_the_safe_side_of_the_trigger(
+            entry in price(),
+            size in 1..=1_000_000 * PRICE_PRECISION,
+            collateral in -1_000_000_000 * PRICE_PRECISION as i64..=1_000_000_000 * PRICE_PRECISION as i64,
+            haircut in 0..=1_000_000 * PRICE_PRECISION as i128,
+            is_long in any::<bool>(),
+            tiers in tiers(),
+            price in price(),
+        ) {
+            let t = terms(entry, size, collateral, is_long);
+            let haircut = QuoteAmount(haircut);
+            let trigger = liquidation_trigger(&t, haircut, &tiers).unwrap_or(LiquidationTrigger::always(is_long));
+            if !trigger.is_crossed(Price(price), is_long) {
+                let health = evaluate_position(&t, Price(price), haircut, |n| tier_maintenance_margin_bps(&tiers, n)).unwrap();
+                prop_assert!(health.is_healthy(), "{:?} at {} with trigger {:?}", health, price, trigger);
+            }
+        }
+    }
+}
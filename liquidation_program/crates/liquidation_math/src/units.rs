@@ -0,0 +1,276 @@
+//! Fixed-point units. Each kind of number gets its own type, so a price can't
+//! be passed where a size is expected, and the only arithmetic on offer is
+//! checked and names its rounding direction. Nothing implements the `std::ops`
+//! traits, so nothing can overflow or truncate silently.
+
+use crate::{BPS_DENOM, PRICE_PRECISION};
+
+/// Which way to round a result that falls between two representable values.
+/// `Down` is towards negative infinity, `Up` towards positive infinity,
+/// `Nearest` to the closer of the two with ties away from zero.
+///
+/// Callers choose whichever direction favours the protocol: amounts paid
+/// out or credited round down, losses and requirements round up. `Nearest`
+/// is for converting prices, which favour no one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Round {
+    Down,
+    Up,
+    Nearest,
+}
+
+/// `numerator / denominator` rounded in `round`'s direction. `None` if
+/// `denominator` isn't positive or the result overflows.
+pub fn div_round(numerator: i128, denominator: i128, round: Round) -> Option<i128> {
+    if denominator <= 0 {
+        return None;
+    }
+    let quotient = numerator.div_euclid(denominator);
+    let remainder = numerator.rem_euclid(denominator);
+    if remainder == 0 {
+        return Some(quotient);
+    }
+    match round {
+        Round::Down => Some(quotient),
+        Round::Up => quotient.checked_add(1),
+        // the quotient is the floor, so a tie rounds up when positive and
+        // stays put when negative, away from zero either way
+        Round::Nearest => {
+            let rest = denominator - remainder;
+            if remainder > rest || (remainder == rest && numerator > 0) {
+                quotient.checked_add(1)
+            } else {
+                Some(quotient)
+            }
+        }
+    }
+}
+
+/// Quote per whole unit of base, PRICE_PRECISION fixed point.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Price(pub u64);
+
+/// Base size, PRICE_PRECISION fixed point.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Quantity(pub u64);
+
+/// Quote in base units of the quote mint. Signed, since PnL and margin can be
+/// negative; token transfers take the `to_u64` of one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct QuoteAmount(pub i128);
+
+/// Basis points, 10_000 = 100%.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Bps(pub u64);
+
+impl Price {
+    /// Value of `quantity` at this price.
+    pub fn notional(self, quantity: Quantity, round: Round) -> Option<QuoteAmount> {
+        // a u64 by u64 product only fits unsigned, the quotient fits either
+        let product = (self.0 as u128) * (quantity.0 as u128);
+        let precision = PRICE_PRECISION as u128;
+        let quotient = product / precision;
+        let remainder = product % precision;
+        let up = match round {
+            Round::Down => false,
+            Round::Up => remainder > 0,
+            Round::Nearest => remainder > 0 && remainder >= precision - remainder,
+        };
+        (quotient + up as u128).try_into().ok().map(QuoteAmount)
+    }
+
+    /// Gain per whole unit of a position opened at `entry` and marked at
+    /// this price. Long: price - entry. Short: entry - price.
+    pub fn pnl_per_unit(self, entry: Price, is_long: bool) -> i128 {
+        if is_long {
+            self.0 as i128 - entry.0 as i128
+        } else {
+            entry.0 as i128 - self.0 as i128
+        }
+    }
+
+    /// PnL of `quantity` opened at `entry` and marked at this price.
+    pub fn pnl(self, entry: Price, quantity: Quantity, is_long: bool, round: Round) -> Option<QuoteAmount> {
+        let product = self.pnl_per_unit(entry, is_long).checked_mul(quantity.0 as i128)?;
+        div_round(product, PRICE_PRECISION as i128, round).map(QuoteAmount)
+    }
+}
+
+impl Quantity {
+    pub const ZERO: Quantity = Quantity(0);
+
+    /// Half of the size, rounded down.
+    pub fn half(self) -> Quantity {
+        Quantity(self.0 / 2)
+    }
+
+    /// `bps` of the size, rounded down.
+    pub fn portion(self, bps: Bps) -> Option<Quantity> {
+        let product = (self.0 as u128).checked_mul(bps.0 as u128)?;
+        (product / BPS_DENOM as u128).try_into().ok().map(Quantity)
+    }
+
+    pub fn checked_sub(self, other: Quantity) -> Option<Quantity> {
+        self.0.checked_sub(other.0).map(Quantity)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl QuoteAmount {
+    pub const ZERO: QuoteAmount = QuoteAmount(0);
+
+    pub fn from_u64(amount: u64) -> Self {
+        QuoteAmount(amount as i128)
+    }
+
+    pub fn from_i64(amount: i64) -> Self {
+        QuoteAmount(amount as i128)
+    }
+
+    pub fn checked_add(self, other: QuoteAmount) -> Option<QuoteAmount> {
+        self.0.checked_add(other.0).map(QuoteAmount)
+    }
+
+    pub fn checked_sub(self, other: QuoteAmount) -> Option<QuoteAmount> {
+        self.0.checked_sub(other.0).map(QuoteAmount)
+    }
+
+    pub fn checked_neg(self) -> Option<QuoteAmount> {
+        self.0.checked_neg().map(QuoteAmount)
+    }
+
+    /// `bps` of this amount.
+    pub fn apply_bps(self, bps: Bps, round: Round) -> Option<QuoteAmount> {
+        let product = self.0.checked_mul(bps.0 as i128)?;
+        div_round(product, BPS_DENOM as i128, round).map(QuoteAmount)
+    }
+
+    /// This amount as basis points of `notional`. A zero notional has an
+    /// unbounded ratio; a negative one is `None`.
+    pub fn ratio_bps(self, notional: QuoteAmount, round: Round) -> Option<i128> {
+        match notional.0 {
+            0 => Some(i128::MAX),
+            n if n < 0 => None,
+            n => div_round(self.0.checked_mul(BPS_DENOM as i128)?, n, round),
+        }
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// `None` if negative or too large for a token amount.
+    pub fn to_u64(self) -> Option<u64> {
+        self.0.try_into().ok()
+    }
+
+    /// `None` if it doesn't fit a stored collateral or margin field.
+    pub fn to_i64(self) -> Option<i64> {
+        self.0.try_into().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigInt;
+    use num_integer::Integer;
+    use proptest::prelude::*;
+
+    #[test]
+    fn rounding_examples() {
+        // 1.5 units at 0.000001 is 0.0000015 quote
+        let price = Price(1);
+        let qty = Quantity(1_500_000);
+        assert_eq!(price.notional(qty, Round::Down), Some(QuoteAmount(1)));
+        assert_eq!(price.notional(qty, Round::Up), Some(QuoteAmount(2)));
+
+        // a loss of 1.5 base units books as 2 rounding down, 1 rounding up
+        assert_eq!(Price(0).pnl(Price(1), qty, true, Round::Down), Some(QuoteAmount(-2)));
+        assert_eq!(Price(0).pnl(Price(1), qty, true, Round::Up), Some(QuoteAmount(-1)));
+
+        assert_eq!(QuoteAmount(399).apply_bps(Bps(250), Round::Down), Some(QuoteAmount(9)));
+        assert_eq!(QuoteAmount(399).apply_bps(Bps(250), Round::Up), Some(QuoteAmount(10)));
+        assert_eq!(QuoteAmount(399).apply_bps(Bps(250), Round::Nearest), Some(QuoteAmount(10)));
+        assert_eq!(QuoteAmount(-399).apply_bps(Bps(250), Round::Nearest), Some(QuoteAmount(-10)));
+        assert_eq!(QuoteAmount(-1).ratio_bps(QuoteAmount(3), Round::Down), Some(-3334));
+        assert_eq!(QuoteAmount(-1).ratio_bps(QuoteAmount::ZERO, Round::Down), Some(i128::MAX));
+        assert_eq!(QuoteAmount(1).ratio_bps(QuoteAmount(-1), Round::Down), None);
+    }
+
+    #[test]
+    fn conversions_are_checked() {
+        assert_eq!(QuoteAmount(-1).to_u64(), None);
+        assert_eq!(QuoteAmount(u64::MAX as i128 + 1).to_u64(), None);
+        assert_eq!(QuoteAmount(i64::MIN as i128).to_i64(), Some(i64::MIN));
+        assert_eq!(QuoteAmount(i64::MIN as i128 - 1).to_i64(), None);
+        assert_eq!(Quantity(1).checked_sub(Quantity(2)), None);
+        assert_eq!(QuoteAmount(i128::MIN).checked_neg(), None);
+    }
+
+    fn reference(numerator: BigInt, denominator: BigInt, round: Round) -> BigInt {
+        match round {
+            Round::Down => numerator.div_floor(&denominator),
+            Round::Up => numerator.div_ceil(&denominator),
+            Round::Nearest => {
+                // ties away from zero
+                let (floor, remainder) = numerator.div_mod_floor(&denominator);
+                let twice = remainder * 2;
+                if twice > denominator || (twice == denominator && numerator > BigInt::from(0)) {
+                    floor + 1
+                } else {
+                    floor
+                }
+            }
+        }
+    }
+
+    fn round() -> impl Strategy<Value = Round> {
+        prop_oneof![Just(Round::Down), Just(Round::Up), Just(Round::Nearest)]
+    }
+
+    proptest! {
+        #[test]
+        fn notional_matches_bigint_reference(price in any::<u64>(), qty in any::<u64>(), round in round()) {
+            let expected = reference(BigInt::from(price) * BigInt::from(qty), BigInt::from(PRICE_PRECISION), round);
+            let notional = Price(price).notional(Quantity(qty), round).unwrap();
+            prop_assert_eq!(BigInt::from(notional.0), expected);
+        }
+
+        #[test]
+        fn apply_bps_matches_bigint_reference(amount in any::<i64>(), bps in 0..=BPS_DENOM, round in round()) {
+            let expected = reference(BigInt::from(amount) * BigInt::from(bps), BigInt::from(BPS_DENOM), round);
+            let applied = QuoteAmount::from_i64(amount).apply_bps(Bps(bps), round).unwrap();
+            prop_assert_eq!(BigInt::from(applied.0), expected);
+        }
+
+        #[test]
+        fn half_is_a_5000_bps_portion(size in any::<u64>()) {
+            prop_assert_eq!(Quantity(size).portion(Bps(BPS_DENOM / 2)), Some(Quantity(size).half()));
+            prop_assert_eq!(Quantity(size).portion(Bps(BPS_DENOM)), Some(Quantity(size)));
+        }
+
+        #[test]
+        fn div_round_matches_bigint_reference(a in any::<i128>(), b in 1..=i128::MAX, round in round()) {
+            let expected = reference(BigInt::from(a), BigInt::from(b), round);
+            prop_assert_eq!(div_round(a, b, round).map(BigInt::from), Some(expected));
+        }
+
+        #[test]
+        fn up_is_never_below_down(a in any::<i64>(), b in 1..=i64::MAX) {
+            let down = div_round(a as i128, b as i128, Round::Down).unwrap();
+            let up = div_round(a as i128, b as i128, Round::Up).unwrap();
+            prop_assert!(up == down || up == down + 1);
+            prop_assert_eq!(up == down, a as i128 % b as i128 == 0);
+            let nearest = div_round(a as i128, b as i128, Round::Nearest).unwrap();
+            prop_assert!(down <= nearest && nearest <= up);
+        }
+    }
+}
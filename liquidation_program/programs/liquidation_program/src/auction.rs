@@ -0,0 +1,99 @@
+use anchor_lang::prelude::*;
+
+use crate::constants::*;
+use crate::state::ErrorCode;
+use crate::CollateralAuction;
+
+/// Opening and floor prices for an auction of an asset trading at
+/// `oracle_price`: start above the oracle, decay to a discount below it.
+pub fn auction_price_range(oracle_price: u64) -> Result<(u64, u64)> {
+    let start = (oracle_price as u128) * ((BPS_DENOM + AUCTION_START_PREMIUM_BPS) as u128)
+        / (BPS_DENOM as u128);
+    let end = (oracle_price as u128) * ((BPS_DENOM - AUCTION_MAX_DISCOUNT_BPS) as u128)
+        / (BPS_DENOM as u128);
+
+    Ok((
+        start.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))?,
+        end.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))?,
+    ))
+}
+
+/// Price per whole token at `now`: linear from `start_price` to `end_price`
+/// over `duration`, then flat at `end_price`.
+pub fn current_auction_price(auction: &CollateralAuction, now: i64) -> u64 {
+    let elapsed = now.saturating_sub(auction.start_ts).max(0) as u128;
+    let duration = auction.duration.max(1) as u128;
+
+    if elapsed >= duration {
+        return auction.end_price;
+    }
+
+    let drop = (auction.start_price.saturating_sub(auction.end_price) as u128) * elapsed / duration;
+    auction.start_price - drop as u64
+}
+
+/// Quote owed for `amount` base units at `price`. Rounds up, so a bidder
+/// never pays less than the auction price.
+pub fn auction_cost(amount: u64, price: u64, decimals: u8) -> Result<u64> {
+    let scale = 10u128
+        .checked_pow(decimals as u32)
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+
+    ((amount as u128) * (price as u128) + scale - 1)
+        .checked_div(scale)
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?
+        .try_into()
+        .map_err(|_| error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// Largest amount whose cost does not exceed `budget` at `price`.
+pub fn amount_for_budget(budget: u64, price: u64, decimals: u8) -> Result<u64> {
+    require!(price > 0, ErrorCode::InvalidOraclePrice);
+
+    let scale = 10u128
+        .checked_pow(decimals as u32)
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+
+    Ok(((budget as u128) * scale / (price as u128)).min(u64::MAX as u128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auction() -> CollateralAuction {
+        CollateralAuction {
+            start_price: 110_000_000,
+            end_price: 80_000_000,
+            start_ts: 1_000,
+            duration: 100,
+            ..CollateralAuction::default()
+        }
+    }
+
+    #[test]
+    fn price_decays_linearly_then_floors() {
+        let a = auction();
+        assert_eq!(current_auction_price(&a, 900), 110_000_000);
+        assert_eq!(current_auction_price(&a, 1_000), 110_000_000);
+        assert_eq!(current_auction_price(&a, 1_050), 95_000_000);
+        assert_eq!(current_auction_price(&a, 1_100), 80_000_000);
+        assert_eq!(current_auction_price(&a, 9_999), 80_000_000);
+    }
+
+    #[test]
+    fn price_range_brackets_oracle() {
+        let (start, end) = auction_price_range(100_000_000).unwrap();
+        assert!(start > 100_000_000);
+        assert!(end < 100_000_000);
+    }
+
+    #[test]
+    fn cost_rounds_up_and_budget_rounds_down() {
+        // 1.5 tokens (9 decimals) at 3.333333
+        assert_eq!(auction_cost(1_500_000_000, 3_333_333, 9).unwrap(), 5_000_000);
+        assert_eq!(auction_cost(1, 3_333_333, 9).unwrap(), 1);
+        let amount = amount_for_budget(5_000_000, 3_333_333, 9).unwrap();
+        assert!(auction_cost(amount, 3_333_333, 9).unwrap() <= 5_000_000);
+    }
+}
@@ -0,0 +1,218 @@
+use anchor_lang::prelude::*;
+
+use crate::constants::*;
+use crate::oracle::get_oracle_price;
+use crate::state::ErrorCode;
+use crate::{CollateralRegistry, Position};
+
+/// A non-quote collateral mint accepted by the protocol.
+///
+/// Quote collateral (the protocol_vault mint) lives in `Position.collateral`
+/// and always counts at full value. Everything here is valued at its oracle
+/// price times `weight_bps`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CollateralAsset {
+    pub mint: Pubkey,               // 32
+    pub oracle: Pubkey,             // 32 price of one whole token in quote, PRICE_PRECISION
+    pub vault: Pubkey,              // 32 PDA token account, seeds = [COLLATERAL_VAULT_SEED, mint]
+    pub decimals: u8,               // 1
+    pub weight_bps: u64,            // 8  10_000 = no haircut
+    pub total_deposits: u64,        // 8
+}
+
+impl CollateralAsset {
+    pub const LEN: usize = 32 + 32 + 32 + 1 + 8 + 8;
+}
+
+/// Quote value of `amount` base units at `price` (quote per whole token).
+/// Rounds down.
+pub fn asset_value(amount: u64, price: u64, decimals: u8) -> Result<u128> {
+    let scale = 10u128
+        .checked_pow(decimals as u32)
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+
+    Ok((amount as u128) * (price as u128) / scale)
+}
+
+/// Haircut value of `amount` base units. Rounds down, in the protocol's favour.
+pub fn weighted_asset_value(asset: &CollateralAsset, amount: u64, price: u64) -> Result<u128> {
+    let value = asset_value(amount, price, asset.decimals)?;
+    Ok(value * (asset.weight_bps as u128) / (BPS_DENOM as u128))
+}
+
+/// Base units of the asset worth `value` quote at `price`. Rounds down, so
+/// a liquidator never receives more than they paid for.
+pub fn amount_for_value(value: u128, price: u64, decimals: u8) -> Result<u64> {
+    require!(price > 0, ErrorCode::InvalidOraclePrice);
+
+    let scale = 10u128
+        .checked_pow(decimals as u32)
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+
+    value
+        .checked_mul(scale)
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?
+        .checked_div(price as u128)
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?
+        .try_into()
+        .map_err(|_| error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// Oracle prices for every asset the position holds, read from the accounts
+/// passed after the instruction's named accounts. Order doesn't matter; each
+/// asset's oracle is looked up by key. Assets with no deposit are left at 0.
+pub fn load_asset_prices(
+    registry: &CollateralRegistry,
+    position: &Position,
+    oracles: &[AccountInfo],
+) -> Result<[u64; MAX_COLLATERAL_ASSETS]> {
+    let mut prices = [0u64; MAX_COLLATERAL_ASSETS];
+
+    for (i, asset) in registry.assets[..registry.num_assets as usize].iter().enumerate() {
+        if position.deposits[i] == 0 {
+            continue;
+        }
+
+        let oracle = oracles
+            .iter()
+            .find(|acc| acc.key == &asset.oracle)
+            .ok_or(error!(ErrorCode::MissingCollateralOracle))?;
+
+        prices[i] = get_oracle_price(oracle)?;
+    }
+
+    Ok(prices)
+}
+
+/// Sum of haircut values of all non-quote deposits on the position, in quote.
+pub fn haircut_collateral_value(
+    registry: &CollateralRegistry,
+    position: &Position,
+    prices: &[u64; MAX_COLLATERAL_ASSETS],
+) -> Result<i128> {
+    let mut total: u128 = 0;
+
+    for (i, asset) in registry.assets[..registry.num_assets as usize].iter().enumerate() {
+        if position.deposits[i] == 0 {
+            continue;
+        }
+
+        total = total
+            .checked_add(weighted_asset_value(asset, position.deposits[i], prices[i])?)
+            .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+    }
+
+    total.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// True if the position still holds any non-quote collateral.
+pub fn has_deposits(position: &Position) -> bool {
+    position.deposits.iter().any(|&amount| amount > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NINE: u64 = 1_000_000_000; // one whole token at 9 decimals
+
+    fn asset(decimals: u8, weight_bps: u64) -> CollateralAsset {
+        CollateralAsset { decimals, weight_bps, ..CollateralAsset::default() }
+    }
+
+    // a 9-decimal asset at a 50% weight and a 6-decimal one at 80%
+    fn registry() -> CollateralRegistry {
+        let mut registry = CollateralRegistry { num_assets: 2, ..CollateralRegistry::default() };
+        registry.assets[0] = asset(9, 5_000);
+        registry.assets[1] = asset(6, 8_000);
+        registry
+    }
+
+    fn position(deposits: &[u64]) -> Position {
+        let mut position = Position::default();
+        position.deposits[..deposits.len()].copy_from_slice(deposits);
+        position
+    }
+
+    fn code(err: Error) -> u32 {
+        match err {
+            Error::AnchorError(e) => e.error_code_number,
+            Error::ProgramError(_) => panic!("expected anchor error"),
+        }
+    }
+
+    #[test]
+    fn value_scales_by_decimals_and_rounds_down() {
+        assert_eq!(asset_value(3 * NINE / 2, 2_000_000, 9).unwrap(), 3_000_000);
+        assert_eq!(asset_value(1_500_000, 2_000_000, 6).unwrap(), 3_000_000);
+        assert_eq!(asset_value(1, 999_999, 6).unwrap(), 0);
+    }
+
+    #[test]
+    fn weight_is_applied_after_valuing_and_rounds_down() {
+        // 3 tokens at 1.000001 = 3.000003, half of which is 1.5000015
+        assert_eq!(weighted_asset_value(&asset(9, 5_000), 3 * NINE, 1_000_001).unwrap(), 1_500_001);
+        assert_eq!(weighted_asset_value(&asset(9, BPS_DENOM), 3 * NINE, 1_000_001).unwrap(), 3_000_003);
+        assert_eq!(weighted_asset_value(&asset(9, 0), 3 * NINE, 1_000_001).unwrap(), 0);
+    }
+
+    #[test]
+    fn amount_for_value_rounds_down() {
+        assert_eq!(amount_for_value(3_000_000, 2_000_000, 9).unwrap(), 3 * NINE / 2);
+        // a third of a base unit's worth is not a unit
+        let amount = amount_for_value(1, 3_000_000, 9).unwrap();
+        assert_eq!(amount, 333);
+        assert!(asset_value(amount, 3_000_000, 9).unwrap() <= 1);
+    }
+
+    #[test]
+    fn amount_for_value_needs_a_price() {
+        let err = amount_for_value(1, 0, 9).unwrap_err();
+        assert_eq!(code(err), u32::from(ErrorCode::InvalidOraclePrice));
+    }
+
+    #[test]
+    fn haircut_sums_weighted_deposits() {
+        let registry = registry();
+        let mut prices = [0; MAX_COLLATERAL_ASSETS];
+        prices[0] = 2_000_000;
+        prices[1] = 10_000_000;
+
+        // 4 tokens at 2 at 50% = 4, plus 0.5 token at 10 at 80% = 4
+        let pos = position(&[4 * NINE, 500_000]);
+        assert_eq!(haircut_collateral_value(&registry, &pos, &prices).unwrap(), 8_000_000);
+
+        // each asset is rounded on its own: a base unit of the 9-decimal
+        // asset is worth nothing, one of the 6-decimal asset 10 * 80%
+        let pos = position(&[1, 1]);
+        assert_eq!(haircut_collateral_value(&registry, &pos, &prices).unwrap(), 8);
+    }
+
+    #[test]
+    fn haircut_skips_empty_and_unlisted_slots() {
+        let registry = registry();
+        // a price for an asset with no deposit is never read
+        let prices = [7_000_000; MAX_COLLATERAL_ASSETS];
+
+        assert_eq!(haircut_collateral_value(&registry, &position(&[]), &prices).unwrap(), 0);
+        // a deposit past num_assets is not counted
+        let pos = position(&[0, 0, NINE]);
+        assert_eq!(haircut_collateral_value(&registry, &pos, &prices).unwrap(), 0);
+    }
+
+    #[test]
+    fn missing_oracle_is_an_error() {
+        let registry = registry();
+        let err = load_asset_prices(&registry, &position(&[0, 1]), &[]).unwrap_err();
+        assert_eq!(code(err), u32::from(ErrorCode::MissingCollateralOracle));
+
+        // nothing deposited, nothing to price
+        assert_eq!(load_asset_prices(&registry, &position(&[]), &[]).unwrap(), [0; MAX_COLLATERAL_ASSETS]);
+    }
+
+    #[test]
+    fn deposits_are_any_nonzero_slot() {
+        assert!(!has_deposits(&position(&[])));
+        assert!(has_deposits(&position(&[0, 0, 0, 0, 0, 0, 0, 1])));
+    }
+}
@@ -1,10 +1,21 @@
-pub const PRICE_PRECISION: u64 = 1_000_000; // 1e6
-pub const BPS_DENOM: u64 = 10_000;
-pub const LIQUIDATOR_REWARD_BPS: u64 = 250; // 2.5%
+// shared with the keeper through liquidation_math
+pub use liquidation_math::{
+    BPS_DENOM, DEFAULT_MAINTENANCE_MARGIN_BPS, LIQUIDATOR_REWARD_BPS, PRICE_PRECISION,
+};
+pub const PRICE_EXPONENT: i32 = -6; // PRICE_PRECISION = 10^-PRICE_EXPONENT
+pub const MAX_RISK_TIERS: usize = 8;
 pub const VAULT_SEED: &[u8] = b"vault";
 pub const VAULT_AUTH_SEED: &[u8] = b"vault-auth";
 pub const INSURANCE_SEED: &[u8] = b"insurance";
 pub const INSURANCE_AUTH_SEED: &[u8] = b"insurance-auth";
 pub const LIQ_RECORD_SEED: &[u8] = b"liq_record";
+pub const MARKET_SEED: &[u8] = b"market";
+pub const COLLATERAL_REGISTRY_SEED: &[u8] = b"collateral-registry";
+pub const COLLATERAL_VAULT_SEED: &[u8] = b"collateral-vault";
+pub const MAX_COLLATERAL_ASSETS: usize = 8;
+pub const AUCTION_SEED: &[u8] = b"auction";
+pub const AUCTION_DURATION: i64 = 3_600; // seconds from start to floor price
+pub const AUCTION_START_PREMIUM_BPS: u64 = 500; // open 5% above oracle
+pub const AUCTION_MAX_DISCOUNT_BPS: u64 = 2_000; // floor 20% below oracle
 pub const MAX_ORACLE_STALENESS: i64 = 30; // seconds
 pub const MAX_CONF_FACTOR: i64 = 100; // conf < price/100 (1%)
@@ -1,58 +1,90 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions},
+};
+use anchor_spl::token_interface::{self, TransferChecked};
 
+use crate::state::ErrorCode;
+
+/// Transfer fee `mint` withholds on a transfer of `amount`. Zero for legacy
+/// SPL mints and Token-2022 mints without the transfer-fee extension.
+pub fn transfer_fee(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    if mint.owner != &spl_token_2022::ID {
+        return Ok(0);
+    }
+
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+
+    match state.get_extension::<TransferFeeConfig>() {
+        Ok(config) => config
+            .calculate_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or(error!(ErrorCode::ArithmeticOverflow)),
+        Err(_) => Ok(0),
+    }
+}
+
+/// transfer_checked signed by a wallet. Works with both the legacy Token
+/// program and Token-2022. Returns the amount that reaches `to` after any
+/// transfer fee.
 pub fn token_transfer<'info>(
     from: AccountInfo<'info>,
     to: AccountInfo<'info>,
     authority: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
     token_program: AccountInfo<'info>,
     amount: u64,
-) -> Result<()> {
-    let cpi_accounts = Transfer {
+    decimals: u8,
+) -> Result<u64> {
+    let fee = transfer_fee(&mint, amount)?;
+
+    let cpi_accounts = TransferChecked {
         from,
+        mint,
         to,
         authority,
     };
 
     let cpi_ctx = CpiContext::new(token_program, cpi_accounts);
 
-    token::transfer(cpi_ctx, amount)?;
+    token_interface::transfer_checked(cpi_ctx, amount, decimals)?;
 
-    Ok(())
+    Ok(amount - fee)
 }
 
+/// transfer_checked signed by a program PDA. Returns the amount that reaches
+/// `to` after any transfer fee.
+#[allow(clippy::too_many_arguments)]
 pub fn token_transfer_pda<'info>(
     from: AccountInfo<'info>,
     to: AccountInfo<'info>,
     authority: AccountInfo<'info>,   // PDA
+    mint: AccountInfo<'info>,
     token_program: AccountInfo<'info>,
     amount: u64,
+    decimals: u8,
     authority_bump: u8,
     authority_seeds: &[&[u8]],
-) -> Result<()> {
-    let cpi_accounts = Transfer {
+) -> Result<u64> {
+    let fee = transfer_fee(&mint, amount)?;
+
+    let cpi_accounts = TransferChecked {
         from,
+        mint,
         to,
         authority,
     };
 
-    let seeds = &[authority_seeds, &[&[authority_bump]]].concat();
+    let bump = [authority_bump];
+    let mut seeds = authority_seeds.to_vec();
+    seeds.push(&bump);
 
     let signer = &[&seeds[..]];
 
     let cpi_ctx = CpiContext::new_with_signer(token_program, cpi_accounts, signer);
 
-    token::transfer(cpi_ctx, amount)?;
+    token_interface::transfer_checked(cpi_ctx, amount, decimals)?;
 
-    Ok(())
+    Ok(amount - fee)
 }
-
-
-pub fn write_liquidation_record<'info>(
-    ctx: Context<'_, '_, '_, 'info, CreateLiquidationRecord<'info>>,
-    data: LiquidationRecord,
-) -> Result<()> {
-    let record = &mut ctx.accounts.record;
-    *record = data;
-    Ok(())
-}
\ No newline at end of file
@@ -1,17 +1,36 @@
+// P, E, Q, N, C: price, entry, quantity, notional, collateral as in the margin formulas
+#![allow(non_snake_case)]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 use std::convert::TryInto;
 
 
-pub mod math;
+pub mod auction;
+pub mod collateral;
+pub mod constants;
+pub mod limits;
+#[cfg(feature = "mock-oracle")]
+pub mod mock_oracle;
 pub mod oracle;
+pub mod pda;
+pub mod price_guard;
+pub mod rescale;
+pub mod risk_tiers;
 pub mod state;
 pub mod cpi_helpers;
 
-use crate::math::*;
+use crate::auction::*;
+use crate::collateral::*;
+use crate::constants::*;
+use crate::limits::*;
 use crate::oracle::*;
+use crate::price_guard::*;
+use crate::risk_tiers::*;
 use crate::state::*;
-use crate::cpi_helpers::{token_transfer_pda};
+use crate::state::ErrorCode;
+use crate::cpi_helpers::{token_transfer, token_transfer_pda};
+use liquidation_math::*;
 
 declare_id!("3cVSJYSXY3yscUwcxrWR5sqoJ4Mcbu1qrQKRjgXbi5AS");
 
@@ -20,10 +39,11 @@ pub mod liquidation_program {
     use super::*;
 
     pub fn initialize_insurance_fund(
-        ctx: Context<initializeInsuranceFund>,
+        ctx: Context<InitializeInsuranceFund>,
         authority: Pubkey) -> Result<()>{
             let fund = &mut ctx.accounts.insurance_fund;
             fund.authority = authority;
+            fund.insurance_vault = ctx.accounts.insurance_vault.key();
             fund.balance = 0;
             fund.total_bad_debt_covered = 0;
             fund.total_contributions = 0;
@@ -31,6 +51,11 @@ pub mod liquidation_program {
             Ok(())
     }
 
+    // Creates the quote vault at [VAULT_SEED], owned by the vault authority PDA.
+    pub fn initialize_protocol_vault(_ctx: Context<InitializeProtocolVault>) -> Result<()> {
+        Ok(())
+    }
+
     pub fn create_position(
         ctx: Context<CreatePosition>,
         entry_price: u64,
@@ -39,8 +64,18 @@ pub mod liquidation_program {
         is_long:bool,
         leverage:u16,
     )-> Result<()>{
+        require!(size > 0, ErrorCode::ZeroPosition);
+
+        // size the position at whichever of entry and the last accepted
+        // oracle price is higher, so a low entry_price can't dodge the cap
+        let market = &mut ctx.accounts.market;
+        let notional = position_notional(size, entry_price.max(market.last_accepted_price))?;
+        check_position_notional(market, notional)?;
+        increase_open_interest(market, is_long, size)?;
+
         let pos =&mut ctx.accounts.position;
         pos.owner = ctx.accounts.owner.key();
+        pos.market = market.key();
         pos.entry_price = entry_price;
         pos.size = size;
         pos.collateral = collateral;
@@ -49,531 +84,881 @@ pub mod liquidation_program {
         pos.last_update_ts = Clock::get()?.unix_timestamp;
         Ok(())
     }
-    
-    pub fn get_maintenance_margin_bps(leverage: u16) -> Result<u64> {
-        let bps = match leverage {
-            1..=20 => 250,      // 2.5%
-            21..=50 => 100,     // 1.0%
-            51..=100 => 50,     // 0.5%
-            101..=500 => 25,    // 0.25%
-            501..=1000 => 10,   // 0.1%
-            _ => 250,           // safest default (2.5%)
-        };
 
-        Ok(bps)
+    // Close by the owner. No tokens move here, mirroring create_position: the
+    // PnL is booked into the position's collateral instead. A loss the
+    // deposits carried leaves it negative, which blocks withdrawals until a
+    // collateral auction has covered it; a gain stays on the position. A
+    // position below maintenance has to be liquidated instead.
+    //
+    // Oracles for every asset the position holds go in remaining_accounts.
+    pub fn close_position(ctx: Context<ClosePosition>) -> Result<()> {
+        let pos = &mut ctx.accounts.position;
+        require!(pos.size > 0, ErrorCode::ZeroPosition);
+
+        // same price and margin check as the liquidations
+        let P_u64 = get_oracle_price(&ctx.accounts.oracle)?;
+        let market = &mut ctx.accounts.market;
+        require!(
+            apply_price_guard(market, P_u64, Clock::get()?.slot) == PriceCheck::Accepted,
+            ErrorCode::PriceDeviationTooLarge
+        );
+
+        let registry = &ctx.accounts.collateral_registry;
+        let prices = load_asset_prices(registry, pos, ctx.remaining_accounts)?;
+        let haircut = QuoteAmount(haircut_collateral_value(registry, pos, &prices)?);
+        let health = evaluate_position(&pos.terms(), Price(P_u64), haircut, |notional| {
+            maintenance_margin_bps(market, notional)
+        })
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        require!(health.is_healthy(), ErrorCode::PositionUnhealthy);
+
+        let collateral = QuoteAmount::from_i64(pos.collateral)
+            .checked_add(health.pnl)
+            .and_then(QuoteAmount::to_i64)
+            .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+
+        decrease_open_interest(market, pos.is_long, pos.size);
+
+        pos.size = 0;
+        pos.collateral = collateral;
+        pos.last_update_ts = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn initialize_market(
+        ctx: Context<InitializeMarket>,
+        market_index: u16,
+        symbol: [u8; 16],
+        max_price_deviation_bps: u64,
+        deviation_persist_slots: u64,
+        max_open_interest: u64,
+        max_position_notional: u64,
+    ) -> Result<()> {
+        require!(
+            max_price_deviation_bps > 0 && max_price_deviation_bps <= BPS_DENOM,
+            ErrorCode::InvalidMarketConfig
+        );
+
+        // seed the deviation guard with the current oracle price
+        let price = get_oracle_price(&ctx.accounts.oracle)?;
+        let clock = Clock::get()?;
+
+        let market = &mut ctx.accounts.market;
+        market.authority = ctx.accounts.authority.key();
+        market.oracle = ctx.accounts.oracle.key();
+        market.market_index = market_index;
+        market.symbol = symbol;
+        market.max_price_deviation_bps = max_price_deviation_bps;
+        market.deviation_persist_slots = deviation_persist_slots;
+        market.last_accepted_price = price;
+        market.last_accepted_slot = clock.slot;
+        market.pending_price = 0;
+        market.pending_since_slot = 0;
+        market.long_open_interest = 0;
+        market.short_open_interest = 0;
+        market.max_open_interest = max_open_interest;
+        market.max_position_notional = max_position_notional;
+
+        // one flat tier until set_risk_tiers is called
+        market.risk_tiers = [RiskTier::default(); MAX_RISK_TIERS];
+        market.risk_tiers[0] = RiskTier {
+            max_notional: u64::MAX,
+            maintenance_margin_bps: DEFAULT_MAINTENANCE_MARGIN_BPS,
+        };
+        market.num_risk_tiers = 1;
+        Ok(())
     }
 
+    pub fn update_market_config(
+        ctx: Context<UpdateMarketConfig>,
+        max_price_deviation_bps: u64,
+        deviation_persist_slots: u64,
+    ) -> Result<()> {
+        require!(
+            max_price_deviation_bps > 0 && max_price_deviation_bps <= BPS_DENOM,
+            ErrorCode::InvalidMarketConfig
+        );
+
+        let market = &mut ctx.accounts.market;
+        market.max_price_deviation_bps = max_price_deviation_bps;
+        market.deviation_persist_slots = deviation_persist_slots;
+        Ok(())
+    }
 
-    pub fn write_liquidation_record(
-        ctx: Context<WriteLiquidationRecord>,
-        position_owner: Pubkey,
-        liquidator: Pubkey,
-        symbol_id: u16,
-        liquidated_size: u64,
-        liquidation_price: u64,
-        margin_before: i64,
-        margin_after: i64,
-        liquidator_reward: u64,
-        bad_debt: u64,
-        timestamp: i64,
+    // Lowering a cap below current open interest only blocks new opens.
+    pub fn set_market_limits(
+        ctx: Context<UpdateMarketConfig>,
+        max_open_interest: u64,
+        max_position_notional: u64,
     ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        market.max_open_interest = max_open_interest;
+        market.max_position_notional = max_position_notional;
+        Ok(())
+    }
 
-        let record = &mut ctx.accounts.liquidation_record;
+    // Replaces the market's maintenance margin tiers (see risk_tiers.rs).
+    pub fn set_risk_tiers(ctx: Context<UpdateMarketConfig>, tiers: Vec<RiskTier>) -> Result<()> {
+        validate_risk_tiers(&tiers)?;
 
-        record.position_owner = position_owner;
-        record.liquidator = liquidator;
-        record.symbol_id = symbol_id;
-        record.liquidated_size = liquidated_size;
-        record.liquidation_price = liquidation_price;
-        record.margin_before = margin_before;
-        record.margin_after = margin_after;
-        record.liquidator_reward = liquidator_reward;
-        record.bad_debt = bad_debt;
-        record.timestamp = timestamp;
+        let market = &mut ctx.accounts.market;
+        market.risk_tiers = [RiskTier::default(); MAX_RISK_TIERS];
+        market.risk_tiers[..tiers.len()].copy_from_slice(&tiers);
+        market.num_risk_tiers = tiers.len() as u8;
+        Ok(())
+    }
 
+    // Permissionless crank: records out-of-band prices so they can start
+    // persisting. Liquidations revert on a pending price, so they can't.
+    pub fn refresh_market_price(ctx: Context<RefreshMarketPrice>) -> Result<()> {
+        let price = get_oracle_price(&ctx.accounts.oracle)?;
+        let slot = Clock::get()?.slot;
+
+        apply_price_guard(&mut ctx.accounts.market, price, slot);
         Ok(())
     }
 
+    pub fn initialize_collateral_registry(ctx: Context<InitializeCollateralRegistry>) -> Result<()> {
+        let registry = &mut ctx.accounts.collateral_registry;
+        registry.authority = ctx.accounts.authority.key();
+        registry.num_assets = 0;
+        registry.assets = [CollateralAsset::default(); MAX_COLLATERAL_ASSETS];
+        Ok(())
+    }
 
+    pub fn add_collateral_asset(ctx: Context<AddCollateralAsset>, weight_bps: u64) -> Result<()> {
+        require!(weight_bps <= BPS_DENOM, ErrorCode::InvalidCollateralWeight);
 
-    // Partial liquidation
-    pub fn liquidate_partial(ctx: Context<LiquidatePartial>) -> Result<()> {
-        use crate::math::*;
-        // constants (i128)
-        const PRICE_PRECISION_I128: i128 = PRICE_PRECISION as i128;
-        const BPS_DENOM_I128: i128 = BPS_DENOM as i128;
-        let reward_bps_i128: i128 = LIQUIDATOR_REWARD_BPS as i128;
+        let registry = &mut ctx.accounts.collateral_registry;
+        let index = registry.num_assets as usize;
+        require!(index < MAX_COLLATERAL_ASSETS, ErrorCode::CollateralRegistryFull);
 
-        // bump for vault authority signing
-        let vault_bump = *ctx
-            .bumps
-            .get("vault_authority")
-            .ok_or(error!(ErrorCode::InvalidVaultBump))?;
+        registry.assets[index] = CollateralAsset {
+            mint: ctx.accounts.mint.key(),
+            oracle: ctx.accounts.oracle.key(),
+            vault: ctx.accounts.collateral_vault.key(),
+            decimals: ctx.accounts.mint.decimals,
+            weight_bps,
+            total_deposits: 0,
+        };
+        registry.num_assets += 1;
+        Ok(())
+    }
 
-        // load accounts
+    // Lowering a weight can make existing positions liquidatable immediately.
+    pub fn set_collateral_weight(
+        ctx: Context<UpdateCollateralRegistry>,
+        asset_index: u8,
+        weight_bps: u64,
+    ) -> Result<()> {
+        require!(weight_bps <= BPS_DENOM, ErrorCode::InvalidCollateralWeight);
+
+        let registry = &mut ctx.accounts.collateral_registry;
+        require!(asset_index < registry.num_assets, ErrorCode::InvalidCollateralAsset);
+
+        registry.assets[asset_index as usize].weight_bps = weight_bps;
+        Ok(())
+    }
+
+    pub fn deposit_collateral(
+        ctx: Context<DepositCollateral>,
+        asset_index: u8,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::ZeroAmount);
+
+        // credit what reached the vault, not what left the owner
+        let received = token_transfer(
+            ctx.accounts.owner_token_account.to_account_info(),
+            ctx.accounts.collateral_vault.to_account_info(),
+            ctx.accounts.owner.to_account_info(),
+            ctx.accounts.collateral_mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            amount,
+            ctx.accounts.collateral_mint.decimals,
+        )?;
+        require!(received > 0, ErrorCode::ZeroAmount);
+
+        let i = asset_index as usize;
         let pos = &mut ctx.accounts.position;
-        let fund = &mut ctx.accounts.insurance_fund;
-        let liquidator = &ctx.accounts.liquidator;
+        pos.deposits[i] = pos.deposits[i]
+            .checked_add(received)
+            .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
 
-        // price
+        let asset = &mut ctx.accounts.collateral_registry.assets[i];
+        asset.total_deposits = asset.total_deposits
+            .checked_add(received)
+            .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        Ok(())
+    }
+
+    // Only from closed positions: withdrawing from an open one would need a
+    // full health check against every collateral oracle. A full liquidation
+    // can close a position with its deficit still owed (see FullPlan::Deferred);
+    // its deposits stay until auctions have covered it.
+    pub fn withdraw_collateral(
+        ctx: Context<WithdrawCollateral>,
+        asset_index: u8,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::ZeroAmount);
+        let pos = &ctx.accounts.position;
+        require!(pos.size == 0, ErrorCode::PositionStillOpen);
+        require!(pos.collateral >= 0, ErrorCode::PositionInDeficit);
+        require!(pos.open_auctions == 0, ErrorCode::AuctionOpen);
+
+        let i = asset_index as usize;
+        let pos = &mut ctx.accounts.position;
+        pos.deposits[i] = pos.deposits[i]
+            .checked_sub(amount)
+            .ok_or(error!(ErrorCode::InsufficientCollateral))?;
+
+        let asset = &mut ctx.accounts.collateral_registry.assets[i];
+        asset.total_deposits = asset.total_deposits.saturating_sub(amount);
+
+        token_transfer_pda(
+            ctx.accounts.collateral_vault.to_account_info(),
+            ctx.accounts.owner_token_account.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.collateral_mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            amount,
+            ctx.accounts.collateral_mint.decimals,
+            pda::vault_authority().1,
+            &[VAULT_AUTH_SEED, VAULT_SEED],
+        )?;
+        Ok(())
+    }
+
+    // Liquidator pays `repay_amount` quote into protocol_vault and receives
+    // that value of the chosen collateral asset plus LIQUIDATOR_REWARD_BPS,
+    // at the asset's oracle price. Capped by what the position holds.
+    //
+    // Oracles for every asset the position holds go in remaining_accounts.
+    pub fn liquidate_collateral(
+        ctx: Context<LiquidateCollateral>,
+        asset_index: u8,
+        repay_amount: u64,
+    ) -> Result<()> {
+        require!(repay_amount > 0, ErrorCode::ZeroAmount);
+
+        let i = asset_index as usize;
+        let pos = &mut ctx.accounts.position;
+        let registry = &mut ctx.accounts.collateral_registry;
+        require!(pos.deposits[i] > 0, ErrorCode::InsufficientCollateral);
+
+        // market price + deviation guard
         let P_u64 = get_oracle_price(&ctx.accounts.oracle)?;
-        let P_i128: i128 = P_u64 as i128;
-        require!(P_i128 > 0, ErrorCode::InvalidOraclePrice);
-
-        // position fields
-        let E_i128: i128 = pos.entry_price as i128;
-        let Q_i128: i128 = pos.size as i128;
-        let C_i128: i128 = pos.collateral as i128;
-        require!(Q_i128 > 0, ErrorCode::ZeroPosition);
-
-        // notional N = Q * P / PRICE_PRECISION
-        let N_i128 = if Q_i128 == 0 {
-            0
-        } else {
-            (Q_i128
-                .checked_mul(P_i128)
-                .ok_or(error!(ErrorCode::ArithmeticOverflow))?)
-            .checked_div(PRICE_PRECISION_I128)
-            .ok_or(error!(ErrorCode::ArithmeticOverflow))?
-        };
+        let market = &mut ctx.accounts.market;
+        require!(
+            apply_price_guard(market, P_u64, Clock::get()?.slot) == PriceCheck::Accepted,
+            ErrorCode::PriceDeviationTooLarge
+        );
 
-        // per-unit pnl (long/short)
-        let raw_pnl_per_unit = if pos.is_long {
-            P_i128.checked_sub(E_i128)
+        let prices = load_asset_prices(registry, pos, ctx.remaining_accounts)?;
+        let haircut = QuoteAmount(haircut_collateral_value(registry, pos, &prices)?);
+
+        // health: collateral + upl + haircut collateral against the notional tier;
+        // a closed position only needs a non-negative margin
+        let health = evaluate_position(&pos.terms(), Price(P_u64), haircut, |notional| {
+            maintenance_margin_bps(market, notional)
+        })
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        let healthy = if health.notional.is_zero() {
+            !health.margin.is_negative()
         } else {
-            E_i128.checked_sub(P_i128)
+            health.is_healthy()
+        };
+        require!(!healthy, ErrorCode::PositionHealthy);
+
+        // seized = repay * (1 + reward) in asset units, capped by the deposit
+        let asset = registry.assets[i];
+        let asset_price = prices[i];
+        let seize_value = (repay_amount as u128) * ((BPS_DENOM + LIQUIDATOR_REWARD_BPS) as u128)
+            / (BPS_DENOM as u128);
+        let mut seize_amount = amount_for_value(seize_value, asset_price, asset.decimals)?;
+        let mut repay = repay_amount;
+
+        if seize_amount > pos.deposits[i] {
+            // liquidator only pays for what is there (rounded up)
+            seize_amount = pos.deposits[i];
+            let value = asset_value(seize_amount, asset_price, asset.decimals)?;
+            let denom = (BPS_DENOM + LIQUIDATOR_REWARD_BPS) as u128;
+            repay = (value * (BPS_DENOM as u128))
+                .div_ceil(denom)
+                .try_into()
+                .map_err(|_| error!(ErrorCode::ArithmeticOverflow))?;
         }
-        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        require!(seize_amount > 0 && repay > 0, ErrorCode::ZeroAmount);
+
+        // quote in, collateral out
+        let repaid = token_transfer(
+            ctx.accounts.liquidator_quote_account.to_account_info(),
+            ctx.accounts.protocol_vault.to_account_info(),
+            ctx.accounts.liquidator.to_account_info(),
+            ctx.accounts.quote_mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            repay,
+            ctx.accounts.quote_mint.decimals,
+        )?;
+
+        token_transfer_pda(
+            ctx.accounts.collateral_vault.to_account_info(),
+            ctx.accounts.liquidator_collateral_account.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.collateral_mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            seize_amount,
+            ctx.accounts.collateral_mint.decimals,
+            pda::vault_authority().1,
+            &[VAULT_AUTH_SEED, VAULT_SEED],
+        )?;
+
+        // the position is credited with the quote that reached the vault
+        pos.deposits[i] -= seize_amount;
+        pos.collateral = pos.collateral
+            .checked_add(i64::try_from(repaid).map_err(|_| error!(ErrorCode::ArithmeticOverflow))?)
+            .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        pos.last_update_ts = Clock::get()?.unix_timestamp;
+        registry.assets[i].total_deposits = registry.assets[i].total_deposits.saturating_sub(seize_amount);
 
-        // current upl
-        let upl_i128 = raw_pnl_per_unit
-            .checked_mul(Q_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?
-            .checked_div(PRICE_PRECISION_I128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        emit!(CollateralSeizedEvent {
+            position_owner: pos.owner,
+            liquidator: ctx.accounts.liquidator.key(),
+            mint: asset.mint,
+            seized_amount: seize_amount,
+            repaid_amount: repaid,
+            collateral_price: asset_price,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
 
-        // margin and ratio
-        let margin_i128 = C_i128.checked_add(upl_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-        let margin_ratio_bps_i128 = if N_i128 == 0 {
-            i128::MAX
-        } else {
-            (margin_i128
-                .checked_mul(BPS_DENOM_I128).ok_or(error!(ErrorCode::ArithmeticOverflow))?)
-                .checked_div(N_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?
-        };
+        Ok(())
+    }
 
-        // check healthy
-        let mmr_bps_i128 = get_maintenance_margin_bps(pos.leverage)? as i128;
-        if margin_i128 > 0 && margin_ratio_bps_i128 >= mmr_bps_i128 {
-            return Ok(());
-        }
+    // Sells a closed position's collateral for quote to work off its
+    // deficit. Permissionless; the caller pays rent and gets it back on settle.
+    pub fn start_collateral_auction(
+        ctx: Context<StartCollateralAuction>,
+        asset_index: u8,
+    ) -> Result<()> {
+        let i = asset_index as usize;
+        let pos = &mut ctx.accounts.position;
+        require!(pos.size == 0, ErrorCode::PositionStillOpen);
+        require!(pos.collateral < 0, ErrorCode::NoDeficit);
 
-        // closed quantity (50% partial)
-        let closed_qty_i128 = (pos.size / 2) as i128;
-        if closed_qty_i128 <= 0 {
-            return Err(error!(ErrorCode::TooSmallToPartial));
-        }
+        let amount = pos.deposits[i];
+        require!(amount > 0, ErrorCode::InsufficientCollateral);
 
-        // proceeds from selling closed_qty: closed_qty * P / PRICE_PRECISION
-        let proceeds_i128 = (closed_qty_i128
-            .checked_mul(P_i128)
-            .ok_or(error!(ErrorCode::ArithmeticOverflow))?)
-        .checked_div(PRICE_PRECISION_I128)
-        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        let asset = ctx.accounts.collateral_registry.assets[i];
+        let oracle_price = get_oracle_price(&ctx.accounts.collateral_oracle)?;
+        let (start_price, end_price) = auction_price_range(oracle_price)?;
 
-        // liquidator reward from proceeds
-        let reward_i128 = (proceeds_i128
-            .checked_mul(reward_bps_i128)
-            .ok_or(error!(ErrorCode::ArithmeticOverflow))?)
-        .checked_div(BPS_DENOM_I128)
-        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        // the tokens stay in the collateral vault; the auction takes custody on paper
+        pos.deposits[i] = 0;
+        pos.open_auctions = pos.open_auctions
+            .checked_add(1)
+            .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
 
-        // net proceeds
-        let net_proceeds_i128 = proceeds_i128.checked_sub(reward_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        let auction = &mut ctx.accounts.auction;
+        auction.position = pos.key();
+        auction.payer = ctx.accounts.keeper.key();
+        auction.mint = asset.mint;
+        auction.asset_index = asset_index;
+        auction.decimals = asset.decimals;
+        auction.initial_amount = amount;
+        auction.remaining_amount = amount;
+        auction.proceeds = 0;
+        auction.start_price = start_price;
+        auction.end_price = end_price;
+        auction.start_ts = Clock::get()?.unix_timestamp;
+        auction.duration = AUCTION_DURATION;
+
+        emit!(CollateralAuctionStartedEvent {
+            position_owner: pos.owner,
+            mint: asset.mint,
+            amount,
+            deficit: pos.collateral.unsigned_abs(),
+            start_price,
+            end_price,
+            timestamp: auction.start_ts,
+        });
 
-        // remaining qty, new notional & new upl (use same per-unit pnl sign)
-        let remaining_qty_i128 = Q_i128.checked_sub(closed_qty_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-        let new_notional_i128 = (remaining_qty_i128
-            .checked_mul(P_i128)
-            .ok_or(error!(ErrorCode::ArithmeticOverflow))?)
-        .checked_div(PRICE_PRECISION_I128)
-        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        Ok(())
+    }
+
+    // Buy up to `max_amount` at the current auction price, refusing if it is
+    // above `max_price`. Fills are capped at what the deficit still needs.
+    pub fn bid_collateral_auction(
+        ctx: Context<BidCollateralAuction>,
+        max_amount: u64,
+        max_price: u64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let auction = &mut ctx.accounts.auction;
+        let pos = &mut ctx.accounts.position;
 
-        let new_upl_i128 = raw_pnl_per_unit
-            .checked_mul(remaining_qty_i128)
-            .ok_or(error!(ErrorCode::ArithmeticOverflow))?
-            .checked_div(PRICE_PRECISION_I128)
+        require!(auction.remaining_amount > 0, ErrorCode::AuctionFinished);
+        require!(pos.collateral < 0, ErrorCode::AuctionFinished);
+
+        let price = current_auction_price(auction, now);
+        require!(price <= max_price, ErrorCode::AuctionPriceAboveLimit);
+
+        // don't sell more than the deficit needs (at least one unit so dust clears)
+        let deficit = pos.collateral.unsigned_abs();
+        let needed = amount_for_budget(deficit, price, auction.decimals)?.max(1);
+        let amount = max_amount.min(auction.remaining_amount).min(needed);
+        let cost = auction_cost(amount, price, auction.decimals)?;
+        require!(amount > 0 && cost > 0, ErrorCode::ZeroAmount);
+
+        let received = token_transfer(
+            ctx.accounts.bidder_quote_account.to_account_info(),
+            ctx.accounts.protocol_vault.to_account_info(),
+            ctx.accounts.bidder.to_account_info(),
+            ctx.accounts.quote_mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            cost,
+            ctx.accounts.quote_mint.decimals,
+        )?;
+
+        token_transfer_pda(
+            ctx.accounts.collateral_vault.to_account_info(),
+            ctx.accounts.bidder_collateral_account.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.collateral_mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            amount,
+            ctx.accounts.collateral_mint.decimals,
+            pda::vault_authority().1,
+            &[VAULT_AUTH_SEED, VAULT_SEED],
+        )?;
+
+        auction.remaining_amount -= amount;
+        auction.proceeds = auction.proceeds
+            .checked_add(received)
             .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
 
-        // new margin after crediting net proceeds (collateral + new_upl + net_proceeds)
-        let new_margin_i128 = C_i128
-            .checked_add(new_upl_i128)
-            .ok_or(error!(ErrorCode::ArithmeticOverflow))?
-            .checked_add(net_proceeds_i128)
+        // proceeds that reached the vault pay down the deficit; any rounding
+        // surplus is the trader's
+        pos.collateral = pos.collateral
+            .checked_add(i64::try_from(received).map_err(|_| error!(ErrorCode::ArithmeticOverflow))?)
             .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        pos.last_update_ts = now;
 
-        // new margin ratio
-        let new_margin_ratio_bps_i128 = if new_notional_i128 == 0 {
-            i128::MAX
-        } else {
-            (new_margin_i128
-                .checked_mul(BPS_DENOM_I128)
-                .ok_or(error!(ErrorCode::ArithmeticOverflow))?)
-            .checked_div(new_notional_i128)
-            .ok_or(error!(ErrorCode::ArithmeticOverflow))?
-        };
+        let asset = &mut ctx.accounts.collateral_registry.assets[auction.asset_index as usize];
+        asset.total_deposits = asset.total_deposits.saturating_sub(amount);
+
+        emit!(CollateralAuctionBidEvent {
+            position_owner: pos.owner,
+            bidder: ctx.accounts.bidder.key(),
+            mint: auction.mint,
+            amount,
+            price,
+            cost,
+            timestamp: now,
+        });
+
+        Ok(())
+    }
 
-        // decide: execute partial only if new state healthy
-        if new_margin_i128 > 0 && new_margin_ratio_bps_i128 >= mmr_bps_i128 {
-            // execute partial atomically
-            pos.size = remaining_qty_i128 as u64;
+    // Ends an auction once it has sold out, covered the deficit or run its
+    // full duration. Unsold collateral goes back on the position. When the
+    // last auction ends with nothing left to sell, the remaining deficit goes
+    // to the insurance fund and anything beyond it is protocol bad debt.
+    pub fn settle_collateral_auction(ctx: Context<SettleCollateralAuction>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let auction = &ctx.accounts.auction;
+        let pos = &mut ctx.accounts.position;
+
+        require!(
+            auction.remaining_amount == 0
+                || pos.collateral >= 0
+                || now >= auction.start_ts.saturating_add(auction.duration),
+            ErrorCode::AuctionStillRunning
+        );
+
+        let i = auction.asset_index as usize;
+        pos.deposits[i] = pos.deposits[i]
+            .checked_add(auction.remaining_amount)
+            .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        pos.open_auctions = pos.open_auctions.saturating_sub(1);
+
+        let mut covered: u64 = 0;
+        let mut bad_debt: u64 = 0;
 
-            // **Important**: update collateral to the new margin (no artificial 'entry cost removal')
-            let new_collateral_i128 = new_margin_i128;
-            pos.collateral = new_collateral_i128.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))?;
-            pos.last_update_ts = Clock::get()?.unix_timestamp;
+        if pos.open_auctions == 0 && pos.collateral < 0 && !has_deposits(pos) {
+            let shortfall = pos.collateral.unsigned_abs();
+            covered = shortfall.min(ctx.accounts.insurance_vault.amount);
 
-            // transfer tokens from protocol_vault to liquidator + trader (only if amounts positive)
-            if reward_i128 > 0 {
-                let reward_u64: u64 = reward_i128.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))?;
-                token_transfer_pda(
+            // a transfer fee on the quote mint is a loss like any other
+            let mut received = 0;
+            if covered > 0 {
+                received = token_transfer_pda(
+                    ctx.accounts.insurance_vault.to_account_info(),
                     ctx.accounts.protocol_vault.to_account_info(),
-                    ctx.accounts.liquidator_token_account.to_account_info(),
-                    ctx.accounts.vault_authority.to_account_info(),
+                    ctx.accounts.insurance_authority.to_account_info(),
+                    ctx.accounts.quote_mint.to_account_info(),
                     ctx.accounts.token_program.to_account_info(),
-                    reward_u64,
-                    vault_bump,
-                    &[VAULT_AUTH_SEED, VAULT_SEED],
+                    covered,
+                    ctx.accounts.quote_mint.decimals,
+                    pda::insurance_authority().1,
+                    &[INSURANCE_AUTH_SEED, INSURANCE_SEED],
                 )?;
             }
+            bad_debt = shortfall - received;
 
-            if net_proceeds_i128 > 0 {
-                let net_u64: u64 = net_proceeds_i128.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))?;
-                token_transfer_pda(
-                    ctx.accounts.protocol_vault.to_account_info(),
-                    ctx.accounts.trader_token_account.to_account_info(),
-                    ctx.accounts.vault_authority.to_account_info(),
-                    ctx.accounts.token_program.to_account_info(),
-                    net_u64,
-                    vault_bump,
-                    &[VAULT_AUTH_SEED, VAULT_SEED],
-                )?;
+            let fund = &mut ctx.accounts.insurance_fund;
+            fund.balance = ctx.accounts.insurance_vault.amount
+                .checked_sub(covered)
+                .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+            fund.total_bad_debt_covered = fund.total_bad_debt_covered
+                .checked_add(covered)
+                .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+
+            pos.collateral = 0;
+            pos.last_update_ts = now;
+
+            if bad_debt > 0 {
+                emit!(ProtocolInsolvencyEvent {
+                    amount: bad_debt,
+                    timestamp: now,
+                });
             }
+        }
 
-            emit!(LiquidationEvent {
-                position_owner: pos.owner,
-                liquidator: liquidator.key(),
-                symbol_id: 0u16,
-                liquidated_size: closed_qty_i128 as u64,
-                liquidation_price: P_u64,
-                margin_before: margin_i128 as i64,
-                margin_after: new_collateral_i128 as i64,
-                liquidator_reward: reward_i128.try_into().unwrap_or(0),
-                bad_debt: 0u64,
-                timestamp: Clock::get()?.unix_timestamp,
-            });
+        emit!(CollateralAuctionSettledEvent {
+            position_owner: pos.owner,
+            mint: auction.mint,
+            sold_amount: auction.initial_amount - auction.remaining_amount,
+            proceeds: auction.proceeds,
+            returned_amount: auction.remaining_amount,
+            insurance_covered: covered,
+            bad_debt,
+            timestamp: now,
+        });
+
+        Ok(())
+    }
 
+    // Instructions this module doesn't define: only the mock oracle's, and
+    // only in `mock-oracle` builds (see `mock_oracle`).
+    pub fn fallback<'info>(program_id: &Pubkey, accounts: &'info [AccountInfo<'info>], data: &[u8]) -> Result<()> {
+        #[cfg(feature = "mock-oracle")]
+        if let Some(result) = mock_oracle::dispatch(program_id, accounts, data) {
+            return result;
+        }
+        #[cfg(not(feature = "mock-oracle"))]
+        let _ = (program_id, accounts);
 
-            // write to liquidation record
-            let clock = Clock::get()?;
-            let ts = clock.unix_timestamp;
-
-            let symbol_bytes = {
-                let mut arr = [0u8; 16];
-                let sym = b"BTCUSD";   // replace with real symbol
-                arr[..sym.len()].copy_from_slice(sym);
-                arr
-            };
-
-            let record_data = LiquidationRecord {
-                position_owner: pos.owner,
-                liquidator: liquidator.key(),
-                symbol: symbol_bytes,
-                liquidated_size: closed_qty_i128 as u64,
-                liquidation_price: P_u64,
-                margin_before: margin_i128 as i64,
-                margin_after: new_collateral_i128 as i64,
-                liquidator_reward: reward_i128 as u64,
-                bad_debt: 0,
-                timestamp: ts,
-            };
-
-            let cpi_ctx = Context::new(
-                ctx.program_id,
-                CreateLiquidationRecord {
-                    record: ctx.accounts.liq_record.to_account_info(),
-                    payer: ctx.accounts.liquidator.to_account_info(),
-                    system_program: ctx.accounts.system_program.to_account_info(),
-                },
-                vec![],
-            );
-
-            write_liquidation_record(cpi_ctx, record_data)?;
-
-            return Ok(());
-        } else {
-            return Err(error!(ErrorCode::PartialInsufficient));
+        if data.len() < 8 {
+            return Err(anchor_lang::error::ErrorCode::InstructionMissing.into());
         }
+        Err(anchor_lang::error::ErrorCode::InstructionFallbackNotFound.into())
     }
+    
+    // Partial liquidation
+    pub fn liquidate_partial(ctx: Context<LiquidatePartial>) -> Result<()> {
+        // bump for vault authority signing
+        let vault_bump = pda::vault_authority().1;
 
-    pub fn liquidate_full(ctx: Context<LiquidateFull>) -> Result<()> {
-        
-        const PRICE_PRECISION_I128: i128 = PRICE_PRECISION as i128;
-        const BPS_DENOM_I128: i128 = BPS_DENOM as i128;
-        let reward_bps_i128: i128 = LIQUIDATOR_REWARD_BPS as i128;
+        // load accounts
+        let pos = &mut ctx.accounts.position;
+        let liquidator = &ctx.accounts.liquidator;
+
+        // price
+        let P_u64 = get_oracle_price(&ctx.accounts.oracle)?;
+        require!(P_u64 > 0, ErrorCode::InvalidOraclePrice);
+
+        // refuse prices that jumped away from the last accepted one
+        let market = &mut ctx.accounts.market;
+        require!(
+            apply_price_guard(market, P_u64, Clock::get()?.slot) == PriceCheck::Accepted,
+            ErrorCode::PriceDeviationTooLarge
+        );
+        require!(pos.size > 0, ErrorCode::ZeroPosition);
+
+        // non-quote collateral at its haircut value
+        let prices = load_asset_prices(&ctx.accounts.collateral_registry, pos, ctx.remaining_accounts)?;
+        let haircut = QuoteAmount(haircut_collateral_value(&ctx.accounts.collateral_registry, pos, &prices)?);
+
+        // the tier comes from the current notional, not pos.leverage
+        let (health, plan) = plan_partial(
+            &pos.terms(),
+            Price(P_u64),
+            haircut,
+            Bps(LIQUIDATOR_REWARD_BPS),
+            |notional| maintenance_margin_bps(market, notional),
+        )
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+
+        let fill = match plan {
+            // nothing to record; refund the record's rent
+            PartialPlan::Healthy => {
+                return ctx.accounts.liquidation_record.close(ctx.accounts.liquidator.to_account_info());
+            }
+            PartialPlan::TooSmall => return Err(error!(ErrorCode::TooSmallToPartial)),
+            PartialPlan::Insufficient => return Err(error!(ErrorCode::PartialInsufficient)),
+            PartialPlan::Execute(fill) => fill,
+        };
+        let margin_before = health.margin.to_i64().ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+
+        // execute partial atomically
+        pos.size = fill.remaining_size.0;
+        decrease_open_interest(market, pos.is_long, fill.closed_size.0);
+
+        // the PnL and the reward are booked into the collateral; mark the rest
+        // to the liquidation price so its PnL isn't counted twice
+        pos.collateral = fill.new_collateral;
+        pos.entry_price = fill.entry_price.0;
+        pos.last_update_ts = Clock::get()?.unix_timestamp;
+
+        // pay the reward out of the position's share of the protocol vault; the
+        // rest of its margin stays there behind the remaining size
+        if fill.liquidator_reward > 0 {
+            token_transfer_pda(
+                ctx.accounts.protocol_vault.to_account_info(),
+                ctx.accounts.liquidator_token_account.to_account_info(),
+                ctx.accounts.vault_authority.to_account_info(),
+                ctx.accounts.quote_mint.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                fill.liquidator_reward,
+                ctx.accounts.quote_mint.decimals,
+                vault_bump,
+                &[VAULT_AUTH_SEED, VAULT_SEED],
+            )?;
+        }
+
+        emit!(LiquidationEvent {
+            position_owner: pos.owner,
+            liquidator: liquidator.key(),
+            symbol_id: market.market_index,
+            liquidated_size: fill.closed_size.0,
+            liquidation_price: P_u64,
+            margin_before,
+            margin_after: fill.new_collateral,
+            liquidator_reward: fill.liquidator_reward,
+            trader_payout: 0,
+            insurance_covered: 0,
+            bad_debt: 0u64,
+            timestamp: Clock::get()?.unix_timestamp,
+            reward_source: RewardSource::ProtocolVault,
+        });
+
+
+        // write to liquidation record
+        let clock = Clock::get()?;
+        let ts = clock.unix_timestamp;
+
+        let symbol_bytes = market.symbol;
+
+        let record_data = LiquidationRecord {
+            position_owner: pos.owner,
+            liquidator: liquidator.key(),
+            symbol: symbol_bytes,
+            liquidated_size: fill.closed_size.0,
+            liquidation_price: P_u64,
+            margin_before,
+            margin_after: fill.new_collateral,
+            liquidator_reward: fill.liquidator_reward,
+            bad_debt: 0,
+            timestamp: ts,
+        };
 
+        ctx.accounts.liquidation_record.set_inner(record_data);
+
+        pos.liquidation_count = pos.liquidation_count.wrapping_add(1);
+
+        Ok(())
+    }
+
+    pub fn liquidate_full(ctx: Context<LiquidateFull>) -> Result<()> {
         // load accounts
         let pos = &mut ctx.accounts.position;
         let fund = &mut ctx.accounts.insurance_fund;
         let liquidator = &ctx.accounts.liquidator;
 
         // vault bump for PDA signing
-        let vault_bump = *ctx
-            .bumps
-            .get("vault_authority")
-            .ok_or(error!(ErrorCode::InvalidVaultBump))?;
+        let vault_bump = pda::vault_authority().1;
 
         // insurance bump
-        let insurance_bump = *ctx
-            .bumps
-            .get("insurance_authority")
-            .ok_or(error!(ErrorCode::InvalidInsuranceBump))?;
+        let insurance_bump = pda::insurance_authority().1;
 
         // read oracle price
         let P_u64 = get_oracle_price(&ctx.accounts.oracle)?;
-        let P_i128: i128 = P_u64 as i128;
-        require!(P_i128 > 0, ErrorCode::InvalidOraclePrice);
-
-        // fields
-        let E_i128: i128 = pos.entry_price as i128;
-        let Q_i128: i128 = pos.size as i128;
-        let C_i128: i128 = pos.collateral as i128; // current collateral (signed)
-        require!(Q_i128 > 0, ErrorCode::ZeroPosition);
-
-        // per-unit pnl
-        let raw_pnl_per_unit = if pos.is_long {
-            P_i128.checked_sub(E_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?
-        } else {
-            E_i128.checked_sub(P_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?
-        };
-
-        // upl
-        let upl_i128 = raw_pnl_per_unit
-            .checked_mul(Q_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?
-            .checked_div(PRICE_PRECISION_I128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-
-        //notional
-        let notional_i128 = (
-            Q_i128
-            .checked_mul(P_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?)
-            .checked_div(PRICE_PRECISION_I128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-
-        // final margin after closing position 
-        let final_margin_i128 = C_i128.checked_add(upl_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-
-
-        // If final_margin > 0 => leftover exists and liquidator gets reward
-        if final_margin_i128 >= 0 {
-            // compute liquidator reward (from final_margin)
-            let reward_i128 = (final_margin_i128
-                .checked_mul(reward_bps_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?)
-                .checked_div(BPS_DENOM_I128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-
-            // remaining to user after reward
-            let remaining_i128 = final_margin_i128
-                .checked_sub(reward_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-
-            // update position: close
-            pos.size = 0;
-            pos.collateral = 0; // cleared (we will record leftover/transfer via CPI)
-            pos.last_update_ts = Clock::get()?.unix_timestamp;
-
-            // transfer reward from protocol_vault
-            if reward_i128 > 0 {
-                let reward_u64: u64 = reward_i128.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))?;
-                token_transfer_pda(
-                    ctx.accounts.protocol_vault.to_account_info(),
-                    ctx.accounts.liquidator_token_account.to_account_info(),
-                    ctx.accounts.vault_authority.to_account_info(),
-                    ctx.accounts.token_program.to_account_info(),
-                    reward_u64,
-                    vault_bump,
-                    &[VAULT_AUTH_SEED, VAULT_SEED],
-                )?;
-            }
-
-            // transfer remainder to trader if >0
-            if remaining_i128 > 0 {
-                let rem_u64: u64 = remaining_i128.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))?;
-                token_transfer_pda(
-                    ctx.accounts.protocol_vault.to_account_info(),
-                    ctx.accounts.trader_token_account.to_account_info(),
-                    ctx.accounts.vault_authority.to_account_info(),
-                    ctx.accounts.token_program.to_account_info(),
-                    rem_u64,
-                    vault_bump,
-                    &[VAULT_AUTH_SEED, VAULT_SEED],
-                )?;
-            }
-
-            // Emit event (bad_debt = 0)
-            emit!(LiquidationEvent {
-                position_owner: pos.owner,
-                liquidator: liquidator.key(),
-                symbol_id: 0u16,
-                liquidated_size: Q_i128 as u64,
-                liquidation_price: P_u64,
-                margin_before: (C_i128.checked_add(upl_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?) as i64,
-                margin_after: remaining_i128 as i64,
-                liquidator_reward: reward_i128 as u64,
-                bad_debt: 0u64,
-                timestamp: Clock::get()?.unix_timestamp,
-            });
-
-            let ts = Clock::get()?.unix_timestamp;
-
-            let mut symbol_bytes = [0u8; 16];
-            let sym = b"BTCUSD";
-            symbol_bytes[..sym.len()].copy_from_slice(sym);
-
-            let record_data = LiquidationRecord {
-                position_owner: pos.owner,
-                liquidator: liquidator.key(),
-                symbol: symbol_bytes,
-                liquidated_size: Q_i128 as u64,
-                liquidation_price: P_u64,
-                margin_before: (C_i128 + upl_i128) as i64,
-                margin_after: 0,
-                liquidator_reward: reward_paid_i128 as u64,
-                bad_debt: leftover_bad_debt_i128 as u64,
-                timestamp: ts,
-            };
-
-            let cpi_ctx = Context::new(
-                ctx.program_id,
-                CreateLiquidationRecord {
-                    record: ctx.accounts.liq_record.to_account_info(),
-                    payer: ctx.accounts.liquidator.to_account_info(),
-                    system_program: ctx.accounts.system_program.to_account_info(),
-                },
-                vec![],
-            );
-
-            write_liquidation_record(cpi_ctx, record_data)?;
-
-
-            return Ok(());
-        }
+        require!(P_u64 > 0, ErrorCode::InvalidOraclePrice);
 
-         // final_margin < 0 -> bad debt
-        let bad_debt_i128 = final_margin_i128.checked_neg().ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-
-        // try to cover from insurance vault (real tokens)
-        // The insurance vault must hold tokens; fund.balance is bookkeeping derived from real vault.
-        let insurance_balance_i128 = ctx.accounts.insurance_vault.amount as i128;
-
-        let mut bad_debt_covered_i128: i128 = 0;
-        let mut leftover_bad_debt_i128: i128 = bad_debt_i128;
-
-        if insurance_balance_i128 >= bad_debt_i128 {
-            bad_debt_covered_i128 = bad_debt_i128;
-            // transfer actual tokens from insurance_vault -> protocol_vault (or directly to liquidator/trader as needed)
-            // We'll transfer to liquidator as reward and any remainder adjust fund accounting
-            // for simplicity: move bad_debt_covered to protocol_vault, then reimburse parties
-            // but better: pay liquidator reward from insurance directly
-            // Here we just deduct bookkeeping and leave transfer to reward below.
-            // Subtract from insurance_vault physically by moving to protocol_vault (optional)
-            // We'll keep it simple: reduce insurance.vault bookkeeping and later transfer reward only.
-            // Update insurance fund accounting
-            // (We do not do a big transfer of bad debt dollars to traders here)
-            // Instead, we'll use fund.balance bookkeeping to record coverage.
-            let new_balance = insurance_balance_i128.checked_sub(bad_debt_covered_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-            // For bookkeeping: set fund.balance to new_balance (u64)
-            fund.balance = new_balance.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))?;
-            leftover_bad_debt_i128 = 0;
-        } else {
-            // partial cover
-            bad_debt_covered_i128 = insurance_balance_i128;
-            leftover_bad_debt_i128 = bad_debt_i128.checked_sub(insurance_balance_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-            fund.balance = 0;
+        // refuse prices that jumped away from the last accepted one
+        let market = &mut ctx.accounts.market;
+        require!(
+            apply_price_guard(market, P_u64, Clock::get()?.slot) == PriceCheck::Accepted,
+            ErrorCode::PriceDeviationTooLarge
+        );
+        require!(pos.size > 0, ErrorCode::ZeroPosition);
+
+        // same health check as liquidate_partial: nothing to do above maintenance margin
+        let prices = load_asset_prices(&ctx.accounts.collateral_registry, pos, ctx.remaining_accounts)?;
+        let haircut = QuoteAmount(haircut_collateral_value(&ctx.accounts.collateral_registry, pos, &prices)?);
+        let health = evaluate_position(&pos.terms(), Price(P_u64), haircut, |notional| {
+            maintenance_margin_bps(market, notional)
+        })
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        if health.is_healthy() {
+            // nothing to record; refund the record's rent
+            return ctx.accounts.liquidation_record.close(ctx.accounts.liquidator.to_account_info());
         }
 
-        fund.total_bad_debt_covered = fund.total_bad_debt_covered.checked_add(bad_debt_covered_i128 as u64).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+        let plan = plan_full(
+            &pos.terms(),
+            Price(P_u64),
+            has_deposits(pos),
+            ctx.accounts.insurance_vault.amount,
+            Bps(LIQUIDATOR_REWARD_BPS),
+        )
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
 
-        // close position numerically
+        // close the perp side whatever happens to the margin
+        let size = pos.size;
+        decrease_open_interest(market, pos.is_long, size);
         pos.size = 0;
-        pos.collateral = 0;
         pos.last_update_ts = Clock::get()?.unix_timestamp;
 
-        // reward calculation: pay reward from covered amount proportionally
-        let max_reward_i128 = (bad_debt_i128.checked_mul(reward_bps_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?).checked_div(BPS_DENOM_I128).ok_or(error!(ErrorCode::ArithmeticOverflow))?;
-        let reward_paid_i128 = if bad_debt_covered_i128 > 0 {
-            if bad_debt_covered_i128 >= max_reward_i128 { max_reward_i128 } else { bad_debt_covered_i128 }
-        } else {
-            0
-        };
+        let (margin_before, margin_after, reward, trader_payout, insurance_covered, bad_debt, reward_source) = match plan {
+            // Deficit but non-quote collateral left: leave the deficit on the
+            // position. Collateral auctions work it off and hand any shortfall
+            // to the insurance fund when they settle.
+            FullPlan::Deferred { margin } => {
+                pos.collateral = margin;
+                (margin, margin, 0, 0, 0, 0, RewardSource::ProtocolVault)
+            }
 
-        if reward_paid_i128 > 0 {
-            let reward_paid_u64: u64 = reward_paid_i128.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))?;
-            // pay liquidator from insurance_vault
-            token_transfer_pda(
-                ctx.accounts.insurance_vault.to_account_info(),
-                ctx.accounts.liquidator_token_account.to_account_info(),
-                ctx.accounts.insurance_authority.to_account_info(),
-                ctx.accounts.token_program.to_account_info(),
-                reward_paid_u64,
-                insurance_bump,
-                &[INSURANCE_AUTH_SEED, INSURANCE_SEED],
-            )?;
-        }
+            // leftover exists and liquidator gets reward
+            FullPlan::Leftover { margin, liquidator_reward, trader_payout } => {
+                let margin_after = i64::try_from(trader_payout).map_err(|_| error!(ErrorCode::ArithmeticOverflow))?;
+                pos.collateral = 0; // cleared (we will record leftover/transfer via CPI)
+
+                // transfer reward from protocol_vault
+                if liquidator_reward > 0 {
+                    token_transfer_pda(
+                        ctx.accounts.protocol_vault.to_account_info(),
+                        ctx.accounts.liquidator_token_account.to_account_info(),
+                        ctx.accounts.vault_authority.to_account_info(),
+                        ctx.accounts.quote_mint.to_account_info(),
+                        ctx.accounts.token_program.to_account_info(),
+                        liquidator_reward,
+                        ctx.accounts.quote_mint.decimals,
+                        vault_bump,
+                        &[VAULT_AUTH_SEED, VAULT_SEED],
+                    )?;
+                }
+
+                // transfer remainder to trader if >0
+                let mut received: u64 = 0;
+                if trader_payout > 0 {
+                    received = token_transfer_pda(
+                        ctx.accounts.protocol_vault.to_account_info(),
+                        ctx.accounts.trader_token_account.to_account_info(),
+                        ctx.accounts.vault_authority.to_account_info(),
+                        ctx.accounts.quote_mint.to_account_info(),
+                        ctx.accounts.token_program.to_account_info(),
+                        trader_payout,
+                        ctx.accounts.quote_mint.decimals,
+                        vault_bump,
+                        &[VAULT_AUTH_SEED, VAULT_SEED],
+                    )?;
+                }
+
+                (margin, margin_after, liquidator_reward, received, 0, 0, RewardSource::ProtocolVault)
+            }
+
+            // bad debt: the insurance vault refills the protocol vault for as
+            // much of the deficit as it can; a transfer fee on the quote mint
+            // is a loss like any other
+            FullPlan::BadDebt { margin, bad_debt, insurance_covered, liquidator_reward } => {
+                pos.collateral = 0;
+
+                let mut received: u64 = 0;
+                if insurance_covered > 0 {
+                    received = token_transfer_pda(
+                        ctx.accounts.insurance_vault.to_account_info(),
+                        ctx.accounts.protocol_vault.to_account_info(),
+                        ctx.accounts.insurance_authority.to_account_info(),
+                        ctx.accounts.quote_mint.to_account_info(),
+                        ctx.accounts.token_program.to_account_info(),
+                        insurance_covered,
+                        ctx.accounts.quote_mint.decimals,
+                        insurance_bump,
+                        &[INSURANCE_AUTH_SEED, INSURANCE_SEED],
+                    )?;
+                }
+
+                // the liquidator's reward comes out of whatever the fund has left
+                if liquidator_reward > 0 {
+                    token_transfer_pda(
+                        ctx.accounts.insurance_vault.to_account_info(),
+                        ctx.accounts.liquidator_token_account.to_account_info(),
+                        ctx.accounts.insurance_authority.to_account_info(),
+                        ctx.accounts.quote_mint.to_account_info(),
+                        ctx.accounts.token_program.to_account_info(),
+                        liquidator_reward,
+                        ctx.accounts.quote_mint.decimals,
+                        insurance_bump,
+                        &[INSURANCE_AUTH_SEED, INSURANCE_SEED],
+                    )?;
+                }
+
+                fund.balance = ctx.accounts.insurance_vault.amount
+                    .checked_sub(insurance_covered)
+                    .and_then(|left| left.checked_sub(liquidator_reward))
+                    .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+                fund.total_bad_debt_covered = fund.total_bad_debt_covered
+                    .checked_add(insurance_covered)
+                    .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+
+                (margin, 0, liquidator_reward, 0, insurance_covered, bad_debt - received, RewardSource::InsuranceVault)
+            }
+        };
 
-        // bookkeeping event
         emit!(LiquidationEvent {
             position_owner: pos.owner,
             liquidator: liquidator.key(),
-            symbol_id: 0u16,
-            liquidated_size: Q_i128 as u64,
+            symbol_id: market.market_index,
+            liquidated_size: size,
             liquidation_price: P_u64,
-            margin_before: (C_i128.checked_add(upl_i128).ok_or(error!(ErrorCode::ArithmeticOverflow))?) as i64,
-            margin_after: 0i64,
-            liquidator_reward: reward_paid_i128.try_into().unwrap_or(0),
-            bad_debt: leftover_bad_debt_i128 as u64,
+            margin_before,
+            margin_after,
+            liquidator_reward: reward,
+            trader_payout,
+            insurance_covered,
+            bad_debt,
             timestamp: Clock::get()?.unix_timestamp,
+            reward_source,
         });
 
         let ts = Clock::get()?.unix_timestamp;
 
-        let mut symbol_bytes = [0u8; 16];
-        let sym = b"BTCUSD";
-        symbol_bytes[..sym.len()].copy_from_slice(sym);
+        let symbol_bytes = market.symbol;
 
         let record_data = LiquidationRecord {
             position_owner: pos.owner,
             liquidator: liquidator.key(),
             symbol: symbol_bytes,
-            liquidated_size: Q_i128 as u64,
+            liquidated_size: size,
             liquidation_price: P_u64,
-            margin_before: (C_i128 + upl_i128) as i64,
-            margin_after: 0,
-            liquidator_reward: reward_paid_i128 as u64,
-            bad_debt: leftover_bad_debt_i128 as u64,
+            margin_before,
+            margin_after,
+            liquidator_reward: reward,
+            bad_debt,
             timestamp: ts,
         };
 
-        let cpi_ctx = Context::new(
-            ctx.program_id,
-            CreateLiquidationRecord {
-                record: ctx.accounts.liq_record.to_account_info(),
-                payer: ctx.accounts.liquidator.to_account_info(),
-                system_program: ctx.accounts.system_program.to_account_info(),
-            },
-            vec![],
-        );
+        ctx.accounts.liquidation_record.set_inner(record_data);
 
-        write_liquidation_record(cpi_ctx, record_data)?;
+        pos.liquidation_count = pos.liquidation_count.wrapping_add(1);
 
 
-        if leftover_bad_debt_i128 > 0 {
+        if bad_debt > 0 {
             emit!(ProtocolInsolvencyEvent {
-                amount: leftover_bad_debt_i128 as u64,
+                amount: bad_debt,
                 timestamp: Clock::get()?.unix_timestamp,
             });
         }
@@ -596,156 +981,644 @@ pub struct InitializeInsuranceFund<'info> {
         payer = payer,
         token::mint = mint,
         token::authority = insurance_authority,
+        token::token_program = token_program,
         seeds = [INSURANCE_SEED],
         bump,
     )]
-    pub insurance_vault: Account<'info, TokenAccount>,
+    pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
 
     /// CHECK: PDA authority for insurance_vault
-    #[account(
-        seeds = [INSURANCE_AUTH_SEED, INSURANCE_SEED],
-        bump
-    )]
+    #[account(address = pda::insurance_authority().0 @ ErrorCode::InsuranceAuthorityMismatch)]
     pub insurance_authority: UncheckedAccount<'info>,
 
     #[account(mut)]
     pub payer: Signer<'info>,
 
-    /// CHECK: mint for token (e.g., USDC)
-    pub mint: UncheckedAccount<'info>,
+    // Quote mint (e.g., USDC); legacy SPL or Token-2022
+    pub mint: InterfaceAccount<'info, Mint>,
 
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub rent: Sysvar<'info, Rent>,
 }
 
 
 
-
 #[derive(Accounts)]
-pub struct CreatePosition<'info> {
-    #[account(init , payer = owner , space = Position::LEN)]
-    pub position: Account<'info,Position>,
+pub struct InitializeProtocolVault<'info> {
+    #[account(
+        init,
+        payer = payer,
+        token::mint = mint,
+        token::authority = vault_authority,
+        token::token_program = token_program,
+        seeds = [VAULT_SEED],
+        bump,
+    )]
+    pub protocol_vault: InterfaceAccount<'info, TokenAccount>,
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    pub system_program: Program<'info,System>,
-}
+    /// CHECK: PDA that signs for the vaults
+    #[account(address = pda::vault_authority().0 @ ErrorCode::VaultAuthorityMismatch)]
+    pub vault_authority: UncheckedAccount<'info>,
 
+    // the insurance fund is initialized first and fixes the quote mint
+    #[account(address = pda::insurance_vault() @ ErrorCode::InsuranceVaultMismatch)]
+    pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(mut)]
+    pub payer: Signer<'info>,
 
+    // Quote mint; must match the insurance vault's
+    #[account(constraint = mint.key() == insurance_vault.mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
 
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
 
-#[derive(Accounts)]
-pub struct LiquidatePartial<'info> {
 
-    #[account(mut)]
-    pub position: Account<'info, Position>,
 
-    #[account(mut)]
-    pub insurance_fund: Account<'info, InsuranceFund>,
 
-    // SPL token vault owned by PDA
+#[derive(Accounts)]
+#[instruction(market_index: u16)]
+pub struct InitializeMarket<'info> {
     #[account(
-        mut,
-        seeds = [VAULT_SEED],
+        init,
+        payer = authority,
+        space = Market::LEN,
+        seeds = [MARKET_SEED, &market_index.to_le_bytes()],
         bump,
     )]
-    pub protocol_vault: Account<'info, TokenAccount>,
-
-    // PDA that signs all SPL CPI transfers from protocol_vault
-    #[account(
-        seeds = [VAULT_SEED],
-        bump
-    )]
-    pub vault_authority: UncheckedAccount<'info>,
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Oracle account; validated in logic
+    pub oracle: UncheckedAccount<'info>,
 
-    // Liquidator receives rewards
     #[account(mut)]
-    pub liquidator_token_account: Account<'info, TokenAccount>,
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+
+
+#[derive(Accounts)]
+pub struct UpdateMarketConfig<'info> {
+    #[account(mut, has_one = authority @ ErrorCode::Unauthorized)]
+    pub market: Account<'info, Market>,
+
+    pub authority: Signer<'info>,
+}
+
+
+
+#[derive(Accounts)]
+pub struct RefreshMarketPrice<'info> {
+    #[account(mut, has_one = oracle @ ErrorCode::OracleMismatch)]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Oracle account; must be the market's oracle
+    pub oracle: UncheckedAccount<'info>,
+}
+
+
+
+#[derive(Accounts)]
+pub struct CreatePosition<'info> {
+    #[account(init , payer = owner , space = Position::LEN)]
+    pub position: Account<'info,Position>,
 
-    // Trader receives leftover margin
     #[account(mut)]
-    pub trader_token_account: Account<'info, TokenAccount>,
+    pub market: Account<'info, Market>,
 
-    #[account(signer)]
-    pub liquidator: Signer<'info>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info,System>,
+}
 
-    /// CHECK: Oracle account; validated in logic
+
+
+#[derive(Accounts)]
+pub struct ClosePosition<'info> {
+    #[account(
+        mut,
+        has_one = owner @ ErrorCode::Unauthorized,
+        has_one = market @ ErrorCode::MarketMismatch,
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(mut, has_one = oracle @ ErrorCode::OracleMismatch)]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market oracle; validated in logic
     pub oracle: UncheckedAccount<'info>,
 
-    pub token_program: Program<'info, Token>,
+    #[account(address = pda::collateral_registry() @ ErrorCode::CollateralRegistryMismatch)]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
+
+    pub owner: Signer<'info>,
+}
 
-    pub liquidation_record: Account<'info, LiquidationRecord>,
 
-    #[account()]
+
+
+
+#[derive(Accounts)]
+pub struct InitializeCollateralRegistry<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = CollateralRegistry::LEN,
+        seeds = [COLLATERAL_REGISTRY_SEED],
+        bump,
+    )]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
 
 
-
 #[derive(Accounts)]
-pub struct LiquidateFull<'info> {
+pub struct AddCollateralAsset<'info> {
+    #[account(
+        mut,
+        address = pda::collateral_registry() @ ErrorCode::CollateralRegistryMismatch,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: Oracle for `mint`, priced in quote; validated when read
+    pub oracle: UncheckedAccount<'info>,
+
+    // one vault per mint; init fails if the mint is already listed
+    #[account(
+        init,
+        payer = authority,
+        token::mint = mint,
+        token::authority = vault_authority,
+        token::token_program = token_program,
+        seeds = [COLLATERAL_VAULT_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: PDA that signs for all collateral vaults
+    #[account(address = pda::vault_authority().0 @ ErrorCode::VaultAuthorityMismatch)]
+    pub vault_authority: UncheckedAccount<'info>,
+
     #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+
+
+#[derive(Accounts)]
+pub struct UpdateCollateralRegistry<'info> {
+    #[account(
+        mut,
+        address = pda::collateral_registry() @ ErrorCode::CollateralRegistryMismatch,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
+
+    pub authority: Signer<'info>,
+}
+
+
+
+#[derive(Accounts)]
+#[instruction(asset_index: u8)]
+pub struct DepositCollateral<'info> {
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
+    pub position: Account<'info, Position>,
+
+    #[account(
+        mut,
+        address = pda::collateral_registry() @ ErrorCode::CollateralRegistryMismatch,
+        constraint = asset_index < collateral_registry.num_assets @ ErrorCode::InvalidCollateralAsset,
+    )]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
+
+    #[account(
+        mut,
+        address = collateral_registry.assets[asset_index as usize].vault @ ErrorCode::InvalidCollateralVault,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = collateral_registry.assets[asset_index as usize].mint @ ErrorCode::CollateralMintMismatch)]
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == collateral_mint.key() @ ErrorCode::OwnerTokenMintMismatch,
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+
+
+#[derive(Accounts)]
+#[instruction(asset_index: u8)]
+pub struct WithdrawCollateral<'info> {
+    #[account(mut, has_one = owner @ ErrorCode::Unauthorized)]
     pub position: Account<'info, Position>,
 
+    #[account(
+        mut,
+        address = pda::collateral_registry() @ ErrorCode::CollateralRegistryMismatch,
+        constraint = asset_index < collateral_registry.num_assets @ ErrorCode::InvalidCollateralAsset,
+    )]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
+
+    #[account(
+        mut,
+        address = collateral_registry.assets[asset_index as usize].vault @ ErrorCode::InvalidCollateralVault,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: PDA that signs for all collateral vaults
+    #[account(address = pda::vault_authority().0 @ ErrorCode::VaultAuthorityMismatch)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(address = collateral_registry.assets[asset_index as usize].mint @ ErrorCode::CollateralMintMismatch)]
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == collateral_mint.key() @ ErrorCode::OwnerTokenMintMismatch,
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+
+
+#[derive(Accounts)]
+#[instruction(asset_index: u8)]
+pub struct LiquidateCollateral<'info> {
     #[account(mut)]
-    pub insurance_fund: Account<'info, InsuranceFund>,
+    pub position: Account<'info, Position>,
 
-    // SPL token vault owned by PDA (holds protocol collateral + insurance)
     #[account(
         mut,
-        seeds = [VAULT_SEED],
+        constraint = position.market == market.key() @ ErrorCode::MarketMismatch,
+        has_one = oracle @ ErrorCode::OracleMismatch,
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market oracle; validated in logic
+    pub oracle: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        address = pda::collateral_registry() @ ErrorCode::CollateralRegistryMismatch,
+        constraint = asset_index < collateral_registry.num_assets @ ErrorCode::InvalidCollateralAsset,
+    )]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
+
+    #[account(
+        mut,
+        address = collateral_registry.assets[asset_index as usize].vault @ ErrorCode::InvalidCollateralVault,
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut, address = pda::protocol_vault() @ ErrorCode::ProtocolVaultMismatch)]
+    pub protocol_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: PDA that signs for the vaults
+    #[account(address = pda::vault_authority().0 @ ErrorCode::VaultAuthorityMismatch)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    #[account(address = protocol_vault.mint @ ErrorCode::QuoteMintMismatch)]
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(address = collateral_registry.assets[asset_index as usize].mint @ ErrorCode::CollateralMintMismatch)]
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+    // Liquidator pays quote from here
+    #[account(
+        mut,
+        constraint = liquidator_quote_account.mint == quote_mint.key() @ ErrorCode::LiquidatorTokenMintMismatch,
+    )]
+    pub liquidator_quote_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Liquidator receives the seized collateral here
+    #[account(
+        mut,
+        constraint = liquidator_collateral_account.mint == collateral_mint.key() @ ErrorCode::LiquidatorCollateralMintMismatch,
+    )]
+    pub liquidator_collateral_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub liquidator: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+
+
+
+
+#[derive(Accounts)]
+#[instruction(asset_index: u8)]
+pub struct StartCollateralAuction<'info> {
+    #[account(mut)]
+    pub position: Account<'info, Position>,
+
+    #[account(
+        address = pda::collateral_registry() @ ErrorCode::CollateralRegistryMismatch,
+        constraint = asset_index < collateral_registry.num_assets @ ErrorCode::InvalidCollateralAsset,
+    )]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
+
+    /// CHECK: Oracle of the auctioned asset; validated in logic
+    #[account(address = collateral_registry.assets[asset_index as usize].oracle @ ErrorCode::OracleMismatch)]
+    pub collateral_oracle: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = keeper,
+        space = CollateralAuction::LEN,
+        seeds = [AUCTION_SEED, position.key().as_ref(), &[asset_index]],
         bump,
     )]
-    pub protocol_vault: Account<'info, TokenAccount>,
+    pub auction: Account<'info, CollateralAuction>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+
+
+#[derive(Accounts)]
+pub struct BidCollateralAuction<'info> {
+    #[account(mut, has_one = position @ ErrorCode::InvalidAuction)]
+    pub auction: Account<'info, CollateralAuction>,
+
+    #[account(mut)]
+    pub position: Account<'info, Position>,
+
+    #[account(mut, address = pda::collateral_registry() @ ErrorCode::CollateralRegistryMismatch)]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
 
-    // PDA that signs SPL CPI transfers from protocol_vault
     #[account(
-        seeds = [VAULT_SEED],
-        bump
+        mut,
+        address = collateral_registry.assets[auction.asset_index as usize].vault @ ErrorCode::InvalidCollateralVault,
     )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut, address = pda::protocol_vault() @ ErrorCode::ProtocolVaultMismatch)]
+    pub protocol_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: PDA that signs for the vaults
+    #[account(address = pda::vault_authority().0 @ ErrorCode::VaultAuthorityMismatch)]
     pub vault_authority: UncheckedAccount<'info>,
 
-    // Liquidator receives rewards
+    #[account(address = protocol_vault.mint @ ErrorCode::QuoteMintMismatch)]
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(address = auction.mint @ ErrorCode::AuctionMintMismatch)]
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+    // Bidder pays quote from here
+    #[account(
+        mut,
+        constraint = bidder_quote_account.mint == quote_mint.key() @ ErrorCode::BidderQuoteMintMismatch,
+    )]
+    pub bidder_quote_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Bidder receives the collateral here
+    #[account(
+        mut,
+        constraint = bidder_collateral_account.mint == collateral_mint.key() @ ErrorCode::BidderCollateralMintMismatch,
+    )]
+    pub bidder_collateral_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub bidder: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+
+
+#[derive(Accounts)]
+pub struct SettleCollateralAuction<'info> {
+    #[account(
+        mut,
+        has_one = position @ ErrorCode::InvalidAuction,
+        has_one = payer @ ErrorCode::InvalidAuction,
+        close = payer,
+    )]
+    pub auction: Account<'info, CollateralAuction>,
+
     #[account(mut)]
-    pub liquidator_token_account: Account<'info, TokenAccount>,
+    pub position: Account<'info, Position>,
 
-    // Trader receives leftover margin (if any)
+    /// CHECK: receives the auction's rent; checked against auction.payer
     #[account(mut)]
-    pub trader_token_account: Account<'info, TokenAccount>,
+    pub payer: UncheckedAccount<'info>,
 
-    #[account(signer)]
-    pub liquidator: Signer<'info>,
+    #[account(mut, has_one = insurance_vault @ ErrorCode::InsuranceFundMismatch)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
 
-    /// CHECK: Oracle account (Pyth). validated in program logic
-    pub oracle: UncheckedAccount<'info>,
+    #[account(mut, address = pda::insurance_vault() @ ErrorCode::InsuranceVaultMismatch)]
+    pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
 
-    pub token_program: Program<'info, Token>,
+    /// CHECK: PDA authority for insurance_vault
+    #[account(address = pda::insurance_authority().0 @ ErrorCode::InsuranceAuthorityMismatch)]
+    pub insurance_authority: UncheckedAccount<'info>,
+
+    #[account(mut, address = pda::protocol_vault() @ ErrorCode::ProtocolVaultMismatch)]
+    pub protocol_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = protocol_vault.mint @ ErrorCode::QuoteMintMismatch)]
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
 
+
+
+
+
+#[derive(Accounts)]
+pub struct LiquidatePartial<'info> {
+
+    #[account(mut)]
+    pub position: Account<'info, Position>,
+
+    // Reference price for the deviation guard; must match the position and oracle
+    #[account(
+        mut,
+        constraint = position.market == market.key() @ ErrorCode::MarketMismatch,
+        has_one = oracle @ ErrorCode::OracleMismatch,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(address = pda::collateral_registry() @ ErrorCode::CollateralRegistryMismatch)]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
+
+    #[account(mut, has_one = insurance_vault @ ErrorCode::InsuranceFundMismatch)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    /// CHECK: PDA authority for insurance_vault
+    #[account(address = pda::insurance_authority().0 @ ErrorCode::InsuranceAuthorityMismatch)]
+    pub insurance_authority: UncheckedAccount<'info>,
+
+    #[account(
+        address = pda::insurance_vault() @ ErrorCode::InsuranceVaultMismatch,
+        constraint = insurance_vault.owner == insurance_authority.key() @ ErrorCode::InvalidInsuranceAuthority,
+    )]
+    pub insurance_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: PDA that signs all SPL CPI transfers from protocol_vault
+    #[account(address = pda::vault_authority().0 @ ErrorCode::VaultAuthorityMismatch)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    // SPL token vault owned by PDA
+    #[account(
+        mut,
+        address = pda::protocol_vault() @ ErrorCode::ProtocolVaultMismatch,
+        constraint = protocol_vault.owner == vault_authority.key() @ ErrorCode::InvalidVaultAuthority,
+    )]
+    pub protocol_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = protocol_vault.mint @ ErrorCode::QuoteMintMismatch)]
+    pub quote_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    // Liquidator receives rewards
+    #[account(
+        mut,
+        constraint = liquidator_token_account.mint == protocol_vault.mint @ ErrorCode::LiquidatorTokenMintMismatch,
+    )]
+    pub liquidator_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // Nothing is paid out on a partial; kept so the layout matches liquidate_full
+    #[account(
+        mut,
+        constraint = trader_token_account.mint == protocol_vault.mint @ ErrorCode::TraderTokenMintMismatch,
+        constraint = trader_token_account.owner == position.owner @ ErrorCode::TraderAccountOwnerMismatch,
+    )]
+    pub trader_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = liquidator,
+        space = LiquidationRecord::LEN,
+        seeds = [
+            LIQ_RECORD_SEED,
+            position.key().as_ref(),
+            &position.liquidation_count.to_le_bytes(),
+        ],
+        bump,
+    )]
     pub liquidation_record: Account<'info, LiquidationRecord>,
+
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+
+    /// CHECK: Oracle account; validated in logic
+    pub oracle: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
 }
 
 
+
+
 #[derive(Accounts)]
-pub struct WriteLiquidationRecord<'info> {
+pub struct LiquidateFull<'info> {
+    #[account(mut)]
+    pub position: Account<'info, Position>,
+
+    // Reference price for the deviation guard; must match the position and oracle
+    #[account(
+        mut,
+        constraint = position.market == market.key() @ ErrorCode::MarketMismatch,
+        has_one = oracle @ ErrorCode::OracleMismatch,
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(address = pda::collateral_registry() @ ErrorCode::CollateralRegistryMismatch)]
+    pub collateral_registry: Box<Account<'info, CollateralRegistry>>,
+
+    #[account(mut, has_one = insurance_vault @ ErrorCode::InsuranceFundMismatch)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    /// CHECK: PDA authority for insurance_vault
+    #[account(address = pda::insurance_authority().0 @ ErrorCode::InsuranceAuthorityMismatch)]
+    pub insurance_authority: UncheckedAccount<'info>,
+
+    // Covers bad debt
+    #[account(
+        mut,
+        address = pda::insurance_vault() @ ErrorCode::InsuranceVaultMismatch,
+        constraint = insurance_vault.owner == insurance_authority.key() @ ErrorCode::InvalidInsuranceAuthority,
+    )]
+    pub insurance_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: PDA that signs SPL CPI transfers from protocol_vault
+    #[account(address = pda::vault_authority().0 @ ErrorCode::VaultAuthorityMismatch)]
+    pub vault_authority: UncheckedAccount<'info>,
+
+    // SPL token vault owned by PDA (holds protocol collateral)
+    #[account(
+        mut,
+        address = pda::protocol_vault() @ ErrorCode::ProtocolVaultMismatch,
+        constraint = protocol_vault.owner == vault_authority.key() @ ErrorCode::InvalidVaultAuthority,
+    )]
+    pub protocol_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = protocol_vault.mint @ ErrorCode::QuoteMintMismatch)]
+    pub quote_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    // Liquidator receives rewards
+    #[account(
+        mut,
+        constraint = liquidator_token_account.mint == protocol_vault.mint @ ErrorCode::LiquidatorTokenMintMismatch,
+    )]
+    pub liquidator_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // Trader receives leftover margin (if any)
+    #[account(
+        mut,
+        constraint = trader_token_account.mint == protocol_vault.mint @ ErrorCode::TraderTokenMintMismatch,
+        constraint = trader_token_account.owner == position.owner @ ErrorCode::TraderAccountOwnerMismatch,
+    )]
+    pub trader_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
     #[account(
         init,
-        payer = payer,
+        payer = liquidator,
         space = LiquidationRecord::LEN,
         seeds = [
-            b"record",
-            position_owner.key().as_ref(),
-            &timestamp.to_le_bytes(),
+            LIQ_RECORD_SEED,
+            position.key().as_ref(),
+            &position.liquidation_count.to_le_bytes(),
         ],
-        bump
+        bump,
     )]
     pub liquidation_record: Account<'info, LiquidationRecord>,
 
     #[account(mut)]
-    pub payer: Signer<'info>,
+    pub liquidator: Signer<'info>,
 
+    /// CHECK: Oracle account (Pyth). validated in program logic
+    pub oracle: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
@@ -753,10 +1626,14 @@ pub struct WriteLiquidationRecord<'info> {
 
 
 #[account]
+#[derive(Default)]
 pub struct Position {
     // Trader who owns the position
     pub owner: Pubkey,
 
+    // Market this position trades on
+    pub market: Pubkey,
+
     // Base asset size (e.g., number of contracts)
     pub size: u64,          // 0 means closed
 
@@ -773,15 +1650,32 @@ pub struct Position {
     // Timestamp of last update (important for cooldown)
     pub last_update_ts: i64,
 
-    // Cached leverage for easier margin checks
+    // Leverage declared at open. Informational only: maintenance margin
+    // comes from the market's risk tiers at the current notional.
     pub leverage: u16,      // e.g., 100 = 100x
 
-    // Padding for account alignment (optional)
-    pub padding: [u8; 5],
+    // Non-quote collateral, base units, indexed like CollateralRegistry.assets
+    pub deposits: [u64; MAX_COLLATERAL_ASSETS],
+
+    // Collateral auctions running against this position's deficit
+    pub open_auctions: u8,
+
+    // Liquidations so far; seeds the next LiquidationRecord PDA
+    pub liquidation_count: u32,
 }
 
 impl Position {
-    pub const LEN: usize = 8 + 32 + 8 + 8 + 8 + 1 + 8 + 2 + 5;
+    pub const LEN: usize = 8 + 32 + 32 + 8 + 8 + 8 + 1 + 8 + 2 + 8 * MAX_COLLATERAL_ASSETS + 1 + 4;
+
+    /// The fields the margin math reads.
+    pub fn terms(&self) -> PositionTerms {
+        PositionTerms {
+            entry_price: Price(self.entry_price),
+            size: Quantity(self.size),
+            collateral: QuoteAmount::from_i64(self.collateral),
+            is_long: self.is_long,
+        }
+    }
 }
 
 
@@ -809,6 +1703,7 @@ impl LiquidationRecord {
 #[account]
 pub struct InsuranceFund {
     pub authority: Pubkey,             // 32
+    pub insurance_vault: Pubkey,       // 32
     pub balance: u64,                  // 8
     pub total_contributions: u64,      // 8
     pub total_bad_debt_covered: u64,   // 8
@@ -816,5 +1711,95 @@ pub struct InsuranceFund {
 }
 
 impl InsuranceFund {
-    pub const LEN: usize = 8 + 32 + 8 + 8 + 8 + 8;
+    pub const LEN: usize = 8 + 32 + 32 + 8 + 8 + 8 + 8;
+}
+
+
+
+#[account]
+#[derive(Default)]
+pub struct Market {
+    pub authority: Pubkey,                // 32
+    pub oracle: Pubkey,                   // 32
+    pub market_index: u16,                // 2
+    pub symbol: [u8; 16],                 // 16
+
+    // Deviation guard (see price_guard.rs)
+    pub max_price_deviation_bps: u64,     // 8  allowed band around last_accepted_price
+    pub deviation_persist_slots: u64,     // 8  slots an out-of-band price must persist
+    pub last_accepted_price: u64,         // 8  PRICE_PRECISION
+    pub last_accepted_slot: u64,          // 8
+    pub pending_price: u64,               // 8  0 = nothing pending
+    pub pending_since_slot: u64,          // 8
+
+    // Open interest (base size) and limits (see limits.rs)
+    pub long_open_interest: u64,          // 8
+    pub short_open_interest: u64,         // 8
+    pub max_open_interest: u64,           // 8  per side, base size
+    pub max_position_notional: u64,       // 8  per position, quote
+
+    // Maintenance margin by notional (see risk_tiers.rs)
+    pub risk_tiers: [RiskTier; MAX_RISK_TIERS], // 16 * MAX_RISK_TIERS
+    pub num_risk_tiers: u8,               // 1
+}
+
+impl Market {
+    pub const LEN: usize = 8 + 32 + 32 + 2 + 16 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8
+        + RiskTier::LEN * MAX_RISK_TIERS + 1;
+}
+
+
+
+// Non-quote collateral mints accepted by the protocol (see collateral.rs).
+#[account]
+#[derive(Default)]
+pub struct CollateralRegistry {
+    pub authority: Pubkey,                                 // 32
+    pub num_assets: u8,                                    // 1
+    pub assets: [CollateralAsset; MAX_COLLATERAL_ASSETS],  // CollateralAsset::LEN * MAX
+}
+
+impl CollateralRegistry {
+    pub const LEN: usize = 8 + 32 + 1 + CollateralAsset::LEN * MAX_COLLATERAL_ASSETS;
+}
+
+
+
+// Dutch auction of seized non-quote collateral (see auction.rs).
+#[account]
+#[derive(Default)]
+pub struct CollateralAuction {
+    pub position: Pubkey,              // 32
+    pub payer: Pubkey,                 // 32 keeper that paid rent
+    pub mint: Pubkey,                  // 32
+    pub asset_index: u8,               // 1
+    pub decimals: u8,                  // 1
+    pub initial_amount: u64,           // 8
+    pub remaining_amount: u64,         // 8
+    pub proceeds: u64,                 // 8  quote
+    pub start_price: u64,              // 8  quote per whole token, PRICE_PRECISION
+    pub end_price: u64,                // 8
+    pub start_ts: i64,                 // 8
+    pub duration: i64,                 // 8  seconds
+}
+
+impl CollateralAuction {
+    pub const LEN: usize = 8 + 32 + 32 + 32 + 1 + 1 + 8 + 8 + 8 + 8 + 8 + 8 + 8;
+}
+
+
+
+// Test-only price feed, see `oracle::MockPriceSource`.
+#[account]
+pub struct MockOracle {
+    pub authority: Pubkey,             // 32
+    pub price: i64,                    // 8
+    pub conf: u64,                     // 8
+    pub expo: i32,                     // 4
+    pub publish_time: i64,             // 8
+    pub status: OracleStatus,          // 1
+}
+
+impl MockOracle {
+    pub const LEN: usize = 8 + 32 + 8 + 8 + 4 + 8 + 1;
 }
@@ -0,0 +1,168 @@
+use anchor_lang::prelude::*;
+
+use liquidation_math::{Price, Quantity, QuoteAmount, Round};
+
+use crate::state::ErrorCode;
+use crate::Market;
+
+/// Notional of `size` at `price`, in quote units (PRICE_PRECISION scaled).
+/// Rounded up, so the cap can't be slipped under by a fraction.
+pub fn position_notional(size: u64, price: u64) -> Result<u64> {
+    Price(price)
+        .notional(Quantity(size), Round::Up)
+        .and_then(QuoteAmount::to_u64)
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// Check a new position against the market's per-position notional cap.
+pub fn check_position_notional(market: &Market, notional: u64) -> Result<()> {
+    require!(
+        notional <= market.max_position_notional,
+        ErrorCode::PositionNotionalLimitExceeded
+    );
+    Ok(())
+}
+
+/// Add `size` to the long or short open interest, refusing to go over the cap.
+pub fn increase_open_interest(market: &mut Market, is_long: bool, size: u64) -> Result<()> {
+    let cap = market.max_open_interest;
+    let side = if is_long {
+        &mut market.long_open_interest
+    } else {
+        &mut market.short_open_interest
+    };
+
+    let new_oi = side
+        .checked_add(size)
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+    require!(
+        new_oi <= cap,
+        ErrorCode::OpenInterestLimitExceeded
+    );
+
+    *side = new_oi;
+    Ok(())
+}
+
+/// Remove `size` from the long or short open interest (close or liquidation).
+/// Never refused: reducing risk must always be possible.
+pub fn decrease_open_interest(market: &mut Market, is_long: bool, size: u64) {
+    let side = if is_long {
+        &mut market.long_open_interest
+    } else {
+        &mut market.short_open_interest
+    };
+
+    *side = side.saturating_sub(size);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::PRICE_PRECISION;
+
+    const SIZE: u64 = 10 * PRICE_PRECISION;
+
+    // open interest capped at 15 units a side, positions at 1_000 quote
+    fn market() -> Market {
+        Market {
+            max_open_interest: 15 * PRICE_PRECISION,
+            max_position_notional: 1_000 * PRICE_PRECISION,
+            ..Market::default()
+        }
+    }
+
+    fn code(err: Error) -> u32 {
+        match err {
+            Error::AnchorError(e) => e.error_code_number,
+            Error::ProgramError(_) => panic!("expected anchor error"),
+        }
+    }
+
+    #[test]
+    fn notional_rounds_up() {
+        assert_eq!(position_notional(SIZE, 100 * PRICE_PRECISION).unwrap(), 1_000 * PRICE_PRECISION);
+        // a millionth of a unit at 0.5 is worth half a quote unit
+        assert_eq!(position_notional(1, PRICE_PRECISION / 2).unwrap(), 1);
+        assert_eq!(position_notional(0, 100 * PRICE_PRECISION).unwrap(), 0);
+    }
+
+    #[test]
+    fn notional_at_the_cap_is_allowed() {
+        let market = market();
+        assert!(check_position_notional(&market, 1_000 * PRICE_PRECISION).is_ok());
+        assert_eq!(
+            code(check_position_notional(&market, 1_000 * PRICE_PRECISION + 1).unwrap_err()),
+            u32::from(ErrorCode::PositionNotionalLimitExceeded)
+        );
+    }
+
+    #[test]
+    fn open_interest_is_capped_per_side() {
+        let mut market = market();
+        increase_open_interest(&mut market, true, SIZE).unwrap();
+
+        let err = increase_open_interest(&mut market, true, SIZE).unwrap_err();
+        assert_eq!(code(err), u32::from(ErrorCode::OpenInterestLimitExceeded));
+        assert_eq!(market.long_open_interest, SIZE);
+
+        // the short side has its own room, and the long side fills to the cap exactly
+        increase_open_interest(&mut market, false, SIZE).unwrap();
+        increase_open_interest(&mut market, true, 5 * PRICE_PRECISION).unwrap();
+        assert_eq!(market.long_open_interest, 15 * PRICE_PRECISION);
+        assert_eq!(market.short_open_interest, SIZE);
+    }
+
+    #[test]
+    fn open_interest_overflow_is_an_error() {
+        let mut market = Market { max_open_interest: u64::MAX, long_open_interest: u64::MAX, ..Market::default() };
+        let err = increase_open_interest(&mut market, true, 1).unwrap_err();
+        assert_eq!(code(err), u32::from(ErrorCode::ArithmeticOverflow));
+    }
+
+    #[test]
+    fn close_releases_the_whole_size() {
+        let mut market = market();
+        increase_open_interest(&mut market, true, SIZE).unwrap();
+        increase_open_interest(&mut market, false, SIZE).unwrap();
+
+        decrease_open_interest(&mut market, true, SIZE);
+        assert_eq!(market.long_open_interest, 0);
+        assert_eq!(market.short_open_interest, SIZE);
+    }
+
+    #[test]
+    fn partial_then_full_liquidation_release_in_steps() {
+        let mut market = market();
+        increase_open_interest(&mut market, false, SIZE).unwrap();
+
+        // a partial closes half, the full liquidation the rest
+        decrease_open_interest(&mut market, false, SIZE / 2);
+        assert_eq!(market.short_open_interest, SIZE / 2);
+        decrease_open_interest(&mut market, false, SIZE / 2);
+        assert_eq!(market.short_open_interest, 0);
+
+        // released room can be taken again
+        increase_open_interest(&mut market, false, 15 * PRICE_PRECISION).unwrap();
+    }
+
+    #[test]
+    fn decrease_never_goes_below_zero() {
+        let mut market = market();
+        increase_open_interest(&mut market, true, SIZE).unwrap();
+        decrease_open_interest(&mut market, true, 2 * SIZE);
+        assert_eq!(market.long_open_interest, 0);
+    }
+
+    #[test]
+    fn lowered_cap_blocks_only_new_opens() {
+        let mut market = market();
+        increase_open_interest(&mut market, true, SIZE).unwrap();
+        market.max_open_interest = PRICE_PRECISION;
+
+        let err = increase_open_interest(&mut market, true, 1).unwrap_err();
+        assert_eq!(code(err), u32::from(ErrorCode::OpenInterestLimitExceeded));
+        decrease_open_interest(&mut market, true, SIZE / 2);
+        assert_eq!(market.long_open_interest, SIZE / 2);
+    }
+}
@@ -0,0 +1,179 @@
+//! Instructions for the test-only `MockOracle` price feed, compiled into
+//! `mock-oracle` builds only. `#[program]` can't leave a handler out of a
+//! build, so these live outside it and are reached through its fallback,
+//! dispatched the way its generated handlers are. They have no IDL entry;
+//! `accounts` and `instruction` stand in for the generated client modules.
+
+use std::collections::BTreeSet;
+
+use anchor_lang::prelude::*;
+use anchor_lang::{AccountsExit, Bumps, Discriminator};
+
+use crate::oracle::OracleStatus;
+use crate::state::ErrorCode;
+use crate::MockOracle;
+
+#[derive(Accounts)]
+pub struct InitializeMockOracle<'info> {
+    #[account(init, payer = authority, space = MockOracle::LEN)]
+    pub mock_oracle: Account<'info, MockOracle>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetMockOracle<'info> {
+    #[account(mut, has_one = authority @ ErrorCode::Unauthorized)]
+    pub mock_oracle: Account<'info, MockOracle>,
+
+    pub authority: Signer<'info>,
+}
+
+pub fn initialize_mock_oracle(ctx: Context<InitializeMockOracle>, price: i64, conf: u64, expo: i32) -> Result<()> {
+    let oracle = &mut ctx.accounts.mock_oracle;
+    oracle.authority = ctx.accounts.authority.key();
+    oracle.price = price;
+    oracle.conf = conf;
+    oracle.expo = expo;
+    oracle.publish_time = Clock::get()?.unix_timestamp;
+    oracle.status = OracleStatus::Trading;
+    Ok(())
+}
+
+// publish_time defaults to the current clock; pass one explicitly to test staleness.
+pub fn set_mock_oracle(
+    ctx: Context<SetMockOracle>,
+    price: i64,
+    conf: u64,
+    expo: i32,
+    publish_time: Option<i64>,
+    status: OracleStatus,
+) -> Result<()> {
+    let oracle = &mut ctx.accounts.mock_oracle;
+    oracle.price = price;
+    oracle.conf = conf;
+    oracle.expo = expo;
+    oracle.publish_time = match publish_time {
+        Some(ts) => ts,
+        None => Clock::get()?.unix_timestamp,
+    };
+    oracle.status = status;
+    Ok(())
+}
+
+/// Account metas for the builders, as in `crate::accounts`.
+pub mod accounts {
+    pub use super::__client_accounts_initialize_mock_oracle::*;
+    pub use super::__client_accounts_set_mock_oracle::*;
+}
+
+/// Instruction data, as in `crate::instruction`: the handler's arguments
+/// after the usual `global:<name>` sighash.
+pub mod instruction {
+    use anchor_lang::prelude::*;
+    use anchor_lang::{Discriminator, InstructionData};
+
+    use crate::oracle::OracleStatus;
+
+    #[derive(AnchorSerialize, AnchorDeserialize)]
+    pub struct InitializeMockOracle {
+        pub price: i64,
+        pub conf: u64,
+        pub expo: i32,
+    }
+
+    impl Discriminator for InitializeMockOracle {
+        const DISCRIMINATOR: [u8; 8] = [18, 149, 36, 131, 144, 177, 67, 101];
+    }
+
+    impl InstructionData for InitializeMockOracle {}
+
+    #[derive(AnchorSerialize, AnchorDeserialize)]
+    pub struct SetMockOracle {
+        pub price: i64,
+        pub conf: u64,
+        pub expo: i32,
+        pub publish_time: Option<i64>,
+        pub status: OracleStatus,
+    }
+
+    impl Discriminator for SetMockOracle {
+        const DISCRIMINATOR: [u8; 8] = [117, 178, 5, 205, 237, 50, 255, 213];
+    }
+
+    impl InstructionData for SetMockOracle {}
+}
+
+/// Run the mock oracle instruction `data` is for, or `None` if it's for
+/// neither of them.
+pub fn dispatch<'info>(
+    program_id: &Pubkey,
+    accounts: &'info [AccountInfo<'info>],
+    data: &[u8],
+) -> Option<Result<()>> {
+    if data.len() < 8 {
+        return None;
+    }
+    let (sighash, args) = data.split_at(8);
+
+    if sighash == instruction::InitializeMockOracle::DISCRIMINATOR {
+        return Some(run(program_id, accounts, args, "InitializeMockOracle", |ctx, ix: instruction::InitializeMockOracle| {
+            initialize_mock_oracle(ctx, ix.price, ix.conf, ix.expo)
+        }));
+    }
+    if sighash == instruction::SetMockOracle::DISCRIMINATOR {
+        return Some(run(program_id, accounts, args, "SetMockOracle", |ctx, ix: instruction::SetMockOracle| {
+            set_mock_oracle(ctx, ix.price, ix.conf, ix.expo, ix.publish_time, ix.status)
+        }));
+    }
+    None
+}
+
+// What `#[program]` generates for each of its handlers: decode the
+// arguments, validate the accounts, call the handler and persist the
+// accounts it changed.
+fn run<'info, T, A>(
+    program_id: &Pubkey,
+    accounts: &'info [AccountInfo<'info>],
+    args: &[u8],
+    name: &str,
+    handler: impl FnOnce(Context<'_, '_, '_, 'info, T>, A) -> Result<()>,
+) -> Result<()>
+where
+    T: Bumps + Accounts<'info, T::Bumps> + AccountsExit<'info>,
+    T::Bumps: Default,
+    A: AnchorDeserialize,
+{
+    #[cfg(not(feature = "no-log-ix-name"))]
+    msg!("Instruction: {}", name);
+    #[cfg(feature = "no-log-ix-name")]
+    let _ = name;
+
+    let ix = A::deserialize(&mut &args[..]).map_err(|_| anchor_lang::error::ErrorCode::InstructionDidNotDeserialize)?;
+
+    let mut bumps = T::Bumps::default();
+    let mut reallocs = BTreeSet::new();
+    let mut remaining_accounts: &[AccountInfo] = accounts;
+    let mut accounts = T::try_accounts(program_id, &mut remaining_accounts, args, &mut bumps, &mut reallocs)?;
+
+    handler(Context::new(program_id, &mut accounts, remaining_accounts, bumps), ix)?;
+    accounts.exit(program_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::hash::hash;
+
+    fn sighash(name: &str) -> [u8; 8] {
+        hash(format!("global:{name}").as_bytes()).to_bytes()[..8].try_into().unwrap()
+    }
+
+    #[test]
+    fn discriminators_are_the_sighash_of_the_handler() {
+        assert_eq!(instruction::InitializeMockOracle::DISCRIMINATOR, sighash("initialize_mock_oracle"));
+        assert_eq!(instruction::SetMockOracle::DISCRIMINATOR, sighash("set_mock_oracle"));
+    }
+}
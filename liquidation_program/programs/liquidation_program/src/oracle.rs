@@ -1,24 +1,175 @@
 use anchor_lang::prelude::*;
-use pyth_sdk_solana::state::load_price_feed_from_account_info;
+use pyth_sdk_solana::state::{load_price_account, PriceStatus};
 use anchor_lang::solana_program::account_info::AccountInfo;
 
 use crate::constants::*;
-use crate::state::*;
+use crate::rescale::*;
+use crate::state::ErrorCode;
+use crate::MockOracle;
 
 
+/// Trading status reported by a price source.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OracleStatus {
+    #[default]
+    Unknown,
+    Trading,
+    Halted,
+    Auction,
+    Ignored,
+}
+
+impl From<PriceStatus> for OracleStatus {
+    fn from(status: PriceStatus) -> Self {
+        match status {
+            PriceStatus::Unknown => OracleStatus::Unknown,
+            PriceStatus::Trading => OracleStatus::Trading,
+            PriceStatus::Halted => OracleStatus::Halted,
+            PriceStatus::Auction => OracleStatus::Auction,
+            PriceStatus::Ignored => OracleStatus::Ignored,
+        }
+    }
+}
+
+/// Anything that can quote a price in Pyth form: `price * 10^exponent`.
+pub trait PriceSource {
+    fn price(&self) -> i64;
+    fn confidence(&self) -> u64;
+    fn exponent(&self) -> i32;
+    fn publish_time(&self) -> i64;
+    fn status(&self) -> OracleStatus;
+}
+
+/// Price read from a Pyth price account.
+pub struct PythPriceSource {
+    price: pyth_sdk_solana::Price,
+    status: OracleStatus,
+}
+
+impl PythPriceSource {
+    pub fn load(oracle_acc: &AccountInfo) -> Result<Self> {
+        let data = oracle_acc
+            .try_borrow_data()
+            .map_err(|_| error!(ErrorCode::InvalidOracleAccount))?;
+        let price_account = load_price_account(&data)
+            .map_err(|_| error!(ErrorCode::InvalidOracleAccount))?;
+
+        let feed = price_account.to_price_feed(oracle_acc.key);
+
+        Ok(Self {
+            price: feed.get_price_unchecked(),
+            status: price_account.agg.status.into(),
+        })
+    }
+}
+
+impl PriceSource for PythPriceSource {
+    fn price(&self) -> i64 {
+        self.price.price
+    }
+
+    fn confidence(&self) -> u64 {
+        self.price.conf
+    }
+
+    fn exponent(&self) -> i32 {
+        self.price.expo
+    }
+
+    fn publish_time(&self) -> i64 {
+        self.price.publish_time
+    }
+
+    fn status(&self) -> OracleStatus {
+        self.status
+    }
+}
+
+/// Fixed price, either built directly in unit tests or read from a
+/// `MockOracle` account on a local validator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MockPriceSource {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub publish_time: i64,
+    pub status: OracleStatus,
+}
+
+impl MockPriceSource {
+    pub fn load(oracle_acc: &AccountInfo) -> Result<Self> {
+        let data = oracle_acc
+            .try_borrow_data()
+            .map_err(|_| error!(ErrorCode::InvalidOracleAccount))?;
+        let mock = MockOracle::try_deserialize(&mut &data[..])
+            .map_err(|_| error!(ErrorCode::InvalidOracleAccount))?;
+
+        Ok(Self::from(&mock))
+    }
+}
+
+impl From<&MockOracle> for MockPriceSource {
+    fn from(mock: &MockOracle) -> Self {
+        Self {
+            price: mock.price,
+            conf: mock.conf,
+            expo: mock.expo,
+            publish_time: mock.publish_time,
+            status: mock.status,
+        }
+    }
+}
+
+impl PriceSource for MockPriceSource {
+    fn price(&self) -> i64 {
+        self.price
+    }
+
+    fn confidence(&self) -> u64 {
+        self.conf
+    }
+
+    fn exponent(&self) -> i32 {
+        self.expo
+    }
+
+    fn publish_time(&self) -> i64 {
+        self.publish_time
+    }
+
+    fn status(&self) -> OracleStatus {
+        self.status
+    }
+}
+
 
 pub fn get_oracle_price(oracle_acc: &AccountInfo) -> Result<u64> {
-    //  Parse Pyth price feed
-    let price_feed = load_price_feed_from_account_info(oracle_acc)
-        .map_err(|_| error!(ErrorCode::InvalidOracleAccount))?;
+    let now = Clock::get()?.unix_timestamp;
 
-    // Latest price data
-    let price_data = price_feed
-        .get_price_no_older_than(Clock::get()?.unix_timestamp, MAX_ORACLE_STALENESS)
-        .ok_or(error!(ErrorCode::StaleOraclePrice))?;
+    // MockOracle accounts are owned by this program. They are only honoured
+    // in builds with the `mock-oracle` feature, never in production.
+    #[cfg(feature = "mock-oracle")]
+    if oracle_acc.owner == &crate::ID {
+        return validate_price(&MockPriceSource::load(oracle_acc)?, now);
+    }
+
+    validate_price(&PythPriceSource::load(oracle_acc)?, now)
+}
 
-    let price_i64 = price_data.price;
-    let conf_i64 = price_data.conf;
+/// Run the staleness, status, sign and confidence checks on a price source
+/// and scale the result to PRICE_PRECISION.
+pub fn validate_price(source: &impl PriceSource, now: i64) -> Result<u64> {
+    require!(
+        source.status() == OracleStatus::Trading,
+        ErrorCode::OracleNotTrading
+    );
+
+    require!(
+        now.saturating_sub(source.publish_time()) <= MAX_ORACLE_STALENESS,
+        ErrorCode::StaleOraclePrice
+    );
+
+    let price_i64 = source.price();
 
     // Reject negative or zero prices
     require!(price_i64 > 0, ErrorCode::InvalidOraclePrice);
@@ -29,7 +180,7 @@ pub fn get_oracle_price(oracle_acc: &AccountInfo) -> Result<u64> {
     // (You may tighten or loosen this)
     //
     require!(
-        conf_i64 < price_i64 / MAX_CONF_FACTOR,
+        source.confidence() < (price_i64 / MAX_CONF_FACTOR) as u64,
         ErrorCode::OracleConfidenceTooHigh
     );
 
@@ -38,42 +189,96 @@ pub fn get_oracle_price(oracle_acc: &AccountInfo) -> Result<u64> {
     // Pyth returns prices in 10^exponent scaling.
     // exponent usually = -8 for BTC, -6 for SOL etc.
     //
-    let exponent = price_data.expo;
-
-    let scaled_price = scale_price_to_precision(price_i64, exponent)?;
-
-    Ok(scaled_price)
+    scale_price_to_precision(price_i64, source.exponent())
 }
 
 /// Convert Pyth price from exponent form into your PRICE_PRECISION (1e6)
+///
+/// Rounds to the nearest unit: the same price feeds both long and short
+/// margin checks, so neither side should get the benefit of truncation.
 pub fn scale_price_to_precision(price: i64, expo: i32) -> Result<u64> {
-    // convert i64→i128 for safe math
-    let mut v = price as i128;
+    require!(is_supported_exponent(expo), ErrorCode::UnsupportedOracleExponent);
 
-    // Compute how much to shift from Pyth exponent to PRICE_PRECISION exponent.
-    //
     // Example:
-    // pyth exponent = -8
-    // PRICE_PRECISION exponent = -6
-    // need to divide by 10^( -6 - (-8) ) = 10^2
-    //
-    let target_exponent: i32 = -6; // because PRICE_PRECISION = 1e6
-    let delta = target_exponent - expo;
-
-    if delta < 0 {
-        for _ in 0..delta {
-            v = v
-                .checked_mul(10)
-                .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+    // pyth exponent = -8, PRICE_EXPONENT = -6
+    // 12_345_678_901 * 10^-8 -> 123_456_789 * 10^-6
+    rescale_to_u64(price as i128, expo, PRICE_EXPONENT, Round::Nearest)
+        .ok_or(error!(ErrorCode::ArithmeticOverflow))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn btc() -> MockPriceSource {
+        MockPriceSource {
+            price: 6_500_012_345_678, // 65_000.12345678 at expo -8
+            conf: 1_000_000,
+            expo: -8,
+            publish_time: NOW,
+            status: OracleStatus::Trading,
+        }
+    }
+
+    fn code(err: Error) -> u32 {
+        match err {
+            Error::AnchorError(e) => e.error_code_number,
+            Error::ProgramError(_) => panic!("expected anchor error"),
         }
-    } else if delta > 0 {
-        for _ in 0..(-delta) {
-            v = v
-                .checked_div(10)
-                .ok_or(error!(ErrorCode::ArithmeticOverflow))?;
+    }
+
+    fn expect_err(source: MockPriceSource, expected: ErrorCode) {
+        let err = validate_price(&source, NOW).unwrap_err();
+        assert_eq!(code(err), u32::from(expected));
+    }
+
+    #[test]
+    fn scales_valid_price() {
+        assert_eq!(validate_price(&btc(), NOW).unwrap(), 65_000_123_457);
+    }
+
+    #[test]
+    fn accepts_price_at_staleness_limit() {
+        let source = MockPriceSource { publish_time: NOW - MAX_ORACLE_STALENESS, ..btc() };
+        assert!(validate_price(&source, NOW).is_ok());
+    }
+
+    #[test]
+    fn rejects_stale_price() {
+        let source = MockPriceSource { publish_time: NOW - MAX_ORACLE_STALENESS - 1, ..btc() };
+        expect_err(source, ErrorCode::StaleOraclePrice);
+    }
+
+    #[test]
+    fn rejects_non_trading_status() {
+        for status in [
+            OracleStatus::Unknown,
+            OracleStatus::Halted,
+            OracleStatus::Auction,
+            OracleStatus::Ignored,
+        ] {
+            expect_err(MockPriceSource { status, ..btc() }, ErrorCode::OracleNotTrading);
         }
     }
 
-    // convert back to u64
-    Ok(v.try_into().map_err(|_| error!(ErrorCode::ArithmeticOverflow))?)
+    #[test]
+    fn rejects_non_positive_price() {
+        expect_err(MockPriceSource { price: 0, ..btc() }, ErrorCode::InvalidOraclePrice);
+        expect_err(MockPriceSource { price: -1, ..btc() }, ErrorCode::InvalidOraclePrice);
+    }
+
+    #[test]
+    fn rejects_wide_confidence() {
+        let price = btc().price;
+        let at_limit = (price / MAX_CONF_FACTOR) as u64;
+        expect_err(MockPriceSource { conf: at_limit, ..btc() }, ErrorCode::OracleConfidenceTooHigh);
+        assert!(validate_price(&MockPriceSource { conf: at_limit - 1, ..btc() }, NOW).is_ok());
+    }
+
+    #[test]
+    fn rejects_unsupported_exponent() {
+        expect_err(MockPriceSource { expo: -19, ..btc() }, ErrorCode::UnsupportedOracleExponent);
+    }
 }
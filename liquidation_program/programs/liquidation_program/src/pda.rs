@@ -0,0 +1,30 @@
+use anchor_lang::prelude::*;
+
+use crate::constants::*;
+
+// Fixed program addresses. Account contexts check them with
+// `address = ... @ ErrorCode::...` rather than `seeds = [...], bump`, which
+// can only fail with anchor's generic ConstraintSeeds.
+
+/// Signs transfers out of the protocol vault and the collateral vaults.
+pub fn vault_authority() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_AUTH_SEED, VAULT_SEED], &crate::ID)
+}
+
+/// Signs transfers out of the insurance vault.
+pub fn insurance_authority() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[INSURANCE_AUTH_SEED, INSURANCE_SEED], &crate::ID)
+}
+
+/// Quote vault backing every position.
+pub fn protocol_vault() -> Pubkey {
+    Pubkey::find_program_address(&[VAULT_SEED], &crate::ID).0
+}
+
+pub fn insurance_vault() -> Pubkey {
+    Pubkey::find_program_address(&[INSURANCE_SEED], &crate::ID).0
+}
+
+pub fn collateral_registry() -> Pubkey {
+    Pubkey::find_program_address(&[COLLATERAL_REGISTRY_SEED], &crate::ID).0
+}
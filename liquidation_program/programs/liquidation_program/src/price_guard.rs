@@ -0,0 +1,135 @@
+use crate::constants::*;
+use crate::Market;
+
+/// Outcome of running an oracle price through a market's deviation guard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriceCheck {
+    /// Price is within the band (or has persisted long enough) and is now
+    /// the market's reference price.
+    Accepted,
+    /// Price is outside the band and has not persisted for
+    /// `deviation_persist_slots` yet.
+    Pending,
+}
+
+/// True if `price` is within `band_bps` of `reference`.
+/// A zero reference means nothing has been accepted yet.
+pub fn is_within_band(reference: u64, price: u64, band_bps: u64) -> bool {
+    if reference == 0 {
+        return true;
+    }
+
+    let diff = reference.abs_diff(price) as u128;
+    diff * (BPS_DENOM as u128) <= (reference as u128) * (band_bps as u128)
+}
+
+/// Feed a fresh oracle price through the market's deviation guard.
+///
+/// In-band prices are accepted immediately. An out-of-band price starts (or
+/// continues) a pending window; it is accepted once prices have stayed within
+/// the band around the pending level for `deviation_persist_slots`.
+///
+/// Liquidations revert on `Pending`, so the pending window only survives when
+/// it is recorded by `refresh_market_price`.
+pub fn apply_price_guard(market: &mut Market, price: u64, slot: u64) -> PriceCheck {
+    let band = market.max_price_deviation_bps;
+
+    if is_within_band(market.last_accepted_price, price, band) {
+        accept_price(market, price, slot);
+        return PriceCheck::Accepted;
+    }
+
+    // out of band: start a new window unless we are still near the pending level
+    if market.pending_price == 0 || !is_within_band(market.pending_price, price, band) {
+        market.pending_price = price;
+        market.pending_since_slot = slot;
+    }
+
+    if slot.saturating_sub(market.pending_since_slot) >= market.deviation_persist_slots {
+        accept_price(market, price, slot);
+        return PriceCheck::Accepted;
+    }
+
+    PriceCheck::Pending
+}
+
+fn accept_price(market: &mut Market, price: u64, slot: u64) {
+    market.last_accepted_price = price;
+    market.last_accepted_slot = slot;
+    market.pending_price = 0;
+    market.pending_since_slot = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market(reference: u64) -> Market {
+        Market {
+            last_accepted_price: reference,
+            last_accepted_slot: 10,
+            max_price_deviation_bps: 1_000, // 10%
+            deviation_persist_slots: 5,
+            ..Market::default()
+        }
+    }
+
+    #[test]
+    fn band_edges() {
+        assert!(is_within_band(100_000_000, 110_000_000, 1_000));
+        assert!(is_within_band(100_000_000, 90_000_000, 1_000));
+        assert!(!is_within_band(100_000_000, 110_000_001, 1_000));
+        assert!(!is_within_band(100_000_000, 89_999_999, 1_000));
+        assert!(is_within_band(0, 1, 0));
+    }
+
+    #[test]
+    fn in_band_price_moves_reference() {
+        let mut m = market(100_000_000);
+        assert_eq!(apply_price_guard(&mut m, 105_000_000, 11), PriceCheck::Accepted);
+        assert_eq!(m.last_accepted_price, 105_000_000);
+        assert_eq!(m.last_accepted_slot, 11);
+    }
+
+    #[test]
+    fn jump_is_refused_until_it_persists() {
+        let mut m = market(100_000_000);
+
+        assert_eq!(apply_price_guard(&mut m, 140_000_000, 20), PriceCheck::Pending);
+        assert_eq!(m.last_accepted_price, 100_000_000);
+        assert_eq!(apply_price_guard(&mut m, 141_000_000, 24), PriceCheck::Pending);
+        assert_eq!(apply_price_guard(&mut m, 139_000_000, 25), PriceCheck::Accepted);
+
+        assert_eq!(m.last_accepted_price, 139_000_000);
+        assert_eq!(m.pending_price, 0);
+    }
+
+    #[test]
+    fn wandering_price_restarts_window() {
+        let mut m = market(100_000_000);
+
+        assert_eq!(apply_price_guard(&mut m, 140_000_000, 20), PriceCheck::Pending);
+        // far from the pending level: new window
+        assert_eq!(apply_price_guard(&mut m, 60_000_000, 24), PriceCheck::Pending);
+        assert_eq!(m.pending_since_slot, 24);
+        assert_eq!(apply_price_guard(&mut m, 60_000_000, 28), PriceCheck::Pending);
+        assert_eq!(apply_price_guard(&mut m, 60_000_000, 29), PriceCheck::Accepted);
+    }
+
+    #[test]
+    fn return_to_band_clears_pending() {
+        let mut m = market(100_000_000);
+
+        assert_eq!(apply_price_guard(&mut m, 140_000_000, 20), PriceCheck::Pending);
+        assert_eq!(apply_price_guard(&mut m, 101_000_000, 21), PriceCheck::Accepted);
+        assert_eq!(m.pending_price, 0);
+        assert_eq!(apply_price_guard(&mut m, 140_000_000, 22), PriceCheck::Pending);
+        assert_eq!(m.pending_since_slot, 22);
+    }
+
+    #[test]
+    fn zero_persist_slots_accepts_immediately() {
+        let mut m = Market { deviation_persist_slots: 0, ..market(100_000_000) };
+        assert_eq!(apply_price_guard(&mut m, 140_000_000, 20), PriceCheck::Accepted);
+    }
+}
@@ -0,0 +1,230 @@
+//! Fixed-point decimal rescaling.
+//!
+//! A value `v` at exponent `e` represents `v * 10^e`. Rescaling moves it to a
+//! different exponent: going to a smaller exponent multiplies (exact, may
+//! overflow), going to a larger exponent divides (may drop digits, so the
+//! caller picks the rounding direction).
+
+// one rounding type and division for the whole program, shared with the
+// margin math
+pub use liquidation_math::{div_round, Round};
+
+/// Smallest exponent accepted on either side of a rescale.
+pub const MIN_EXPONENT: i32 = -18;
+
+/// Largest exponent accepted on either side of a rescale.
+pub const MAX_EXPONENT: i32 = 18;
+
+// 10^0 ..= 10^36. The widest shift is MAX_EXPONENT - MIN_EXPONENT = 36,
+// and 10^36 still fits in an i128 (max ~1.7e38).
+const POW10: [i128; 37] = {
+    let mut table = [1i128; 37];
+    let mut i = 1;
+    while i < 37 {
+        table[i] = table[i - 1] * 10;
+        i += 1;
+    }
+    table
+};
+
+/// 10^n, or `None` if it does not fit in an i128.
+pub fn pow10(n: u32) -> Option<i128> {
+    POW10.get(n as usize).copied()
+}
+
+/// Returns true if `expo` is inside the supported exponent range.
+pub fn is_supported_exponent(expo: i32) -> bool {
+    (MIN_EXPONENT..=MAX_EXPONENT).contains(&expo)
+}
+
+/// Rescale `value * 10^from_expo` into units of `10^to_expo`.
+///
+/// Returns `None` if either exponent is outside
+/// [`MIN_EXPONENT`, `MAX_EXPONENT`] or the result overflows an i128.
+pub fn rescale(value: i128, from_expo: i32, to_expo: i32, round: Round) -> Option<i128> {
+    if !is_supported_exponent(from_expo) || !is_supported_exponent(to_expo) {
+        return None;
+    }
+
+    let delta = from_expo - to_expo;
+
+    if delta >= 0 {
+        // target is finer grained: multiply, exact
+        value.checked_mul(pow10(delta as u32)?)
+    } else {
+        // target is coarser: divide, rounding the dropped digits
+        div_round(value, pow10((-delta) as u32)?, round)
+    }
+}
+
+/// Rescale and convert to u64, rejecting negative or oversized results.
+pub fn rescale_to_u64(value: i128, from_expo: i32, to_expo: i32, round: Round) -> Option<u64> {
+    rescale(value, from_expo, to_expo, round)?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigInt;
+    use num_integer::Integer;
+    use proptest::prelude::*;
+
+    fn big_pow10(n: u32) -> BigInt {
+        BigInt::from(10).pow(n)
+    }
+
+    /// Reference implementation on arbitrary precision integers.
+    fn reference(value: i128, from_expo: i32, to_expo: i32, round: Round) -> Option<i128> {
+        let v = BigInt::from(value);
+        let delta = from_expo - to_expo;
+
+        let result = if delta >= 0 {
+            v * big_pow10(delta as u32)
+        } else {
+            let d = big_pow10((-delta) as u32);
+            let (floor, rem) = v.div_mod_floor(&d);
+            if rem == BigInt::from(0) {
+                floor
+            } else {
+                match round {
+                    Round::Down => floor,
+                    Round::Up => floor + 1,
+                    Round::Nearest => {
+                        // rem is in (0, d); ties go away from zero
+                        let twice = &rem * 2;
+                        if twice > d || (twice == d && value > 0) {
+                            floor + 1
+                        } else {
+                            floor
+                        }
+                    }
+                }
+            }
+        };
+
+        i128::try_from(result).ok()
+    }
+
+    fn rounding() -> impl Strategy<Value = Round> {
+        prop_oneof![
+            Just(Round::Down),
+            Just(Round::Up),
+            Just(Round::Nearest),
+        ]
+    }
+
+    fn exponent() -> impl Strategy<Value = i32> {
+        MIN_EXPONENT..=MAX_EXPONENT
+    }
+
+    #[test]
+    fn pyth_exponents_to_price_precision() {
+        // 123.45678901 at expo -8 -> 123.456789 at expo -6
+        assert_eq!(rescale(12_345_678_901, -8, -6, Round::Down), Some(123_456_789));
+        assert_eq!(rescale(12_345_678_901, -8, -6, Round::Up), Some(123_456_790));
+        assert_eq!(rescale(12_345_678_901, -8, -6, Round::Nearest), Some(123_456_789));
+
+        // same exponent is the identity
+        assert_eq!(rescale(42_000_000, -6, -6, Round::Down), Some(42_000_000));
+
+        // coarser feed gains digits
+        assert_eq!(rescale(4_200, -2, -6, Round::Down), Some(42_000_000));
+        assert_eq!(rescale(42, 0, -6, Round::Down), Some(42_000_000));
+        assert_eq!(rescale(3, 2, -6, Round::Down), Some(300_000_000));
+    }
+
+    #[test]
+    fn rounding_modes_on_negative_values() {
+        assert_eq!(rescale(-15, -1, 0, Round::Down), Some(-2));
+        assert_eq!(rescale(-15, -1, 0, Round::Up), Some(-1));
+        assert_eq!(rescale(-15, -1, 0, Round::Nearest), Some(-2));
+        assert_eq!(rescale(-14, -1, 0, Round::Nearest), Some(-1));
+        assert_eq!(rescale(-16, -1, 0, Round::Nearest), Some(-2));
+    }
+
+    #[test]
+    fn nearest_ties_away_from_zero() {
+        assert_eq!(rescale(25, -1, 0, Round::Nearest), Some(3));
+        assert_eq!(rescale(24, -1, 0, Round::Nearest), Some(2));
+        assert_eq!(rescale(-25, -1, 0, Round::Nearest), Some(-3));
+    }
+
+    #[test]
+    fn rejects_unsupported_exponents() {
+        assert_eq!(rescale(1, -19, -6, Round::Down), None);
+        assert_eq!(rescale(1, 19, -6, Round::Down), None);
+        assert_eq!(rescale(1, -6, -19, Round::Down), None);
+        assert!(rescale(1, MIN_EXPONENT, MAX_EXPONENT, Round::Down).is_some());
+        assert!(rescale(1, MAX_EXPONENT, MIN_EXPONENT, Round::Down).is_some());
+    }
+
+    #[test]
+    fn overflow_is_reported() {
+        assert_eq!(rescale(i128::MAX, 0, -1, Round::Down), None);
+        assert_eq!(rescale(1_000, MAX_EXPONENT, MIN_EXPONENT, Round::Down), None);
+    }
+
+    #[test]
+    fn extreme_values_do_not_panic_when_dividing() {
+        for r in [Round::Down, Round::Up, Round::Nearest] {
+            assert_eq!(rescale(i128::MIN, MIN_EXPONENT, MAX_EXPONENT, r), reference(i128::MIN, MIN_EXPONENT, MAX_EXPONENT, r));
+            assert_eq!(rescale(i128::MAX, MIN_EXPONENT, MAX_EXPONENT, r), reference(i128::MAX, MIN_EXPONENT, MAX_EXPONENT, r));
+        }
+    }
+
+    #[test]
+    fn to_u64_rejects_negative_and_oversized() {
+        assert_eq!(rescale_to_u64(-1, -6, -6, Round::Down), None);
+        assert_eq!(rescale_to_u64(u64::MAX as i128 + 1, -6, -6, Round::Down), None);
+        assert_eq!(rescale_to_u64(u64::MAX as i128, -6, -6, Round::Down), Some(u64::MAX));
+    }
+
+    proptest! {
+        #[test]
+        fn matches_bigint_reference(
+            value in any::<i64>(),
+            from in exponent(),
+            to in exponent(),
+            r in rounding(),
+        ) {
+            let value = value as i128;
+            prop_assert_eq!(rescale(value, from, to, r), reference(value, from, to, r));
+        }
+
+        #[test]
+        fn matches_bigint_reference_wide(
+            value in any::<i128>(),
+            from in exponent(),
+            to in exponent(),
+            r in rounding(),
+        ) {
+            prop_assert_eq!(rescale(value, from, to, r), reference(value, from, to, r));
+        }
+
+        #[test]
+        fn floor_le_nearest_le_ceil(value in any::<i64>(), from in exponent(), to in exponent()) {
+            let value = value as i128;
+            let f = rescale(value, from, to, Round::Down);
+            let n = rescale(value, from, to, Round::Nearest);
+            let c = rescale(value, from, to, Round::Up);
+            if let (Some(f), Some(n), Some(c)) = (f, n, c) {
+                prop_assert!(f <= n && n <= c);
+                prop_assert!(c - f <= 1);
+            } else {
+                // overflow only happens when multiplying, and then all modes agree
+                prop_assert!(f.is_none() && n.is_none() && c.is_none());
+            }
+        }
+
+        #[test]
+        fn round_trip_is_exact(value in any::<i64>(), from in exponent(), to in exponent()) {
+            // going finer and back again never loses anything
+            let (fine, coarse) = if from <= to { (from, to) } else { (to, from) };
+            let v = value as i128;
+            if let Some(up) = rescale(v, coarse, fine, Round::Down) {
+                prop_assert_eq!(rescale(up, fine, coarse, Round::Down), Some(v));
+                prop_assert_eq!(rescale(up, fine, coarse, Round::Up), Some(v));
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use anchor_lang::prelude::*;
+
+use liquidation_math::{tier_maintenance_margin_bps, Tier};
+
+use crate::constants::*;
+use crate::state::ErrorCode;
+use crate::Market;
+
+/// One maintenance margin tier. A position uses the first tier whose
+/// `max_notional` covers its current notional.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RiskTier {
+    pub max_notional: u64,             // quote, PRICE_PRECISION scaled
+    pub maintenance_margin_bps: u64,
+}
+
+impl RiskTier {
+    pub const LEN: usize = 8 + 8;
+}
+
+/// Tiers must be non-empty, at most MAX_RISK_TIERS long, strictly increasing
+/// in notional and non-decreasing in margin, so bigger positions never need
+/// less margin than smaller ones.
+pub fn validate_risk_tiers(tiers: &[RiskTier]) -> Result<()> {
+    require!(
+        !tiers.is_empty() && tiers.len() <= MAX_RISK_TIERS,
+        ErrorCode::InvalidRiskTiers
+    );
+
+    for tier in tiers {
+        require!(
+            tier.maintenance_margin_bps > 0 && tier.maintenance_margin_bps <= BPS_DENOM,
+            ErrorCode::InvalidRiskTiers
+        );
+    }
+
+    for pair in tiers.windows(2) {
+        require!(
+            pair[0].max_notional < pair[1].max_notional
+                && pair[0].maintenance_margin_bps <= pair[1].maintenance_margin_bps,
+            ErrorCode::InvalidRiskTiers
+        );
+    }
+
+    Ok(())
+}
+
+/// Maintenance margin for a position of `notional` (size * price, computed
+/// at liquidation time). Notionals above the last tier use the last tier.
+pub fn maintenance_margin_bps(market: &Market, notional: u64) -> u64 {
+    let tiers = market.risk_tiers.map(|tier| Tier {
+        max_notional: tier.max_notional,
+        maintenance_margin_bps: tier.maintenance_margin_bps,
+    });
+
+    tier_maintenance_margin_bps(&tiers[..market.num_risk_tiers as usize], notional)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tier(max_notional: u64, maintenance_margin_bps: u64) -> RiskTier {
+        RiskTier { max_notional, maintenance_margin_bps }
+    }
+
+    fn market_with(tiers: &[RiskTier]) -> Market {
+        let mut market = Market::default();
+        market.risk_tiers[..tiers.len()].copy_from_slice(tiers);
+        market.num_risk_tiers = tiers.len() as u8;
+        market
+    }
+
+    #[test]
+    fn picks_tier_by_notional() {
+        let market = market_with(&[
+            tier(100_000 * PRICE_PRECISION, 50),
+            tier(1_000_000 * PRICE_PRECISION, 100),
+            tier(10_000_000 * PRICE_PRECISION, 250),
+        ]);
+
+        assert_eq!(maintenance_margin_bps(&market, 0), 50);
+        assert_eq!(maintenance_margin_bps(&market, 100_000 * PRICE_PRECISION), 50);
+        assert_eq!(maintenance_margin_bps(&market, 100_000 * PRICE_PRECISION + 1), 100);
+        assert_eq!(maintenance_margin_bps(&market, 5_000_000 * PRICE_PRECISION), 250);
+        // above the last threshold stays on the last tier
+        assert_eq!(maintenance_margin_bps(&market, u64::MAX), 250);
+    }
+
+    #[test]
+    fn empty_market_uses_default() {
+        assert_eq!(
+            maintenance_margin_bps(&Market::default(), 1),
+            DEFAULT_MAINTENANCE_MARGIN_BPS
+        );
+    }
+
+    #[test]
+    fn validation() {
+        assert!(validate_risk_tiers(&[tier(u64::MAX, 250)]).is_ok());
+        assert!(validate_risk_tiers(&[tier(10, 50), tier(20, 50)]).is_ok());
+
+        assert!(validate_risk_tiers(&[]).is_err());
+        assert!(validate_risk_tiers(&[tier(10, 0)]).is_err());
+        assert!(validate_risk_tiers(&[tier(10, BPS_DENOM + 1)]).is_err());
+        // notional must increase
+        assert!(validate_risk_tiers(&[tier(20, 50), tier(20, 100)]).is_err());
+        // margin must not decrease
+        assert!(validate_risk_tiers(&[tier(10, 100), tier(20, 50)]).is_err());
+        assert!(validate_risk_tiers(&[tier(1, 1); MAX_RISK_TIERS + 1]).is_err());
+    }
+}
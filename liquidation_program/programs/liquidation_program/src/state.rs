@@ -1,5 +1,12 @@
 use anchor_lang::prelude::*;
 
+/// Vault a liquidator's reward was paid from.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RewardSource {
+    ProtocolVault,
+    InsuranceVault,
+}
+
 #[event]
 pub struct LiquidationEvent {
     pub position_owner: Pubkey,
@@ -10,6 +17,56 @@ pub struct LiquidationEvent {
     pub margin_before: i64,
     pub margin_after: i64,
     pub liquidator_reward: u64,
+    // Quote that reached the trader, net of any Token-2022 transfer fee
+    pub trader_payout: u64,
+    // Quote moved from the insurance vault into the protocol vault to cover a deficit
+    pub insurance_covered: u64,
+    pub bad_debt: u64,
+    pub timestamp: i64,
+    pub reward_source: RewardSource,
+}
+
+#[event]
+pub struct CollateralSeizedEvent {
+    pub position_owner: Pubkey,
+    pub liquidator: Pubkey,
+    pub mint: Pubkey,
+    pub seized_amount: u64,
+    pub repaid_amount: u64,
+    pub collateral_price: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CollateralAuctionStartedEvent {
+    pub position_owner: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub deficit: u64,
+    pub start_price: u64,
+    pub end_price: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CollateralAuctionBidEvent {
+    pub position_owner: Pubkey,
+    pub bidder: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub price: u64,
+    pub cost: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CollateralAuctionSettledEvent {
+    pub position_owner: Pubkey,
+    pub mint: Pubkey,
+    pub sold_amount: u64,
+    pub proceeds: u64,
+    pub returned_amount: u64,
+    pub insurance_covered: u64,
     pub bad_debt: u64,
     pub timestamp: i64,
 }
@@ -22,6 +79,7 @@ pub struct ProtocolInsolvencyEvent {
 
 
 // MERGED ERROR CODES
+// Append only: clients map errors by number, so reordering breaks them.
 #[error_code]
 pub enum ErrorCode {
     #[msg("Invalid oracle account")]
@@ -46,4 +104,106 @@ pub enum ErrorCode {
     InvalidInsuranceBump,
     #[msg("Unauthorized")]
     Unauthorized,
-}
\ No newline at end of file
+    #[msg("Oracle price deviates too far from the last accepted price")]
+    PriceDeviationTooLarge,
+    #[msg("Invalid market config")]
+    InvalidMarketConfig,
+    #[msg("Oracle does not match market")]
+    OracleMismatch,
+    #[msg("Position does not belong to market")]
+    MarketMismatch,
+    #[msg("Market open interest limit exceeded")]
+    OpenInterestLimitExceeded,
+    #[msg("Position notional limit exceeded")]
+    PositionNotionalLimitExceeded,
+    #[msg("Invalid risk tiers")]
+    InvalidRiskTiers,
+    #[msg("Invalid collateral weight")]
+    InvalidCollateralWeight,
+    #[msg("Collateral registry is full")]
+    CollateralRegistryFull,
+    #[msg("Invalid collateral asset")]
+    InvalidCollateralAsset,
+    #[msg("Collateral vault does not match registry")]
+    InvalidCollateralVault,
+    #[msg("Missing oracle for a collateral asset")]
+    MissingCollateralOracle,
+    #[msg("Insufficient collateral")]
+    InsufficientCollateral,
+    #[msg("Amount must be positive")]
+    ZeroAmount,
+    #[msg("Position is still open")]
+    PositionStillOpen,
+    #[msg("Position is healthy")]
+    PositionHealthy,
+    #[msg("Position has no deficit to auction for")]
+    NoDeficit,
+    #[msg("Auction does not match position or payer")]
+    InvalidAuction,
+    #[msg("Auction has finished")]
+    AuctionFinished,
+    #[msg("Auction is still running")]
+    AuctionStillRunning,
+    #[msg("Auction price is above the bid limit")]
+    AuctionPriceAboveLimit,
+
+    // Token accounts
+    #[msg("Mint does not match the vault's mint")]
+    InvalidMint,
+    #[msg("Trader token account is not owned by the position owner")]
+    TraderAccountOwnerMismatch,
+    #[msg("Protocol vault is not owned by the vault authority")]
+    InvalidVaultAuthority,
+    #[msg("Insurance fund does not match the insurance vault")]
+    InsuranceFundMismatch,
+    #[msg("Insurance vault is not owned by the insurance authority")]
+    InvalidInsuranceAuthority,
+
+    // Oracles
+    #[msg("Unsupported oracle exponent")]
+    UnsupportedOracleExponent,
+    #[msg("Oracle is not trading")]
+    OracleNotTrading,
+    // No longer returned: the mock oracle instructions are compiled out of
+    // builds without `mock-oracle` instead.
+    #[msg("Mock oracle is disabled in this build")]
+    MockOracleDisabled,
+
+    // Account checks
+    #[msg("Quote mint does not match the protocol vault")]
+    QuoteMintMismatch,
+    #[msg("Collateral mint does not match the registry")]
+    CollateralMintMismatch,
+    #[msg("Collateral mint does not match the auction")]
+    AuctionMintMismatch,
+    #[msg("Trader token account is not for the quote mint")]
+    TraderTokenMintMismatch,
+    #[msg("Liquidator token account is not for the quote mint")]
+    LiquidatorTokenMintMismatch,
+    #[msg("Liquidator collateral account is not for the collateral mint")]
+    LiquidatorCollateralMintMismatch,
+    #[msg("Owner token account is not for the collateral mint")]
+    OwnerTokenMintMismatch,
+    #[msg("Bidder quote account is not for the quote mint")]
+    BidderQuoteMintMismatch,
+    #[msg("Bidder collateral account is not for the auctioned mint")]
+    BidderCollateralMintMismatch,
+    #[msg("Vault authority is not the program's vault authority")]
+    VaultAuthorityMismatch,
+    #[msg("Insurance authority is not the program's insurance authority")]
+    InsuranceAuthorityMismatch,
+    #[msg("Protocol vault is not the program's protocol vault")]
+    ProtocolVaultMismatch,
+    #[msg("Insurance vault is not the program's insurance vault")]
+    InsuranceVaultMismatch,
+    #[msg("Collateral registry is not the program's registry")]
+    CollateralRegistryMismatch,
+
+    // Positions
+    #[msg("Position is below maintenance margin")]
+    PositionUnhealthy,
+    #[msg("Position has a deficit its collateral must cover first")]
+    PositionInDeficit,
+    #[msg("Position has a collateral auction running")]
+    AuctionOpen,
+}
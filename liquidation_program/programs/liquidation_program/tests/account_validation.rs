@@ -0,0 +1,287 @@
+//! Every account check on the liquidation contexts, each with the account
+//! swapped for a wrong one, against both liquidate_partial and liquidate_full;
+//! and the protocol vault's mint check.
+
+mod common;
+
+use anchor_lang::prelude::*;
+use anchor_lang::AccountSerialize;
+
+use common::*;
+use liquidation_program::constants::*;
+use liquidation_program::state::ErrorCode;
+use liquidation_program::InsuranceFund;
+
+const KINDS: [Liquidation; 2] = [Liquidation::Partial, Liquidation::Full];
+
+// A long position that is underwater enough for either instruction, with
+// other traders' collateral in the vault so payouts can't run it dry.
+fn setup() -> (Fixture, OpenPosition) {
+    let mut f = Fixture::new(price(100));
+    let open = f.open_position(10 * PRICE_PRECISION, price(100), 60_000_000, true);
+    let vault = f.protocol_vault;
+    mint_to(&mut f.rt, &vault, 10_000_000_000);
+    f.set_price(price(96));
+    (f, open)
+}
+
+#[test]
+fn valid_accounts_pass() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        assert_ok(f.liquidate_checked(kind, &open));
+    }
+}
+
+#[test]
+fn rejects_position_from_another_market() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        let other_oracle = Pubkey::new_unique();
+        f.set_oracle_price(other_oracle, price(100));
+        let other_market = f.add_market(1, other_oracle);
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.market = other_market;
+        accounts.oracle = other_oracle;
+        assert_error(f.rt.process(accounts.instruction(kind)), program_error(ErrorCode::MarketMismatch));
+    }
+}
+
+#[test]
+fn rejects_oracle_not_of_the_market() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        let other_oracle = Pubkey::new_unique();
+        f.set_oracle_price(other_oracle, price(50));
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.oracle = other_oracle;
+        assert_error(f.rt.process(accounts.instruction(kind)), program_error(ErrorCode::OracleMismatch));
+    }
+}
+
+// An insurance fund account the program owns, pointing at `insurance_vault`.
+fn fake_insurance_fund(f: &mut Fixture, insurance_vault: Pubkey) -> Pubkey {
+    let fake_fund = Pubkey::new_unique();
+    let mut data = Vec::new();
+    InsuranceFund {
+        authority: f.admin,
+        insurance_vault,
+        balance: 0,
+        total_contributions: 0,
+        total_bad_debt_covered: 0,
+        utilization_ratio: 0,
+    }
+    .try_serialize(&mut data)
+    .unwrap();
+    f.rt.set_account(
+        fake_fund,
+        AccountState {
+            lamports: SOL,
+            data,
+            owner: liquidation_program::ID,
+            executable: false,
+        },
+    );
+    fake_fund
+}
+
+#[test]
+fn rejects_insurance_fund_for_another_vault() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        let fake_fund = fake_insurance_fund(&mut f, Pubkey::new_unique());
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.insurance_fund = fake_fund;
+        assert_error(
+            f.rt.process(accounts.instruction(kind)),
+            program_error(ErrorCode::InsuranceFundMismatch),
+        );
+    }
+}
+
+#[test]
+fn rejects_protocol_vault_not_owned_by_vault_authority() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        let (vault, mint) = (f.protocol_vault, f.quote_mint);
+        let balance = f.balance(&vault);
+        set_token_account(&mut f.rt, vault, &mint, &Pubkey::new_unique(), balance);
+
+        assert_error(f.liquidate(kind, &open), program_error(ErrorCode::InvalidVaultAuthority));
+    }
+}
+
+#[test]
+fn rejects_insurance_vault_not_owned_by_insurance_authority() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        let (vault, mint) = (f.insurance_vault, f.quote_mint);
+        set_token_account(&mut f.rt, vault, &mint, &Pubkey::new_unique(), 0);
+
+        assert_error(
+            f.liquidate(kind, &open),
+            program_error(ErrorCode::InvalidInsuranceAuthority),
+        );
+    }
+}
+
+#[test]
+fn rejects_vault_authority_with_wrong_seeds() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.vault_authority = pda(&[VAULT_SEED]);
+        assert_error(
+            f.rt.process(accounts.instruction(kind)),
+            program_error(ErrorCode::VaultAuthorityMismatch),
+        );
+    }
+}
+
+#[test]
+fn rejects_insurance_authority_with_wrong_seeds() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.insurance_authority = pda(&[INSURANCE_SEED]);
+        assert_error(
+            f.rt.process(accounts.instruction(kind)),
+            program_error(ErrorCode::InsuranceAuthorityMismatch),
+        );
+    }
+}
+
+#[test]
+fn rejects_protocol_vault_at_another_address() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        // right mint and authority, but not the program's vault
+        let mint = f.quote_mint;
+        let other_vault = create_token_account(&mut f.rt, &mint, &vault_authority(), 10_000_000_000);
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.protocol_vault = other_vault;
+        assert_error(
+            f.rt.process(accounts.instruction(kind)),
+            program_error(ErrorCode::ProtocolVaultMismatch),
+        );
+    }
+}
+
+#[test]
+fn rejects_insurance_vault_at_another_address() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        let mint = f.quote_mint;
+        let other_vault = create_token_account(&mut f.rt, &mint, &insurance_authority(), 0);
+        let fake_fund = fake_insurance_fund(&mut f, other_vault);
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.insurance_fund = fake_fund;
+        accounts.insurance_vault = other_vault;
+        assert_error(
+            f.rt.process(accounts.instruction(kind)),
+            program_error(ErrorCode::InsuranceVaultMismatch),
+        );
+    }
+}
+
+#[test]
+fn rejects_collateral_registry_at_another_address() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        // a byte-for-byte copy of the registry, owned by the program
+        let registry = f.rt.account(&f.collateral_registry).unwrap();
+        let copy = Pubkey::new_unique();
+        f.rt.set_account(copy, registry);
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.collateral_registry = copy;
+        assert_error(
+            f.rt.process(accounts.instruction(kind)),
+            program_error(ErrorCode::CollateralRegistryMismatch),
+        );
+    }
+}
+
+#[test]
+fn rejects_quote_mint_other_than_the_vaults() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        let other_mint = create_mint(&mut f.rt, QUOTE_DECIMALS);
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.quote_mint = other_mint;
+        assert_error(f.rt.process(accounts.instruction(kind)), program_error(ErrorCode::QuoteMintMismatch));
+    }
+}
+
+#[test]
+fn rejects_trader_account_with_wrong_mint() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        let other_mint = create_mint(&mut f.rt, QUOTE_DECIMALS);
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.trader_token_account = create_token_account(&mut f.rt, &other_mint, &open.trader, 0);
+        assert_error(
+            f.rt.process(accounts.instruction(kind)),
+            program_error(ErrorCode::TraderTokenMintMismatch),
+        );
+    }
+}
+
+#[test]
+fn rejects_liquidator_account_with_wrong_mint() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+        let other_mint = create_mint(&mut f.rt, QUOTE_DECIMALS);
+
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.liquidator_token_account =
+            create_token_account(&mut f.rt, &other_mint, &open.liquidator, 0);
+        assert_error(
+            f.rt.process(accounts.instruction(kind)),
+            program_error(ErrorCode::LiquidatorTokenMintMismatch),
+        );
+    }
+}
+
+#[test]
+fn rejects_trader_account_owned_by_someone_else() {
+    for kind in KINDS {
+        let (mut f, open) = setup();
+
+        // the liquidator tries to route the trader's leftover to themselves
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.trader_token_account = open.liquidator_token_account;
+        assert_error(
+            f.rt.process(accounts.instruction(kind)),
+            program_error(ErrorCode::TraderAccountOwnerMismatch),
+        );
+    }
+}
+
+#[test]
+fn rejects_protocol_vault_for_another_mint() {
+    let mut rt = TestRuntime::new(liquidation_program::ID);
+    let payer = rt.keypair();
+    rt.airdrop(&payer, 10 * SOL);
+    let quote_mint = create_mint(&mut rt, QUOTE_DECIMALS);
+    let other_mint = create_mint(&mut rt, QUOTE_DECIMALS);
+    let token_program = rt.account(&quote_mint).unwrap().owner;
+
+    let fund = rt.keypair();
+    assert_ok(rt.process(initialize_insurance_fund(fund, payer, quote_mint, token_program).signed_by(&[fund])));
+
+    assert_error(
+        rt.process(initialize_protocol_vault(payer, other_mint, token_program)),
+        program_error(ErrorCode::InvalidMint),
+    );
+    assert_ok(rt.process(initialize_protocol_vault(payer, quote_mint, token_program)));
+}
@@ -0,0 +1,323 @@
+//! Collateral auctions after a deferred full liquidation: start, bids as
+//! the price decays, settlement back to the position or to the insurance
+//! fund, and the rent refund to the keeper who started them. Every bid and
+//! settlement also goes through the conservation check in common::invariants.
+
+mod common;
+
+use anchor_lang::error::ErrorCode as AnchorErrorCode;
+use anchor_lang::prelude::*;
+use anchor_lang::AccountSerialize;
+
+use common::*;
+use liquidation_program::constants::*;
+use liquidation_program::state::{
+    CollateralAuctionBidEvent, CollateralAuctionSettledEvent, CollateralAuctionStartedEvent, ErrorCode,
+    ProtocolInsolvencyEvent,
+};
+use liquidation_program::{CollateralAuction, InsuranceFund};
+
+const SIZE: u64 = 10 * PRICE_PRECISION;
+const COLLATERAL: i64 = 60_000_000;
+const VAULT_FLOAT: u64 = 10_000_000_000;
+const DECIMALS: u8 = 9;
+const ONE: u64 = 1_000_000_000;
+const DEFICIT: i64 = -40_000_000;
+
+// 10 units long at 100 backed by 60 quote and `deposit` of an asset worth 1
+// at a 50% weight, fully liquidated at 90: a 40 deficit left for auctions.
+fn deferred(deposit: u64) -> (Fixture, OpenPosition, Collateral) {
+    let mut f = Fixture::new(price(100));
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let vault = f.protocol_vault;
+    mint_to(&mut f.rt, &vault, VAULT_FLOAT);
+    let asset = f.add_collateral(DECIMALS, 5_000, price(1));
+
+    let account = create_token_account(&mut f.rt, &asset.mint, &open.trader, deposit);
+    assert_ok(f.deposit(&open, &asset, account, deposit));
+    f.set_price(price(90));
+    assert_ok(f.liquidate(Liquidation::Full, &open));
+    assert_eq!(f.position(&open.position).collateral, DEFICIT);
+    (f, open, asset)
+}
+
+fn auction(f: &Fixture, open: &OpenPosition, asset: &Collateral) -> Option<CollateralAuction> {
+    let account = f.rt.account(&auction_address(&open.position, asset.index))?;
+    Some(CollateralAuction::try_deserialize(&mut &account.data[..]).unwrap())
+}
+
+fn insurance_fund(f: &Fixture) -> InsuranceFund {
+    let account = f.rt.account(&f.insurance_fund).expect("insurance fund");
+    InsuranceFund::try_deserialize(&mut &account.data[..]).unwrap()
+}
+
+fn lamports(f: &Fixture, key: &Pubkey) -> u64 {
+    f.rt.account(key).map_or(0, |account| account.lamports)
+}
+
+#[test]
+fn start_takes_the_deposit_into_the_auction() {
+    let (mut f, open, asset) = deferred(100 * ONE);
+    let keeper = f.wallet();
+    let keeper_lamports = lamports(&f, &keeper);
+
+    let meta = assert_ok(f.start_auction(&open, &asset, keeper));
+
+    let a = auction(&f, &open, &asset).expect("auction");
+    assert_eq!(a.position, open.position);
+    assert_eq!(a.payer, keeper);
+    assert_eq!(a.mint, asset.mint);
+    assert_eq!((a.initial_amount, a.remaining_amount, a.proceeds), (100 * ONE, 100 * ONE, 0));
+    // opens 5% above the oracle and decays to 20% below it
+    assert_eq!((a.start_price, a.end_price), (1_050_000, 800_000));
+    assert_eq!(a.start_ts, f.rt.clock.unix_timestamp);
+    assert_eq!(a.duration, AUCTION_DURATION);
+
+    let pos = f.position(&open.position);
+    assert_eq!(pos.deposits[asset.index as usize], 0);
+    assert_eq!(pos.open_auctions, 1);
+    // the tokens stay in the collateral vault
+    assert_eq!(f.balance(&asset.vault), 100 * ONE);
+    assert_eq!(lamports(&f, &keeper), keeper_lamports - rent_exempt(CollateralAuction::LEN));
+
+    let event = events::<CollateralAuctionStartedEvent>(&meta).pop().expect("started event");
+    assert_eq!((event.amount, event.deficit), (100 * ONE, DEFICIT.unsigned_abs()));
+}
+
+#[test]
+fn start_needs_a_closed_position_with_a_deficit() {
+    let mut f = Fixture::new(price(100));
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let asset = f.add_collateral(DECIMALS, 5_000, price(1));
+    let account = create_token_account(&mut f.rt, &asset.mint, &open.trader, ONE);
+    assert_ok(f.deposit(&open, &asset, account, ONE));
+    let keeper = f.wallet();
+
+    assert_error(f.start_auction(&open, &asset, keeper), program_error(ErrorCode::PositionStillOpen));
+    assert_ok(f.close_position(&open));
+    assert_error(f.start_auction(&open, &asset, keeper), program_error(ErrorCode::NoDeficit));
+
+    let wrong_oracle = Collateral { oracle: f.oracle, ..asset };
+    assert_error(f.start_auction(&open, &wrong_oracle, keeper), program_error(ErrorCode::OracleMismatch));
+}
+
+#[test]
+fn bids_pay_the_decaying_price_and_stop_at_the_deficit() {
+    let (mut f, open, asset) = deferred(100 * ONE);
+    let keeper = f.wallet();
+    assert_ok(f.start_auction(&open, &asset, keeper));
+    let bidder = f.buyer(&asset, 100_000_000);
+    let vault_before = f.balance(&f.protocol_vault);
+
+    // 10 tokens at the opening 1.05
+    let meta = assert_ok(f.bid_checked(&open, &asset, &bidder, 10 * ONE, 1_050_000));
+    let event = events::<CollateralAuctionBidEvent>(&meta).pop().expect("bid event");
+    assert_eq!((event.amount, event.price, event.cost), (10 * ONE, 1_050_000, 10_500_000));
+    assert_eq!(f.position(&open.position).collateral, DEFICIT + 10_500_000);
+
+    // halfway down: 0.925. Asking for everything only fills the 29.5 still owed
+    f.rt.warp(AUCTION_DURATION / 2);
+    assert_error(
+        f.bid_checked(&open, &asset, &bidder, 100 * ONE, 924_999),
+        program_error(ErrorCode::AuctionPriceAboveLimit),
+    );
+    let meta = assert_ok(f.bid_checked(&open, &asset, &bidder, 100 * ONE, 925_000));
+    let filled = 31_891_891_891;
+    let event = events::<CollateralAuctionBidEvent>(&meta).pop().expect("bid event");
+    assert_eq!((event.amount, event.price, event.cost), (filled, 925_000, 29_500_000));
+
+    let pos = f.position(&open.position);
+    assert_eq!(pos.collateral, 0);
+    let a = auction(&f, &open, &asset).unwrap();
+    assert_eq!(a.remaining_amount, 90 * ONE - filled);
+    assert_eq!(a.proceeds, 40_000_000);
+
+    assert_eq!(f.balance(&bidder.collateral_account), 10 * ONE + filled);
+    assert_eq!(f.balance(&bidder.quote_account), 60_000_000);
+    assert_eq!(f.balance(&f.protocol_vault), vault_before + 40_000_000);
+    assert_eq!(f.balance(&asset.vault), 90 * ONE - filled);
+    assert_eq!(f.registry().assets[asset.index as usize].total_deposits, 90 * ONE - filled);
+
+    // nothing left to cover
+    assert_error(f.bid_checked(&open, &asset, &bidder, ONE, u64::MAX), program_error(ErrorCode::AuctionFinished));
+}
+
+#[test]
+fn bids_after_expiry_pay_the_floor_price() {
+    let (mut f, open, asset) = deferred(100 * ONE);
+    let keeper = f.wallet();
+    assert_ok(f.start_auction(&open, &asset, keeper));
+    let bidder = f.buyer(&asset, 100_000_000);
+
+    f.rt.warp(AUCTION_DURATION + 600);
+    let meta = assert_ok(f.bid_checked(&open, &asset, &bidder, 5 * ONE, 800_000));
+    let event = events::<CollateralAuctionBidEvent>(&meta).pop().expect("bid event");
+    assert_eq!((event.amount, event.price, event.cost), (5 * ONE, 800_000, 4_000_000));
+
+    // once settled the auction is gone, and so are its bids
+    assert_ok(f.settle_auction_checked(&open, &asset, keeper));
+    assert_error(
+        f.bid_checked(&open, &asset, &bidder, ONE, u64::MAX),
+        anchor_error(AnchorErrorCode::AccountNotInitialized),
+    );
+}
+
+#[test]
+fn bids_check_their_accounts() {
+    let (mut f, open, asset) = deferred(100 * ONE);
+    let keeper = f.wallet();
+    assert_ok(f.start_auction(&open, &asset, keeper));
+    let bidder = f.buyer(&asset, 100_000_000);
+    let other_mint = create_mint(&mut f.rt, DECIMALS);
+    let other = create_token_account(&mut f.rt, &other_mint, &bidder.authority, 100_000_000);
+
+    let wrong_quote = Buyer { quote_account: other, ..bidder };
+    assert_error(
+        f.bid_checked(&open, &asset, &wrong_quote, ONE, u64::MAX),
+        program_error(ErrorCode::BidderQuoteMintMismatch),
+    );
+    let wrong_collateral = Buyer { collateral_account: other, ..bidder };
+    assert_error(
+        f.bid_checked(&open, &asset, &wrong_collateral, ONE, u64::MAX),
+        program_error(ErrorCode::BidderCollateralMintMismatch),
+    );
+    let wrong_mint = Collateral { mint: other_mint, ..asset };
+    assert_error(
+        f.bid_checked(&open, &wrong_mint, &bidder, ONE, u64::MAX),
+        program_error(ErrorCode::AuctionMintMismatch),
+    );
+    assert_eq!(f.balance(&bidder.quote_account), 100_000_000);
+}
+
+#[test]
+fn settle_returns_unsold_collateral_and_refunds_rent() {
+    let (mut f, open, asset) = deferred(100 * ONE);
+    let keeper = f.wallet();
+    let keeper_lamports = lamports(&f, &keeper);
+    assert_ok(f.start_auction(&open, &asset, keeper));
+    let bidder = f.buyer(&asset, 100_000_000);
+
+    // part of the deficit covered, still running
+    assert_ok(f.bid_checked(&open, &asset, &bidder, 10 * ONE, u64::MAX));
+    assert_error(f.settle_auction_checked(&open, &asset, keeper), program_error(ErrorCode::AuctionStillRunning));
+
+    // the rest of it covered
+    assert_ok(f.bid_checked(&open, &asset, &bidder, 100 * ONE, u64::MAX));
+    let sold = 100 * ONE - auction(&f, &open, &asset).unwrap().remaining_amount;
+    let insurance_before = f.balance(&f.insurance_vault);
+
+    let meta = assert_ok(f.settle_auction_checked(&open, &asset, keeper));
+
+    let event = events::<CollateralAuctionSettledEvent>(&meta).pop().expect("settled event");
+    assert_eq!((event.sold_amount, event.returned_amount), (sold, 100 * ONE - sold));
+    assert_eq!((event.insurance_covered, event.bad_debt), (0, 0));
+
+    let pos = f.position(&open.position);
+    assert_eq!(pos.deposits[asset.index as usize], 100 * ONE - sold);
+    assert_eq!(pos.open_auctions, 0);
+    assert!(pos.collateral >= 0);
+    assert_eq!(f.balance(&f.insurance_vault), insurance_before);
+
+    // the auction account is closed and its rent is back with the keeper
+    assert!(auction(&f, &open, &asset).is_none());
+    assert_eq!(lamports(&f, &keeper), keeper_lamports);
+}
+
+#[test]
+fn expired_auction_settles_back_to_the_position() {
+    let (mut f, open, asset) = deferred(100 * ONE);
+    let keeper = f.wallet();
+    assert_ok(f.start_auction(&open, &asset, keeper));
+
+    f.rt.warp(AUCTION_DURATION);
+    assert_ok(f.settle_auction_checked(&open, &asset, keeper));
+
+    // nothing sold: the deficit and the deposit are both still there, so
+    // the insurance fund isn't touched and another auction can start
+    let pos = f.position(&open.position);
+    assert_eq!(pos.collateral, DEFICIT);
+    assert_eq!(pos.deposits[asset.index as usize], 100 * ONE);
+    assert_eq!(pos.open_auctions, 0);
+    f.set_oracle_price(asset.oracle, price(1));
+    assert_ok(f.start_auction(&open, &asset, keeper));
+}
+
+#[test]
+fn shortfall_after_the_last_auction_goes_to_insurance() {
+    // 10 tokens can't cover a 40 deficit
+    let (mut f, open, asset) = deferred(10 * ONE);
+    let insurance_vault = f.insurance_vault;
+    mint_to(&mut f.rt, &insurance_vault, 100_000_000);
+    let keeper = f.wallet();
+    assert_ok(f.start_auction(&open, &asset, keeper));
+    let bidder = f.buyer(&asset, 100_000_000);
+    assert_ok(f.bid_checked(&open, &asset, &bidder, 10 * ONE, u64::MAX));
+    let vault_before = f.balance(&f.protocol_vault);
+
+    // sold out for 10.5, leaving 29.5 owed
+    let meta = assert_ok(f.settle_auction_checked(&open, &asset, keeper));
+
+    let event = events::<CollateralAuctionSettledEvent>(&meta).pop().expect("settled event");
+    assert_eq!((event.insurance_covered, event.bad_debt), (29_500_000, 0));
+    assert!(events::<ProtocolInsolvencyEvent>(&meta).is_empty());
+
+    assert_eq!(f.balance(&f.insurance_vault), 70_500_000);
+    assert_eq!(f.balance(&f.protocol_vault), vault_before + 29_500_000);
+    // the fund's balance follows the vault, including the direct top-up
+    let fund = insurance_fund(&f);
+    assert_eq!(fund.balance, 70_500_000);
+    assert_eq!(fund.total_bad_debt_covered, 29_500_000);
+
+    let pos = f.position(&open.position);
+    assert_eq!(pos.collateral, 0);
+    assert_eq!(pos.open_auctions, 0);
+}
+
+#[test]
+fn shortfall_beyond_insurance_is_bad_debt() {
+    let (mut f, open, asset) = deferred(10 * ONE);
+    let insurance_vault = f.insurance_vault;
+    mint_to(&mut f.rt, &insurance_vault, 20_000_000);
+    let keeper = f.wallet();
+    assert_ok(f.start_auction(&open, &asset, keeper));
+    let bidder = f.buyer(&asset, 100_000_000);
+    assert_ok(f.bid_checked(&open, &asset, &bidder, 10 * ONE, u64::MAX));
+
+    let meta = assert_ok(f.settle_auction_checked(&open, &asset, keeper));
+
+    let event = events::<CollateralAuctionSettledEvent>(&meta).pop().expect("settled event");
+    assert_eq!((event.insurance_covered, event.bad_debt), (20_000_000, 9_500_000));
+    let insolvency = events::<ProtocolInsolvencyEvent>(&meta).pop().expect("insolvency event");
+    assert_eq!(insolvency.amount, 9_500_000);
+
+    assert_eq!(f.balance(&f.insurance_vault), 0);
+    let fund = insurance_fund(&f);
+    assert_eq!(fund.balance, 0);
+    assert_eq!(fund.total_bad_debt_covered, 20_000_000);
+    assert_eq!(f.position(&open.position).collateral, 0);
+}
+
+#[test]
+fn settle_checks_payer_and_insurance_fund() {
+    let (mut f, open, asset) = deferred(10 * ONE);
+    let keeper = f.wallet();
+    assert_ok(f.start_auction(&open, &asset, keeper));
+    let bidder = f.buyer(&asset, 100_000_000);
+    assert_ok(f.bid_checked(&open, &asset, &bidder, 10 * ONE, u64::MAX));
+
+    let someone = f.wallet();
+    assert_error(f.settle_auction_checked(&open, &asset, someone), program_error(ErrorCode::InvalidAuction));
+
+    // a fund of the program's pointing at some other vault
+    let fake_fund = Pubkey::new_unique();
+    let mut data = Vec::new();
+    InsuranceFund { authority: f.admin, insurance_vault: Pubkey::new_unique(), ..insurance_fund(&f) }
+        .try_serialize(&mut data)
+        .unwrap();
+    f.rt.set_account(
+        fake_fund,
+        AccountState { lamports: SOL, data, owner: liquidation_program::ID, executable: false },
+    );
+    f.insurance_fund = fake_fund;
+    assert_error(f.settle_auction_checked(&open, &asset, keeper), program_error(ErrorCode::InsuranceFundMismatch));
+}
@@ -0,0 +1,301 @@
+//! Non-quote collateral: deposits, withdrawals and liquidate_collateral,
+//! with the token balances of every party checked afterwards.
+//! Every liquidation, bid and settlement also goes through the conservation
+//! check in common::invariants.
+
+mod common;
+
+use anchor_lang::prelude::*;
+
+use common::invariants::{assert_conserved, TokenSnapshot};
+use common::*;
+use liquidation_program::constants::*;
+use liquidation_program::state::{CollateralSeizedEvent, ErrorCode};
+
+const SIZE: u64 = 10 * PRICE_PRECISION;
+const COLLATERAL: i64 = 60_000_000;
+const VAULT_FLOAT: u64 = 10_000_000_000;
+const DECIMALS: u8 = 9;
+const ONE: u64 = 1_000_000_000;
+
+// 10 units long at 100 backed by 60 quote, with a collateral asset worth 1
+// quote per token at a 50% weight.
+fn setup() -> (Fixture, OpenPosition, Collateral) {
+    let mut f = Fixture::new(price(100));
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let vault = f.protocol_vault;
+    mint_to(&mut f.rt, &vault, VAULT_FLOAT);
+    let asset = f.add_collateral(DECIMALS, 5_000, price(1));
+    (f, open, asset)
+}
+
+// `amount` of the asset deposited from a trader account that held exactly that
+fn deposit(f: &mut Fixture, open: &OpenPosition, asset: &Collateral, amount: u64) -> Pubkey {
+    let account = create_token_account(&mut f.rt, &asset.mint, &open.trader, amount);
+    assert_ok(f.deposit(open, asset, account, amount));
+    account
+}
+
+#[test]
+fn deposit_moves_tokens_into_the_vault() {
+    let (mut f, open, asset) = setup();
+    let account = create_token_account(&mut f.rt, &asset.mint, &open.trader, 100 * ONE);
+
+    assert_ok(f.deposit(&open, &asset, account, 40 * ONE));
+    assert_ok(f.deposit(&open, &asset, account, 2 * ONE));
+
+    assert_eq!(f.position(&open.position).deposits[asset.index as usize], 42 * ONE);
+    assert_eq!(f.registry().assets[asset.index as usize].total_deposits, 42 * ONE);
+    assert_eq!(f.balance(&asset.vault), 42 * ONE);
+    assert_eq!(f.balance(&account), 58 * ONE);
+}
+
+#[test]
+fn deposit_checks_amount_owner_and_accounts() {
+    let (mut f, open, asset) = setup();
+    let account = create_token_account(&mut f.rt, &asset.mint, &open.trader, 100 * ONE);
+
+    assert_error(f.deposit(&open, &asset, account, 0), program_error(ErrorCode::ZeroAmount));
+
+    let mut stranger = open;
+    stranger.trader = f.wallet();
+    let theirs = create_token_account(&mut f.rt, &asset.mint, &stranger.trader, 100 * ONE);
+    assert_error(f.deposit(&stranger, &asset, theirs, ONE), program_error(ErrorCode::Unauthorized));
+
+    // a token account for another mint
+    let other_mint = create_mint(&mut f.rt, DECIMALS);
+    let other = create_token_account(&mut f.rt, &other_mint, &open.trader, 100 * ONE);
+    assert_error(f.deposit(&open, &asset, other, ONE), program_error(ErrorCode::OwnerTokenMintMismatch));
+
+    // the registry's vault and mint for the asset, and nothing else
+    let wrong_mint = Collateral { mint: other_mint, ..asset };
+    assert_error(f.deposit(&open, &wrong_mint, other, ONE), program_error(ErrorCode::CollateralMintMismatch));
+    let wrong_vault = Collateral { vault: create_token_account(&mut f.rt, &asset.mint, &vault_authority(), 0), ..asset };
+    assert_error(f.deposit(&open, &wrong_vault, account, ONE), program_error(ErrorCode::InvalidCollateralVault));
+    let unlisted = Collateral { index: 5, ..asset };
+    assert_error(f.deposit(&open, &unlisted, account, ONE), program_error(ErrorCode::InvalidCollateralAsset));
+
+    assert_eq!(f.balance(&account), 100 * ONE);
+    assert_eq!(f.balance(&asset.vault), 0);
+}
+
+#[test]
+fn closed_position_withdraws_its_deposit() {
+    let (mut f, open, asset) = setup();
+    let account = deposit(&mut f, &open, &asset, 100 * ONE);
+    assert_error(f.withdraw(&open, &asset, account, ONE), program_error(ErrorCode::PositionStillOpen));
+
+    assert_ok(f.close_position(&open));
+    assert_ok(f.withdraw(&open, &asset, account, 30 * ONE));
+    assert_ok(f.withdraw(&open, &asset, account, 70 * ONE));
+
+    assert_eq!(f.position(&open.position).deposits[asset.index as usize], 0);
+    assert_eq!(f.registry().assets[asset.index as usize].total_deposits, 0);
+    assert_eq!(f.balance(&asset.vault), 0);
+    assert_eq!(f.balance(&account), 100 * ONE);
+}
+
+#[test]
+fn cannot_withdraw_more_than_was_deposited() {
+    let (mut f, open, asset) = setup();
+    let account = deposit(&mut f, &open, &asset, 100 * ONE);
+    // someone else's deposit in the same vault
+    let other = f.open_position(SIZE, price(100), COLLATERAL, true);
+    deposit(&mut f, &other, &asset, 50 * ONE);
+    assert_ok(f.close_position(&open));
+
+    assert_error(
+        f.withdraw(&open, &asset, account, 100 * ONE + 1),
+        program_error(ErrorCode::InsufficientCollateral),
+    );
+    assert_eq!(f.balance(&asset.vault), 150 * ONE);
+    assert_eq!(f.position(&open.position).deposits[asset.index as usize], 100 * ONE);
+}
+
+#[test]
+fn withdraw_checks_owner_and_accounts() {
+    let (mut f, open, asset) = setup();
+    deposit(&mut f, &open, &asset, 100 * ONE);
+    assert_ok(f.close_position(&open));
+
+    let mut stranger = open;
+    stranger.trader = f.wallet();
+    let theirs = create_token_account(&mut f.rt, &asset.mint, &stranger.trader, 0);
+    assert_error(f.withdraw(&stranger, &asset, theirs, ONE), program_error(ErrorCode::Unauthorized));
+
+    let other_mint = create_mint(&mut f.rt, DECIMALS);
+    let other = create_token_account(&mut f.rt, &other_mint, &open.trader, 0);
+    assert_error(f.withdraw(&open, &asset, other, ONE), program_error(ErrorCode::OwnerTokenMintMismatch));
+    let wrong_mint = Collateral { mint: other_mint, ..asset };
+    assert_error(f.withdraw(&open, &wrong_mint, other, ONE), program_error(ErrorCode::CollateralMintMismatch));
+
+    assert_eq!(f.balance(&asset.vault), 100 * ONE);
+}
+
+#[test]
+fn liquidator_buys_collateral_at_a_discount() {
+    let (mut f, open, asset) = setup();
+    deposit(&mut f, &open, &asset, 100 * ONE);
+    let liquidator = f.buyer(&asset, 100_000_000);
+    let vault_before = f.balance(&f.protocol_vault);
+
+    // margin 60 - 100 + 50 haircut = 10 against 22.5 required
+    f.set_price(price(90));
+    let meta = assert_ok(f.liquidate_collateral_checked(&open, &asset, &liquidator, 20_000_000));
+
+    // 20 repaid buys 20 * 1.025 worth at the asset's oracle price
+    let seized = 20 * ONE + ONE / 2;
+    assert_eq!(f.balance(&liquidator.quote_account), 80_000_000);
+    assert_eq!(f.balance(&liquidator.collateral_account), seized);
+    assert_eq!(f.balance(&f.protocol_vault), vault_before + 20_000_000);
+    assert_eq!(f.balance(&asset.vault), 100 * ONE - seized);
+
+    let pos = f.position(&open.position);
+    assert_eq!(pos.collateral, COLLATERAL + 20_000_000);
+    assert_eq!(pos.deposits[asset.index as usize], 100 * ONE - seized);
+    assert_eq!(pos.size, SIZE);
+    assert_eq!(f.registry().assets[asset.index as usize].total_deposits, 100 * ONE - seized);
+
+    let event = events::<CollateralSeizedEvent>(&meta).pop().expect("seized event");
+    assert_eq!(event.liquidator, liquidator.authority);
+    assert_eq!(event.mint, asset.mint);
+    assert_eq!(event.seized_amount, seized);
+    assert_eq!(event.repaid_amount, 20_000_000);
+    assert_eq!(event.collateral_price, price(1));
+}
+
+#[test]
+#[should_panic(expected = "balance changes don't match")]
+fn conservation_check_catches_untracked_collateral() {
+    let (mut f, open, asset) = setup();
+    deposit(&mut f, &open, &asset, 100 * ONE);
+    let liquidator = f.buyer(&asset, 100_000_000);
+    f.set_price(price(90));
+
+    let before = TokenSnapshot::with_buyer(&f, &open, &liquidator);
+    let meta = assert_ok(f.liquidate_collateral(&open, &asset, &liquidator, 20_000_000));
+
+    // the same transaction, had the vault kept a token it reported seized
+    let mut after = TokenSnapshot::with_buyer(&f, &open, &liquidator);
+    after.collateral_vaults[0].1 += 1;
+    after.buyer_collateral -= 1;
+    assert_conserved(&before, &after, &meta);
+}
+
+#[test]
+fn collateral_liquidation_is_capped_at_the_deposit() {
+    let (mut f, open, asset) = setup();
+    deposit(&mut f, &open, &asset, 10 * ONE);
+    let liquidator = f.buyer(&asset, 100_000_000);
+
+    f.set_price(price(90));
+    assert_ok(f.liquidate_collateral_checked(&open, &asset, &liquidator, 100_000_000));
+
+    // 10 tokens are worth 10; the liquidator pays 10 / 1.025, rounded up
+    let repaid = 9_756_098;
+    assert_eq!(f.balance(&liquidator.collateral_account), 10 * ONE);
+    assert_eq!(f.balance(&liquidator.quote_account), 100_000_000 - repaid);
+    let pos = f.position(&open.position);
+    assert_eq!(pos.deposits[asset.index as usize], 0);
+    assert_eq!(pos.collateral, COLLATERAL + repaid as i64);
+}
+
+#[test]
+fn healthy_position_keeps_its_collateral() {
+    let (mut f, open, asset) = setup();
+    deposit(&mut f, &open, &asset, 100 * ONE);
+    let liquidator = f.buyer(&asset, 100_000_000);
+
+    assert_error(
+        f.liquidate_collateral_checked(&open, &asset, &liquidator, 20_000_000),
+        program_error(ErrorCode::PositionHealthy),
+    );
+    assert_eq!(f.balance(&asset.vault), 100 * ONE);
+}
+
+#[test]
+fn collateral_liquidation_checks_accounts() {
+    let (mut f, open, asset) = setup();
+    deposit(&mut f, &open, &asset, 100 * ONE);
+    let liquidator = f.buyer(&asset, 100_000_000);
+    f.set_price(price(90));
+
+    assert_error(f.liquidate_collateral_checked(&open, &asset, &liquidator, 0), program_error(ErrorCode::ZeroAmount));
+
+    let other_mint = create_mint(&mut f.rt, DECIMALS);
+    let other = create_token_account(&mut f.rt, &other_mint, &liquidator.authority, 100_000_000);
+    let wrong_collateral = Buyer { collateral_account: other, ..liquidator };
+    assert_error(
+        f.liquidate_collateral_checked(&open, &asset, &wrong_collateral, 20_000_000),
+        program_error(ErrorCode::LiquidatorCollateralMintMismatch),
+    );
+    let wrong_quote = Buyer { quote_account: other, ..liquidator };
+    assert_error(
+        f.liquidate_collateral_checked(&open, &asset, &wrong_quote, 20_000_000),
+        program_error(ErrorCode::LiquidatorTokenMintMismatch),
+    );
+    let wrong_mint = Collateral { mint: other_mint, ..asset };
+    assert_error(
+        f.liquidate_collateral_checked(&open, &wrong_mint, &liquidator, 20_000_000),
+        program_error(ErrorCode::CollateralMintMismatch),
+    );
+
+    // without the asset's oracle the position can't be valued
+    let instruction = {
+        let mut accounts = f.liquidation_accounts(&open);
+        accounts.collateral_oracles.clear();
+        accounts.instruction(Liquidation::Full)
+    };
+    assert_error(f.rt.process(instruction), program_error(ErrorCode::MissingCollateralOracle));
+
+    assert_eq!(f.balance(&asset.vault), 100 * ONE);
+    assert_eq!(f.balance(&liquidator.quote_account), 100_000_000);
+}
+
+#[test]
+fn deficit_left_by_a_full_liquidation_blocks_withdrawals() {
+    let (mut f, open, asset) = setup();
+    let account = deposit(&mut f, &open, &asset, 100 * ONE);
+
+    // quote margin 60 - 100 = -40, plus 50 of haircut collateral: 10 against
+    // 22.5 required. The deficit stays on the position for auctions.
+    f.set_price(price(90));
+    assert_ok(f.liquidate(Liquidation::Full, &open));
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, 0);
+    assert_eq!(pos.collateral, -40_000_000);
+    assert_eq!(pos.deposits[asset.index as usize], 100 * ONE);
+
+    assert_error(
+        f.withdraw(&open, &asset, account, 100 * ONE),
+        program_error(ErrorCode::PositionInDeficit),
+    );
+    assert_error(f.withdraw(&open, &asset, account, 1), program_error(ErrorCode::PositionInDeficit));
+    assert_eq!(f.balance(&account), 0);
+    assert_eq!(f.balance(&asset.vault), 100 * ONE);
+}
+
+#[test]
+fn running_auction_blocks_withdrawals_until_settled() {
+    let (mut f, open, asset) = setup();
+    let account = deposit(&mut f, &open, &asset, 100 * ONE);
+    f.set_price(price(90));
+    assert_ok(f.liquidate(Liquidation::Full, &open));
+
+    let keeper = f.wallet();
+    assert_ok(f.start_auction(&open, &asset, keeper));
+    let bidder = f.buyer(&asset, 100_000_000);
+    assert_ok(f.bid_checked(&open, &asset, &bidder, 100 * ONE, u64::MAX));
+
+    // the bid covered the deficit, but the auction still holds the rest
+    let pos = f.position(&open.position);
+    assert!(pos.collateral >= 0);
+    assert_eq!(pos.open_auctions, 1);
+    assert_error(f.withdraw(&open, &asset, account, 1), program_error(ErrorCode::AuctionOpen));
+
+    assert_ok(f.settle_auction_checked(&open, &asset, keeper));
+    let left = f.position(&open.position).deposits[asset.index as usize];
+    assert!(left > 0);
+    assert_ok(f.withdraw(&open, &asset, account, left));
+    assert_eq!(f.balance(&account), left);
+}
@@ -0,0 +1,281 @@
+//! Token conservation around liquidations and collateral sales: every quote
+//! and collateral balance they can touch is snapshotted before and after,
+//! and the deltas must be exactly what the emitted events say moved. The
+//! position's own value is snapshotted too, so that nothing is paid out of
+//! it that it wasn't worth.
+
+use anchor_lang::prelude::Pubkey;
+use liquidation_math::{Price, Round};
+use liquidation_program::state::{
+    CollateralAuctionBidEvent, CollateralAuctionSettledEvent, CollateralSeizedEvent, LiquidationEvent, RewardSource,
+};
+
+use super::{
+    events, mint_state, pyth_price, Buyer, Collateral, Fixture, Liquidation, OpenPosition, TxMeta, TxResult,
+};
+
+/// Quote balances of the vaults, both users and the buyer, the mint's
+/// supply, and the collateral vaults and buyer's collateral account.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TokenSnapshot {
+    pub protocol_vault: u64,
+    pub insurance_vault: u64,
+    pub trader: u64,
+    pub liquidator: u64,
+    pub supply: u64,
+    /// Quote and collateral held by whoever buys collateral; zero without one.
+    pub buyer_quote: u64,
+    pub buyer_collateral: u64,
+    /// Every listed asset's vault balance, by mint.
+    pub collateral_vaults: Vec<(Pubkey, u64)>,
+    /// The position's quote collateral plus its PnL at the oracle price.
+    pub position_value: i128,
+}
+
+impl TokenSnapshot {
+    pub fn take(f: &Fixture, open: &OpenPosition) -> Self {
+        let registry = f.registry();
+        let assets = &registry.assets[..registry.num_assets as usize];
+        let terms = f.position(&open.position).terms();
+        let pnl = Price(pyth_price(&f.rt, &f.oracle))
+            .pnl(terms.entry_price, terms.size, terms.is_long, Round::Down)
+            .unwrap();
+
+        Self {
+            protocol_vault: f.balance(&f.protocol_vault),
+            insurance_vault: f.balance(&f.insurance_vault),
+            trader: f.balance(&open.trader_token_account),
+            liquidator: f.balance(&open.liquidator_token_account),
+            supply: mint_state(&f.rt, &f.quote_mint).supply,
+            buyer_quote: 0,
+            buyer_collateral: 0,
+            collateral_vaults: assets.iter().map(|asset| (asset.mint, f.balance(&asset.vault))).collect(),
+            position_value: terms.collateral.checked_add(pnl).unwrap().0,
+        }
+    }
+
+    /// `take`, plus the buyer's balances.
+    pub fn with_buyer(f: &Fixture, open: &OpenPosition, buyer: &Buyer) -> Self {
+        Self {
+            buyer_quote: f.balance(&buyer.quote_account),
+            buyer_collateral: f.balance(&buyer.collateral_account),
+            ..Self::take(f, open)
+        }
+    }
+
+    fn delta(&self, after: &Self) -> Deltas {
+        let d = |before: u64, after: u64| after as i128 - before as i128;
+        Deltas {
+            protocol_vault: d(self.protocol_vault, after.protocol_vault),
+            insurance_vault: d(self.insurance_vault, after.insurance_vault),
+            trader: d(self.trader, after.trader),
+            liquidator: d(self.liquidator, after.liquidator),
+            supply: d(self.supply, after.supply),
+            buyer_quote: d(self.buyer_quote, after.buyer_quote),
+            buyer_collateral: d(self.buyer_collateral, after.buyer_collateral),
+            collateral_vaults: self
+                .collateral_vaults
+                .iter()
+                .zip(&after.collateral_vaults)
+                .map(|((mint, before), (_, after))| (*mint, d(*before, *after)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Deltas {
+    protocol_vault: i128,
+    insurance_vault: i128,
+    trader: i128,
+    liquidator: i128,
+    supply: i128,
+    buyer_quote: i128,
+    buyer_collateral: i128,
+    collateral_vaults: Vec<(Pubkey, i128)>,
+}
+
+impl Deltas {
+    /// No movement, over the same collateral vaults as `snapshot`.
+    fn none(snapshot: &TokenSnapshot) -> Self {
+        Self {
+            collateral_vaults: snapshot.collateral_vaults.iter().map(|(mint, _)| (*mint, 0)).collect(),
+            ..Self::default()
+        }
+    }
+
+    fn collateral_vault(&mut self, mint: &Pubkey) -> &mut i128 {
+        let (_, delta) = self
+            .collateral_vaults
+            .iter_mut()
+            .find(|(listed, _)| listed == mint)
+            .expect("event for an unlisted mint");
+        delta
+    }
+
+    /// Quote moved by a liquidation, paying the reward from the vault the
+    /// event names.
+    fn liquidation(&mut self, event: &LiquidationEvent) {
+        let reward = event.liquidator_reward as i128;
+        let payout = event.trader_payout as i128;
+        let covered = event.insurance_covered as i128;
+
+        self.protocol_vault += covered - payout;
+        self.insurance_vault -= covered;
+        match event.reward_source {
+            RewardSource::ProtocolVault => self.protocol_vault -= reward,
+            RewardSource::InsuranceVault => self.insurance_vault -= reward,
+        }
+        self.trader += payout;
+        self.liquidator += reward;
+    }
+
+    /// Quote in from the buyer, collateral out to them.
+    fn sale(&mut self, mint: &Pubkey, quote: u64, collateral: u64) {
+        self.protocol_vault += quote as i128;
+        self.buyer_quote -= quote as i128;
+        *self.collateral_vault(mint) -= collateral as i128;
+        self.buyer_collateral += collateral as i128;
+    }
+
+    fn settlement(&mut self, event: &CollateralAuctionSettledEvent) {
+        let covered = event.insurance_covered as i128;
+        self.insurance_vault -= covered;
+        self.protocol_vault += covered;
+    }
+
+    fn quote_total(&self) -> i128 {
+        self.protocol_vault + self.insurance_vault + self.trader + self.liquidator + self.buyer_quote
+    }
+
+    fn collateral_total(&self) -> i128 {
+        self.collateral_vaults.iter().map(|(_, delta)| delta).sum::<i128>() + self.buyer_collateral
+    }
+}
+
+/// Balance changes the instruction's events account for.
+fn expected(before: &TokenSnapshot, meta: &TxMeta) -> Deltas {
+    let liquidations = events::<LiquidationEvent>(meta);
+    assert!(liquidations.len() <= 1, "more than one LiquidationEvent");
+
+    let mut expected = Deltas::none(before);
+    for event in &liquidations {
+        expected.liquidation(event);
+    }
+    for event in events::<CollateralSeizedEvent>(meta) {
+        expected.sale(&event.mint, event.repaid_amount, event.seized_amount);
+    }
+    for event in events::<CollateralAuctionBidEvent>(meta) {
+        expected.sale(&event.mint, event.cost, event.amount);
+    }
+    for event in events::<CollateralAuctionSettledEvent>(meta) {
+        expected.settlement(&event);
+    }
+    expected
+}
+
+/// Quote the instruction's events put behind the position from outside it,
+/// and quote they paid out of it, in that order. A reward the insurance
+/// vault pays is neither.
+fn position_flows(meta: &TxMeta) -> (i128, i128) {
+    let (mut credited, mut paid) = (0i128, 0i128);
+    for event in events::<LiquidationEvent>(meta) {
+        credited += event.insurance_covered as i128 + event.bad_debt as i128;
+        paid += event.trader_payout as i128;
+        if event.reward_source == RewardSource::ProtocolVault {
+            paid += event.liquidator_reward as i128;
+        }
+    }
+    for event in events::<CollateralSeizedEvent>(meta) {
+        credited += event.repaid_amount as i128;
+    }
+    for event in events::<CollateralAuctionBidEvent>(meta) {
+        credited += event.cost as i128;
+    }
+    for event in events::<CollateralAuctionSettledEvent>(meta) {
+        credited += event.insurance_covered as i128 + event.bad_debt as i128;
+    }
+    (credited, paid)
+}
+
+/// Panic unless the balance changes between `before` and `after` match the
+/// instruction's events exactly, no quote or collateral was created or
+/// destroyed, and the position paid out no more than it was worth plus what
+/// was put behind it.
+#[track_caller]
+pub fn assert_conserved(before: &TokenSnapshot, after: &TokenSnapshot, meta: &TxMeta) {
+    let actual = before.delta(after);
+    assert_eq!(actual, expected(before, meta), "balance changes don't match the events");
+
+    assert_eq!(actual.quote_total(), 0, "created or destroyed quote: {actual:?}");
+    assert_eq!(actual.collateral_total(), 0, "created or destroyed collateral: {actual:?}");
+
+    // rounding may leave value behind, but never take more out
+    let (credited, paid) = position_flows(meta);
+    assert!(
+        before.position_value + credited >= after.position_value + paid,
+        "position paid out more than it was worth: {} + {credited} in, {} + {paid} out",
+        before.position_value,
+        after.position_value,
+    );
+}
+
+/// Run `process`, asserting token conservation whether it succeeds or not.
+fn checked(
+    f: &mut Fixture,
+    take: impl Fn(&Fixture) -> TokenSnapshot,
+    process: impl FnOnce(&mut Fixture) -> TxResult,
+) -> TxResult {
+    let before = take(f);
+    let result = process(f);
+    let after = take(f);
+
+    match &result {
+        Ok(meta) => assert_conserved(&before, &after, meta),
+        Err(_) => assert_eq!(before, after, "failed instruction moved tokens"),
+    }
+    result
+}
+
+impl Fixture {
+    /// `liquidate`, asserting token conservation.
+    pub fn liquidate_checked(&mut self, kind: Liquidation, open: &OpenPosition) -> TxResult {
+        checked(self, |f| TokenSnapshot::take(f, open), |f| f.liquidate(kind, open))
+    }
+
+    /// `liquidate_collateral`, asserting token conservation.
+    pub fn liquidate_collateral_checked(
+        &mut self,
+        open: &OpenPosition,
+        asset: &Collateral,
+        buyer: &Buyer,
+        repay: u64,
+    ) -> TxResult {
+        checked(
+            self,
+            |f| TokenSnapshot::with_buyer(f, open, buyer),
+            |f| f.liquidate_collateral(open, asset, buyer, repay),
+        )
+    }
+
+    /// `bid`, asserting token conservation.
+    pub fn bid_checked(
+        &mut self,
+        open: &OpenPosition,
+        asset: &Collateral,
+        buyer: &Buyer,
+        max_amount: u64,
+        max_price: u64,
+    ) -> TxResult {
+        checked(
+            self,
+            |f| TokenSnapshot::with_buyer(f, open, buyer),
+            |f| f.bid(open, asset, buyer, max_amount, max_price),
+        )
+    }
+
+    /// `settle_auction`, asserting token conservation.
+    pub fn settle_auction_checked(&mut self, open: &OpenPosition, asset: &Collateral, payer: Pubkey) -> TxResult {
+        checked(self, |f| TokenSnapshot::take(f, open), |f| f.settle_auction(open, asset, payer))
+    }
+}
@@ -0,0 +1,877 @@
+//! Shared fixtures for the integration tests: a protocol with one quote mint,
+//! a Pyth-format oracle, an insurance fund, a protocol vault and one market.
+
+#![allow(dead_code)]
+
+pub mod invariants;
+pub mod runtime;
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    instruction::{Instruction, InstructionError},
+    program_pack::Pack,
+    system_instruction,
+};
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::token::spl_token;
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{transfer_fee, BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+};
+use pyth_sdk_solana::state::{AccountType, PriceAccount, PriceInfo, PriceStatus, MAGIC, VERSION_2};
+
+use liquidation_program::constants::*;
+use liquidation_program::{accounts, instruction, CollateralRegistry, Position};
+
+pub use runtime::*;
+
+pub const SOL: u64 = 1_000_000_000;
+pub const QUOTE_DECIMALS: u8 = 6;
+pub const PYTH_EXPO: i32 = -8;
+
+/// Whole-token price in PRICE_PRECISION.
+pub const fn price(whole: u64) -> u64 {
+    whole * PRICE_PRECISION
+}
+
+/// Custom error code an instruction fails with for a program error.
+pub fn program_error(code: liquidation_program::state::ErrorCode) -> InstructionError {
+    InstructionError::Custom(code.into())
+}
+
+/// Custom error code for one of anchor's own constraint errors.
+pub fn anchor_error(code: anchor_lang::error::ErrorCode) -> InstructionError {
+    InstructionError::Custom(code.into())
+}
+
+#[track_caller]
+pub fn assert_error(result: TxResult, expected: InstructionError) {
+    match result {
+        Ok(_) => panic!("expected {expected:?}, instruction succeeded"),
+        Err(err) => assert_eq!(err.error, expected, "logs: {:#?}", err.logs),
+    }
+}
+
+#[track_caller]
+pub fn assert_ok(result: TxResult) -> TxMeta {
+    match result {
+        Ok(meta) => meta,
+        Err(err) => panic!("instruction failed: {:?}\nlogs: {:#?}", err.error, err.logs),
+    }
+}
+
+/// Events of type `T` emitted by a successful instruction, in order.
+pub fn events<T: anchor_lang::Event>(meta: &TxMeta) -> Vec<T> {
+    meta.events
+        .iter()
+        .filter(|data| data.starts_with(&T::discriminator()))
+        .map(|data| T::try_from_slice(&data[8..]).unwrap())
+        .collect()
+}
+
+pub fn ix(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: liquidation_program::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+pub fn pda(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &liquidation_program::ID).0
+}
+
+pub fn vault_authority() -> Pubkey {
+    pda(&[VAULT_AUTH_SEED, VAULT_SEED])
+}
+
+pub fn insurance_authority() -> Pubkey {
+    pda(&[INSURANCE_AUTH_SEED, INSURANCE_SEED])
+}
+
+pub fn market_address(index: u16) -> Pubkey {
+    pda(&[MARKET_SEED, &index.to_le_bytes()])
+}
+
+pub fn liquidation_record_address(position: &Pubkey, count: u32) -> Pubkey {
+    pda(&[LIQ_RECORD_SEED, position.as_ref(), &count.to_le_bytes()])
+}
+
+pub fn auction_address(position: &Pubkey, asset_index: u8) -> Pubkey {
+    pda(&[AUCTION_SEED, position.as_ref(), &[asset_index]])
+}
+
+/// Which liquidation instruction to send.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Liquidation {
+    Partial,
+    Full,
+}
+
+/// Accounts shared by `liquidate_partial` and `liquidate_full`.
+#[derive(Clone, Debug)]
+pub struct LiquidationAccounts {
+    pub position: Pubkey,
+    pub market: Pubkey,
+    pub collateral_registry: Pubkey,
+    pub insurance_fund: Pubkey,
+    pub insurance_vault: Pubkey,
+    pub insurance_authority: Pubkey,
+    pub protocol_vault: Pubkey,
+    pub vault_authority: Pubkey,
+    pub quote_mint: Pubkey,
+    pub liquidator_token_account: Pubkey,
+    pub trader_token_account: Pubkey,
+    pub liquidation_record: Pubkey,
+    pub liquidator: Pubkey,
+    pub oracle: Pubkey,
+    pub token_program: Pubkey,
+    /// Oracles of the position's non-quote collateral, passed as remaining
+    /// accounts.
+    pub collateral_oracles: Vec<Pubkey>,
+}
+
+impl LiquidationAccounts {
+    pub fn instruction(&self, kind: Liquidation) -> Instruction {
+        let instruction = match kind {
+            Liquidation::Partial => ix(
+                accounts::LiquidatePartial {
+                    position: self.position,
+                    market: self.market,
+                    collateral_registry: self.collateral_registry,
+                    insurance_fund: self.insurance_fund,
+                    insurance_vault: self.insurance_vault,
+                    insurance_authority: self.insurance_authority,
+                    protocol_vault: self.protocol_vault,
+                    vault_authority: self.vault_authority,
+                    quote_mint: self.quote_mint,
+                    liquidator_token_account: self.liquidator_token_account,
+                    trader_token_account: self.trader_token_account,
+                    liquidation_record: self.liquidation_record,
+                    liquidator: self.liquidator,
+                    oracle: self.oracle,
+                    token_program: self.token_program,
+                    system_program: System::id(),
+                },
+                instruction::LiquidatePartial {},
+            ),
+            Liquidation::Full => ix(
+                accounts::LiquidateFull {
+                    position: self.position,
+                    market: self.market,
+                    collateral_registry: self.collateral_registry,
+                    insurance_fund: self.insurance_fund,
+                    insurance_vault: self.insurance_vault,
+                    insurance_authority: self.insurance_authority,
+                    protocol_vault: self.protocol_vault,
+                    vault_authority: self.vault_authority,
+                    quote_mint: self.quote_mint,
+                    liquidator_token_account: self.liquidator_token_account,
+                    trader_token_account: self.trader_token_account,
+                    liquidation_record: self.liquidation_record,
+                    liquidator: self.liquidator,
+                    oracle: self.oracle,
+                    token_program: self.token_program,
+                    system_program: System::id(),
+                },
+                instruction::LiquidateFull {},
+            ),
+        };
+        with_oracles(instruction, &self.collateral_oracles)
+    }
+}
+
+/// Append `oracles` as read-only remaining accounts.
+pub fn with_oracles(mut instruction: Instruction, oracles: &[Pubkey]) -> Instruction {
+    instruction.accounts.extend(oracles.iter().map(|oracle| AccountMeta::new_readonly(*oracle, false)));
+    instruction
+}
+
+/// An open position with a funded trader and liquidator.
+#[derive(Clone, Copy, Debug)]
+pub struct OpenPosition {
+    pub position: Pubkey,
+    pub trader: Pubkey,
+    pub trader_token_account: Pubkey,
+    pub liquidator: Pubkey,
+    pub liquidator_token_account: Pubkey,
+}
+
+/// A non-quote collateral asset listed in the registry.
+#[derive(Clone, Copy, Debug)]
+pub struct Collateral {
+    pub index: u8,
+    pub mint: Pubkey,
+    pub oracle: Pubkey,
+    pub vault: Pubkey,
+    pub token_program: Pubkey,
+}
+
+/// Someone buying collateral off the protocol, in a collateral liquidation
+/// or an auction: pays quote from one account, receives collateral in another.
+#[derive(Clone, Copy, Debug)]
+pub struct Buyer {
+    pub authority: Pubkey,
+    pub quote_account: Pubkey,
+    pub collateral_account: Pubkey,
+}
+
+pub struct Fixture {
+    pub rt: TestRuntime,
+    pub admin: Pubkey,
+    pub token_program: Pubkey,
+    pub quote_mint: Pubkey,
+    pub oracle: Pubkey,
+    pub market: Pubkey,
+    pub collateral_registry: Pubkey,
+    pub insurance_fund: Pubkey,
+    pub insurance_vault: Pubkey,
+    pub protocol_vault: Pubkey,
+}
+
+impl Fixture {
+    /// Protocol at `initial_price` with the default single 2.5% tier and no
+    /// position limits.
+    pub fn new(initial_price: u64) -> Self {
+        let mut rt = TestRuntime::new(liquidation_program::ID);
+        let quote_mint = create_mint(&mut rt, QUOTE_DECIMALS);
+        Self::with_quote_mint(rt, quote_mint, initial_price)
+    }
+
+    /// As `new`, with a Token-2022 quote mint that withholds `fee_bps` of
+    /// every transfer, up to `max_fee`.
+    pub fn with_transfer_fee(initial_price: u64, fee_bps: u16, max_fee: u64) -> Self {
+        let mut rt = TestRuntime::new(liquidation_program::ID);
+        let quote_mint = create_mint_with_transfer_fee(&mut rt, QUOTE_DECIMALS, fee_bps, max_fee);
+        Self::with_quote_mint(rt, quote_mint, initial_price)
+    }
+
+    /// Protocol over `quote_mint`, under whichever token program owns it.
+    fn with_quote_mint(mut rt: TestRuntime, quote_mint: Pubkey, initial_price: u64) -> Self {
+        let admin = rt.keypair();
+        rt.airdrop(&admin, 1_000 * SOL);
+
+        let token_program = rt.account(&quote_mint).expect("quote mint").owner;
+        let oracle = Pubkey::new_unique();
+        let insurance_fund = rt.keypair();
+
+        let mut fixture = Self {
+            rt,
+            admin,
+            token_program,
+            quote_mint,
+            oracle,
+            market: market_address(0),
+            collateral_registry: pda(&[COLLATERAL_REGISTRY_SEED]),
+            insurance_fund,
+            insurance_vault: pda(&[INSURANCE_SEED]),
+            protocol_vault: pda(&[VAULT_SEED]),
+        };
+        fixture.set_price(initial_price);
+
+        assert_ok(fixture.rt.process(
+            initialize_insurance_fund(fixture.insurance_fund, admin, quote_mint, token_program)
+                .signed_by(&[fixture.insurance_fund]),
+        ));
+        assert_ok(fixture.rt.process(initialize_protocol_vault(admin, quote_mint, token_program)));
+
+        assert_ok(fixture.rt.process(ix(
+            accounts::InitializeCollateralRegistry {
+                collateral_registry: fixture.collateral_registry,
+                authority: admin,
+                system_program: System::id(),
+            },
+            instruction::InitializeCollateralRegistry {},
+        )));
+
+        fixture.market = fixture.add_market(0, oracle);
+        fixture
+    }
+
+    pub fn add_market(&mut self, index: u16, oracle: Pubkey) -> Pubkey {
+        let market = market_address(index);
+        let mut symbol = [0u8; 16];
+        symbol[..7].copy_from_slice(b"SOL-USD");
+
+        assert_ok(self.rt.process(ix(
+            accounts::InitializeMarket {
+                market,
+                oracle,
+                authority: self.admin,
+                system_program: System::id(),
+            },
+            instruction::InitializeMarket {
+                market_index: index,
+                symbol,
+                max_price_deviation_bps: 5_000,
+                deviation_persist_slots: 0,
+                max_open_interest: u64::MAX,
+                max_position_notional: u64::MAX,
+            },
+        )));
+        market
+    }
+
+    /// Publish `price` (PRICE_PRECISION) on the oracle at the current clock.
+    pub fn set_price(&mut self, price: u64) {
+        let oracle = self.oracle;
+        self.set_oracle_price(oracle, price);
+    }
+
+    pub fn set_oracle_price(&mut self, oracle: Pubkey, price: u64) {
+        let now = self.rt.clock.unix_timestamp;
+        write_pyth_price(&mut self.rt, oracle, price, now);
+    }
+
+    pub fn wallet(&mut self) -> Pubkey {
+        let wallet = self.rt.keypair();
+        self.rt.airdrop(&wallet, 10 * SOL);
+        wallet
+    }
+
+    pub fn quote_account(&mut self, owner: &Pubkey) -> Pubkey {
+        let mint = self.quote_mint;
+        create_token_account(&mut self.rt, &mint, owner, 0)
+    }
+
+    /// Open a position on the default market and put its collateral in the
+    /// protocol vault, the way a deposit would.
+    pub fn open_position(
+        &mut self,
+        size: u64,
+        entry_price: u64,
+        collateral: i64,
+        is_long: bool,
+    ) -> OpenPosition {
+        match self.try_open_position(size, entry_price, collateral, is_long) {
+            Ok(open) => open,
+            Err(err) => panic!("opening the position failed: {:?}\nlogs: {:#?}", err.error, err.logs),
+        }
+    }
+
+    /// `open_position`, handing back the error if the program refuses it.
+    pub fn try_open_position(
+        &mut self,
+        size: u64,
+        entry_price: u64,
+        collateral: i64,
+        is_long: bool,
+    ) -> std::result::Result<OpenPosition, TxError> {
+        let trader = self.wallet();
+        let liquidator = self.wallet();
+        let trader_token_account = self.quote_account(&trader);
+        let liquidator_token_account = self.quote_account(&liquidator);
+        let position = self.rt.keypair();
+        let market = self.market;
+
+        self.rt.process(ix(
+            accounts::CreatePosition {
+                position,
+                market,
+                owner: trader,
+                system_program: System::id(),
+            },
+            instruction::CreatePosition {
+                entry_price,
+                size,
+                collateral,
+                is_long,
+                leverage: 10,
+            },
+        ).signed_by(&[position]))?;
+
+        if collateral > 0 {
+            let vault = self.protocol_vault;
+            mint_to(&mut self.rt, &vault, collateral as u64);
+        }
+
+        Ok(OpenPosition {
+            position,
+            trader,
+            trader_token_account,
+            liquidator,
+            liquidator_token_account,
+        })
+    }
+
+    /// Cap open interest per side and notional per position on the default market.
+    pub fn set_market_limits(&mut self, max_open_interest: u64, max_position_notional: u64) -> TxResult {
+        self.rt.process(ix(
+            accounts::UpdateMarketConfig {
+                market: self.market,
+                authority: self.admin,
+            },
+            instruction::SetMarketLimits {
+                max_open_interest,
+                max_position_notional,
+            },
+        ))
+    }
+
+    pub fn position(&self, key: &Pubkey) -> Position {
+        let account = self.rt.account(key).expect("position");
+        Position::try_deserialize(&mut &account.data[..]).unwrap()
+    }
+
+    pub fn balance(&self, token_account: &Pubkey) -> u64 {
+        token_balance(&self.rt, token_account)
+    }
+
+    pub fn liquidation_accounts(&self, open: &OpenPosition) -> LiquidationAccounts {
+        let count = self.position(&open.position).liquidation_count;
+
+        LiquidationAccounts {
+            position: open.position,
+            market: self.position(&open.position).market,
+            collateral_registry: self.collateral_registry,
+            insurance_fund: self.insurance_fund,
+            insurance_vault: self.insurance_vault,
+            insurance_authority: insurance_authority(),
+            protocol_vault: self.protocol_vault,
+            vault_authority: vault_authority(),
+            quote_mint: self.quote_mint,
+            liquidator_token_account: open.liquidator_token_account,
+            trader_token_account: open.trader_token_account,
+            liquidation_record: liquidation_record_address(&open.position, count),
+            liquidator: open.liquidator,
+            oracle: self.oracle,
+            token_program: self.token_program,
+            collateral_oracles: self.collateral_oracles(&open.position),
+        }
+    }
+
+    /// Close `open` as its trader, on the default market's oracle.
+    pub fn close_position(&mut self, open: &OpenPosition) -> TxResult {
+        let market = self.position(&open.position).market;
+        let instruction = ix(
+            accounts::ClosePosition {
+                position: open.position,
+                market,
+                oracle: self.oracle,
+                collateral_registry: self.collateral_registry,
+                owner: open.trader,
+            },
+            instruction::ClosePosition {},
+        );
+        let oracles = self.collateral_oracles(&open.position);
+        self.rt.process(with_oracles(instruction, &oracles))
+    }
+
+    pub fn registry(&self) -> CollateralRegistry {
+        let account = self.rt.account(&self.collateral_registry).expect("collateral registry");
+        CollateralRegistry::try_deserialize(&mut &account.data[..]).unwrap()
+    }
+
+    /// Oracles of every asset the position holds, in registry order.
+    pub fn collateral_oracles(&self, position: &Pubkey) -> Vec<Pubkey> {
+        let (registry, position) = (self.registry(), self.position(position));
+        registry.assets[..registry.num_assets as usize]
+            .iter()
+            .zip(position.deposits)
+            .filter(|(_, deposit)| *deposit > 0)
+            .map(|(asset, _)| asset.oracle)
+            .collect()
+    }
+
+    /// List a new legacy mint as collateral at `weight_bps`, priced at
+    /// `price` quote per whole token.
+    pub fn add_collateral(&mut self, decimals: u8, weight_bps: u64, price: u64) -> Collateral {
+        let mint = create_mint(&mut self.rt, decimals);
+        self.list_collateral(mint, weight_bps, price)
+    }
+
+    /// List an existing mint as collateral, under the token program that
+    /// owns it.
+    pub fn list_collateral(&mut self, mint: Pubkey, weight_bps: u64, price: u64) -> Collateral {
+        let token_program = self.rt.account(&mint).expect("mint").owner;
+        let asset = Collateral {
+            index: self.registry().num_assets,
+            mint,
+            oracle: Pubkey::new_unique(),
+            vault: pda(&[COLLATERAL_VAULT_SEED, mint.as_ref()]),
+            token_program,
+        };
+        self.set_oracle_price(asset.oracle, price);
+
+        assert_ok(self.rt.process(ix(
+            accounts::AddCollateralAsset {
+                collateral_registry: self.collateral_registry,
+                mint,
+                oracle: asset.oracle,
+                collateral_vault: asset.vault,
+                vault_authority: vault_authority(),
+                authority: self.admin,
+                system_program: System::id(),
+                token_program,
+                rent: anchor_lang::solana_program::sysvar::rent::ID,
+            },
+            instruction::AddCollateralAsset { weight_bps },
+        )));
+        asset
+    }
+
+    /// Deposit `amount` of `asset` from the trader's `from` account.
+    pub fn deposit(&mut self, open: &OpenPosition, asset: &Collateral, from: Pubkey, amount: u64) -> TxResult {
+        self.rt.process(ix(
+            accounts::DepositCollateral {
+                position: open.position,
+                collateral_registry: self.collateral_registry,
+                collateral_vault: asset.vault,
+                collateral_mint: asset.mint,
+                owner_token_account: from,
+                owner: open.trader,
+                token_program: asset.token_program,
+            },
+            instruction::DepositCollateral { asset_index: asset.index, amount },
+        ))
+    }
+
+    /// Withdraw `amount` of `asset` to the trader's `to` account.
+    pub fn withdraw(&mut self, open: &OpenPosition, asset: &Collateral, to: Pubkey, amount: u64) -> TxResult {
+        self.rt.process(ix(
+            accounts::WithdrawCollateral {
+                position: open.position,
+                collateral_registry: self.collateral_registry,
+                collateral_vault: asset.vault,
+                vault_authority: vault_authority(),
+                collateral_mint: asset.mint,
+                owner_token_account: to,
+                owner: open.trader,
+                token_program: asset.token_program,
+            },
+            instruction::WithdrawCollateral { asset_index: asset.index, amount },
+        ))
+    }
+
+    /// Repay `repay` of the position's deficit in exchange for its `asset`.
+    pub fn liquidate_collateral(
+        &mut self,
+        open: &OpenPosition,
+        asset: &Collateral,
+        liquidator: &Buyer,
+        repay: u64,
+    ) -> TxResult {
+        let market = self.position(&open.position).market;
+        let instruction = ix(
+            accounts::LiquidateCollateral {
+                position: open.position,
+                market,
+                oracle: self.oracle,
+                collateral_registry: self.collateral_registry,
+                collateral_vault: asset.vault,
+                protocol_vault: self.protocol_vault,
+                vault_authority: vault_authority(),
+                quote_mint: self.quote_mint,
+                collateral_mint: asset.mint,
+                liquidator_quote_account: liquidator.quote_account,
+                liquidator_collateral_account: liquidator.collateral_account,
+                liquidator: liquidator.authority,
+                token_program: self.token_program,
+            },
+            instruction::LiquidateCollateral { asset_index: asset.index, repay_amount: repay },
+        );
+        let oracles = self.collateral_oracles(&open.position);
+        self.rt.process(with_oracles(instruction, &oracles))
+    }
+
+    /// Start auctioning the position's `asset`, with `keeper` paying rent.
+    pub fn start_auction(&mut self, open: &OpenPosition, asset: &Collateral, keeper: Pubkey) -> TxResult {
+        self.rt.process(ix(
+            accounts::StartCollateralAuction {
+                position: open.position,
+                collateral_registry: self.collateral_registry,
+                collateral_oracle: asset.oracle,
+                auction: auction_address(&open.position, asset.index),
+                keeper,
+                system_program: System::id(),
+            },
+            instruction::StartCollateralAuction { asset_index: asset.index },
+        ))
+    }
+
+    /// A funded wallet with `quote` to spend and an empty account for `asset`.
+    pub fn buyer(&mut self, asset: &Collateral, quote: u64) -> Buyer {
+        let authority = self.wallet();
+        let quote_mint = self.quote_mint;
+        Buyer {
+            authority,
+            quote_account: create_token_account(&mut self.rt, &quote_mint, &authority, quote),
+            collateral_account: create_token_account(&mut self.rt, &asset.mint, &authority, 0),
+        }
+    }
+
+    pub fn bid(
+        &mut self,
+        open: &OpenPosition,
+        asset: &Collateral,
+        bidder: &Buyer,
+        max_amount: u64,
+        max_price: u64,
+    ) -> TxResult {
+        self.rt.process(ix(
+            accounts::BidCollateralAuction {
+                auction: auction_address(&open.position, asset.index),
+                position: open.position,
+                collateral_registry: self.collateral_registry,
+                collateral_vault: asset.vault,
+                protocol_vault: self.protocol_vault,
+                vault_authority: vault_authority(),
+                quote_mint: self.quote_mint,
+                collateral_mint: asset.mint,
+                bidder_quote_account: bidder.quote_account,
+                bidder_collateral_account: bidder.collateral_account,
+                bidder: bidder.authority,
+                token_program: self.token_program,
+            },
+            instruction::BidCollateralAuction { max_amount, max_price },
+        ))
+    }
+
+    /// Settle the auction of the position's `asset`, refunding rent to `payer`.
+    pub fn settle_auction(&mut self, open: &OpenPosition, asset: &Collateral, payer: Pubkey) -> TxResult {
+        self.rt.process(ix(
+            accounts::SettleCollateralAuction {
+                auction: auction_address(&open.position, asset.index),
+                position: open.position,
+                payer,
+                insurance_fund: self.insurance_fund,
+                insurance_vault: self.insurance_vault,
+                insurance_authority: insurance_authority(),
+                protocol_vault: self.protocol_vault,
+                quote_mint: self.quote_mint,
+                token_program: self.token_program,
+            },
+            instruction::SettleCollateralAuction {},
+        ))
+    }
+
+    pub fn liquidate(&mut self, kind: Liquidation, open: &OpenPosition) -> TxResult {
+        let accounts = self.liquidation_accounts(open);
+        self.rt.process(accounts.instruction(kind))
+    }
+}
+
+/// `initialize_insurance_fund` for a new `insurance_fund` keypair account,
+/// with `payer` as its authority.
+pub fn initialize_insurance_fund(insurance_fund: Pubkey, payer: Pubkey, mint: Pubkey, token_program: Pubkey) -> Instruction {
+    ix(
+        accounts::InitializeInsuranceFund {
+            insurance_fund,
+            insurance_vault: pda(&[INSURANCE_SEED]),
+            insurance_authority: insurance_authority(),
+            payer,
+            mint,
+            system_program: System::id(),
+            token_program,
+            rent: anchor_lang::solana_program::sysvar::rent::ID,
+        },
+        instruction::InitializeInsuranceFund { authority: payer },
+    )
+}
+
+pub fn initialize_protocol_vault(payer: Pubkey, mint: Pubkey, token_program: Pubkey) -> Instruction {
+    ix(
+        accounts::InitializeProtocolVault {
+            protocol_vault: pda(&[VAULT_SEED]),
+            vault_authority: vault_authority(),
+            insurance_vault: pda(&[INSURANCE_SEED]),
+            payer,
+            mint,
+            system_program: System::id(),
+            token_program,
+            rent: anchor_lang::solana_program::sysvar::rent::ID,
+        },
+        instruction::InitializeProtocolVault {},
+    )
+}
+
+pub trait SignedBy {
+    /// Mark extra accounts (new keypair accounts) as signers.
+    fn signed_by(self, signers: &[Pubkey]) -> Self;
+}
+
+impl SignedBy for Instruction {
+    fn signed_by(mut self, signers: &[Pubkey]) -> Self {
+        for meta in self.accounts.iter_mut() {
+            if signers.contains(&meta.pubkey) {
+                meta.is_signer = true;
+            }
+        }
+        self
+    }
+}
+
+/// Pyth v2 price account trading at `price` (PRICE_PRECISION), published at `publish_time`.
+pub fn write_pyth_price(rt: &mut TestRuntime, oracle: Pubkey, price: u64, publish_time: i64) {
+    let scale = 10i64.pow((-PYTH_EXPO) as u32) / PRICE_PRECISION as i64;
+    let account = PriceAccount {
+        magic: MAGIC,
+        ver: VERSION_2,
+        atype: AccountType::Price as u32,
+        expo: PYTH_EXPO,
+        timestamp: publish_time,
+        agg: PriceInfo {
+            price: price as i64 * scale,
+            conf: 0,
+            status: PriceStatus::Trading,
+            ..PriceInfo::default()
+        },
+        ..PriceAccount::default()
+    };
+
+    rt.set_account(
+        oracle,
+        AccountState {
+            lamports: SOL,
+            data: bytemuck::bytes_of(&account).to_vec(),
+            owner: Pubkey::new_unique(),
+            executable: false,
+        },
+    );
+}
+
+/// The price (PRICE_PRECISION) a `write_pyth_price` account trades at.
+pub fn pyth_price(rt: &TestRuntime, oracle: &Pubkey) -> u64 {
+    let data = rt.account(oracle).expect("oracle").data;
+    let account: PriceAccount = bytemuck::pod_read_unaligned(&data[..std::mem::size_of::<PriceAccount>()]);
+    let scale = 10i64.pow((-PYTH_EXPO) as u32) / PRICE_PRECISION as i64;
+    (account.agg.price / scale) as u64
+}
+
+/// Rent-exempt lamports for `len` bytes.
+pub fn rent_exempt(len: usize) -> u64 {
+    Rent::default().minimum_balance(len)
+}
+
+/// Create an account of `len` bytes owned by `owner` at a new keypair.
+fn create_keypair_account(rt: &mut TestRuntime, len: usize, owner: &Pubkey) -> Pubkey {
+    let key = rt.keypair();
+    let payer = rt.payer();
+    assert_ok(rt.process(system_instruction::create_account(
+        &payer,
+        &key,
+        rent_exempt(len),
+        len as u64,
+        owner,
+    )));
+    key
+}
+
+/// Legacy SPL mint whose authority is a runtime keypair.
+pub fn create_mint(rt: &mut TestRuntime, decimals: u8) -> Pubkey {
+    let mint = create_keypair_account(rt, spl_token::state::Mint::LEN, &spl_token::ID);
+    let authority = rt.keypair();
+    assert_ok(rt.process(
+        spl_token_2022::instruction::initialize_mint2(&spl_token::ID, &mint, &authority, None, decimals)
+            .unwrap(),
+    ));
+    mint
+}
+
+/// Token-2022 mint with a transfer-fee config whose authority is a runtime
+/// keypair.
+pub fn create_mint_with_transfer_fee(rt: &mut TestRuntime, decimals: u8, fee_bps: u16, max_fee: u64) -> Pubkey {
+    let len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[
+        ExtensionType::TransferFeeConfig,
+    ])
+    .unwrap();
+    let mint = create_keypair_account(rt, len, &spl_token_2022::ID);
+    let authority = rt.keypair();
+    assert_ok(rt.process(
+        transfer_fee::instruction::initialize_transfer_fee_config(
+            &spl_token_2022::ID,
+            &mint,
+            Some(&authority),
+            Some(&authority),
+            fee_bps,
+            max_fee,
+        )
+        .unwrap(),
+    ));
+    assert_ok(rt.process(
+        spl_token_2022::instruction::initialize_mint2(&spl_token_2022::ID, &mint, &authority, None, decimals)
+            .unwrap(),
+    ));
+    mint
+}
+
+/// Transfer fees withheld in the token account at `key`; zero for accounts
+/// without the transfer-fee extension.
+pub fn withheld_fees(rt: &TestRuntime, key: &Pubkey) -> u64 {
+    let account = rt.account(key).expect("token account");
+    let state = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data).unwrap();
+    state
+        .get_extension::<transfer_fee::TransferFeeAmount>()
+        .map_or(0, |fees| u64::from(fees.withheld_amount))
+}
+
+/// Token account for `mint` under whichever token program owns the mint,
+/// with room for the extensions the mint requires, holding `amount`.
+pub fn create_token_account(rt: &mut TestRuntime, mint: &Pubkey, owner: &Pubkey, amount: u64) -> Pubkey {
+    let mint_account = rt.account(mint).expect("mint");
+    let token_program = mint_account.owner;
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_account.data).unwrap();
+    let required = ExtensionType::get_required_init_account_extensions(&state.get_extension_types().unwrap());
+    let len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(&required).unwrap();
+
+    let key = create_keypair_account(rt, len, &token_program);
+    assert_ok(rt.process(
+        spl_token_2022::instruction::initialize_account3(&token_program, &key, mint, owner).unwrap(),
+    ));
+    if amount > 0 {
+        mint_to(rt, &key, amount);
+    }
+    key
+}
+
+/// Write a legacy token account directly, replacing whatever is at `key`.
+pub fn set_token_account(rt: &mut TestRuntime, key: Pubkey, mint: &Pubkey, owner: &Pubkey, amount: u64) {
+    let mut data = vec![0u8; spl_token::state::Account::LEN];
+    spl_token::state::Account {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: spl_token::state::AccountState::Initialized,
+        ..spl_token::state::Account::default()
+    }
+    .pack_into_slice(&mut data);
+
+    rt.set_account(
+        key,
+        AccountState {
+            lamports: rent_exempt(data.len()),
+            data,
+            owner: spl_token::ID,
+            executable: false,
+        },
+    );
+}
+
+pub fn token_state(rt: &TestRuntime, key: &Pubkey) -> spl_token_2022::state::Account {
+    let account = rt.account(key).expect("token account");
+    StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+        .unwrap()
+        .base
+}
+
+pub fn token_balance(rt: &TestRuntime, key: &Pubkey) -> u64 {
+    token_state(rt, key).amount
+}
+
+pub fn mint_state(rt: &TestRuntime, mint: &Pubkey) -> spl_token_2022::state::Mint {
+    let account = rt.account(mint).expect("mint");
+    StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&account.data)
+        .unwrap()
+        .base
+}
+
+/// Mint `amount` into the token account at `key`, signed by the mint's authority.
+pub fn mint_to(rt: &mut TestRuntime, key: &Pubkey, amount: u64) {
+    let account = rt.account(key).expect("token account");
+    let mint = token_state(rt, key).mint;
+    let authority = mint_state(rt, &mint).mint_authority.unwrap();
+
+    assert_ok(rt.process(
+        spl_token_2022::instruction::mint_to(&account.owner, &mint, key, &authority, &[], amount).unwrap(),
+    ));
+}
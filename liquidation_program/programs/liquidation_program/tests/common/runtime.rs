@@ -0,0 +1,294 @@
+//! solana-program-test harness driven synchronously.
+//!
+//! The program runs natively through `processor!`; the system program and
+//! both token programs are the real ones program-test ships, so ownership,
+//! signer, writability and rent rules are the runtime's own. Each `process`
+//! call is one transaction holding one instruction, paid for by the
+//! program-test payer and signed by every keypair the runtime handed out
+//! that the instruction marks as a signer.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Once;
+
+use anchor_lang::solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{Instruction, InstructionError},
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    system_program,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use solana_program_test::{processor, ProgramTest, ProgramTestBanksClientExt, ProgramTestContext};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    signature::{Keypair, Signature, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccountState {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+    pub executable: bool,
+}
+
+impl From<Account> for AccountState {
+    fn from(account: Account) -> Self {
+        Self {
+            lamports: account.lamports,
+            data: account.data,
+            owner: account.owner,
+            executable: account.executable,
+        }
+    }
+}
+
+/// Logs and `emit!` payloads from one instruction.
+#[derive(Clone, Debug, Default)]
+pub struct TxMeta {
+    pub logs: Vec<String>,
+    pub events: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TxError {
+    pub error: InstructionError,
+    pub logs: Vec<String>,
+}
+
+pub type TxResult = std::result::Result<TxMeta, TxError>;
+
+/// Prefix `sol_log_data` payloads are logged under.
+const DATA_LOG: &str = "Program data: ";
+
+/// program-test 1.16 only prints `sol_log_data` to stdout in native mode, so
+/// `emit!` would never reach the transaction logs. Route it through
+/// `sol_log` with the payload the runtime would have logged; everything
+/// else goes to program-test's own stubs.
+struct EventStubs(Box<dyn SyscallStubs>);
+
+impl SyscallStubs for EventStubs {
+    fn sol_log(&self, message: &str) {
+        self.0.sol_log(message)
+    }
+
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        let encoded: Vec<String> = fields.iter().map(|field| STANDARD.encode(field)).collect();
+        self.0.sol_log(&format!("{DATA_LOG}{}", encoded.join(" ")))
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        self.0.sol_invoke_signed(instruction, account_infos, signers_seeds)
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_clock_sysvar(var_addr)
+    }
+
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_epoch_schedule_sysvar(var_addr)
+    }
+
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_fees_sysvar(var_addr)
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_rent_sysvar(var_addr)
+    }
+
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        self.0.sol_get_return_data()
+    }
+
+    fn sol_set_return_data(&self, data: &[u8]) {
+        self.0.sol_set_return_data(data)
+    }
+
+    fn sol_get_stack_height(&self) -> u64 {
+        self.0.sol_get_stack_height()
+    }
+}
+
+struct NoStubs;
+
+impl SyscallStubs for NoStubs {}
+
+/// Wrap program-test's stubs, which it installs the first time a bank is
+/// set up.
+fn install_event_stubs() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        let program_test = set_syscall_stubs(Box::new(NoStubs));
+        set_syscall_stubs(Box::new(EventStubs(program_test)));
+    });
+}
+
+/// `emit!` payloads in the order they were logged.
+fn events(logs: &[String]) -> Vec<Vec<u8>> {
+    logs.iter()
+        .filter_map(|line| line.split_once(DATA_LOG))
+        .map(|(_, data)| STANDARD.decode(data).expect("event payload"))
+        .collect()
+}
+
+fn process_instruction<'a>(program_id: &Pubkey, accounts: &[AccountInfo<'a>], data: &[u8]) -> ProgramResult {
+    // anchor's entry ties the slice to its AccountInfos' lifetime, which the
+    // processor signature can't; leak the handles (not the account data)
+    let accounts: &'a [AccountInfo<'a>] = Box::leak(accounts.to_vec().into_boxed_slice());
+    liquidation_program::entry(program_id, accounts, data)
+}
+
+/// A program-test bank plus the clock the program sees. The clock is
+/// written to the bank before every transaction, so tests move time by
+/// changing it.
+pub struct TestRuntime {
+    tokio: tokio::runtime::Runtime,
+    context: ProgramTestContext,
+    keypairs: HashMap<Pubkey, Keypair>,
+    sent: HashSet<Signature>,
+    pub clock: Clock,
+}
+
+impl TestRuntime {
+    pub fn new(program_id: Pubkey) -> Self {
+        // program-test logs every instruction at debug unless told otherwise
+        if std::env::var_os("RUST_LOG").is_none() {
+            std::env::set_var("RUST_LOG", "error");
+        }
+
+        let tokio = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let program_test = ProgramTest::new(
+            "liquidation_program",
+            program_id,
+            processor!(process_instruction),
+        );
+        let context = tokio.block_on(program_test.start_with_context());
+        install_event_stubs();
+
+        Self {
+            tokio,
+            context,
+            keypairs: HashMap::new(),
+            sent: HashSet::new(),
+            clock: Clock {
+                slot: 1_000,
+                epoch_start_timestamp: 1_700_000_000,
+                epoch: 1,
+                leader_schedule_epoch: 1,
+                unix_timestamp: 1_700_000_000,
+            },
+        }
+    }
+
+    /// A new keypair the runtime signs with whenever an instruction marks
+    /// its key as a signer.
+    pub fn keypair(&mut self) -> Pubkey {
+        let keypair = Keypair::new();
+        let key = keypair.pubkey();
+        self.keypairs.insert(key, keypair);
+        key
+    }
+
+    /// The program-test payer, which funds every transaction.
+    pub fn payer(&self) -> Pubkey {
+        self.context.payer.pubkey()
+    }
+
+    pub fn set_account(&mut self, key: Pubkey, state: AccountState) {
+        let account = Account {
+            lamports: state.lamports,
+            data: state.data,
+            owner: state.owner,
+            executable: state.executable,
+            rent_epoch: 0,
+        };
+        self.context.set_account(&key, &AccountSharedData::from(account));
+    }
+
+    pub fn account(&self, key: &Pubkey) -> Option<AccountState> {
+        let mut banks_client = self.context.banks_client.clone();
+        self.tokio
+            .block_on(banks_client.get_account(*key))
+            .expect("banks client")
+            .map(AccountState::from)
+    }
+
+    pub fn airdrop(&mut self, key: &Pubkey, lamports: u64) {
+        let mut account = self.account(key).unwrap_or(AccountState {
+            owner: system_program::ID,
+            ..AccountState::default()
+        });
+        account.lamports += lamports;
+        self.set_account(*key, account);
+    }
+
+    /// Move the clock forward by `seconds`, one slot per 400ms.
+    pub fn warp(&mut self, seconds: i64) {
+        self.clock.unix_timestamp += seconds;
+        self.clock.slot += (seconds as u64) * 5 / 2;
+    }
+
+    pub fn warp_slots(&mut self, slots: u64) {
+        self.clock.slot += slots;
+    }
+
+    pub fn process(&mut self, instruction: Instruction) -> TxResult {
+        self.context.set_sysvar(&self.clock);
+
+        let mut signers: Vec<&Keypair> = vec![&self.context.payer];
+        for meta in instruction.accounts.iter().filter(|meta| meta.is_signer) {
+            if let Some(keypair) = self.keypairs.get(&meta.pubkey) {
+                if !signers.iter().any(|signer| signer.pubkey() == meta.pubkey) {
+                    signers.push(keypair);
+                }
+            }
+        }
+
+        let payer = self.context.payer.pubkey();
+        let sign = |blockhash| {
+            Transaction::new_signed_with_payer(std::slice::from_ref(&instruction), Some(&payer), &signers, blockhash)
+        };
+
+        let mut banks_client = self.context.banks_client.clone();
+        let mut blockhash = self.tokio.block_on(banks_client.get_latest_blockhash()).expect("blockhash");
+        let mut transaction = sign(blockhash);
+
+        // an identical transaction would be dropped as already processed;
+        // wait for the next blockhash to tell them apart
+        while !self.sent.insert(transaction.signatures[0]) {
+            blockhash = self
+                .tokio
+                .block_on(banks_client.get_new_latest_blockhash(&blockhash))
+                .expect("blockhash");
+            transaction = sign(blockhash);
+        }
+
+        let outcome = self
+            .tokio
+            .block_on(banks_client.process_transaction_with_metadata(transaction))
+            .expect("banks client");
+        let logs = outcome.metadata.map(|meta| meta.log_messages).unwrap_or_default();
+
+        match outcome.result {
+            Ok(()) => Ok(TxMeta {
+                events: events(&logs),
+                logs,
+            }),
+            Err(TransactionError::InstructionError(_, error)) => Err(TxError { error, logs }),
+            Err(error) => panic!("transaction rejected before the program ran: {error:?}\nlogs: {logs:#?}"),
+        }
+    }
+}
@@ -0,0 +1,423 @@
+//! End-to-end liquidation scenarios: each outcome of liquidate_partial and
+//! liquidate_full, with the token balances of every party checked afterwards.
+//! Every call also goes through the conservation check in common::invariants.
+
+mod common;
+
+use anchor_lang::prelude::*;
+
+use common::invariants::{assert_conserved, TokenSnapshot};
+use common::*;
+use liquidation_program::constants::*;
+use liquidation_program::state::{ErrorCode, LiquidationEvent, ProtocolInsolvencyEvent, RewardSource};
+use liquidation_program::{InsuranceFund, LiquidationRecord};
+
+const SIZE: u64 = 10 * PRICE_PRECISION;
+const COLLATERAL: i64 = 60_000_000;
+
+// 10 units at 100 backed by 60 quote, the only quote in the protocol vault,
+// so a payout beyond the position's own margin fails the transfer; and
+// `insurance` in the insurance vault.
+fn setup(is_long: bool, insurance: u64) -> (Fixture, OpenPosition) {
+    let mut f = Fixture::new(price(100));
+    let open = f.open_position(SIZE, price(100), COLLATERAL, is_long);
+    let insurance_vault = f.insurance_vault;
+    if insurance > 0 {
+        mint_to(&mut f.rt, &insurance_vault, insurance);
+    }
+    (f, open)
+}
+
+fn insurance_fund(f: &Fixture) -> InsuranceFund {
+    let account = f.rt.account(&f.insurance_fund).expect("insurance fund");
+    InsuranceFund::try_deserialize(&mut &account.data[..]).unwrap()
+}
+
+fn record(f: &Fixture, open: &OpenPosition, count: u32) -> LiquidationRecord {
+    let account = f
+        .rt
+        .account(&liquidation_record_address(&open.position, count))
+        .expect("liquidation record");
+    LiquidationRecord::try_deserialize(&mut &account.data[..]).unwrap()
+}
+
+fn single_event(meta: &TxMeta) -> LiquidationEvent {
+    let mut emitted = events::<LiquidationEvent>(meta);
+    assert_eq!(emitted.len(), 1, "expected one LiquidationEvent");
+    emitted.pop().unwrap()
+}
+
+#[test]
+fn partial_on_healthy_position_is_a_no_op() {
+    let (mut f, open) = setup(true, 0);
+    let before = TokenSnapshot::take(&f, &open);
+    let lamports = f.rt.account(&open.liquidator).unwrap().lamports;
+
+    let meta = assert_ok(f.liquidate_checked(Liquidation::Partial, &open));
+
+    assert!(events::<LiquidationEvent>(&meta).is_empty());
+    assert_eq!(TokenSnapshot::take(&f, &open), before);
+
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, SIZE);
+    assert_eq!(pos.collateral, COLLATERAL);
+    assert_eq!(pos.liquidation_count, 0);
+
+    // the record was closed again and its rent handed back
+    assert!(f.rt.account(&liquidation_record_address(&open.position, 0)).is_none());
+    assert_eq!(f.rt.account(&open.liquidator).unwrap().lamports, lamports);
+}
+
+#[test]
+fn full_on_healthy_position_is_a_no_op() {
+    let (mut f, open) = setup(true, 100_000_000);
+    let lamports = f.rt.account(&open.liquidator).unwrap().lamports;
+
+    let meta = assert_ok(f.liquidate_checked(Liquidation::Full, &open));
+
+    assert!(events::<LiquidationEvent>(&meta).is_empty());
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, SIZE);
+    assert_eq!(pos.collateral, COLLATERAL);
+    assert_eq!(pos.liquidation_count, 0);
+    assert!(f.rt.account(&liquidation_record_address(&open.position, 0)).is_none());
+    assert_eq!(f.rt.account(&open.liquidator).unwrap().lamports, lamports);
+}
+
+#[test]
+fn partial_closes_half_and_books_the_pnl() {
+    let (mut f, open) = setup(true, 0);
+    f.set_price(price(96));
+    let before = TokenSnapshot::take(&f, &open);
+
+    let meta = assert_ok(f.liquidate_checked(Liquidation::Partial, &open));
+
+    // margin 60 - 40 = 20 on 960 notional; the closed half carried 10 of it
+    // and 2.5% of that goes to the liquidator, the trader is paid nothing
+    let reward = 250_000;
+    let after = TokenSnapshot::take(&f, &open);
+    assert_eq!(after.protocol_vault, COLLATERAL as u64 - reward);
+    assert_eq!(after.liquidator, before.liquidator + reward);
+    assert_eq!(after.trader, before.trader);
+    assert_eq!(after.insurance_vault, before.insurance_vault);
+
+    let event = single_event(&meta);
+    assert_eq!(event.liquidated_size, SIZE / 2);
+    assert_eq!(event.liquidation_price, price(96));
+    assert_eq!(event.margin_before, 20_000_000);
+    assert_eq!(event.margin_after, 19_750_000);
+    assert_eq!(event.liquidator_reward, reward);
+    assert_eq!(event.trader_payout, 0);
+    assert_eq!(event.bad_debt, 0);
+    assert_eq!(event.reward_source, RewardSource::ProtocolVault);
+
+    // the rest of the margin stays behind the other half, marked to 96
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, SIZE / 2);
+    assert_eq!(pos.collateral, 19_750_000);
+    assert_eq!(pos.entry_price, price(96));
+    assert_eq!(pos.liquidation_count, 1);
+
+    let record = record(&f, &open, 0);
+    assert_eq!(record.liquidated_size, SIZE / 2);
+    assert_eq!(record.liquidator_reward, reward);
+    assert_eq!(record.liquidator, open.liquidator);
+}
+
+#[test]
+fn partial_liquidates_an_underwater_short() {
+    let (mut f, open) = setup(false, 0);
+    f.set_price(price(104));
+    let before = TokenSnapshot::take(&f, &open);
+
+    let meta = assert_ok(f.liquidate_checked(Liquidation::Partial, &open));
+
+    // margin 60 - 40 = 20 on 1040 notional, against 26 required
+    let event = single_event(&meta);
+    assert_eq!(event.margin_before, 20_000_000);
+    assert_eq!(event.liquidator_reward, 250_000);
+    assert_eq!(event.trader_payout, 0);
+
+    let after = TokenSnapshot::take(&f, &open);
+    assert_eq!(after.liquidator, before.liquidator + 250_000);
+    assert_eq!(after.trader, before.trader);
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, SIZE / 2);
+    assert_eq!(pos.collateral, 19_750_000);
+}
+
+#[test]
+fn partial_refuses_when_the_reward_leaves_too_little() {
+    let (mut f, open) = setup(true, 0);
+    f.set_price(price(95));
+
+    // 10 of margin less the 0.125 reward against 11.875 required on 475
+    assert_error(
+        f.liquidate_checked(Liquidation::Partial, &open),
+        program_error(ErrorCode::PartialInsufficient),
+    );
+    assert_eq!(f.position(&open.position).size, SIZE);
+}
+
+#[test]
+fn partial_refuses_when_half_is_not_enough() {
+    let (mut f, open) = setup(true, 0);
+    f.set_price(price(40));
+    let before = TokenSnapshot::take(&f, &open);
+
+    assert_error(
+        f.liquidate_checked(Liquidation::Partial, &open),
+        program_error(ErrorCode::PartialInsufficient),
+    );
+
+    assert_eq!(TokenSnapshot::take(&f, &open), before);
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, SIZE);
+    assert_eq!(pos.liquidation_count, 0);
+    assert!(f.rt.account(&liquidation_record_address(&open.position, 0)).is_none());
+
+    // the full path takes over
+    assert_ok(f.liquidate_checked(Liquidation::Full, &open));
+    assert_eq!(f.position(&open.position).size, 0);
+}
+
+#[test]
+fn full_close_pays_leftover_to_trader() {
+    let (mut f, open) = setup(true, 0);
+    f.set_price(price(95));
+    let before = TokenSnapshot::take(&f, &open);
+
+    let meta = assert_ok(f.liquidate_checked(Liquidation::Full, &open));
+
+    // 10 of margin left: 2.5% to the liquidator, the rest to the trader
+    let reward = 250_000;
+    let remaining = 9_750_000;
+    let after = TokenSnapshot::take(&f, &open);
+    assert_eq!(after.protocol_vault, before.protocol_vault - reward - remaining);
+    assert_eq!(after.liquidator, before.liquidator + reward);
+    assert_eq!(after.trader, before.trader + remaining);
+    assert_eq!(after.insurance_vault, before.insurance_vault);
+
+    let event = single_event(&meta);
+    assert_eq!(event.liquidated_size, SIZE);
+    assert_eq!(event.margin_before, 10_000_000);
+    assert_eq!(event.margin_after, remaining as i64);
+    assert_eq!(event.liquidator_reward, reward);
+    assert_eq!(event.trader_payout, remaining);
+    assert_eq!(event.bad_debt, 0);
+    assert_eq!(event.reward_source, RewardSource::ProtocolVault);
+    assert!(events::<ProtocolInsolvencyEvent>(&meta).is_empty());
+
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, 0);
+    assert_eq!(pos.collateral, 0);
+    assert_eq!(pos.liquidation_count, 1);
+    assert_eq!(record(&f, &open, 0).liquidator_reward, reward);
+}
+
+#[test]
+fn full_close_with_bad_debt_covered_by_insurance() {
+    let (mut f, open) = setup(true, 100_000_000);
+    f.set_price(price(90));
+    let before = TokenSnapshot::take(&f, &open);
+
+    let meta = assert_ok(f.liquidate_checked(Liquidation::Full, &open));
+
+    // margin 60 - 100 = -40, all of it refilled from the fund; the
+    // liquidator's 2.5% of the deficit also comes out of the insurance vault
+    let covered = 40_000_000;
+    let reward = 1_000_000;
+    let after = TokenSnapshot::take(&f, &open);
+    assert_eq!(after.insurance_vault, before.insurance_vault - covered - reward);
+    assert_eq!(after.protocol_vault, before.protocol_vault + covered);
+    assert_eq!(after.liquidator, before.liquidator + reward);
+    assert_eq!(after.trader, before.trader);
+
+    let event = single_event(&meta);
+    assert_eq!(event.margin_before, -40_000_000);
+    assert_eq!(event.margin_after, 0);
+    assert_eq!(event.liquidator_reward, reward);
+    assert_eq!(event.trader_payout, 0);
+    assert_eq!(event.insurance_covered, covered);
+    assert_eq!(event.bad_debt, 0);
+    assert_eq!(event.reward_source, RewardSource::InsuranceVault);
+    assert!(events::<ProtocolInsolvencyEvent>(&meta).is_empty());
+
+    let fund = insurance_fund(&f);
+    assert_eq!(fund.total_bad_debt_covered, covered);
+    assert_eq!(fund.balance, after.insurance_vault);
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, 0);
+    assert_eq!(pos.collateral, 0);
+}
+
+#[test]
+fn full_close_with_bad_debt_partly_covered() {
+    let (mut f, open) = setup(true, 20_000_000);
+    f.set_price(price(90));
+    let before = TokenSnapshot::take(&f, &open);
+
+    let meta = assert_ok(f.liquidate_checked(Liquidation::Full, &open));
+
+    // the whole fund goes to the deficit, leaving nothing for a reward
+    let after = TokenSnapshot::take(&f, &open);
+    assert_eq!(after.insurance_vault, 0);
+    assert_eq!(after.protocol_vault, before.protocol_vault + 20_000_000);
+    assert_eq!(after.liquidator, before.liquidator);
+    assert_eq!(after.trader, before.trader);
+
+    // 20 covered, the other 20 is the protocol's
+    let event = single_event(&meta);
+    assert_eq!(event.insurance_covered, 20_000_000);
+    assert_eq!(event.liquidator_reward, 0);
+    assert_eq!(event.bad_debt, 20_000_000);
+    let insolvency = events::<ProtocolInsolvencyEvent>(&meta);
+    assert_eq!(insolvency.len(), 1);
+    assert_eq!(insolvency[0].amount, 20_000_000);
+
+    let fund = insurance_fund(&f);
+    assert_eq!(fund.total_bad_debt_covered, 20_000_000);
+    assert_eq!(fund.balance, 0);
+}
+
+#[test]
+fn full_close_with_uncovered_bad_debt() {
+    let (mut f, open) = setup(true, 0);
+    f.set_price(price(90));
+    let before = TokenSnapshot::take(&f, &open);
+
+    let meta = assert_ok(f.liquidate_checked(Liquidation::Full, &open));
+
+    // nothing in the fund: no reward, no tokens move, the whole deficit is
+    // written off the position and reported
+    assert_eq!(TokenSnapshot::take(&f, &open), TokenSnapshot { position_value: 0, ..before });
+
+    let event = single_event(&meta);
+    assert_eq!(event.liquidator_reward, 0);
+    assert_eq!(event.trader_payout, 0);
+    assert_eq!(event.bad_debt, 40_000_000);
+    let insolvency = events::<ProtocolInsolvencyEvent>(&meta);
+    assert_eq!(insolvency.len(), 1);
+    assert_eq!(insolvency[0].amount, 40_000_000);
+
+    assert_eq!(insurance_fund(&f).total_bad_debt_covered, 0);
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, 0);
+    assert_eq!(pos.liquidation_count, 1);
+    assert_eq!(record(&f, &open, 0).bad_debt, 40_000_000);
+}
+
+#[test]
+fn closed_position_cannot_be_liquidated_again() {
+    let (mut f, open) = setup(true, 0);
+    f.set_price(price(95));
+    assert_ok(f.liquidate_checked(Liquidation::Full, &open));
+
+    for kind in [Liquidation::Partial, Liquidation::Full] {
+        assert_error(f.liquidate_checked(kind, &open), program_error(ErrorCode::ZeroPosition));
+    }
+}
+
+#[test]
+fn repeated_liquidations_write_one_record_each() {
+    let (mut f, open) = setup(false, 0);
+    f.set_price(price(104));
+    assert_ok(f.liquidate_checked(Liquidation::Partial, &open));
+
+    // healthy again: the rest stays open until the price runs away
+    assert_ok(f.liquidate_checked(Liquidation::Full, &open));
+    assert_eq!(f.position(&open.position).size, SIZE / 2);
+
+    f.set_price(price(220));
+    assert_ok(f.liquidate_checked(Liquidation::Full, &open));
+
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, 0);
+    assert_eq!(pos.liquidation_count, 2);
+    assert_eq!(record(&f, &open, 0).liquidated_size, SIZE / 2);
+    assert_eq!(record(&f, &open, 1).liquidated_size, SIZE / 2);
+}
+
+#[test]
+#[should_panic(expected = "balance changes don't match")]
+fn conservation_check_catches_untracked_movement() {
+    let (mut f, open) = setup(true, 100_000_000);
+    f.set_price(price(90));
+    let before = TokenSnapshot::take(&f, &open);
+    let meta = assert_ok(f.liquidate(Liquidation::Full, &open));
+
+    // the same transaction, had the deficit only been booked on the fund
+    let mut after = TokenSnapshot::take(&f, &open);
+    after.insurance_vault += 40_000_000;
+    after.protocol_vault -= 40_000_000;
+    assert_conserved(&before, &after, &meta);
+}
+
+#[test]
+#[should_panic(expected = "paid out more than it was worth")]
+fn conservation_check_catches_value_created_for_the_position() {
+    let (mut f, open) = setup(true, 0);
+    f.set_price(price(96));
+    let before = TokenSnapshot::take(&f, &open);
+    let meta = assert_ok(f.liquidate(Liquidation::Partial, &open));
+
+    // the same transaction, had the partial left the position its margin
+    // and paid the liquidator on top of it
+    let mut after = TokenSnapshot::take(&f, &open);
+    after.position_value += 250_000;
+    assert_conserved(&before, &after, &meta);
+}
+
+#[test]
+fn keeper_plan_matches_on_chain_outcome() {
+    use liquidation_math::{plan_liquidation, Bps, FullPlan, LiquidationContext, LiquidationPlan, Price, QuoteAmount};
+
+    for (is_long, whole) in [(true, 100), (true, 96), (true, 95), (true, 90), (true, 40), (false, 104), (false, 150)] {
+        let (mut f, open) = setup(is_long, 20_000_000);
+        f.set_price(price(whole));
+
+        // what a keeper would predict from the accounts alone
+        let pos = f.position(&open.position);
+        let context = LiquidationContext {
+            haircut: QuoteAmount::ZERO,
+            has_deposits: false,
+            insurance_balance: f.balance(&f.insurance_vault),
+            reward_bps: Bps(LIQUIDATOR_REWARD_BPS),
+        };
+        let (_, plan) =
+            plan_liquidation(&pos.terms(), Price(price(whole)), &context, |_| DEFAULT_MAINTENANCE_MARGIN_BPS).unwrap();
+
+        let kind = match plan {
+            LiquidationPlan::Partial(_) | LiquidationPlan::Healthy => Liquidation::Partial,
+            LiquidationPlan::Full(_) => Liquidation::Full,
+        };
+        let meta = assert_ok(f.liquidate_checked(kind, &open));
+        let emitted = events::<LiquidationEvent>(&meta);
+
+        match plan {
+            LiquidationPlan::Healthy => assert!(emitted.is_empty()),
+            LiquidationPlan::Partial(fill) => {
+                let event = &emitted[0];
+                assert_eq!(event.liquidated_size, fill.closed_size.0);
+                assert_eq!(event.liquidator_reward, fill.liquidator_reward);
+                assert_eq!(event.trader_payout, 0);
+                assert_eq!(event.margin_after, fill.new_collateral);
+                assert_eq!(f.position(&open.position).entry_price, fill.entry_price.0);
+            }
+            LiquidationPlan::Full(FullPlan::Leftover { margin, liquidator_reward, trader_payout }) => {
+                let event = &emitted[0];
+                assert_eq!(event.margin_before, margin);
+                assert_eq!(event.liquidator_reward, liquidator_reward);
+                assert_eq!(event.trader_payout, trader_payout);
+            }
+            LiquidationPlan::Full(FullPlan::BadDebt { margin, bad_debt, insurance_covered, liquidator_reward }) => {
+                let event = &emitted[0];
+                assert_eq!(event.margin_before, margin);
+                assert_eq!(event.insurance_covered, insurance_covered);
+                assert_eq!(event.liquidator_reward, liquidator_reward);
+                assert_eq!(event.bad_debt, bad_debt - insurance_covered);
+            }
+            LiquidationPlan::Full(FullPlan::Deferred { .. }) => unreachable!("no deposits"),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! A market priced by a MockOracle, in a `mock-oracle` build: the mock
+//! instructions reach their handlers through the program's fallback, and a
+//! liquidation reads its price from the mock account.
+
+mod common;
+
+use anchor_lang::prelude::*;
+
+use common::*;
+use liquidation_program::constants::*;
+use liquidation_program::mock_oracle::{accounts, instruction};
+use liquidation_program::oracle::OracleStatus;
+use liquidation_program::state::{ErrorCode, LiquidationEvent};
+
+const SIZE: u64 = 10 * PRICE_PRECISION;
+const COLLATERAL: i64 = 60_000_000;
+
+// a second market on a mock oracle at 100, made the fixture's default
+fn setup() -> (Fixture, Pubkey) {
+    let mut f = Fixture::new(price(100));
+    let mock = f.rt.keypair();
+    let authority = f.admin;
+    assert_ok(f.rt.process(
+        ix(
+            accounts::InitializeMockOracle { mock_oracle: mock, authority, system_program: System::id() },
+            instruction::InitializeMockOracle { price: price(100) as i64, conf: 0, expo: PRICE_EXPONENT },
+        )
+        .signed_by(&[mock]),
+    ));
+    f.oracle = mock;
+    f.market = f.add_market(1, mock);
+    (f, mock)
+}
+
+fn set_mock_price(f: &mut Fixture, mock: Pubkey, authority: Pubkey, price: u64, publish_time: Option<i64>) -> TxResult {
+    f.rt.process(ix(
+        accounts::SetMockOracle { mock_oracle: mock, authority },
+        instruction::SetMockOracle {
+            price: price as i64,
+            conf: 0,
+            expo: PRICE_EXPONENT,
+            publish_time,
+            status: OracleStatus::Trading,
+        },
+    ))
+}
+
+#[test]
+fn liquidation_reads_the_mock_price() {
+    let (mut f, mock) = setup();
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let admin = f.admin;
+
+    let meta = assert_ok(f.liquidate(Liquidation::Full, &open));
+    assert!(events::<LiquidationEvent>(&meta).is_empty());
+
+    // 10 of margin left at 95: 2.5% to the liquidator, the rest to the trader
+    assert_ok(set_mock_price(&mut f, mock, admin, price(95), None));
+    let meta = assert_ok(f.liquidate(Liquidation::Full, &open));
+
+    let event = events::<LiquidationEvent>(&meta).pop().expect("a LiquidationEvent");
+    assert_eq!(event.liquidation_price, price(95));
+    assert_eq!(event.margin_before, 10_000_000);
+    assert_eq!(event.liquidator_reward, 250_000);
+    assert_eq!(event.trader_payout, 9_750_000);
+    assert_eq!(f.balance(&open.liquidator_token_account), 250_000);
+    assert_eq!(f.balance(&open.trader_token_account), 9_750_000);
+    assert_eq!(f.position(&open.position).size, 0);
+}
+
+#[test]
+fn stale_mock_price_is_refused() {
+    let (mut f, mock) = setup();
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let (admin, now) = (f.admin, f.rt.clock.unix_timestamp);
+
+    assert_ok(set_mock_price(&mut f, mock, admin, price(95), Some(now - MAX_ORACLE_STALENESS - 1)));
+    assert_error(f.liquidate(Liquidation::Full, &open), program_error(ErrorCode::StaleOraclePrice));
+}
+
+#[test]
+fn only_the_authority_sets_the_mock_price() {
+    let (mut f, mock) = setup();
+    let stranger = f.wallet();
+
+    assert_error(set_mock_price(&mut f, mock, stranger, price(95), None), program_error(ErrorCode::Unauthorized));
+}
@@ -0,0 +1,178 @@
+//! Opening and closing positions outside of liquidation, and the market's
+//! open interest and position size caps.
+
+mod common;
+
+use anchor_lang::prelude::*;
+
+use common::*;
+use liquidation_program::constants::*;
+use liquidation_program::state::ErrorCode;
+use liquidation_program::{accounts, instruction, Market};
+
+const SIZE: u64 = 10 * PRICE_PRECISION;
+const COLLATERAL: i64 = 60_000_000;
+
+fn market(f: &Fixture) -> Market {
+    let account = f.rt.account(&f.market).expect("market");
+    Market::try_deserialize(&mut &account.data[..]).unwrap()
+}
+
+// record the oracle price as the market's last accepted one
+fn refresh(f: &mut Fixture) {
+    let (market, oracle) = (f.market, f.oracle);
+    assert_ok(f.rt.process(ix(
+        accounts::RefreshMarketPrice { market, oracle },
+        instruction::RefreshMarketPrice {},
+    )));
+}
+
+#[test]
+fn owner_closes_a_healthy_position() {
+    let mut f = Fixture::new(price(100));
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+    assert_eq!(market(&f).long_open_interest, SIZE);
+
+    // 60 - 20 of margin against a 2.5% requirement on 980
+    f.set_price(price(98));
+    assert_ok(f.close_position(&open));
+
+    // the 20 loss is booked against the collateral
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, 0);
+    assert_eq!(pos.collateral, COLLATERAL - 20_000_000);
+    assert_eq!(market(&f).long_open_interest, 0);
+}
+
+#[test]
+fn loss_carried_by_deposits_blocks_withdrawal_after_close() {
+    let mut f = Fixture::new(price(100));
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let asset = f.add_collateral(9, 5_000, price(1));
+    let account = create_token_account(&mut f.rt, &asset.mint, &open.trader, 100_000_000_000);
+    assert_ok(f.deposit(&open, &asset, account, 100_000_000_000));
+
+    // 60 - 70 + 50 of haircut deposits against 23.25 required on 930
+    f.set_price(price(93));
+    assert_ok(f.close_position(&open));
+
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, 0);
+    assert_eq!(pos.collateral, COLLATERAL - 70_000_000);
+
+    // the deposits can't leave while they still owe the 10 deficit
+    assert_error(f.withdraw(&open, &asset, account, 1), program_error(ErrorCode::PositionInDeficit));
+    assert_eq!(f.balance(&asset.vault), 100_000_000_000);
+
+    // they go to auction instead
+    let keeper = f.wallet();
+    assert_ok(f.start_auction(&open, &asset, keeper));
+}
+
+#[test]
+fn position_below_maintenance_cannot_be_closed() {
+    let mut f = Fixture::new(price(100));
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+
+    // 60 - 50 of margin against 23.75 required on 950
+    f.set_price(price(95));
+    assert_error(f.close_position(&open), program_error(ErrorCode::PositionUnhealthy));
+
+    let pos = f.position(&open.position);
+    assert_eq!(pos.size, SIZE);
+    assert_eq!(pos.collateral, COLLATERAL);
+    assert_eq!(market(&f).long_open_interest, SIZE);
+
+    // it goes through liquidation instead
+    let vault = f.protocol_vault;
+    mint_to(&mut f.rt, &vault, 10_000_000_000);
+    assert_ok(f.liquidate(Liquidation::Full, &open));
+    assert_eq!(f.position(&open.position).size, 0);
+}
+
+#[test]
+fn close_needs_the_markets_oracle() {
+    let mut f = Fixture::new(price(100));
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let other_oracle = Pubkey::new_unique();
+    f.set_oracle_price(other_oracle, price(100));
+    f.oracle = other_oracle;
+
+    assert_error(f.close_position(&open), program_error(ErrorCode::OracleMismatch));
+}
+
+#[test]
+fn only_the_owner_can_close() {
+    let mut f = Fixture::new(price(100));
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let mut other = open;
+    other.trader = f.wallet();
+
+    assert_error(f.close_position(&other), program_error(ErrorCode::Unauthorized));
+}
+
+#[test]
+fn open_interest_cap_refuses_new_opens() {
+    let mut f = Fixture::new(price(100));
+    assert_ok(f.set_market_limits(15 * PRICE_PRECISION, u64::MAX));
+    f.open_position(SIZE, price(100), COLLATERAL, true);
+
+    let err = f.try_open_position(SIZE, price(100), COLLATERAL, true).unwrap_err();
+    assert_eq!(err.error, program_error(ErrorCode::OpenInterestLimitExceeded));
+    assert_eq!(market(&f).long_open_interest, SIZE);
+
+    // the short side has its own room, and the long side fills to the cap
+    f.open_position(SIZE, price(100), COLLATERAL, false);
+    f.open_position(5 * PRICE_PRECISION, price(100), COLLATERAL, true);
+    let market = market(&f);
+    assert_eq!(market.long_open_interest, 15 * PRICE_PRECISION);
+    assert_eq!(market.short_open_interest, SIZE);
+}
+
+#[test]
+fn position_notional_cap_refuses_large_positions() {
+    let mut f = Fixture::new(price(100));
+    assert_ok(f.set_market_limits(u64::MAX, 1_000 * PRICE_PRECISION));
+    f.open_position(SIZE, price(100), COLLATERAL, true);
+
+    let err = f.try_open_position(SIZE + 1, price(100), COLLATERAL, true).unwrap_err();
+    assert_eq!(err.error, program_error(ErrorCode::PositionNotionalLimitExceeded));
+
+    // a low entry price is sized at the last accepted oracle price instead
+    refresh(&mut f);
+    let err = f.try_open_position(SIZE + 1, price(50), COLLATERAL, true).unwrap_err();
+    assert_eq!(err.error, program_error(ErrorCode::PositionNotionalLimitExceeded));
+    assert_eq!(market(&f).long_open_interest, SIZE);
+}
+
+#[test]
+fn closing_and_liquidating_release_open_interest() {
+    let mut f = Fixture::new(price(100));
+    assert_ok(f.set_market_limits(3 * SIZE, u64::MAX));
+    let closed = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let partly = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let liquidated = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let vault = f.protocol_vault;
+    mint_to(&mut f.rt, &vault, 10_000_000_000);
+    assert_eq!(market(&f).long_open_interest, 3 * SIZE);
+
+    f.set_price(price(98));
+    assert_ok(f.close_position(&closed));
+    assert_eq!(market(&f).long_open_interest, 2 * SIZE);
+
+    // a partial releases the half it closes, a full liquidation everything
+    f.set_price(price(96));
+    assert_ok(f.liquidate(Liquidation::Partial, &partly));
+    assert_eq!(market(&f).long_open_interest, SIZE + SIZE / 2);
+    f.set_price(price(95));
+    assert_ok(f.liquidate(Liquidation::Full, &liquidated));
+    assert_eq!(f.position(&liquidated.position).size, 0);
+    assert_eq!(market(&f).long_open_interest, SIZE / 2);
+
+    // the partly liquidated position is healthy again and can be closed
+    assert_ok(f.close_position(&partly));
+    assert_eq!(market(&f).long_open_interest, 0);
+
+    // and the room can be taken again
+    f.open_position(3 * SIZE, price(95), 3 * COLLATERAL, true);
+}
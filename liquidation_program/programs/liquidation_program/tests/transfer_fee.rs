@@ -0,0 +1,107 @@
+//! Liquidations over a Token-2022 quote mint that charges a transfer fee:
+//! the vaults pay out the gross amounts, the trader and liquidator receive
+//! them net of the fee, and the fee stays withheld in their accounts.
+
+mod common;
+
+use common::*;
+use liquidation_program::constants::PRICE_PRECISION;
+use liquidation_program::state::{LiquidationEvent, ProtocolInsolvencyEvent};
+
+const SIZE: u64 = 10 * PRICE_PRECISION;
+const COLLATERAL: i64 = 60_000_000;
+const VAULT_FLOAT: u64 = 10_000_000_000;
+const FEE_BPS: u16 = 100;
+
+/// 1% of `amount`, rounded up the way Token-2022 rounds it.
+fn fee(amount: u64) -> u64 {
+    (amount * FEE_BPS as u64).div_ceil(10_000)
+}
+
+// the same position as in liquidations.rs, over a quote mint taking 1% of
+// every transfer
+fn setup(insurance: u64) -> (Fixture, OpenPosition) {
+    let mut f = Fixture::with_transfer_fee(price(100), FEE_BPS, u64::MAX);
+    let open = f.open_position(SIZE, price(100), COLLATERAL, true);
+    let (vault, insurance_vault) = (f.protocol_vault, f.insurance_vault);
+    mint_to(&mut f.rt, &vault, VAULT_FLOAT);
+    if insurance > 0 {
+        mint_to(&mut f.rt, &insurance_vault, insurance);
+    }
+    (f, open)
+}
+
+fn single_event(meta: &TxMeta) -> LiquidationEvent {
+    let mut emitted = events::<LiquidationEvent>(meta);
+    assert_eq!(emitted.len(), 1, "expected one LiquidationEvent");
+    emitted.pop().unwrap()
+}
+
+#[test]
+fn partial_pays_the_reward_net_of_the_fee() {
+    let (mut f, open) = setup(0);
+    f.set_price(price(96));
+    let vault = f.balance(&f.protocol_vault);
+
+    let meta = assert_ok(f.liquidate(Liquidation::Partial, &open));
+
+    // the trader is paid nothing on a partial, so only the reward is charged
+    let reward = 250_000;
+    assert_eq!(f.balance(&f.protocol_vault), vault - reward);
+    assert_eq!(f.balance(&open.liquidator_token_account), reward - fee(reward));
+    assert_eq!(f.balance(&open.trader_token_account), 0);
+    assert_eq!(withheld_fees(&f.rt, &open.liquidator_token_account), fee(reward));
+
+    // the reward is what the vault paid
+    let event = single_event(&meta);
+    assert_eq!(event.liquidator_reward, reward);
+    assert_eq!(event.trader_payout, 0);
+    assert_eq!(f.position(&open.position).size, SIZE / 2);
+}
+
+#[test]
+fn full_pays_leftover_net_of_the_fee() {
+    let (mut f, open) = setup(0);
+    f.set_price(price(95));
+    let vault = f.balance(&f.protocol_vault);
+
+    let meta = assert_ok(f.liquidate(Liquidation::Full, &open));
+
+    let (reward, remaining) = (250_000, 9_750_000);
+    assert_eq!(f.balance(&f.protocol_vault), vault - reward - remaining);
+    assert_eq!(f.balance(&open.liquidator_token_account), reward - fee(reward));
+    assert_eq!(f.balance(&open.trader_token_account), remaining - fee(remaining));
+    assert_eq!(withheld_fees(&f.rt, &open.liquidator_token_account), fee(reward));
+    assert_eq!(withheld_fees(&f.rt, &open.trader_token_account), fee(remaining));
+
+    let event = single_event(&meta);
+    assert_eq!(event.liquidator_reward, reward);
+    assert_eq!(event.trader_payout, remaining - fee(remaining));
+    assert_eq!(event.bad_debt, 0);
+    assert_eq!(f.position(&open.position).size, 0);
+}
+
+#[test]
+fn fee_on_insurance_cover_is_reported_as_bad_debt() {
+    let (mut f, open) = setup(100_000_000);
+    f.set_price(price(90));
+    let (vault, insurance) = (f.balance(&f.protocol_vault), f.balance(&f.insurance_vault));
+
+    let meta = assert_ok(f.liquidate(Liquidation::Full, &open));
+
+    // the fund sends the whole 40 deficit, the vault receives 1% less
+    let (covered, reward) = (40_000_000, 1_000_000);
+    assert_eq!(f.balance(&f.insurance_vault), insurance - covered - reward);
+    assert_eq!(f.balance(&f.protocol_vault), vault + covered - fee(covered));
+    assert_eq!(withheld_fees(&f.rt, &f.protocol_vault), fee(covered));
+    assert_eq!(f.balance(&open.liquidator_token_account), reward - fee(reward));
+    assert_eq!(f.balance(&open.trader_token_account), 0);
+
+    let event = single_event(&meta);
+    assert_eq!(event.insurance_covered, covered);
+    assert_eq!(event.liquidator_reward, reward);
+    assert_eq!(event.bad_debt, fee(covered));
+    let insolvency = events::<ProtocolInsolvencyEvent>(&meta);
+    assert_eq!(insolvency.len(), 1);
+    assert_eq!(insolvency[0].amount, fee(covered));
+}